@@ -49,6 +49,12 @@ impl Position {
         self.start = start;
         self.end = end;
     }
+
+    /// Move the position up or down by `delta` lines, keeping its columns
+    pub fn shift_lines(&mut self, delta: isize) {
+        self.line = self.line.saturating_add_signed(delta);
+        self.end_line = self.end_line.saturating_add_signed(delta);
+    }
 }
 
 /// Base trait for all AST nodes
@@ -57,6 +63,506 @@ pub trait AstNode {
     fn position_mut(&mut self) -> &mut Position;
 }
 
+/// Shift every position in a subtree by a number of lines
+pub trait ShiftLines {
+    fn shift_lines(&mut self, delta: isize);
+}
+
+impl ShiftLines for Position {
+    fn shift_lines(&mut self, delta: isize) {
+        Position::shift_lines(self, delta);
+    }
+}
+
+impl<T: ShiftLines> ShiftLines for Box<T> {
+    fn shift_lines(&mut self, delta: isize) {
+        self.as_mut().shift_lines(delta);
+    }
+}
+
+impl<T: ShiftLines> ShiftLines for Option<T> {
+    fn shift_lines(&mut self, delta: isize) {
+        if let Some(value) = self {
+            value.shift_lines(delta);
+        }
+    }
+}
+
+impl<T: ShiftLines> ShiftLines for Vec<T> {
+    fn shift_lines(&mut self, delta: isize) {
+        for value in self {
+            value.shift_lines(delta);
+        }
+    }
+}
+
+/// Visitor called for nodes reached by `Walk::walk`
+pub trait Visitor<'a> {
+    fn visit_symbol(&mut self, _symbol: &'a Symbol) {}
+    /// A string literal with its quote style, none for a multi-line string
+    fn visit_string(&mut self, _position: &'a Position, _value: &'a str, _quote: Option<QuoteStyle>) {}
+}
+
+/// Walk a subtree in source order, passing each visited node to a `Visitor`
+pub trait Walk {
+    fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>);
+}
+
+impl Walk for Position {
+    fn walk<'a>(&'a self, _visitor: &mut dyn Visitor<'a>) {}
+}
+
+impl<T: Walk> Walk for Box<T> {
+    fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+        self.as_ref().walk(visitor);
+    }
+}
+
+impl<T: Walk> Walk for Option<T> {
+    fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+        if let Some(value) = self {
+            value.walk(visitor);
+        }
+    }
+}
+
+impl<T: Walk> Walk for Vec<T> {
+    fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+        for value in self {
+            value.walk(visitor);
+        }
+    }
+}
+
+/// Collects the string literals reached by a walk
+struct StringLiteralCollector<'a> {
+    strings: Vec<(&'a Position, &'a str, Option<QuoteStyle>)>,
+}
+
+impl<'a> Visitor<'a> for StringLiteralCollector<'a> {
+    fn visit_string(&mut self, position: &'a Position, value: &'a str, quote: Option<QuoteStyle>) {
+        self.strings.push((position, value, quote));
+    }
+}
+
+/// All string and multi-line string literals in a module with their positions
+/// and quote styles, `None` for a multi-line string
+pub fn string_literals(module: &Module) -> Vec<(&Position, &str, Option<QuoteStyle>)> {
+    let mut collector = StringLiteralCollector { strings: Vec::new() };
+    module.walk(&mut collector);
+    collector.strings
+}
+
+/// Mutable visitor called for nodes reached by `WalkMut::walk_mut`
+pub trait VisitorMut {
+    fn visit_symbol(&mut self, _symbol: &mut Symbol) {}
+    fn visit_position(&mut self, _position: &mut Position) {}
+    fn visit_string(&mut self, _string: &mut StringLiteral) {}
+}
+
+/// Walk a subtree mutably, passing each visited node to a `VisitorMut`
+pub trait WalkMut {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut);
+}
+
+impl WalkMut for Position {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        visitor.visit_position(self);
+    }
+}
+
+impl<T: WalkMut> WalkMut for Box<T> {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        self.as_mut().walk_mut(visitor);
+    }
+}
+
+impl<T: WalkMut> WalkMut for Option<T> {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        if let Some(value) = self {
+            value.walk_mut(visitor);
+        }
+    }
+}
+
+impl<T: WalkMut> WalkMut for Vec<T> {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        for value in self {
+            value.walk_mut(visitor);
+        }
+    }
+}
+
+/// Renames symbols of one kind, counting the renamed occurrences
+struct SymbolRenamer<'a> {
+    kind: SymbolKind,
+    from: &'a str,
+    to: &'a str,
+    count: usize,
+}
+
+impl VisitorMut for SymbolRenamer<'_> {
+    fn visit_symbol(&mut self, symbol: &mut Symbol) {
+        if symbol.kind == self.kind && symbol.name == self.from {
+            symbol.name = self.to.to_string();
+            self.count += 1;
+        } else if self.kind == SymbolKind::VarAsName && symbol.kind == SymbolKind::VarRef {
+            // `config.version` refers to the var alias `config`
+            if let Some(rest) = symbol.name.strip_prefix(self.from).and_then(|rest| rest.strip_prefix('.')) {
+                symbol.name = format!("{}.{}", self.to, rest);
+                self.count += 1;
+            }
+        }
+    }
+}
+
+/// Rename every symbol of `kind` named `from` to `to`, returning the number of
+/// renamed occurrences
+///
+/// Renaming a var alias (`SymbolKind::VarAsName`) also renames the
+/// `alias.attr` references to it.
+pub fn rename_symbol(module: &mut Module, kind: SymbolKind, from: &str, to: &str) -> usize {
+    let mut renamer = SymbolRenamer { kind, from, to, count: 0 };
+    module.walk_mut(&mut renamer);
+    renamer.count
+}
+
+/// Remove every `Comment` node from the tree
+pub fn strip_comments(node: &mut AstNodeEnum) {
+    let children = match node {
+        AstNodeEnum::Module(module) => &mut module.children,
+        AstNodeEnum::VarDef(var_def) => &mut var_def.children,
+        AstNodeEnum::GraphDef(graph_def) => &mut graph_def.children,
+        AstNodeEnum::OpDef(op_def) => &mut op_def.children,
+        AstNodeEnum::OpInput(input) => &mut input.children,
+        AstNodeEnum::OpOutput(output) => &mut output.children,
+        AstNodeEnum::OpConfig(config) => &mut config.children,
+        _ => return,
+    };
+    children.retain(|child| !matches!(child, AstNodeEnum::Comment(_)));
+    children.iter_mut().for_each(strip_comments);
+}
+
+/// Resets every position to the default so trees can be compared structurally
+struct PositionEraser;
+
+impl VisitorMut for PositionEraser {
+    fn visit_position(&mut self, position: &mut Position) {
+        *position = Position::new(0, 0, 0);
+    }
+}
+
+/// Compare two AST nodes ignoring source positions
+///
+/// Useful for checking that a reformatted or regenerated source parses back
+/// to the same tree.
+pub fn eq_ignore_position(a: &AstNodeEnum, b: &AstNodeEnum) -> bool {
+    let mut a = a.clone();
+    let mut b = b.clone();
+    a.walk_mut(&mut PositionEraser);
+    b.walk_mut(&mut PositionEraser);
+    a == b
+}
+
+/// Replace `target` by `new`, giving `new` the position of `target`
+///
+/// Errors about the replacement then point at the replaced source, e.g. a
+/// var reference inlined as its value. Only the position of the node itself
+/// is taken over, the positions inside `new` are kept.
+pub fn replace_value_keep_position(target: &mut AstNodeEnum, mut new: AstNodeEnum) {
+    *new.position_mut() = target.position().clone();
+    *target = new;
+}
+
+/// Implement `ShiftLines`, `Walk` and `WalkMut` for a struct by walking the listed fields
+macro_rules! impl_traversal {
+    ($($type:ident { $($field:ident),* $(,)? })*) => {
+        $(
+            impl ShiftLines for $type {
+                fn shift_lines(&mut self, delta: isize) {
+                    $(self.$field.shift_lines(delta);)*
+                }
+            }
+
+            impl Walk for $type {
+                fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+                    $(self.$field.walk(visitor);)*
+                }
+            }
+
+            impl WalkMut for $type {
+                fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+                    $(self.$field.walk_mut(visitor);)*
+                }
+            }
+        )*
+    };
+}
+
+/// Implement `ShiftLines` and `Walk` for a string literal struct
+macro_rules! impl_string_literal {
+    ($($type:ident => $quote:expr),* $(,)?) => {
+        $(
+            impl ShiftLines for $type {
+                fn shift_lines(&mut self, delta: isize) {
+                    self.position.shift_lines(delta);
+                }
+            }
+
+            impl Walk for $type {
+                fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+                    visitor.visit_string(&self.position, &self.value, $quote(self));
+                }
+            }
+        )*
+    };
+}
+
+impl_string_literal!(
+    StringLiteral => |string: &StringLiteral| Some(string.quote),
+    MultiLineStringLiteral => |_: &MultiLineStringLiteral| None,
+);
+
+impl WalkMut for StringLiteral {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        self.position.walk_mut(visitor);
+        visitor.visit_string(self);
+    }
+}
+
+impl WalkMut for MultiLineStringLiteral {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        self.position.walk_mut(visitor);
+    }
+}
+
+impl ShiftLines for Symbol {
+    fn shift_lines(&mut self, delta: isize) {
+        self.position.shift_lines(delta);
+    }
+}
+
+impl Walk for Symbol {
+    fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+        visitor.visit_symbol(self);
+    }
+}
+
+impl WalkMut for Symbol {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        self.position.walk_mut(visitor);
+        visitor.visit_symbol(self);
+    }
+}
+
+impl_traversal! {
+    Module { position, children }
+    Comment { position }
+    NumberLiteral { position }
+    FloatLiteral { position }
+    BoolLiteral { position }
+    DateTimeLiteral { position }
+    DateLiteral { position }
+    NullLiteral { position }
+    DictStatement { position, items }
+    DictItem { position, key, value }
+    ListStatement { position, items }
+    TupleStatement { position, items }
+    SetStatement { position, items }
+    Import { position, items }
+    ImportItem { position, path, alias }
+    AttrDef { position, name, value, condition, else_value }
+    RefDef { position, name, value, condition, default }
+    Annotation { position, name, args }
+    VarDef { position, annotations, children, alias }
+    GraphDef { position, annotations, children, alias, version, template_graph, template_version }
+    NodeDef { position, outputs, value }
+    NodeBlock { position, name, inputs, attrs }
+    RefGraphBlock { position, ref_name, inputs, attrs }
+    NodeInputTuple { position, items }
+    NodeInputKeyDef { position, items }
+    NodeInputKeyItem { position, key, value }
+    NodeInputValues { position, items }
+    NodeAttr { position, name, value }
+    ParamDef { position, name, value }
+    ConditionDef { position, outputs, value }
+    ConditionBlock { position, condition, true_branch, false_branch }
+    ForLoopBlock { position, inputs, outputs, node, condition }
+    OpDef { position, annotations, children, alias }
+    OpMeta { position, children }
+    OpInput { position, children }
+    OpOutput { position, children }
+    OpConfig { position, children }
+    OpSpec { position, name, items }
+    OpSpecItem { position, value }
+    ClosedInterval { position, ge, le }
+    MixInterval { position, ge, gt, le, lt }
+}
+
+impl ShiftLines for NodeInputDef {
+    fn shift_lines(&mut self, delta: isize) {
+        match self {
+            NodeInputDef::Tuple(tuple) => tuple.shift_lines(delta),
+            NodeInputDef::KeyValue(key_def) => key_def.shift_lines(delta),
+        }
+    }
+}
+
+impl ShiftLines for NodeAttrValue {
+    fn shift_lines(&mut self, delta: isize) {
+        match self {
+            NodeAttrValue::Symbol(symbol) => symbol.shift_lines(delta),
+            NodeAttrValue::String(string) => string.shift_lines(delta),
+            NodeAttrValue::Bool(boolean) => boolean.shift_lines(delta),
+            NodeAttrValue::ListSymbol(symbols) => symbols.shift_lines(delta),
+            NodeAttrValue::ListParamDef(params) => params.shift_lines(delta),
+        }
+    }
+}
+
+impl ShiftLines for IntervalBound {
+    fn shift_lines(&mut self, delta: isize) {
+        match self {
+            IntervalBound::Number(number) => number.shift_lines(delta),
+            IntervalBound::Float(float) => float.shift_lines(delta),
+        }
+    }
+}
+
+impl ShiftLines for ConditionExpr {
+    fn shift_lines(&mut self, delta: isize) {
+        match self {
+            ConditionExpr::Statement(stmt) => stmt.shift_lines(delta),
+            ConditionExpr::Block(block) => block.shift_lines(delta),
+            ConditionExpr::Symbol(symbol) => symbol.shift_lines(delta),
+            ConditionExpr::String(string) => string.shift_lines(delta),
+            ConditionExpr::MultiLineString(string) => string.shift_lines(delta),
+            ConditionExpr::And(left, right) | ConditionExpr::Or(left, right) => {
+                left.shift_lines(delta);
+                right.shift_lines(delta);
+            }
+            ConditionExpr::Not(inner) | ConditionExpr::Group(inner) => inner.shift_lines(delta),
+        }
+    }
+}
+
+impl Walk for NodeInputDef {
+    fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+        match self {
+            NodeInputDef::Tuple(tuple) => tuple.walk(visitor),
+            NodeInputDef::KeyValue(key_def) => key_def.walk(visitor),
+        }
+    }
+}
+
+impl Walk for NodeAttrValue {
+    fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+        match self {
+            NodeAttrValue::Symbol(symbol) => symbol.walk(visitor),
+            NodeAttrValue::Bool(boolean) => boolean.walk(visitor),
+            NodeAttrValue::ListSymbol(symbols) => symbols.walk(visitor),
+            NodeAttrValue::String(string) => string.walk(visitor),
+            NodeAttrValue::ListParamDef(params) => params.walk(visitor),
+        }
+    }
+}
+
+impl Walk for IntervalBound {
+    fn walk<'a>(&'a self, _visitor: &mut dyn Visitor<'a>) {}
+}
+
+impl Walk for ConditionExpr {
+    fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+        match self {
+            ConditionExpr::Statement(stmt) => stmt.walk(visitor),
+            ConditionExpr::Block(block) => block.walk(visitor),
+            ConditionExpr::Symbol(symbol) => symbol.walk(visitor),
+            ConditionExpr::String(string) => string.walk(visitor),
+            ConditionExpr::MultiLineString(string) => string.walk(visitor),
+            ConditionExpr::And(left, right) | ConditionExpr::Or(left, right) => {
+                left.walk(visitor);
+                right.walk(visitor);
+            }
+            ConditionExpr::Not(inner) | ConditionExpr::Group(inner) => inner.walk(visitor),
+        }
+    }
+}
+
+impl WalkMut for NodeInputDef {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        match self {
+            NodeInputDef::Tuple(tuple) => tuple.walk_mut(visitor),
+            NodeInputDef::KeyValue(key_def) => key_def.walk_mut(visitor),
+        }
+    }
+}
+
+impl WalkMut for NodeAttrValue {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        match self {
+            NodeAttrValue::Symbol(symbol) => symbol.walk_mut(visitor),
+            NodeAttrValue::String(string) => string.walk_mut(visitor),
+            NodeAttrValue::Bool(boolean) => boolean.walk_mut(visitor),
+            NodeAttrValue::ListSymbol(symbols) => symbols.walk_mut(visitor),
+            NodeAttrValue::ListParamDef(params) => params.walk_mut(visitor),
+        }
+    }
+}
+
+impl WalkMut for IntervalBound {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        match self {
+            IntervalBound::Number(number) => number.walk_mut(visitor),
+            IntervalBound::Float(float) => float.walk_mut(visitor),
+        }
+    }
+}
+
+impl ShiftLines for ConditionStatement {
+    fn shift_lines(&mut self, delta: isize) {
+        self.position.shift_lines(delta);
+        self.left_operand.shift_lines(delta);
+        self.right_operand.shift_lines(delta);
+    }
+}
+
+impl Walk for ConditionStatement {
+    fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+        self.left_operand.walk(visitor);
+        self.right_operand.walk(visitor);
+    }
+}
+
+impl WalkMut for ConditionStatement {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        let before = (self.left_operand.clone(), self.right_operand.clone());
+        self.position.walk_mut(visitor);
+        self.left_operand.walk_mut(visitor);
+        self.right_operand.walk_mut(visitor);
+        // `raw` no longer spells the operands once a visitor rewrites them
+        if !eq_ignore_position(&before.0, &self.left_operand) || !eq_ignore_position(&before.1, &self.right_operand) {
+            self.raw.clear();
+        }
+    }
+}
+
+impl WalkMut for ConditionExpr {
+    fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+        match self {
+            ConditionExpr::Statement(stmt) => stmt.walk_mut(visitor),
+            ConditionExpr::Block(block) => block.walk_mut(visitor),
+            ConditionExpr::Symbol(symbol) => symbol.walk_mut(visitor),
+            ConditionExpr::String(string) => string.walk_mut(visitor),
+            ConditionExpr::MultiLineString(string) => string.walk_mut(visitor),
+            ConditionExpr::And(left, right) | ConditionExpr::Or(left, right) => {
+                left.walk_mut(visitor);
+                right.walk_mut(visitor);
+            }
+            ConditionExpr::Not(inner) | ConditionExpr::Group(inner) => inner.walk_mut(visitor),
+        }
+    }
+}
+
 /// Symbol kinds corresponding to SymbolKind enum in Python
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SymbolKind {
@@ -87,6 +593,7 @@ pub enum SymbolKind {
     OpSpecDtype,
     ForLoopInputs,
     ForLoopOutputs,
+    AnnotationName,
 }
 
 /// Module - top-level AST node representing a GOS file
@@ -96,11 +603,25 @@ pub struct Module {
     pub children: Vec<AstNodeEnum>,
 }
 
+/// Kind of a comment
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CommentKind {
+    /// `# text`, `// text` or `/* text */`
+    #[default]
+    Regular,
+    /// `/// text`, documents the item that follows
+    Doc,
+    /// `//! text`, documents the module
+    ModuleDoc,
+}
+
 /// Comment node
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Comment {
     pub position: Position,
     pub value: String,
+    #[serde(default)]
+    pub kind: CommentKind,
 }
 
 /// Symbol - represents identifiers with kind information
@@ -128,6 +649,25 @@ impl Symbol {
     pub fn set_kind(&mut self, kind: SymbolKind) {
         self.kind = kind;
     }
+
+    /// Node name and output index of an indexed input, see `split_output_index`
+    pub fn output_index(&self) -> Option<(&str, usize)> {
+        split_output_index(&self.name)
+    }
+}
+
+/// Split an input picking one output of a multi-output node into the node
+/// name and the index, e.g. `node.1` or `node[1]` into `("node", 1)`
+pub fn split_output_index(name: &str) -> Option<(&str, usize)> {
+    if let Some(rest) = name.strip_suffix(']') {
+        let (base, index) = rest.rsplit_once('[')?;
+        return Some((base, index.parse().ok()?));
+    }
+    let (base, index) = name.rsplit_once('.')?;
+    if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((base, index.parse().ok()?))
 }
 
 /// Reference to another symbol
@@ -136,11 +676,27 @@ pub struct Ref {
     pub name: Symbol,
 }
 
+/// Quote a string literal is written with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum QuoteStyle {
+    /// `"text"`
+    #[default]
+    Double,
+    /// `'text'`
+    Single,
+}
+
 /// String literal
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StringLiteral {
     pub position: Position,
     pub value: String,
+    #[serde(default)]
+    pub quote: QuoteStyle,
+    /// Byte offsets in `value` of each `${` written `\${`, kept as it is by
+    /// var interpolation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub escaped_interpolations: Vec<usize>,
 }
 
 /// Multi-line string literal
@@ -193,6 +749,7 @@ pub struct DateLiteral {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NullLiteral {
     pub position: Position,
+    pub raw: String,
 }
 
 /// Dictionary statement
@@ -238,6 +795,15 @@ pub struct Import {
     pub items: Vec<ImportItem>,
 }
 
+/// Annotation before a definition, `@name` or `@name(args)`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub position: Position,
+    /// Name without the leading `@`
+    pub name: Symbol,
+    pub args: Vec<AstNodeEnum>,
+}
+
 /// Import item
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ImportItem {
@@ -270,6 +836,7 @@ pub struct RefDef {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VarDef {
     pub position: Position,
+    pub annotations: Vec<Annotation>,
     pub children: Vec<AstNodeEnum>,
     pub alias: Option<Symbol>,
     pub offset: Option<HashMap<String, usize>>,
@@ -279,6 +846,7 @@ pub struct VarDef {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct GraphDef {
     pub position: Position,
+    pub annotations: Vec<Annotation>,
     pub children: Vec<AstNodeEnum>,
     pub alias: Option<Symbol>,
     pub version: Option<Box<AstNodeEnum>>,
@@ -349,6 +917,138 @@ pub struct NodeInputValues {
     pub items: Vec<Symbol>,
 }
 
+impl NodeDef {
+    /// Name of the op, or of the graph for a `ref(...)` node
+    pub fn op_name(&self) -> &str {
+        self.value.op_name()
+    }
+
+    /// Names of the outputs, e.g. `["a", "b"]` for `a, b = split(x);`
+    pub fn output_names(&self) -> Vec<&str> {
+        self.outputs.iter().map(|output| output.name.as_str()).collect()
+    }
+
+    /// See `NodeBlock::input_names`
+    pub fn input_names(&self) -> Vec<&str> {
+        self.value.input_names()
+    }
+
+    /// See `NodeBlock::is_ref`
+    pub fn is_ref(&self) -> bool {
+        self.value.is_ref()
+    }
+}
+
+impl NodeBlock {
+    /// Name of the op, or of the graph for a `ref(...)` node
+    pub fn op_name(&self) -> &str {
+        &self.name.name
+    }
+
+    /// Names wired into the node, positional or keyword, in source order;
+    /// literal inputs are skipped
+    pub fn input_names(&self) -> Vec<&str> {
+        self.input_symbols().into_iter().map(|symbol| symbol.name.as_str()).collect()
+    }
+
+    /// Same as `input_names`, with positions
+    pub fn input_symbols(&self) -> Vec<&Symbol> {
+        let values: Vec<&AstNodeEnum> = match &self.inputs {
+            Some(NodeInputDef::Tuple(tuple)) => tuple.items.iter().map(|item| item.as_ref()).collect(),
+            Some(NodeInputDef::KeyValue(key_def)) => key_def.items.iter().map(|item| item.value.as_ref()).collect(),
+            None => Vec::new(),
+        };
+        let mut symbols = Vec::new();
+        for value in values {
+            match value {
+                AstNodeEnum::Symbol(symbol) => symbols.push(symbol),
+                AstNodeEnum::NodeInputValues(values) => symbols.extend(values.items.iter()),
+                _ => {}
+            }
+        }
+        symbols
+    }
+
+    /// Inputs with positional and keyword forms unified, one entry per
+    /// argument in source order; positional entries have no key
+    ///
+    /// A literal input gives its value as written, e.g. `3` in `foo(x, 3)`.
+    pub fn resolved_inputs(&self) -> Vec<ResolvedInput> {
+        let Ok(inputs) = self.try_resolved_inputs(|value| Ok::<_, std::convert::Infallible>(literal_text(value)));
+        inputs
+    }
+
+    /// Same as `resolved_inputs`, with `literal` giving the text of a literal input
+    pub fn try_resolved_inputs<E>(
+        &self,
+        mut literal: impl FnMut(&AstNodeEnum) -> Result<String, E>,
+    ) -> Result<Vec<ResolvedInput>, E> {
+        let items: Vec<(Option<&Symbol>, &AstNodeEnum)> = match &self.inputs {
+            Some(NodeInputDef::Tuple(tuple)) => tuple.items.iter().map(|item| (None, item.as_ref())).collect(),
+            Some(NodeInputDef::KeyValue(key_def)) => {
+                key_def.items.iter().map(|item| (Some(&item.key), item.value.as_ref())).collect()
+            }
+            None => Vec::new(),
+        };
+        let mut inputs = Vec::with_capacity(items.len());
+        for (key, value) in items {
+            let values = match value {
+                AstNodeEnum::Symbol(symbol) => vec![symbol.name.clone()],
+                AstNodeEnum::NodeInputValues(values) => values.items.iter().map(|symbol| symbol.name.clone()).collect(),
+                other => vec![literal(other)?],
+            };
+            inputs.push(ResolvedInput {
+                key: key.map(|key| key.name.clone()),
+                values,
+                grouped: matches!(value, AstNodeEnum::NodeInputValues(_)),
+            });
+        }
+        Ok(inputs)
+    }
+
+    /// Whether the node references a graph, `ref(graph(...))`
+    pub fn is_ref(&self) -> bool {
+        self.name.kind == SymbolKind::RefGraphName
+    }
+}
+
+/// Text of a literal node input, collections written compactly, e.g. `[1,2]`
+fn literal_text(value: &AstNodeEnum) -> String {
+    let join = |items: &[AstNodeEnum]| items.iter().map(literal_text).collect::<Vec<_>>().join(",");
+    match value {
+        AstNodeEnum::StringLiteral(string) => string.value.clone(),
+        AstNodeEnum::MultiLineStringLiteral(string) => string.value.clone(),
+        AstNodeEnum::NumberLiteral(number) => number.raw.clone(),
+        AstNodeEnum::FloatLiteral(float) => float.raw.clone(),
+        AstNodeEnum::BoolLiteral(boolean) => boolean.raw.clone(),
+        AstNodeEnum::NullLiteral(null) => null.raw.clone(),
+        AstNodeEnum::DateTimeLiteral(date_time) => date_time.raw.clone(),
+        AstNodeEnum::DateLiteral(date) => date.value.clone(),
+        AstNodeEnum::Symbol(symbol) => symbol.name.clone(),
+        AstNodeEnum::ListStatement(list) => format!("[{}]", join(&list.items)),
+        AstNodeEnum::TupleStatement(tuple) => format!("({})", join(&tuple.items)),
+        AstNodeEnum::SetStatement(set) => format!("{{{}}}", join(&set.items)),
+        AstNodeEnum::DictStatement(dict) => {
+            let items: Vec<String> = dict.items.iter()
+                .map(|item| format!("{}:{}", literal_text(&item.key), literal_text(&item.value)))
+                .collect();
+            format!("{{{}}}", items.join(","))
+        }
+        _ => String::new(),
+    }
+}
+
+/// A node input argument, see `NodeBlock::resolved_inputs`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolvedInput {
+    /// Keyword, `None` for a positional input
+    pub key: Option<String>,
+    /// Names wired in, a literal gives its value as text
+    pub values: Vec<String>,
+    /// Written as a `(a, b)` group
+    pub grouped: bool,
+}
+
 /// Node attribute
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NodeAttr {
@@ -363,6 +1063,7 @@ pub struct NodeAttr {
 pub enum NodeAttrValue {
     Symbol(Symbol),
     String(StringLiteral),
+    Bool(BoolLiteral),
     ListSymbol(Vec<Symbol>),
     ListParamDef(Vec<ParamDef>),
 }
@@ -396,6 +1097,13 @@ pub struct ConditionBlock {
 pub enum ConditionExpr {
     Statement(Box<ConditionStatement>),
     Block(NodeBlock),
+    Symbol(Symbol),
+    String(StringLiteral),
+    MultiLineString(MultiLineStringLiteral),
+    And(Box<ConditionExpr>, Box<ConditionExpr>),
+    Or(Box<ConditionExpr>, Box<ConditionExpr>),
+    Not(Box<ConditionExpr>),
+    Group(Box<ConditionExpr>),
 }
 
 /// Condition statement
@@ -405,6 +1113,67 @@ pub struct ConditionStatement {
     pub left_operand: Box<AstNodeEnum>,
     pub right_operand: Box<AstNodeEnum>,
     pub operator: String,
+    /// Source text of the comparison, empty when the statement was built or rewritten
+    pub raw: String,
+}
+
+/// Rewrite a condition into a canonical boolean form
+///
+/// Negations are pushed down to the operands with De Morgan's laws and double
+/// negations cancel, so `!` only applies to comparisons, calls and names.
+/// Nested `&&` and `||` chains are flattened into left-nested chains, e.g.
+/// `a || (b || c)` becomes `a || b || c`. Groups are dropped except around an
+/// `||` operand of `&&`, where they are needed to keep the meaning.
+pub fn normalize_condition(expr: &ConditionExpr) -> ConditionExpr {
+    normalize_condition_with(expr, false)
+}
+
+fn normalize_condition_with(expr: &ConditionExpr, negate: bool) -> ConditionExpr {
+    let (left, right, is_and) = match expr {
+        ConditionExpr::Group(inner) => return normalize_condition_with(inner, negate),
+        ConditionExpr::Not(inner) => return normalize_condition_with(inner, !negate),
+        ConditionExpr::And(left, right) => (left, right, true),
+        ConditionExpr::Or(left, right) => (left, right, false),
+        other if negate => return ConditionExpr::Not(Box::new(other.clone())),
+        other => return other.clone(),
+    };
+    // !(a && b) is !a || !b and !(a || b) is !a && !b
+    let is_and = is_and != negate;
+    let mut operands = Vec::new();
+    split_condition_chain(normalize_condition_with(left, negate), is_and, &mut operands);
+    split_condition_chain(normalize_condition_with(right, negate), is_and, &mut operands);
+    let mut operands = operands.into_iter();
+    let first = operands.next().expect("a chain has at least two operands");
+    operands.fold(first, |left, right| {
+        if is_and {
+            ConditionExpr::And(Box::new(group_or(left)), Box::new(group_or(right)))
+        } else {
+            ConditionExpr::Or(Box::new(left), Box::new(right))
+        }
+    })
+}
+
+/// Operands of a normalized chain of `&&` (`is_and`) or `||`, other expressions as is
+fn split_condition_chain(expr: ConditionExpr, is_and: bool, out: &mut Vec<ConditionExpr>) {
+    match expr {
+        ConditionExpr::And(left, right) if is_and => {
+            split_condition_chain(*left, is_and, out);
+            split_condition_chain(*right, is_and, out);
+        }
+        ConditionExpr::Or(left, right) if !is_and => {
+            split_condition_chain(*left, is_and, out);
+            split_condition_chain(*right, is_and, out);
+        }
+        other => out.push(other),
+    }
+}
+
+/// `||` binds looser than `&&`, so it is grouped as an `&&` operand
+fn group_or(expr: ConditionExpr) -> ConditionExpr {
+    match expr {
+        ConditionExpr::Or(..) => ConditionExpr::Group(Box::new(expr)),
+        other => other,
+    }
 }
 
 /// For loop block
@@ -422,6 +1191,7 @@ pub struct ForLoopBlock {
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct OpDef {
     pub position: Position,
+    pub annotations: Vec<Annotation>,
     pub children: Vec<AstNodeEnum>,
     pub alias: Option<Symbol>,
     pub version: Option<String>,
@@ -476,21 +1246,38 @@ pub struct OpSpecItem {
     pub value: Box<AstNodeEnum>,
 }
 
+/// Interval bound, a signed integer or float
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IntervalBound {
+    Number(NumberLiteral),
+    Float(FloatLiteral),
+}
+
+impl IntervalBound {
+    /// Source text of the bound, e.g. `-5` or `0.0`
+    pub fn raw(&self) -> &str {
+        match self {
+            IntervalBound::Number(number) => &number.raw,
+            IntervalBound::Float(float) => &float.raw,
+        }
+    }
+}
+
 /// Interval types
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ClosedInterval {
     pub position: Position,
-    pub ge: Option<NumberLiteral>,
-    pub le: Option<NumberLiteral>,
+    pub ge: Option<IntervalBound>,
+    pub le: Option<IntervalBound>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MixInterval {
     pub position: Position,
-    pub ge: Option<NumberLiteral>,
-    pub gt: Option<NumberLiteral>,
-    pub le: Option<NumberLiteral>,
-    pub lt: Option<NumberLiteral>,
+    pub ge: Option<IntervalBound>,
+    pub gt: Option<IntervalBound>,
+    pub le: Option<IntervalBound>,
+    pub lt: Option<IntervalBound>,
 }
 
 macro_rules! define_ast_enum {
@@ -545,7 +1332,40 @@ macro_rules! define_ast_enum {
             }
         }
 
-        // 4. 可选：生成 From 实现，方便转换
+        // 4. 为 enum 实现 ShiftLines
+        impl ShiftLines for $enum_name {
+            fn shift_lines(&mut self, delta: isize) {
+                match self {
+                    $(
+                        $enum_name::$variant(node) => node.shift_lines(delta),
+                    )*
+                }
+            }
+        }
+
+        // 5. 为 enum 实现 Walk
+        impl Walk for $enum_name {
+            fn walk<'a>(&'a self, visitor: &mut dyn Visitor<'a>) {
+                match self {
+                    $(
+                        $enum_name::$variant(node) => node.walk(visitor),
+                    )*
+                }
+            }
+        }
+
+        // 6. 为 enum 实现 WalkMut
+        impl WalkMut for $enum_name {
+            fn walk_mut(&mut self, visitor: &mut dyn VisitorMut) {
+                match self {
+                    $(
+                        $enum_name::$variant(node) => node.walk_mut(visitor),
+                    )*
+                }
+            }
+        }
+
+        // 7. 可选：生成 From 实现，方便转换
         $(
             impl From<$type> for $enum_name {
                 fn from(value: $type) -> Self {
@@ -23,15 +23,16 @@
 
 #![allow(dead_code)]
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, Map};
 
 use crate::ast::*;
-use crate::error::{ParseError, ParseResult};
+use crate::error::{helpers, ErrorCollection, ParseError, ParseResult};
 
 /// Compilation options
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct CompileOptions {
     /// Return operation names
     pub return_op_names: bool,
@@ -41,6 +42,92 @@ pub struct CompileOptions {
     pub keep_order: bool,
     /// Plugin name for conversion
     pub plugin: Option<String>,
+    /// Validate `graph_alias.output` references between graphs
+    pub check_graph_refs: bool,
+    /// Known values of attribute conditions, e.g. `"debug" => false` compiles
+    /// `value = 42 if "debug" else 52;` to `52`
+    pub condition_context: HashMap<String, bool>,
+    /// Keep the last value of a repeated dict key as JSON does, instead of
+    /// failing with `InvalidValue`
+    pub allow_duplicate_dict_keys: bool,
+    /// Allow at most one `.as(start)` and one `.as(end)` node per graph
+    pub single_start_end: bool,
+    /// Emit vars as nested objects instead of flat dotted keys
+    pub nested_vars: bool,
+    /// Collect warnings for suspicious but valid input, see `Compiler::warnings`
+    pub warnings: bool,
+    /// Add the `(from, to)` node pairs of each graph as `edges`, left out when
+    /// the graph has none
+    pub emit_edges: bool,
+    /// Require graph, op and node versions written as literals to be `x.y.z`
+    pub validate_versions: bool,
+    /// Require `.log(level=...)` to be one of `LOG_LEVELS`, by number or name
+    pub validate_log_levels: bool,
+    /// Fill `CompileResult::source_map` with the GOS position of each graph,
+    /// graph property, node, op and var
+    pub emit_source_map: bool,
+    /// Replace `${path}` in string literals by the value of the var `path`,
+    /// e.g. `"Hello ${config.name}"`, failing on an undefined var. Vars defined
+    /// by earlier statements are in scope, `\${` is kept as `${` and raw
+    /// strings take no escapes, e.g. `r"C:\${dir}"` is `C:\` and the value.
+    pub interpolate_vars: bool,
+    /// Reject node calls other than `NODE_CALLS`, e.g. `.frobnicate()`,
+    /// instead of ignoring them with a warning
+    pub strict_node_calls: bool,
+    /// How `CompileResult::vars` is serialized
+    pub var_encoding: VarEncoding,
+    /// Stop at the first compile error, true by default. Unset, each failing
+    /// statement adds an error and compilation goes on with the next one, see
+    /// `Compiler::compile_with_errors`.
+    pub fail_fast: bool,
+    /// Embed the graph named by an op's `graph_ref` meta, e.g.
+    /// `meta { graph_ref = "scoring"; }`, as the op's `graph`, failing when no
+    /// graph of the module has that alias
+    pub resolve_op_graph_refs: bool,
+}
+
+/// Serialized form of the compiled `vars`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VarEncoding {
+    /// A JSON object, `{"name": value}`, in no particular order
+    #[default]
+    Object,
+    /// An array of `{"key": name, "value": value}` pairs in source order, for
+    /// consumers that cannot rely on the key order of JSON objects
+    OrderedPairs,
+}
+
+/// One var of `VarEncoding::OrderedPairs`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VarPair {
+    pub key: String,
+    pub value: Value,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            return_op_names: false,
+            return_subgraphs: false,
+            keep_order: false,
+            plugin: None,
+            check_graph_refs: false,
+            condition_context: HashMap::new(),
+            allow_duplicate_dict_keys: true,
+            single_start_end: true,
+            nested_vars: false,
+            warnings: false,
+            emit_edges: false,
+            validate_versions: false,
+            validate_log_levels: false,
+            emit_source_map: false,
+            interpolate_vars: false,
+            strict_node_calls: false,
+            var_encoding: VarEncoding::Object,
+            fail_fast: true,
+            resolve_op_graph_refs: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,8 +139,8 @@ pub struct CompileResult {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ops: Option<Vec<OpDict>>,
     /// Variable definitions
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub vars: Option<HashMap<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vars: Option<Vars>,
     /// GOS version
     pub gos_version: String,
     /// Operation names (if requested)
@@ -62,6 +149,95 @@ pub struct CompileResult {
     /// Subgraphs (if requested)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub subgraphs: Option<Vec<String>>,
+    /// GOS positions keyed by JSON path, e.g. `graphs[0].nodes.node1` or
+    /// `graphs[0].properties.name`, with `CompileOptions::emit_source_map`.
+    /// Kept out of the serialized JSON.
+    #[serde(skip)]
+    pub source_map: Option<HashMap<String, Position>>,
+}
+
+/// Compiled vars by key, serialized as an object or, with a source order, as
+/// `VarEncoding::OrderedPairs`
+///
+/// Derefs to the vars map; two `Vars` are equal when their maps are.
+#[derive(Debug, Clone, Default)]
+pub struct Vars {
+    pub values: HashMap<String, Value>,
+    /// Var keys in source order with `VarEncoding::OrderedPairs`
+    pub order: Option<Vec<String>>,
+}
+
+impl From<HashMap<String, Value>> for Vars {
+    fn from(values: HashMap<String, Value>) -> Self {
+        Self { values, order: None }
+    }
+}
+
+impl std::ops::Deref for Vars {
+    type Target = HashMap<String, Value>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.values
+    }
+}
+
+impl std::ops::DerefMut for Vars {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.values
+    }
+}
+
+impl PartialEq for Vars {
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values
+    }
+}
+
+impl Serialize for Vars {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.order {
+            Some(order) => ordered_var_pairs(&self.values, order).serialize(serializer),
+            None => self.values.serialize(serializer),
+        }
+    }
+}
+
+/// Read vars written as an object or as `VarEncoding::OrderedPairs`, keeping
+/// the order of the pairs
+impl<'de> Deserialize<'de> for Vars {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Encoded {
+            Object(HashMap<String, Value>),
+            OrderedPairs(Vec<VarPair>),
+        }
+
+        Ok(match Encoded::deserialize(deserializer)? {
+            Encoded::Object(values) => values.into(),
+            Encoded::OrderedPairs(pairs) => Vars {
+                order: Some(pairs.iter().map(|pair| pair.key.clone()).collect()),
+                values: pairs.into_iter().map(|pair| (pair.key, pair.value)).collect(),
+            },
+        })
+    }
+}
+
+/// `vars` as pairs in `order`, vars missing from `order` follow by name
+fn ordered_var_pairs(vars: &HashMap<String, Value>, order: &[String]) -> Vec<VarPair> {
+    let ordered = order.iter().filter(|key| vars.contains_key(*key));
+    let rest = sorted_entries(vars).into_iter().map(|(key, _)| key).filter(|key| !order.contains(key));
+    ordered
+        .chain(rest)
+        .map(|key| VarPair { key: key.clone(), value: vars[key].clone() })
+        .collect()
+}
+
+impl CompileResult {
+    /// The op declared `as alias`
+    pub fn find_op(&self, alias: &str) -> Option<&OpDict> {
+        self.ops.iter().flatten().find(|op| op.alias() == Some(alias))
+    }
 }
 
 /// Graph dictionary structure
@@ -85,6 +261,38 @@ pub struct GraphDict {
     /// Template version
     #[serde(skip_serializing_if = "Option::is_none")]
     pub template_version: Option<String>,
+    /// Graph metadata from `@annotations`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metas: Option<HashMap<String, Value>>,
+    /// `(from, to)` node key pairs, with `CompileOptions::emit_edges`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edges: Option<Vec<(String, String)>>,
+    /// Names of the properties written as set literals, compiled to arrays in
+    /// insertion order, so the decompiler can write them back as sets
+    #[serde(skip_serializing_if = "Option::is_none", rename = "set_property")]
+    pub set_properties: Option<Vec<String>>,
+}
+
+/// A compiled node input
+///
+/// A positional `(a, b)` group stays one argument and compiles to a nested
+/// array, e.g. `foo(a, (b, c))` to `["a", ["b", "c"]]`. The names of a keyword
+/// input are flattened, `foo(x=a, w=(b, c))` compiles to `["a", "b", "c"]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum NodeInput {
+    Name(String),
+    Group(Vec<String>),
+}
+
+impl NodeInput {
+    /// Names wired in by this input
+    pub fn names(&self) -> &[String] {
+        match self {
+            NodeInput::Name(name) => std::slice::from_ref(name),
+            NodeInput::Group(names) => names,
+        }
+    }
 }
 
 /// Node dictionary structure
@@ -104,7 +312,7 @@ pub struct NodeDict {
     pub outputs: Option<Vec<String>>,
     /// Node inputs
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub inputs: Option<Vec<String>>,
+    pub inputs: Option<Vec<NodeInput>>,
     /// Node dependencies
     #[serde(skip_serializing_if = "Option::is_none")]
     pub depends: Option<Vec<String>>,
@@ -114,15 +322,40 @@ pub struct NodeDict {
     /// Node properties
     #[serde(skip_serializing_if = "Option::is_none")]
     pub properties: Option<HashMap<String, Value>>,
+    /// Logging parameters (log clause)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<HashMap<String, Value>>,
+    /// Metrics parameters (metrics clause)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<HashMap<String, Value>>,
+    /// Funnel parameters (funnel clause) by name, e.g. `.funnel(steps=["view", "buy"], window=7)`
+    /// gives `{"steps": ["view", "buy"], "window": 7}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub funnel: Option<HashMap<String, Value>>,
     /// Node alias
     #[serde(skip_serializing_if = "Option::is_none", rename = "as")]
     pub alias: Option<String>,
+    /// Marked as the graph start with `.as(start)`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<bool>,
+    /// Marked as the graph end with `.as(end)`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<bool>,
     /// Override flag for templates
     #[serde(skip_serializing_if = "Option::is_none")]
     pub override_flag: Option<bool>,
     /// For loop configuration
     #[serde(skip_serializing_if = "Option::is_none")]
     pub for_loop: Option<HashMap<String, Value>>,
+    /// Condition expression (condition nodes only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub condition: Option<String>,
+    /// Node taken when the condition holds (condition nodes only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub true_branch: Option<Box<NodeDict>>,
+    /// Node taken when the condition fails (condition nodes only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub false_branch: Option<Box<NodeDict>>,
 }
 
 /// Operation dictionary structure
@@ -145,59 +378,207 @@ pub struct OpDict {
     pub graph: Option<GraphDict>,
 }
 
+impl OpDict {
+    /// The `as` meta of the op
+    pub fn alias(&self) -> Option<&str> {
+        self.metas.as_ref()?.get("as")?.as_str()
+    }
+
+    /// Spec of the input `name`, e.g. `{"dtype": "int", "default": 1}`
+    pub fn input_spec(&self, name: &str) -> Option<&HashMap<String, Value>> {
+        self.inputs.as_ref()?.get(name)
+    }
+}
+
 /// Main compiler structure
 pub struct Compiler {
     options: CompileOptions,
+    warnings: RefCell<Vec<ParseError>>,
+    /// Errors of the statements compiled so far, only when `fail_fast` is unset
+    errors: RefCell<Vec<ParseError>>,
+    /// Positions of the graph being converted, by path relative to the graph
+    graph_positions: RefCell<Vec<(String, Position)>>,
 }
 
 impl Compiler {
     /// Create a new compiler with default options
     pub fn new() -> Self {
-        Self {
-            options: CompileOptions::default(),
-        }
+        Self::with_options(CompileOptions::default())
     }
 
     /// Create a new compiler with specified options
     pub fn with_options(options: CompileOptions) -> Self {
-        Self { options }
+        Self {
+            options,
+            warnings: RefCell::new(Vec::new()),
+            errors: RefCell::new(Vec::new()),
+            graph_positions: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Compile an AST, returning the result together with all collected errors
+    ///
+    /// With `CompileOptions::fail_fast` unset each statement that fails to
+    /// compile adds one error and the following statements are still compiled;
+    /// the result is only returned when there are none. Warnings collected with
+    /// `CompileOptions::warnings` go to `warnings`.
+    pub fn compile_with_errors(&self, ast: &AstNodeEnum) -> (Option<CompileResult>, ErrorCollection) {
+        self.warnings.take();
+        let result = match ast {
+            AstNodeEnum::Module(module) => self.compile_module_collect(module, &HashMap::new()),
+            _ => Err(ParseError::general("Expected Module as root AST node")),
+        };
+        let mut errors = ErrorCollection::new();
+        self.errors.take().into_iter().for_each(|error| errors.add_error(error));
+        self.warnings.take().into_iter().for_each(|warning| errors.add_warning(warning));
+        match result {
+            Ok(result) if !errors.has_errors() => (Some(result), errors),
+            Ok(_) => (None, errors),
+            Err(error) => {
+                errors.add_error(error);
+                (None, errors)
+            }
+        }
+    }
+
+    /// Warnings of the last `compile` or `compile_many`, only when
+    /// `CompileOptions::warnings` is set
+    pub fn warnings(&self) -> Vec<ParseError> {
+        self.warnings.borrow().clone()
+    }
+
+    /// Fail with `error` when `fail_fast` is set, otherwise keep it and go on
+    fn fail_or_collect(&self, error: ParseError) -> ParseResult<()> {
+        if self.options.fail_fast {
+            return Err(error);
+        }
+        self.errors.borrow_mut().push(error);
+        Ok(())
+    }
+
+    fn add_warning(&self, warning: ParseError) {
+        if self.options.warnings {
+            self.warnings.borrow_mut().push(warning);
+        }
+    }
+
+    fn add_graph_position(&self, path: String, position: &Position) {
+        if self.options.emit_source_map {
+            self.graph_positions.borrow_mut().push((path, position.clone()));
+        }
+    }
+
+    /// Move the positions of the last converted graph into `source_map` under `prefix`
+    fn take_graph_positions(&self, prefix: &str, source_map: &mut HashMap<String, Position>) {
+        for (path, position) in self.graph_positions.borrow_mut().drain(..) {
+            let key = if path.is_empty() { prefix.to_string() } else { format!("{}.{}", prefix, path) };
+            source_map.insert(key, position);
+        }
     }
 
     /// Compile AST to dictionary structure
     pub fn compile(&self, ast: &AstNodeEnum) -> ParseResult<CompileResult> {
+        self.warnings.take();
         match ast {
-            AstNodeEnum::Module(module) => self.compile_module(module),
+            AstNodeEnum::Module(module) => self.compile_module(module, &HashMap::new()),
             _ => Err(ParseError::general("Expected Module as root AST node")),
         }
     }
 
-    /// Compile a module (root AST node)
-    fn compile_module(&self, module: &Module) -> ParseResult<CompileResult> {
+    /// Compile several named modules that all see `shared_vars`
+    ///
+    /// Shared vars resolve like vars defined in the module itself, a module var
+    /// of the same name takes precedence. Only a module's own vars end up in
+    /// its `CompileResult::vars`.
+    pub fn compile_many(
+        &self,
+        modules: &[(&str, &AstNodeEnum)],
+        shared_vars: &HashMap<String, Value>,
+    ) -> ParseResult<Vec<(String, CompileResult)>> {
+        self.warnings.take();
+        modules
+            .iter()
+            .map(|(name, ast)| match ast {
+                AstNodeEnum::Module(module) => Ok((name.to_string(), self.compile_module(module, shared_vars)?)),
+                _ => Err(ParseError::general(format!("Expected Module as root AST node of {}", name))),
+            })
+            .collect()
+    }
+
+    /// Compile a module, failing with every error collected when `fail_fast` is
+    /// unset, combined into one `ParseError::General` if there are several
+    fn compile_module(&self, module: &Module, shared_vars: &HashMap<String, Value>) -> ParseResult<CompileResult> {
+        let result = self.compile_module_collect(module, shared_vars);
+        let mut errors = ErrorCollection::new();
+        self.errors.take().into_iter().for_each(|error| errors.add_error(error));
+        errors.into_result(result?)
+    }
+
+    /// Compile a module (root AST node) with `shared_vars` already in scope,
+    /// leaving the errors of failed statements in `errors` unless `fail_fast`
+    fn compile_module_collect(&self, module: &Module, shared_vars: &HashMap<String, Value>) -> ParseResult<CompileResult> {
         let mut result = CompileResult {
             graphs: None,
             ops: None,
             vars: None,
-            gos_version: "0.5.2".to_string(),
+            gos_version: crate::language_version().to_string(),
             op_names: None,
             subgraphs: None,
+            source_map: None,
         };
 
         let mut graphs = Vec::new();
         let mut ops = Vec::new();
-        let mut vars: HashMap<String, Value> = HashMap::new();
+        let mut source_map = HashMap::new();
+        // Vars in scope, and the subset this module defines
+        let mut vars: HashMap<String, Value> = shared_vars.clone();
+        let mut own_vars: HashMap<String, Value> = HashMap::new();
+        // Ops with a `graph_ref` meta by index, resolved once every graph is compiled
+        let mut op_graph_refs: Vec<(usize, Position)> = Vec::new();
 
-        // Process each child statement
-        for child in &module.children {
+        // Process each child statement, collecting its error unless `fail_fast`
+        let mut compile_child = |child: &AstNodeEnum| -> ParseResult<()> {
+            let interpolated;
+            let child = if self.options.interpolate_vars {
+                interpolated = interpolate_strings(child, &vars)?;
+                &interpolated
+            } else {
+                child
+            };
             match child {
                 AstNodeEnum::VarDef(var_def) => {
-                    self.process_var_def(var_def, &mut vars)?;
+                    let mut defined = HashMap::new();
+                    self.process_var_def(var_def, &mut defined)?;
+                    if self.options.emit_source_map {
+                        for attr_def in var_def.children.iter().filter_map(|child| match child {
+                            AstNodeEnum::AttrDef(attr_def) => Some(attr_def),
+                            _ => None,
+                        }) {
+                            let key = match &var_def.alias {
+                                Some(alias) => format!("vars.{}.{}", alias.name, attr_def.name.name.trim()),
+                                None => format!("vars.{}", attr_def.name.name.trim()),
+                            };
+                            source_map.insert(key, attr_def.position.clone());
+                        }
+                    }
+                    vars.extend(defined.iter().map(|(key, value)| (key.clone(), value.clone())));
+                    own_vars.extend(defined);
                 }
                 AstNodeEnum::GraphDef(graph_def) => {
                     let graph_dict = self.convert_graph_def(graph_def, &vars)?;
+                    self.take_graph_positions(&format!("graphs[{}]", graphs.len()), &mut source_map);
                     graphs.push(graph_dict);
                 }
                 AstNodeEnum::OpDef(op_def) => {
                     let op_dict = self.convert_op_def(op_def, &vars)?;
+                    let prefix = format!("ops[{}]", ops.len());
+                    self.take_graph_positions(&format!("{}.graph", prefix), &mut source_map);
+                    if self.options.emit_source_map {
+                        source_map.insert(prefix, op_def.position.clone());
+                    }
+                    if let Some(attr_def) = op_graph_ref(op_def) {
+                        op_graph_refs.push((ops.len(), attr_def.position.clone()));
+                    }
                     ops.push(op_dict);
                 }
                 AstNodeEnum::Import(_) => {
@@ -211,6 +592,30 @@ impl Compiler {
                     // Handle other statement types as needed
                 }
             }
+            Ok(())
+        };
+        for child in &module.children {
+            if let Err(error) = compile_child(child) {
+                self.fail_or_collect(error)?;
+            }
+        }
+
+        if self.options.resolve_op_graph_refs {
+            for (index, position) in op_graph_refs {
+                if let Err(error) = resolve_op_graph_ref(&mut ops[index], &graphs, &position) {
+                    self.fail_or_collect(error)?;
+                }
+            }
+        }
+        if self.options.check_graph_refs {
+            if let Err(error) = self.check_graph_refs(module, &graphs, &vars) {
+                self.fail_or_collect(error)?;
+            }
+        }
+        if self.options.validate_versions {
+            if let Err(error) = check_versions(&module.children) {
+                self.fail_or_collect(error)?;
+            }
         }
 
         // Set results if not empty
@@ -220,13 +625,125 @@ impl Compiler {
         if !ops.is_empty() {
             result.ops = Some(ops);
         }
-        if !vars.is_empty() {
+        if !own_vars.is_empty() {
+            let mut vars = Vars::from(if self.options.nested_vars {
+                self.nest_vars(module, &own_vars)?
+            } else {
+                own_vars
+            });
+            if self.options.var_encoding == VarEncoding::OrderedPairs {
+                vars.order = Some(var_source_order(module, &vars));
+            }
             result.vars = Some(vars);
         }
+        if self.options.emit_source_map {
+            result.source_map = Some(source_map);
+        }
 
         Ok(result)
     }
 
+    /// Rebuild flat dotted var keys into nested objects,
+    /// e.g. `config.name` becomes `{"config": {"name": ...}}`
+    fn nest_vars(&self, module: &Module, vars: &HashMap<String, Value>) -> ParseResult<HashMap<String, Value>> {
+        let mut root = Map::new();
+        for (key, value) in sorted_entries(vars) {
+            let mut segments: Vec<&str> = key.split('.').collect();
+            let leaf = segments.pop().unwrap_or_default();
+            let mut current = &mut root;
+            for (index, segment) in segments.iter().enumerate() {
+                let entry = current
+                    .entry(segment.to_string())
+                    .or_insert_with(|| Value::Object(Map::new()));
+                current = match entry {
+                    Value::Object(map) => map,
+                    // Report the var that already holds a value at this path
+                    _ => return Err(self.var_conflict(module, &segments[..=index].join("."))),
+                };
+            }
+            if current.contains_key(leaf) {
+                return Err(self.var_conflict(module, key));
+            }
+            current.insert(leaf.to_string(), value.clone());
+        }
+        Ok(root.into_iter().collect())
+    }
+
+    /// Error for a var key that is both a value and a namespace of other vars
+    fn var_conflict(&self, module: &Module, key: &str) -> ParseError {
+        let position = module
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                AstNodeEnum::VarDef(var_def) => Some(var_def),
+                _ => None,
+            })
+            .flat_map(|var_def| {
+                var_def.children.iter().filter_map(move |child| match child {
+                    AstNodeEnum::AttrDef(attr_def) => Some((var_def, attr_def)),
+                    _ => None,
+                })
+            })
+            .find(|(var_def, attr_def)| {
+                let name = attr_def.name.name.trim();
+                match &var_def.alias {
+                    Some(alias) => key == format!("{}.{}", alias.name, name),
+                    None => key == name,
+                }
+            })
+            .map(|(_, attr_def)| &attr_def.position)
+            .unwrap_or(&module.position);
+        ParseError::semantic_error(
+            position.line,
+            position.start,
+            format!("Var {} is both a value and a namespace", key),
+        )
+    }
+
+    /// Check that every `graph_alias.output` property refers to an existing graph output
+    fn check_graph_refs(&self, module: &Module, graphs: &[GraphDict], vars: &HashMap<String, Value>) -> ParseResult<()> {
+        let mut graph_outputs: HashMap<&str, Vec<&str>> = HashMap::new();
+        for graph in graphs {
+            if let Some(alias) = &graph.alias {
+                let outputs = graph_outputs.entry(alias.as_str()).or_default();
+                for (key, node) in graph.nodes.iter().flatten() {
+                    outputs.push(key.as_str());
+                    outputs.extend(node.outputs.iter().flatten().map(|s| s.as_str()));
+                }
+            }
+        }
+
+        for child in &module.children {
+            let AstNodeEnum::GraphDef(graph_def) = child else {
+                continue;
+            };
+            for graph_child in &graph_def.children {
+                let (name, symbol) = match graph_child {
+                    AstNodeEnum::RefDef(ref_def) => (&ref_def.name, &ref_def.value),
+                    AstNodeEnum::AttrDef(attr_def) => match attr_def.value.as_ref() {
+                        AstNodeEnum::Symbol(symbol) => (&attr_def.name, symbol),
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                let Some((graph_alias, output)) = symbol.name.split_once('.') else {
+                    continue;
+                };
+                // Variable references take precedence over graph references
+                if vars.contains_key(&symbol.name) || vars.contains_key(&format!("{}.as", graph_alias)) {
+                    continue;
+                }
+                let message = match graph_outputs.get(graph_alias) {
+                    Some(outputs) if outputs.contains(&output) => continue,
+                    Some(_) => format!("Graph {} has no output {}", graph_alias, output),
+                    None => format!("Graph {} referenced by {} is not defined", graph_alias, name.name),
+                };
+                return Err(ParseError::semantic_error(symbol.position.line, symbol.position.start, message));
+            }
+        }
+        Ok(())
+    }
+
     /// Process variable definition
     fn process_var_def(&self, var_def: &VarDef, vars: &mut HashMap<String, Value>) -> ParseResult<()> {
         for child in &var_def.children {
@@ -237,7 +754,7 @@ impl Compiler {
                     } else {
                         attr_def.name.name.trim().to_string()
                     };
-                    let value = self.convert_ast_to_value(&attr_def.value)?;
+                    let value = self.convert_attr_value(attr_def)?;
                     vars.insert(key, value);
                 }
                 _ => {}
@@ -253,19 +770,65 @@ impl Compiler {
         Ok(())
     }
 
+    /// Convert an attribute value, applying its `if ... else ...` condition
+    ///
+    /// A condition found in `condition_context` selects one branch at compile
+    /// time, any other condition is kept as `{"value", "condition", "else_value"}`.
+    fn convert_attr_value(&self, attr_def: &AttrDef) -> ParseResult<Value> {
+        let Some(condition) = &attr_def.condition else {
+            return self.convert_ast_to_value(&attr_def.value);
+        };
+
+        let condition_key = match condition.as_ref() {
+            AstNodeEnum::StringLiteral(s) => Some(s.value.as_str()),
+            AstNodeEnum::MultiLineStringLiteral(s) => Some(s.value.as_str()),
+            AstNodeEnum::Symbol(s) => Some(s.name.as_str()),
+            _ => None,
+        };
+        match condition_key.and_then(|key| self.options.condition_context.get(key)) {
+            Some(true) => self.convert_ast_to_value(&attr_def.value),
+            Some(false) => match &attr_def.else_value {
+                Some(else_value) => self.convert_ast_to_value(else_value),
+                None => Ok(Value::Null),
+            },
+            None => {
+                let mut map = Map::new();
+                map.insert("value".to_string(), self.convert_ast_to_value(&attr_def.value)?);
+                map.insert("condition".to_string(), self.convert_ast_to_value(condition)?);
+                if let Some(else_value) = &attr_def.else_value {
+                    map.insert("else_value".to_string(), self.convert_ast_to_value(else_value)?);
+                }
+                Ok(Value::Object(map))
+            }
+        }
+    }
+
     /// Convert graph definition to dictionary
     fn convert_graph_def(&self, graph_def: &GraphDef, vars: &HashMap<String, Value>) -> ParseResult<GraphDict> {
         let mut graph_dict = GraphDict {
             properties: None,
             nodes: None,
             alias: graph_def.alias.as_ref().map(|s| s.name.clone()),
-            version: graph_def.version.as_ref().and_then(|v| self.extract_string_value(v)),
+            version: self.graph_version(graph_def.version.as_deref(), vars)?,
             template_graph: graph_def.template_graph.as_ref().map(|s| s.name.clone()),
-            template_version: graph_def.template_version.as_ref().and_then(|v| self.extract_string_value(v)),
+            template_version: self.graph_version(graph_def.template_version.as_deref(), vars)?,
+            metas: None,
+            edges: None,
+            set_properties: None,
         };
+        let metas = self.convert_annotations(&graph_def.annotations, vars)?;
+        if !metas.is_empty() {
+            graph_dict.metas = Some(metas);
+        }
+        // Drop what a failed conversion may have left behind
+        self.graph_positions.borrow_mut().clear();
+        self.add_graph_position(String::new(), &graph_def.position);
 
         let mut properties: HashMap<String, Value> = HashMap::new();
+        let mut set_properties = Vec::new();
         let mut nodes: HashMap<String, NodeDict> = HashMap::new();
+        let mut start_seen = false;
+        let mut end_seen = false;
 
         for child in &graph_def.children {
             match child {
@@ -283,27 +846,70 @@ impl Compiler {
                             depends: None,
                             with: self.extract_node_attributes(node_block, vars)?,
                             properties: None,
+                            log: None,
+                            metrics: None,
+                            funnel: None,
                             alias: None,
+                            start: None,
+                            end: None,
                             override_flag: None,
                             for_loop: None,
+                            condition: None,
+                            true_branch: None,
+                            false_branch: None,
                         };
-                        nodes.insert(attr_def.name.name.clone(), node_dict);
+                        self.add_graph_position(format!("nodes.{}", attr_def.name.name), &attr_def.position);
+                        insert_node(&mut nodes, attr_def.name.name.clone(), node_dict, &attr_def.position)?;
                     } else {
                         // This is a regular property
-                        let value = self.convert_ast_to_value(&attr_def.value)?;
+                        let value = self.convert_attr_value(attr_def)?;
                         let resolved_value = self.resolve_variable_references(&value, vars)?;
+                        // A condition left for runtime keeps both branches in an object
+                        if attr_def.condition.is_none() || !resolved_value.is_object() {
+                            check_reserved_property(&attr_def.name.name, &resolved_value, attr_def.value.position())?;
+                        }
+                        if matches!(&*attr_def.value, AstNodeEnum::SetStatement(_)) && attr_def.condition.is_none() {
+                            set_properties.push(attr_def.name.name.clone());
+                        }
+                        self.add_graph_position(format!("properties.{}", attr_def.name.name), &attr_def.position);
                         properties.insert(attr_def.name.name.clone(), resolved_value);
                     }
                 }
+                AstNodeEnum::RefDef(ref_def) => {
+                    // Variable or cross-graph reference, e.g. `data = other_graph.output`
+                    let value = Value::String(ref_def.value.name.clone());
+                    let resolved_value = self.resolve_variable_references(&value, vars)?;
+                    check_reserved_property(&ref_def.name.name, &resolved_value, &ref_def.value.position)?;
+                    self.add_graph_position(format!("properties.{}", ref_def.name.name), &ref_def.position);
+                    properties.insert(ref_def.name.name.clone(), resolved_value);
+                }
                 AstNodeEnum::NodeDef(node_def) => {
                     let node_dict = self.convert_node_def(node_def, vars)?;
-                    // Use the first output as the key, or generate one
-                    let key = if !node_def.outputs.is_empty() {
-                        node_def.outputs[0].name.clone()
-                    } else {
-                        format!("node_{}", nodes.len())
-                    };
-                    nodes.insert(key, node_dict);
+                    if self.options.single_start_end {
+                        let markers = [
+                            ("start", node_dict.start.is_some(), &mut start_seen),
+                            ("end", node_dict.end.is_some(), &mut end_seen),
+                        ];
+                        for (marker, marked, seen) in markers {
+                            if marked && std::mem::replace(seen, true) {
+                                return Err(ParseError::semantic_error(
+                                    node_def.position.line,
+                                    node_def.position.start,
+                                    format!("Graph has more than one {} node", marker),
+                                ));
+                            }
+                        }
+                    }
+                    let key = node_key(node_dict.alias.as_deref(), &node_def.outputs, nodes.len());
+                    self.add_graph_position(format!("nodes.{}", key), &node_def.position);
+                    insert_node(&mut nodes, key, node_dict, &node_def.position)?;
+                }
+                AstNodeEnum::ConditionDef(cond_def) => {
+                    let mut node_dict = self.convert_condition_block(&cond_def.value, vars)?;
+                    node_dict.outputs = Some(cond_def.outputs.iter().map(|s| s.name.clone()).collect());
+                    let key = node_key(node_dict.alias.as_deref(), &cond_def.outputs, nodes.len());
+                    self.add_graph_position(format!("nodes.{}", key), &cond_def.position);
+                    insert_node(&mut nodes, key, node_dict, &cond_def.position)?;
                 }
                 _ => {}
             }
@@ -312,7 +918,20 @@ impl Compiler {
         if !properties.is_empty() {
             graph_dict.properties = Some(properties);
         }
-        if !nodes.is_empty() {
+        if !set_properties.is_empty() {
+            graph_dict.set_properties = Some(set_properties);
+        }
+        if nodes.is_empty() {
+            let message = match &graph_def.alias {
+                Some(alias) => format!("graph '{}' has no nodes", alias.name),
+                None => "graph has no nodes".to_string(),
+            };
+            self.add_warning(ParseError::semantic_error(graph_def.position.line, graph_def.position.start, message));
+        } else {
+            let edges = if self.options.emit_edges { node_edges(&nodes) } else { Vec::new() };
+            if !edges.is_empty() {
+                graph_dict.edges = Some(edges);
+            }
             graph_dict.nodes = Some(nodes);
         }
 
@@ -330,37 +949,89 @@ impl Compiler {
             depends: None,
             with: None,
             properties: None,
+            log: None,
+            metrics: None,
+            funnel: None,
             alias: None,
+            start: None,
+            end: None,
             override_flag: None,
             for_loop: None,
+            condition: None,
+            true_branch: None,
+            false_branch: None,
         };
 
         // Process node inputs
-        if let Some(inputs) = &node_def.value.inputs {
-            match inputs {
-                NodeInputDef::Tuple(_tuple_inputs) => {
-                    // node_dict.inputs = Some(tuple_inputs.items.iter().map(|s| s.name.clone()).collect());
-                }
-                NodeInputDef::KeyValue(_kv_inputs) => {
-                    // For key-value inputs, we need to process them differently
-                    // let mut input_list = Vec::new();
-                    // for item in &kv_inputs.items {
-                    //     input_list.extend(item.value.items.iter().map(|s| s.name.clone()));
-                    // }
-                    // node_dict.inputs = Some(input_list);
-                }
-            }
-        }
+        node_dict.inputs = self.extract_node_inputs(&node_def.value)?;
 
         // Process node attributes
         if let Some(attrs) = &node_def.value.attrs {
             let mut with_props: HashMap<String, Value> = HashMap::new();
-            let mut _properties: HashMap<String, Value> = HashMap::new();
+            let mut properties: HashMap<String, Value> = HashMap::new();
+            let mut log: HashMap<String, Value> = HashMap::new();
+            let mut metrics: HashMap<String, Value> = HashMap::new();
+            let mut funnel: HashMap<String, Value> = HashMap::new();
             
             for attr in attrs {
+                if !self.check_node_call(attr)? {
+                    continue;
+                }
+                match (attr.name.name.as_str(), &attr.value) {
+                    ("with", NodeAttrValue::ListParamDef(params)) => {
+                        for param in params {
+                            let value = self.convert_ast_to_value(&param.value)?;
+                            let resolved_value = self.resolve_variable_references(&value, vars)?;
+                            with_props.insert(param.name.name.clone(), resolved_value);
+                        }
+                        continue;
+                    }
+                    ("property", NodeAttrValue::ListParamDef(params)) => {
+                        for param in params {
+                            let value = self.convert_ast_to_value(&param.value)?;
+                            let resolved_value = self.resolve_variable_references(&value, vars)?;
+                            properties.insert(param.name.name.clone(), resolved_value);
+                        }
+                        continue;
+                    }
+                    ("log", NodeAttrValue::ListParamDef(params)) => {
+                        for param in params {
+                            let value = self.convert_ast_to_value(&param.value)?;
+                            let resolved_value = self.resolve_variable_references(&value, vars)?;
+                            if self.options.validate_log_levels && param.name.name == "level" {
+                                check_log_level(&resolved_value, param.value.position())?;
+                            }
+                            log.insert(param.name.name.clone(), resolved_value);
+                        }
+                        continue;
+                    }
+                    ("metrics", NodeAttrValue::ListParamDef(params)) => {
+                        for param in params {
+                            let value = self.convert_ast_to_value(&param.value)?;
+                            let resolved_value = self.resolve_variable_references(&value, vars)?;
+                            metrics.insert(param.name.name.clone(), resolved_value);
+                        }
+                        continue;
+                    }
+                    ("funnel", NodeAttrValue::ListParamDef(params)) => {
+                        for param in params {
+                            let value = self.convert_ast_to_value(&param.value)?;
+                            let resolved_value = self.resolve_variable_references(&value, vars)?;
+                            funnel.insert(param.name.name.clone(), resolved_value);
+                        }
+                        continue;
+                    }
+                    ("depend", NodeAttrValue::ListSymbol(symbols)) => {
+                        node_dict.depends = Some(symbols.iter().map(|s| s.name.clone()).collect());
+                        continue;
+                    }
+                    _ => {}
+                }
+
                 let value = match &attr.value {
                     NodeAttrValue::Symbol(symbol) => Value::String(symbol.name.clone()),
                     NodeAttrValue::String(string_lit) => Value::String(string_lit.value.clone()),
+                    NodeAttrValue::Bool(boolean) => Value::Bool(boolean.value),
                     NodeAttrValue::ListParamDef(_list) => {Value::Null}
                     NodeAttrValue::ListSymbol(_list) => {Value::Null}
                 };
@@ -370,7 +1041,11 @@ impl Compiler {
                 // Determine if this should go in 'with' or 'properties'
                 match attr.name.name.as_str() {
                     "version" => node_dict.version = self.value_to_string(&resolved_value),
-                    "as" => node_dict.alias = self.value_to_string(&resolved_value),
+                    "as" => match resolved_value.as_str() {
+                        Some("start") => node_dict.start = Some(true),
+                        Some("end") => node_dict.end = Some(true),
+                        _ => node_dict.alias = self.value_to_string(&resolved_value),
+                    },
                     "override" => node_dict.override_flag = self.value_to_bool(&resolved_value),
                     _ => {
                         with_props.insert(attr.name.name.clone(), resolved_value);
@@ -381,11 +1056,139 @@ impl Compiler {
             if !with_props.is_empty() {
                 node_dict.with = Some(with_props);
             }
+            if !properties.is_empty() {
+                node_dict.properties = Some(properties);
+            }
+            if !log.is_empty() {
+                node_dict.log = Some(log);
+            }
+            if !metrics.is_empty() {
+                node_dict.metrics = Some(metrics);
+            }
+            if !funnel.is_empty() {
+                node_dict.funnel = Some(funnel);
+            }
         }
 
+        if node_dict.start.is_some() && node_dict.end.is_some() {
+            return Err(ParseError::semantic_error(
+                node_def.position.line,
+                node_def.position.start,
+                format!("Node {} cannot be both start and end", node_def.value.name.name),
+            ));
+        }
+
+        Ok(node_dict)
+    }
+
+    /// Convert condition block to a `builtin.conditions.str` node dictionary
+    fn convert_condition_block(&self, cond: &ConditionBlock, vars: &HashMap<String, Value>) -> ParseResult<NodeDict> {
+        let mut node_dict = NodeDict {
+            op_name: Some("builtin.conditions.str".to_string()),
+            ref_graph: None,
+            version: None,
+            outputs: None,
+            inputs: None,
+            depends: None,
+            with: None,
+            properties: None,
+            log: None,
+            metrics: None,
+            funnel: None,
+            alias: None,
+            start: None,
+            end: None,
+            override_flag: None,
+            for_loop: None,
+            condition: Some(self.condition_to_string(&cond.condition)),
+            true_branch: None,
+            false_branch: None,
+        };
+        node_dict.true_branch = Some(Box::new(self.convert_condition_branch(&cond.true_branch, vars)?));
+        node_dict.false_branch = Some(Box::new(self.convert_condition_branch(&cond.false_branch, vars)?));
         Ok(node_dict)
     }
 
+    /// Convert one branch of a condition block to a node dictionary
+    fn convert_condition_branch(&self, branch: &AstNodeEnum, vars: &HashMap<String, Value>) -> ParseResult<NodeDict> {
+        match branch {
+            AstNodeEnum::ConditionBlock(cond) => self.convert_condition_block(cond, vars),
+            AstNodeEnum::NodeBlock(node_block) => Ok(NodeDict {
+                op_name: Some(node_block.name.name.clone()),
+                ref_graph: None,
+                version: None,
+                outputs: None,
+                inputs: self.extract_node_inputs(node_block)?,
+                depends: None,
+                with: self.extract_node_attributes(node_block, vars)?,
+                properties: None,
+                log: None,
+                metrics: None,
+                funnel: None,
+                alias: None,
+                start: None,
+                end: None,
+                override_flag: None,
+                for_loop: None,
+                condition: None,
+                true_branch: None,
+                false_branch: None,
+            }),
+            _ => Err(ParseError::general("Condition branch must be a node block or condition")),
+        }
+    }
+
+    /// Serialize a condition expression back to its GOS source form
+    fn condition_to_string(&self, expr: &ConditionExpr) -> String {
+        match expr {
+            ConditionExpr::Statement(stmt) if !stmt.raw.is_empty() => stmt.raw.clone(),
+            ConditionExpr::Statement(stmt) => format!(
+                "{} {} {}",
+                self.condition_operand_to_string(&stmt.left_operand),
+                stmt.operator,
+                self.condition_operand_to_string(&stmt.right_operand)
+            ),
+            ConditionExpr::Block(block) => {
+                let inputs = match &block.inputs {
+                    Some(NodeInputDef::Tuple(tuple)) => tuple.items.iter()
+                        .map(|item| self.condition_operand_to_string(item))
+                        .collect::<Vec<_>>(),
+                    Some(NodeInputDef::KeyValue(kv)) => kv.items.iter()
+                        .map(|item| format!("{}={}", item.key.name, self.condition_operand_to_string(&item.value)))
+                        .collect::<Vec<_>>(),
+                    None => Vec::new(),
+                };
+                format!("{}({})", block.name.name, inputs.join(", "))
+            }
+            ConditionExpr::Symbol(symbol) => symbol.name.clone(),
+            ConditionExpr::String(string) => string.value.clone(),
+            ConditionExpr::MultiLineString(string) => string.value.clone(),
+            ConditionExpr::And(left, right) => {
+                format!("{} && {}", self.condition_to_string(left), self.condition_to_string(right))
+            }
+            ConditionExpr::Or(left, right) => {
+                format!("{} || {}", self.condition_to_string(left), self.condition_to_string(right))
+            }
+            ConditionExpr::Not(inner) => format!("!{}", self.condition_to_string(inner)),
+            ConditionExpr::Group(inner) => format!("({})", self.condition_to_string(inner)),
+        }
+    }
+
+    /// Serialize a condition operand back to its GOS source form
+    fn condition_operand_to_string(&self, node: &AstNodeEnum) -> String {
+        match node {
+            AstNodeEnum::Symbol(s) => s.name.clone(),
+            AstNodeEnum::NumberLiteral(n) => n.raw.clone(),
+            AstNodeEnum::FloatLiteral(f) => f.raw.clone(),
+            AstNodeEnum::BoolLiteral(b) => b.raw.clone(),
+            AstNodeEnum::NullLiteral(_) => "null".to_string(),
+            AstNodeEnum::StringLiteral(s) => crate::format::format_string(s),
+            other => self.convert_ast_to_value(other)
+                .map(|v| v.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
     /// Convert operation definition to dictionary
     fn convert_op_def(&self, op_def: &OpDef, vars: &HashMap<String, Value>) -> ParseResult<OpDict> {
         let mut op_dict = OpDict {
@@ -396,7 +1199,8 @@ impl Compiler {
             graph: None,
         };
 
-        let mut metas: HashMap<String, Value> = HashMap::new();
+        // Explicit meta entries below override annotations of the same name
+        let mut metas = self.convert_annotations(&op_def.annotations, vars)?;
         let mut inputs: HashMap<String, HashMap<String, Value>> = HashMap::new();
         let mut outputs: HashMap<String, HashMap<String, Value>> = HashMap::new();
         let mut configs: HashMap<String, HashMap<String, Value>> = HashMap::new();
@@ -419,28 +1223,16 @@ impl Compiler {
                     }
                 }
                 AstNodeEnum::OpInput(op_input) => {
-                    for input_child in &op_input.children {
-                        if let AstNodeEnum::OpSpec(spec) = input_child {
-                            let spec_dict = self.convert_op_spec(spec, vars)?;
-                            inputs.insert(spec.name.name.clone(), spec_dict);
-                        }
-                    }
+                    self.convert_op_specs("input", &op_input.children, vars, &mut inputs)?;
                 }
                 AstNodeEnum::OpOutput(op_output) => {
-                    for output_child in &op_output.children {
-                        if let AstNodeEnum::OpSpec(spec) = output_child {
-                            let spec_dict = self.convert_op_spec(spec, vars)?;
-                            outputs.insert(spec.name.name.clone(), spec_dict);
-                        }
-                    }
+                    self.convert_op_specs("output", &op_output.children, vars, &mut outputs)?;
                 }
                 AstNodeEnum::OpConfig(op_config) => {
-                    for config_child in &op_config.children {
-                        if let AstNodeEnum::OpSpec(spec) = config_child {
-                            let spec_dict = self.convert_op_spec(spec, vars)?;
-                            configs.insert(spec.name.name.clone(), spec_dict);
-                        }
-                    }
+                    self.convert_op_specs("config", &op_config.children, vars, &mut configs)?;
+                }
+                AstNodeEnum::GraphDef(graph_def) => {
+                    op_dict.graph = Some(self.convert_graph_def(graph_def, vars)?);
                 }
                 _ => {}
             }
@@ -462,13 +1254,63 @@ impl Compiler {
         Ok(op_dict)
     }
 
+    /// Convert the specs of an op section into `specs`, a name may only appear
+    /// once per section kind
+    fn convert_op_specs(
+        &self,
+        section: &str,
+        children: &[AstNodeEnum],
+        vars: &HashMap<String, Value>,
+        specs: &mut HashMap<String, HashMap<String, Value>>,
+    ) -> ParseResult<()> {
+        for child in children {
+            if let AstNodeEnum::OpSpec(spec) = child {
+                if specs.contains_key(&spec.name.name) {
+                    let position = &spec.name.position;
+                    return Err(helpers::duplicate_op_spec(section, &spec.name.name, position.line, position.start));
+                }
+                let spec_dict = self.convert_op_spec(spec, vars)?;
+                specs.insert(spec.name.name.clone(), spec_dict);
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert `@annotations` to metadata: no args gives `true`, one arg its value,
+    /// several args an array
+    fn convert_annotations(
+        &self,
+        annotations: &[Annotation],
+        vars: &HashMap<String, Value>,
+    ) -> ParseResult<HashMap<String, Value>> {
+        let mut metas = HashMap::new();
+        for annotation in annotations {
+            let mut args = Vec::with_capacity(annotation.args.len());
+            for arg in &annotation.args {
+                let value = self.convert_ast_to_value(arg)?;
+                args.push(self.resolve_variable_references(&value, vars)?);
+            }
+            let value = match args.len() {
+                0 => Value::Bool(true),
+                1 => args.remove(0),
+                _ => Value::Array(args),
+            };
+            metas.insert(annotation.name.name.clone(), value);
+        }
+        Ok(metas)
+    }
+
     /// Convert operation specification to dictionary
     fn convert_op_spec(&self, spec: &OpSpec, vars: &HashMap<String, Value>) -> ParseResult<HashMap<String, Value>> {
         let mut spec_dict: HashMap<String, Value> = HashMap::new();
 
         if let Some(items) = &spec.items {
             for item in items {
-                let value = self.convert_ast_to_value(&item.value)?;
+                let value = match (item.name.as_str(), item.value.as_ref()) {
+                    // A fixed length is stored like an interval with equal bounds
+                    ("length", AstNodeEnum::NumberLiteral(n)) => serde_json::json!({ "eq": n.value }),
+                    _ => self.convert_ast_to_value(&item.value)?,
+                };
                 let resolved_value = self.resolve_variable_references(&value, vars)?;
                 spec_dict.insert(item.name.clone(), resolved_value);
             }
@@ -499,6 +1341,39 @@ impl Compiler {
                     .collect();
                 Ok(Value::Array(values?))
             }
+            // JSON has no tuple or set type, both become arrays
+            AstNodeEnum::TupleStatement(tuple) => {
+                let values: Result<Vec<Value>, _> = tuple.items.iter()
+                    .map(|item| self.convert_ast_to_value(item))
+                    .collect();
+                Ok(Value::Array(values?))
+            }
+            AstNodeEnum::SetStatement(set) => {
+                let values: Result<Vec<Value>, _> = set.items.iter()
+                    .map(|item| self.convert_ast_to_value(item))
+                    .collect();
+                Ok(Value::Array(values?))
+            }
+            AstNodeEnum::ClosedInterval(interval) => {
+                let mut map = Map::new();
+                if let Some(ge) = &interval.ge {
+                    map.insert("ge".to_string(), interval_bound_value(ge));
+                }
+                if let Some(le) = &interval.le {
+                    map.insert("le".to_string(), interval_bound_value(le));
+                }
+                Ok(Value::Object(map))
+            }
+            AstNodeEnum::MixInterval(interval) => {
+                let mut map = Map::new();
+                let bounds = [("ge", &interval.ge), ("gt", &interval.gt), ("le", &interval.le), ("lt", &interval.lt)];
+                for (key, bound) in bounds {
+                    if let Some(bound) = bound {
+                        map.insert(key.to_string(), interval_bound_value(bound));
+                    }
+                }
+                Ok(Value::Object(map))
+            }
             AstNodeEnum::DictStatement(dict) => {
                 let mut map = Map::new();
                 for item in &dict.items {
@@ -506,6 +1381,14 @@ impl Compiler {
                         Value::String(s) => s,
                         other => other.to_string(),
                     };
+                    if !self.options.allow_duplicate_dict_keys && map.contains_key(&key) {
+                        let position = item.key.position();
+                        return Err(ParseError::invalid_value(
+                            format!("Duplicate dict key {}", key),
+                            position.line,
+                            position.start,
+                        ));
+                    }
                     let value = self.convert_ast_to_value(&item.value)?;
                     map.insert(key, value);
                 }
@@ -519,7 +1402,7 @@ impl Compiler {
     fn resolve_variable_references(&self, value: &Value, vars: &HashMap<String, Value>) -> ParseResult<Value> {
         match value {
             Value::String(s) => {
-                if let Some(var_value) = vars.get(s) {
+                if let Some(var_value) = lookup_var_path(s, vars) {
                     Ok(var_value.clone())
                 } else {
                     Ok(value.clone())
@@ -543,9 +1426,20 @@ impl Compiler {
         }
     }
 
-    /// Helper function to extract string value from AST node
-    fn extract_string_value(&self, node: &AstNodeEnum) -> Option<String> {
-        match node {
+    /// Version of a graph or template, a var reference resolved like a node attribute
+    fn graph_version(&self, version: Option<&AstNodeEnum>, vars: &HashMap<String, Value>) -> ParseResult<Option<String>> {
+        match version {
+            Some(AstNodeEnum::Symbol(symbol)) => {
+                let resolved = self.resolve_variable_references(&Value::String(symbol.name.clone()), vars)?;
+                Ok(self.value_to_string(&resolved))
+            }
+            version => Ok(version.and_then(|v| self.extract_string_value(v))),
+        }
+    }
+
+    /// Helper function to extract string value from AST node
+    fn extract_string_value(&self, node: &AstNodeEnum) -> Option<String> {
+        match node {
             AstNodeEnum::StringLiteral(s) => Some(s.value.clone()),
             AstNodeEnum::Symbol(s) => Some(s.name.clone()),
             _ => None,
@@ -569,25 +1463,43 @@ impl Compiler {
     }
 
     /// Extract node inputs from NodeBlock
-    fn extract_node_inputs(&self, node_block: &NodeBlock) -> ParseResult<Option<Vec<String>>> {
-        if let Some(inputs) = &node_block.inputs {
-            match inputs {
-                NodeInputDef::Tuple(_tuple_inputs) => {
-                    // Ok(Some(tuple_inputs.items.iter().map(|s| s.name.clone()).collect()))
-                    Ok(None)
-                }
-                NodeInputDef::KeyValue(_kv_inputs) => {
-                    // let mut input_list = Vec::new();
-                    // for item in &kv_inputs.items {
-                    //     input_list.extend(item.value.items.iter().map(|s| s.name.clone()));
-                    // }
-                    // Ok(Some(input_list))
-                    Ok(None)
-                }
+    ///
+    /// Key-value inputs are flattened in order, e.g. `a=x, b=(y, z)` gives [x, y, z]
+    fn extract_node_inputs(&self, node_block: &NodeBlock) -> ParseResult<Option<Vec<NodeInput>>> {
+        let mut input_list = Vec::new();
+        let inputs = node_block.try_resolved_inputs(|value| {
+            Ok::<_, ParseError>(match self.convert_ast_to_value(value)? {
+                Value::String(s) => s,
+                value => value.to_string(),
+            })
+        })?;
+        for input in inputs {
+            if input.grouped && input.key.is_none() {
+                input_list.push(NodeInput::Group(input.values));
+            } else {
+                input_list.extend(input.values.into_iter().map(NodeInput::Name));
             }
-        } else {
-            Ok(None)
         }
+        Ok(if input_list.is_empty() { None } else { Some(input_list) })
+    }
+
+    /// Whether a node call is known, failing on unknown calls with
+    /// `strict_node_calls` and warning about them otherwise
+    fn check_node_call(&self, attr: &NodeAttr) -> ParseResult<bool> {
+        let name = &attr.name;
+        if NODE_CALLS.contains(&name.name.as_str()) {
+            return Ok(true);
+        }
+        let error = ParseError::semantic_error(
+            name.position.line,
+            name.position.start,
+            format!("Unknown node call .{}(), expected one of {}", name.name, NODE_CALLS.join(", ")),
+        );
+        if self.options.strict_node_calls {
+            return Err(error);
+        }
+        self.add_warning(error);
+        Ok(false)
     }
 
     /// Extract node attributes from NodeBlock
@@ -596,9 +1508,13 @@ impl Compiler {
             let mut with_props: HashMap<String, Value> = HashMap::new();
             
             for attr in attrs {
+                if !self.check_node_call(attr)? {
+                    continue;
+                }
                 let value = match &attr.value {
                     NodeAttrValue::Symbol(symbol) => Value::String(symbol.name.clone()),
                     NodeAttrValue::String(string_lit) => Value::String(string_lit.value.clone()),
+                    NodeAttrValue::Bool(boolean) => Value::Bool(boolean.value),
                     // NodeAttrValue::ListParams(list) => {
                     //     let list_values: Result<Vec<Value>, _> = list.iter()
                     //         .map(|item| self.convert_ast_to_value(item))
@@ -630,6 +1546,100 @@ impl Default for Compiler {
     }
 }
 
+/// Substitutes `${path}` in string literals, keeping the first undefined var
+struct Interpolator<'a> {
+    vars: &'a HashMap<String, Value>,
+    error: Option<ParseError>,
+}
+
+impl VisitorMut for Interpolator<'_> {
+    fn visit_string(&mut self, string: &mut StringLiteral) {
+        if self.error.is_some() {
+            return;
+        }
+        match interpolate(string, self.vars) {
+            Ok(value) => {
+                string.value = value;
+                string.escaped_interpolations.clear();
+            }
+            Err(path) => {
+                self.error = Some(ParseError::semantic_error(
+                    string.position.line,
+                    string.position.start,
+                    format!("Undefined var {} in string interpolation", path),
+                ));
+            }
+        }
+    }
+}
+
+/// A copy of `node` with `${path}` in its string literals replaced by var values
+fn interpolate_strings(node: &AstNodeEnum, vars: &HashMap<String, Value>) -> ParseResult<AstNodeEnum> {
+    let mut node = node.clone();
+    let mut interpolator = Interpolator { vars, error: None };
+    node.walk_mut(&mut interpolator);
+    match interpolator.error {
+        Some(error) => Err(error),
+        None => Ok(node),
+    }
+}
+
+/// Replace each `${path}` of a string, a string var by its text and any other
+/// var by its JSON, returning the undefined path on failure
+///
+/// A `${` written `\${` and an unterminated `${` are kept as they are.
+fn interpolate(string: &StringLiteral, vars: &HashMap<String, Value>) -> Result<String, String> {
+    let text = string.value.as_str();
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(index) = rest.find("${") {
+        let (before, after) = (&rest[..index], &rest[index + 2..]);
+        out.push_str(before);
+        let offset = text.len() - rest.len() + index;
+        if string.escaped_interpolations.contains(&offset) {
+            out.push_str("${");
+            rest = after;
+            continue;
+        }
+        let Some(end) = after.find('}') else {
+            out.push_str("${");
+            rest = after;
+            continue;
+        };
+        let path = after[..end].trim();
+        match lookup_var_path(path, vars) {
+            Some(Value::String(value)) => out.push_str(value),
+            Some(value) => out.push_str(&value.to_string()),
+            None => return Err(path.to_string()),
+        }
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Look up a var reference, following the remaining dotted path into the
+/// value of the longest matching var, e.g. `config.db.host` through `config.db`
+pub(crate) fn lookup_var_path<'a>(name: &str, vars: &'a HashMap<String, Value>) -> Option<&'a Value> {
+    if let Some(value) = vars.get(name) {
+        return Some(value);
+    }
+    for (index, _) in name.rmatch_indices('.') {
+        let Some(mut value) = vars.get(&name[..index]) else {
+            continue;
+        };
+        for segment in name[index + 1..].split('.') {
+            value = match value {
+                Value::Object(map) => map.get(segment)?,
+                Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
+        }
+        return Some(value);
+    }
+    None
+}
+
 /// Convenience function to compile AST with default options
 pub fn compile_ast(ast: &AstNodeEnum) -> ParseResult<CompileResult> {
     let compiler = Compiler::new();
@@ -642,24 +1652,2002 @@ pub fn compile_ast_with_options(ast: &AstNodeEnum, options: CompileOptions) -> P
     compiler.compile(ast)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::ast::{Position, Module};
+/// Compile AST to single-line JSON for embedding in other documents
+///
+/// `None` fields are omitted and no whitespace is emitted outside string values.
+pub fn compile_to_compact_json(ast: &AstNodeEnum) -> ParseResult<String> {
+    let result = compile_ast(ast)?;
+    serde_json::to_string(&result)
+        .map_err(|e| ParseError::general(format!("Failed to serialize compile result: {}", e)))
+}
 
-    #[test]
-    fn test_compile_empty_module() {
-        let module = Module {
-            position: Position::new(1, 1, 1),
-            children: vec![],
+/// Stable fingerprint of a compiled result, e.g. as a build cache key
+///
+/// The result is written as JSON with every object's keys sorted, so the
+/// order of `HashMap` entries does not matter, and hashed with 64-bit FNV-1a,
+/// which unlike `DefaultHasher` stays the same across Rust releases. The
+/// source map is not part of the fingerprint.
+pub fn compile_result_fingerprint(result: &CompileResult) -> String {
+    let value = serde_json::to_value(result).unwrap_or(Value::Null);
+    let mut canonical = String::new();
+    write_canonical_json(&value, &mut canonical);
+    let hash = canonical.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Compact JSON with object keys sorted at every level
+fn write_canonical_json(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (index, (key, value)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical_json(value, out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_canonical_json(item, out);
+            }
+            out.push(']');
+        }
+        _ => out.push_str(&value.to_string()),
+    }
+}
+
+/// Check that every node input in a graph refers to a defined name
+///
+/// Node outputs and graph properties are defined names. A dotted input such as
+/// `config.version` refers to a var or another graph and is not checked here.
+/// Vars are declared outside the graph, so a plain input naming one is flagged;
+/// `validate_module_wiring` counts the vars of the module as defined.
+pub fn validate_wiring(graph: &GraphDef) -> Vec<ParseError> {
+    wiring_errors(graph, &HashSet::new())
+}
+
+/// Same as `validate_wiring` for every graph of a module, with the names
+/// declared in its var blocks defined, `alias.name` for an aliased block
+pub fn validate_module_wiring(module: &Module) -> Vec<ParseError> {
+    let mut vars = HashSet::new();
+    for child in &module.children {
+        if let AstNodeEnum::VarDef(var_def) = child {
+            for attr_def in var_def.children.iter().filter_map(|child| match child {
+                AstNodeEnum::AttrDef(attr_def) => Some(attr_def),
+                _ => None,
+            }) {
+                let name = attr_def.name.name.trim();
+                vars.insert(match &var_def.alias {
+                    Some(alias) => format!("{}.{}", alias.name, name),
+                    None => name.to_string(),
+                });
+            }
+        }
+    }
+    module.children.iter()
+        .filter_map(|child| match child {
+            AstNodeEnum::GraphDef(graph) => Some(graph),
+            _ => None,
+        })
+        .flat_map(|graph| wiring_errors(graph, &vars))
+        .collect()
+}
+
+fn wiring_errors(graph: &GraphDef, vars: &HashSet<String>) -> Vec<ParseError> {
+    let mut defined: HashSet<&str> = vars.iter().map(String::as_str).collect();
+    // Each node block with the key of its node, as `node_key` gives it
+    let mut blocks: Vec<(String, &NodeBlock)> = Vec::new();
+    let mut nodes = 0;
+    for child in &graph.children {
+        match child {
+            AstNodeEnum::NodeDef(node_def) => {
+                defined.extend(node_def.outputs.iter().map(|s| s.name.as_str()));
+                let key = node_key(node_block_alias(&node_def.value), &node_def.outputs, nodes);
+                blocks.push((key, &node_def.value));
+                nodes += 1;
+            }
+            AstNodeEnum::ConditionDef(cond_def) => {
+                defined.extend(cond_def.outputs.iter().map(|s| s.name.as_str()));
+                let key = node_key(None, &cond_def.outputs, nodes);
+                let mut branches = Vec::new();
+                collect_condition_blocks(&cond_def.value, &mut branches);
+                blocks.extend(branches.into_iter().map(|block| (key.clone(), block)));
+                nodes += 1;
+            }
+            AstNodeEnum::AttrDef(attr_def) => {
+                defined.insert(attr_def.name.name.as_str());
+                if let AstNodeEnum::NodeBlock(node_block) = attr_def.value.as_ref() {
+                    blocks.push((attr_def.name.name.clone(), node_block));
+                    nodes += 1;
+                }
+            }
+            AstNodeEnum::RefDef(ref_def) => {
+                defined.insert(ref_def.name.name.as_str());
+            }
+            _ => {}
+        }
+    }
+
+    let mut errors = Vec::new();
+    for (key, block) in blocks {
+        for input in block.input_symbols() {
+            let name = input.output_index().map_or(input.name.as_str(), |(base, _)| base);
+            if name.contains('.') || defined.contains(name) {
+                continue;
+            }
+            errors.push(ParseError::semantic_error(
+                input.position.line,
+                input.position.start,
+                format!("Input {} of node {} is not defined", input.name, key),
+            ));
+        }
+    }
+    errors
+}
+
+/// The `.as()` alias of a node; `start` and `end` are markers, not aliases
+fn node_block_alias(block: &NodeBlock) -> Option<&str> {
+    let attr = block.attrs.iter().flatten().find(|attr| attr.name.name == "as")?;
+    let alias = match &attr.value {
+        NodeAttrValue::Symbol(symbol) => symbol.name.as_str(),
+        NodeAttrValue::String(string_lit) => string_lit.value.as_str(),
+        _ => return None,
+    };
+    (!matches!(alias, "start" | "end")).then_some(alias)
+}
+
+/// Derive the `(from, to)` node key pairs of a graph, sorted and without duplicates
+///
+/// A node is wired from the node producing one of its inputs, including the
+/// inputs of condition branches, and from every node it depends on. A
+/// dependency may name a node by key, alias or output.
+pub(crate) fn node_edges(nodes: &HashMap<String, NodeDict>) -> Vec<(String, String)> {
+    let mut producers: HashMap<&str, &str> = HashMap::new();
+    for (key, node) in nodes {
+        producers.insert(key, key);
+        if let Some(alias) = &node.alias {
+            producers.insert(alias, key);
+        }
+        for output in node.outputs.iter().flatten() {
+            producers.insert(output, key);
+        }
+    }
+
+    let mut edges = BTreeSet::new();
+    for (key, node) in nodes {
+        let mut sources: Vec<&str> = node.depends.iter().flatten().map(String::as_str).collect();
+        let mut pending = vec![node];
+        while let Some(current) = pending.pop() {
+            sources.extend(current.inputs.iter().flatten().flat_map(NodeInput::names).map(String::as_str));
+            pending.extend(current.true_branch.as_deref());
+            pending.extend(current.false_branch.as_deref());
+        }
+        for source in sources {
+            // `node.1` or `node[1]` is wired from `node`
+            let source = split_output_index(source).map_or(source, |(base, _)| base);
+            if let Some(&from) = producers.get(source) {
+                if from != key {
+                    edges.insert((from.to_string(), key.clone()));
+                }
+            }
+        }
+    }
+    edges.into_iter().collect()
+}
+
+/// Check concrete input values against the `input` specs of a compiled op
+///
+/// Each value is checked for its `dtype`, its `length` (of a string, list or
+/// dict), its numeric `range` and its `choice` membership. Unknown dtypes are
+/// accepted, as is an input the op does not declare a spec for.
+pub fn check_op_inputs(op: &OpDict, inputs: &Map<String, Value>) -> Vec<ParseError> {
+    let mut errors = Vec::new();
+    let Some(specs) = &op.inputs else {
+        return errors;
+    };
+    for (name, value) in inputs {
+        let Some(spec) = specs.get(name) else {
+            continue;
         };
-        let ast = AstNodeEnum::Module(module);
-        
-        let result = compile_ast(&ast).unwrap();
-        assert_eq!(result.gos_version, "0.5.2");
-        assert!(result.graphs.is_none());
-        assert!(result.ops.is_none());
-        assert!(result.vars.is_none());
+        if let Some(dtype) = spec.get("dtype").and_then(Value::as_str) {
+            if !dtype_matches(dtype, value) {
+                errors.push(ParseError::general(format!("Input {} expects {}, got {}", name, dtype, value)));
+                continue;
+            }
+        }
+        if let Some(length) = spec.get("length") {
+            let actual = match value {
+                Value::String(s) => Some(s.chars().count()),
+                Value::Array(items) => Some(items.len()),
+                Value::Object(map) => Some(map.len()),
+                _ => None,
+            };
+            if let Some(actual) = actual.filter(|&actual| !in_bounds(length, actual as f64)) {
+                errors.push(ParseError::general(format!(
+                    "Input {} has length {}, expected {}", name, actual, bounds_str(length)
+                )));
+            }
+        }
+        if let (Some(range), Some(number)) = (spec.get("range"), value.as_f64()) {
+            if !in_bounds(range, number) {
+                errors.push(ParseError::general(format!(
+                    "Input {} is {}, expected {}", name, value, bounds_str(range)
+                )));
+            }
+        }
+        if let Some(choice) = spec.get("choice") {
+            let allowed = match choice {
+                Value::Array(choices) => choices.contains(value),
+                single => single == value,
+            };
+            if !allowed {
+                errors.push(ParseError::general(format!("Input {} is {}, expected one of {}", name, value, choice)));
+            }
+        }
+    }
+    errors
+}
+
+/// Check that each node of `graph` destructured into several outputs, e.g.
+/// `a, b = split(x);`, has as many outputs as its op declares
+///
+/// Ops are found by their `as` meta. Nodes of an op not in `ops`, or of one
+/// without an `output` section, are not checked.
+pub fn validate_node_outputs(graph: &GraphDict, ops: &[OpDict]) -> Vec<ParseError> {
+    let mut errors = Vec::new();
+    for (key, node) in sorted_entries(graph.nodes.as_ref().unwrap_or(&HashMap::new())) {
+        let Some(outputs) = node.outputs.as_ref().filter(|outputs| outputs.len() > 1) else {
+            continue;
+        };
+        let Some(op_name) = &node.op_name else {
+            continue;
+        };
+        let declared = ops
+            .iter()
+            .find(|op| op.alias() == Some(op_name.as_str()))
+            .and_then(|op| op.outputs.as_ref());
+        let Some(declared) = declared.filter(|declared| declared.len() != outputs.len()) else {
+            continue;
+        };
+        let mut names: Vec<&str> = declared.keys().map(String::as_str).collect();
+        names.sort();
+        errors.push(ParseError::general(format!(
+            "Node {} has {} outputs, op {} declares {} ({})",
+            key,
+            outputs.len(),
+            op_name,
+            declared.len(),
+            names.join(", ")
+        )));
+    }
+    errors
+}
+
+fn dtype_matches(dtype: &str, value: &Value) -> bool {
+    match dtype {
+        "int" | "integer" | "long" => value.is_i64() || value.is_u64(),
+        "float" | "double" | "number" => value.is_number(),
+        "str" | "string" => value.is_string(),
+        "bool" | "boolean" => value.is_boolean(),
+        "list" | "tuple" | "set" | "array" => value.is_array(),
+        "dict" | "map" | "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+/// Compile an interval bound, keeping floats (including `-0.0`) as floats
+fn interval_bound_value(bound: &IntervalBound) -> Value {
+    match bound {
+        IntervalBound::Number(number) => Value::from(number.value),
+        IntervalBound::Float(float) => serde_json::Number::from_f64(float.value).map_or(Value::Null, Value::Number),
+    }
+}
+
+/// Whether `number` lies within compiled interval bounds (`eq`, `ge`, `gt`, `le`, `lt`)
+fn in_bounds(bounds: &Value, number: f64) -> bool {
+    let bound = |key: &str| bounds.get(key).and_then(Value::as_f64);
+    bound("eq").is_none_or(|eq| number == eq)
+        && bound("ge").is_none_or(|ge| number >= ge)
+        && bound("gt").is_none_or(|gt| number > gt)
+        && bound("le").is_none_or(|le| number <= le)
+        && bound("lt").is_none_or(|lt| number < lt)
+}
+
+/// Render compiled interval bounds the way they are written in GOS
+fn bounds_str(bounds: &Value) -> String {
+    let bound = |key: &str| bounds.get(key).map(Value::to_string);
+    if let Some(eq) = bound("eq") {
+        return eq;
+    }
+    let left = match (bound("ge"), bound("gt")) {
+        (Some(ge), _) => format!("[{}", ge),
+        (None, Some(gt)) => format!("({}", gt),
+        (None, None) => "(".to_string(),
+    };
+    let right = match (bound("le"), bound("lt")) {
+        (Some(le), _) => format!("{}]", le),
+        (None, Some(lt)) => format!("{})", lt),
+        (None, None) => ")".to_string(),
+    };
+    format!("{}, {}", left, right)
+}
+
+/// Node chained calls, e.g. `.with(...)` in `a = load().with(batch=1);`
+pub const NODE_CALLS: [&str; 11] =
+    ["with", "property", "log", "metrics", "funnel", "version", "as", "depend", "override", "name", "condition"];
+
+/// Op meta naming a graph of the module to embed, see
+/// `CompileOptions::resolve_op_graph_refs`
+pub const OP_GRAPH_REF_META: &str = "graph_ref";
+
+/// The `graph_ref` meta entry of an op
+fn op_graph_ref(op_def: &OpDef) -> Option<&AttrDef> {
+    op_def
+        .children
+        .iter()
+        .filter_map(|child| match child {
+            AstNodeEnum::OpMeta(op_meta) => Some(op_meta),
+            _ => None,
+        })
+        .flat_map(|op_meta| &op_meta.children)
+        .find(|attr_def| attr_def.name.name == OP_GRAPH_REF_META)
+}
+
+/// Embed the graph named by the compiled `graph_ref` meta of `op`, replacing
+/// any graph written in the op
+fn resolve_op_graph_ref(op: &mut OpDict, graphs: &[GraphDict], position: &Position) -> ParseResult<()> {
+    let Some(name) = op.metas.as_ref().and_then(|metas| metas.get(OP_GRAPH_REF_META)).and_then(Value::as_str) else {
+        return Err(ParseError::semantic_error(position.line, position.start, "Op meta graph_ref must be a graph name"));
+    };
+    let Some(graph) = graphs.iter().find(|graph| graph.alias.as_deref() == Some(name)) else {
+        return Err(ParseError::semantic_error(
+            position.line,
+            position.start,
+            format!("Graph {} referenced by op meta graph_ref is not defined", name),
+        ));
+    };
+    op.graph = Some(graph.clone());
+    Ok(())
+}
+
+/// `.log(level=...)` levels, a number level is an index into this list
+pub const LOG_LEVELS: [&str; 5] = ["debug", "info", "warning", "error", "fatal"];
+
+fn check_log_level(level: &Value, position: &Position) -> ParseResult<()> {
+    let valid = match level {
+        Value::Number(n) => n.as_u64().is_some_and(|n| n < LOG_LEVELS.len() as u64),
+        Value::String(s) => LOG_LEVELS.contains(&s.as_str()),
+        _ => false,
+    };
+    if valid {
+        return Ok(());
+    }
+    Err(ParseError::semantic_error(
+        position.line,
+        position.start,
+        format!("Invalid log level {}, expected 0-{} or one of {}", level, LOG_LEVELS.len() - 1, LOG_LEVELS.join(", ")),
+    ))
+}
+
+/// Check the literal versions of graphs, ops and nodes, recursing into subgraphs
+///
+/// Versions given by a variable reference are resolved later and not checked.
+/// An op version has no position of its own, the op's position is reported.
+fn check_versions(children: &[AstNodeEnum]) -> ParseResult<()> {
+    for child in children {
+        match child {
+            AstNodeEnum::GraphDef(graph_def) => {
+                for version in [&graph_def.version, &graph_def.template_version].into_iter().flatten() {
+                    if let AstNodeEnum::StringLiteral(s) = version.as_ref() {
+                        check_version(&s.value, &s.position)?;
+                    }
+                }
+                check_versions(&graph_def.children)?;
+            }
+            AstNodeEnum::OpDef(op_def) => {
+                if let Some(version) = &op_def.version {
+                    check_version(version, &op_def.position)?;
+                }
+            }
+            AstNodeEnum::NodeDef(node_def) => check_node_version(&node_def.value)?,
+            AstNodeEnum::ConditionDef(cond_def) => {
+                let mut blocks = Vec::new();
+                collect_condition_blocks(&cond_def.value, &mut blocks);
+                for block in blocks {
+                    check_node_version(block)?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn check_node_version(node_block: &NodeBlock) -> ParseResult<()> {
+    for attr in node_block.attrs.iter().flatten() {
+        if let ("version", NodeAttrValue::String(s)) = (attr.name.name.as_str(), &attr.value) {
+            check_version(&s.value, &s.position)?;
+        }
+    }
+    Ok(())
+}
+
+/// `x.y.z` with numeric parts, e.g. `1.0.0`; `1.0` and `v1.0.0` are rejected
+fn check_version(version: &str, position: &Position) -> ParseResult<()> {
+    let parts: Vec<&str> = version.split('.').collect();
+    if parts.len() == 3 && parts.iter().all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())) {
+        return Ok(());
+    }
+    Err(ParseError::invalid_value(
+        format!("Invalid version {}, expected x.y.z", version),
+        position.line,
+        position.start,
+    ))
+}
+
+fn collect_condition_blocks<'a>(cond: &'a ConditionBlock, blocks: &mut Vec<&'a NodeBlock>) {
+    for branch in [&cond.true_branch, &cond.false_branch] {
+        match branch.as_ref() {
+            AstNodeEnum::NodeBlock(node_block) => blocks.push(node_block),
+            AstNodeEnum::ConditionBlock(inner) => collect_condition_blocks(inner, blocks),
+            _ => {}
+        }
+    }
+}
+
+/// Reconstruct a `Module` AST from a compile result
+///
+/// This is the inverse of `compile_ast`: graphs, ops and vars are turned back
+/// into `GraphDef`, `OpDef` and `VarDef` nodes. Positions are synthetic, and
+/// map entries are emitted in sorted key order so the output is deterministic.
+pub fn compile_result_to_ast(result: &CompileResult) -> ParseResult<AstNodeEnum> {
+    let mut children = Vec::new();
+
+    if let Some(vars) = &result.vars {
+        children.extend(vars_to_ast(vars)?);
+    }
+    if let Some(graphs) = &result.graphs {
+        for graph in graphs {
+            children.push(AstNodeEnum::GraphDef(graph_dict_to_ast(graph)?));
+        }
+    }
+    if let Some(ops) = &result.ops {
+        for op in ops {
+            children.push(AstNodeEnum::OpDef(op_dict_to_ast(op)?));
+        }
+    }
+
+    Ok(AstNodeEnum::Module(Module {
+        position: synthetic_position(),
+        children,
+    }))
+}
+
+/// Position used for AST nodes that have no source location
+fn synthetic_position() -> Position {
+    Position::new(0, 0, 0)
+}
+
+fn synthetic_symbol(name: &str, kind: SymbolKind) -> Symbol {
+    Symbol::new(synthetic_position(), name.to_string()).with_kind(kind)
+}
+
+/// Checks that a property value has the expected type
+type ValueCheck = fn(&Value) -> bool;
+
+/// Graph properties with a fixed meaning, with the type their value must have
+const RESERVED_GRAPH_PROPERTIES: &[(&str, &str, ValueCheck)] = &[
+    ("description", "a string", Value::is_string),
+];
+
+/// Fail with `InvalidValue` when a reserved graph property has the wrong type
+fn check_reserved_property(name: &str, value: &Value, position: &Position) -> ParseResult<()> {
+    match RESERVED_GRAPH_PROPERTIES.iter().find(|(reserved, _, _)| *reserved == name) {
+        Some((_, expected, is_valid)) if !is_valid(value) => Err(ParseError::invalid_value(
+            format!("Graph property {} must be {}, got {}", name, expected, value),
+            position.line,
+            position.start,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Key of a node in its graph: the `.as()` alias if present, else the joined
+/// outputs, the same `output_key` the decompiler compares against
+fn node_key(alias: Option<&str>, outputs: &[Symbol], index: usize) -> String {
+    match alias {
+        Some(alias) => alias.to_string(),
+        None if !outputs.is_empty() => outputs.iter().map(|s| s.name.as_str()).collect::<Vec<_>>().join(","),
+        None => format!("node_{}", index),
+    }
+}
+
+/// Add a node to its graph, failing when another node already has its key
+fn insert_node(nodes: &mut HashMap<String, NodeDict>, key: String, node: NodeDict, position: &Position) -> ParseResult<()> {
+    if nodes.contains_key(&key) {
+        return Err(helpers::duplicate_node_key(&key, position.line, position.start));
+    }
+    nodes.insert(key, node);
+    Ok(())
+}
+
+/// Keys of the compiled `vars` in the order the module defines them, nested
+/// vars by the first definition under each top-level name
+fn var_source_order(module: &Module, vars: &HashMap<String, Value>) -> Vec<String> {
+    let mut order: Vec<String> = Vec::new();
+    for var_def in module.children.iter().filter_map(|child| match child {
+        AstNodeEnum::VarDef(var_def) => Some(var_def),
+        _ => None,
+    }) {
+        let prefix = var_def.alias.as_ref().map(|alias| format!("{}.", alias.name)).unwrap_or_default();
+        let names = var_def.children.iter().filter_map(|child| match child {
+            AstNodeEnum::AttrDef(attr_def) => Some(attr_def.name.name.trim()),
+            _ => None,
+        });
+        let keys = names.chain(var_def.alias.as_ref().map(|_| "as")).map(|name| format!("{}{}", prefix, name));
+        for key in keys {
+            // A nested var is keyed by its first segment
+            let key = if vars.contains_key(&key) { key } else { key.split('.').next().unwrap_or_default().to_string() };
+            if vars.contains_key(&key) && !order.contains(&key) {
+                order.push(key);
+            }
+        }
+    }
+    order
+}
+
+fn sorted_entries<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+/// Convert flattened vars (`alias.name` keys plus `alias.as` markers) back to var blocks
+fn vars_to_ast(vars: &HashMap<String, Value>) -> ParseResult<Vec<AstNodeEnum>> {
+    let mut aliases: Vec<String> = vars
+        .iter()
+        .filter_map(|(key, value)| {
+            let alias = key.strip_suffix(".as")?;
+            (value.as_str() == Some(alias)).then(|| alias.to_string())
+        })
+        .collect();
+    aliases.sort();
+
+    let mut plain = Vec::new();
+    let mut aliased: Vec<Vec<AstNodeEnum>> = vec![Vec::new(); aliases.len()];
+    for (key, value) in sorted_entries(vars) {
+        let owner = aliases
+            .iter()
+            .position(|alias| key.strip_prefix(alias.as_str()).is_some_and(|rest| rest.starts_with('.')));
+        match owner {
+            Some(index) => {
+                let name = &key[aliases[index].len() + 1..];
+                if name != "as" {
+                    aliased[index].push(var_attr_to_ast(name, value)?);
+                }
+            }
+            None => plain.push(var_attr_to_ast(key, value)?),
+        }
+    }
+
+    let mut var_defs = Vec::new();
+    if !plain.is_empty() {
+        var_defs.push(AstNodeEnum::VarDef(VarDef {
+            position: synthetic_position(),
+            annotations: Vec::new(),
+            children: plain,
+            alias: None,
+            offset: None,
+        }));
+    }
+    for (alias, children) in aliases.iter().zip(aliased) {
+        var_defs.push(AstNodeEnum::VarDef(VarDef {
+            position: synthetic_position(),
+            annotations: Vec::new(),
+            children,
+            alias: Some(synthetic_symbol(alias, SymbolKind::VarAsName)),
+            offset: None,
+        }));
+    }
+    Ok(var_defs)
+}
+
+fn var_attr_to_ast(name: &str, value: &Value) -> ParseResult<AstNodeEnum> {
+    Ok(AstNodeEnum::AttrDef(AttrDef {
+        position: synthetic_position(),
+        name: synthetic_symbol(name, SymbolKind::VarAttr),
+        value: Box::new(value_to_ast(value)?),
+        condition: None,
+        else_value: None,
+    }))
+}
+
+fn graph_dict_to_ast(graph: &GraphDict) -> ParseResult<GraphDef> {
+    let mut children = Vec::new();
+
+    if let Some(properties) = &graph.properties {
+        let set_properties = graph.set_properties.as_deref().unwrap_or_default();
+        for (name, value) in sorted_entries(properties) {
+            let mut value = value_to_ast(value)?;
+            if let AstNodeEnum::ListStatement(list) = &mut value {
+                if !list.items.is_empty() && set_properties.contains(name) {
+                    value = AstNodeEnum::SetStatement(SetStatement {
+                        position: synthetic_position(),
+                        items: std::mem::take(&mut list.items),
+                    });
+                }
+            }
+            children.push(AstNodeEnum::AttrDef(AttrDef {
+                position: synthetic_position(),
+                name: synthetic_symbol(name, SymbolKind::GraphProperty),
+                value: Box::new(value),
+                condition: None,
+                else_value: None,
+            }));
+        }
+    }
+    if let Some(nodes) = &graph.nodes {
+        for (key, node) in sorted_entries(nodes) {
+            children.push(node_dict_to_ast(key, node)?);
+        }
+    }
+
+    Ok(GraphDef {
+        position: synthetic_position(),
+        annotations: metas_to_annotations(graph.metas.as_ref())?,
+        children,
+        alias: graph.alias.as_deref().map(|name| synthetic_symbol(name, SymbolKind::GraphAsName)),
+        version: graph.version.as_deref().map(|v| Box::new(string_to_ast(v))),
+        template_graph: graph
+            .template_graph
+            .as_deref()
+            .map(|name| synthetic_symbol(name, SymbolKind::GraphTemplate)),
+        template_version: graph.template_version.as_deref().map(|v| Box::new(string_to_ast(v))),
+        offset: None,
+    })
+}
+
+/// Inverse of `convert_annotations`: `true` has no args, an array spreads into args
+fn metas_to_annotations(metas: Option<&HashMap<String, Value>>) -> ParseResult<Vec<Annotation>> {
+    let Some(metas) = metas else {
+        return Ok(Vec::new());
+    };
+    let mut annotations = Vec::new();
+    for (name, value) in sorted_entries(metas) {
+        let args = match value {
+            Value::Bool(true) => Vec::new(),
+            Value::Array(items) => items.iter().map(value_to_ast).collect::<ParseResult<_>>()?,
+            _ => vec![value_to_ast(value)?],
+        };
+        annotations.push(Annotation {
+            position: synthetic_position(),
+            name: synthetic_symbol(name, SymbolKind::AnnotationName),
+            args,
+        });
+    }
+    Ok(annotations)
+}
+
+fn node_dict_to_ast(key: &str, node: &NodeDict) -> ParseResult<AstNodeEnum> {
+    let outputs: Vec<Symbol> = match &node.outputs {
+        Some(outputs) if !outputs.is_empty() => outputs
+            .iter()
+            .map(|name| synthetic_symbol(name, SymbolKind::NodeOutput))
+            .collect(),
+        _ => vec![synthetic_symbol(key, SymbolKind::NodeOutput)],
+    };
+
+    if let Some(condition) = &node.condition {
+        let true_branch = node
+            .true_branch
+            .as_deref()
+            .ok_or_else(|| ParseError::general(format!("Condition node {} has no true branch", key)))?;
+        let false_branch = node
+            .false_branch
+            .as_deref()
+            .ok_or_else(|| ParseError::general(format!("Condition node {} has no false branch", key)))?;
+        return Ok(AstNodeEnum::ConditionDef(ConditionDef {
+            position: synthetic_position(),
+            outputs,
+            value: Box::new(condition_dict_to_ast(condition, true_branch, false_branch, key)?),
+        }));
+    }
+
+    Ok(AstNodeEnum::NodeDef(NodeDef {
+        position: synthetic_position(),
+        outputs,
+        value: node_block_to_ast(key, node)?,
+    }))
+}
+
+fn condition_dict_to_ast(
+    condition: &str,
+    true_branch: &NodeDict,
+    false_branch: &NodeDict,
+    key: &str,
+) -> ParseResult<ConditionBlock> {
+    Ok(ConditionBlock {
+        position: synthetic_position(),
+        condition: Box::new(ConditionExpr::String(StringLiteral {
+            position: synthetic_position(),
+            value: condition.to_string(),
+            quote: QuoteStyle::Double,
+            escaped_interpolations: Vec::new(),
+        })),
+        true_branch: Box::new(condition_branch_to_ast(true_branch, key)?),
+        false_branch: Box::new(condition_branch_to_ast(false_branch, key)?),
+    })
+}
+
+fn condition_branch_to_ast(branch: &NodeDict, key: &str) -> ParseResult<AstNodeEnum> {
+    match (&branch.condition, &branch.true_branch, &branch.false_branch) {
+        (Some(condition), Some(true_branch), Some(false_branch)) => Ok(AstNodeEnum::ConditionBlock(
+            condition_dict_to_ast(condition, true_branch, false_branch, key)?,
+        )),
+        _ => Ok(AstNodeEnum::NodeBlock(node_block_to_ast(key, branch)?)),
+    }
+}
+
+fn node_block_to_ast(key: &str, node: &NodeDict) -> ParseResult<NodeBlock> {
+    let name = node
+        .op_name
+        .as_deref()
+        .ok_or_else(|| ParseError::general(format!("Node {} has no op_name", key)))?;
+
+    let inputs = node.inputs.as_ref().map(|inputs| {
+        NodeInputDef::Tuple(NodeInputTuple {
+            position: synthetic_position(),
+            items: inputs
+                .iter()
+                .map(|input| {
+                    Box::new(match input {
+                        NodeInput::Name(name) => AstNodeEnum::Symbol(synthetic_symbol(name, SymbolKind::NodeInput)),
+                        NodeInput::Group(names) => AstNodeEnum::NodeInputValues(NodeInputValues {
+                            position: synthetic_position(),
+                            items: names.iter().map(|name| synthetic_symbol(name, SymbolKind::NodeInput)).collect(),
+                        }),
+                    })
+                })
+                .collect(),
+        })
+    });
+
+    let mut attrs = Vec::new();
+    let mut push_attr = |name: &str, value: NodeAttrValue| {
+        attrs.push(NodeAttr {
+            position: synthetic_position(),
+            name: synthetic_symbol(name, SymbolKind::NodeAttrName),
+            value,
+            offset: None,
+        });
+    };
+
+    if let Some(version) = &node.version {
+        push_attr("version", NodeAttrValue::String(StringLiteral {
+            position: synthetic_position(),
+            value: version.clone(),
+            quote: QuoteStyle::Double,
+            escaped_interpolations: Vec::new(),
+        }));
+    }
+    if let Some(alias) = &node.alias {
+        push_attr("as", NodeAttrValue::Symbol(synthetic_symbol(alias, SymbolKind::NodeAsName)));
+    }
+    if node.start == Some(true) {
+        push_attr("as", NodeAttrValue::Symbol(synthetic_symbol("start", SymbolKind::NodeAsName)));
+    }
+    if node.end == Some(true) {
+        push_attr("as", NodeAttrValue::Symbol(synthetic_symbol("end", SymbolKind::NodeAsName)));
+    }
+    if let Some(depends) = &node.depends {
+        push_attr("depend", NodeAttrValue::ListSymbol(
+            depends.iter().map(|name| synthetic_symbol(name, SymbolKind::NodeDepend)).collect(),
+        ));
+    }
+    if let Some(override_flag) = node.override_flag {
+        push_attr("override", NodeAttrValue::Bool(BoolLiteral {
+            position: synthetic_position(),
+            raw: override_flag.to_string(),
+            value: override_flag,
+        }));
+    }
+    if let Some(with) = &node.with {
+        push_attr("with", NodeAttrValue::ListParamDef(params_to_ast(with)?));
+    }
+    if let Some(properties) = &node.properties {
+        push_attr("property", NodeAttrValue::ListParamDef(params_to_ast(properties)?));
+    }
+    if let Some(log) = &node.log {
+        push_attr("log", NodeAttrValue::ListParamDef(params_to_ast(log)?));
+    }
+    if let Some(metrics) = &node.metrics {
+        push_attr("metrics", NodeAttrValue::ListParamDef(params_to_ast(metrics)?));
+    }
+    if let Some(funnel) = &node.funnel {
+        push_attr("funnel", NodeAttrValue::ListParamDef(params_to_ast(funnel)?));
+    }
+
+    Ok(NodeBlock {
+        position: synthetic_position(),
+        name: synthetic_symbol(name, SymbolKind::NodeName),
+        inputs,
+        attrs: if attrs.is_empty() { None } else { Some(attrs) },
+    })
+}
+
+fn params_to_ast(params: &HashMap<String, Value>) -> ParseResult<Vec<ParamDef>> {
+    sorted_entries(params)
+        .into_iter()
+        .map(|(name, value)| {
+            Ok(ParamDef {
+                position: synthetic_position(),
+                name: synthetic_symbol(name, SymbolKind::NodeParamKey),
+                value: Box::new(value_to_ast(value)?),
+            })
+        })
+        .collect()
+}
+
+fn op_dict_to_ast(op: &OpDict) -> ParseResult<OpDef> {
+    let mut children = Vec::new();
+    let mut alias = None;
+    let mut version = None;
+
+    if let Some(metas) = &op.metas {
+        let mut meta_children = Vec::new();
+        for (name, value) in sorted_entries(metas) {
+            match (name.as_str(), value) {
+                ("as", Value::String(s)) => alias = Some(synthetic_symbol(s, SymbolKind::OpAsName)),
+                ("version", Value::String(s)) => version = Some(s.clone()),
+                _ => meta_children.push(AttrDef {
+                    position: synthetic_position(),
+                    name: synthetic_symbol(name, SymbolKind::OpMetaAttr),
+                    value: Box::new(value_to_ast(value)?),
+                    condition: None,
+                    else_value: None,
+                }),
+            }
+        }
+        if !meta_children.is_empty() {
+            children.push(AstNodeEnum::OpMeta(OpMeta {
+                position: synthetic_position(),
+                children: meta_children,
+                offset: None,
+            }));
+        }
+    }
+    if let Some(inputs) = &op.inputs {
+        children.push(AstNodeEnum::OpInput(OpInput {
+            position: synthetic_position(),
+            children: op_specs_to_ast(inputs, SymbolKind::OpInputAttr)?,
+            offset: None,
+        }));
+    }
+    if let Some(outputs) = &op.outputs {
+        children.push(AstNodeEnum::OpOutput(OpOutput {
+            position: synthetic_position(),
+            children: op_specs_to_ast(outputs, SymbolKind::OpOutputAttr)?,
+            offset: None,
+        }));
+    }
+    if let Some(configs) = &op.configs {
+        children.push(AstNodeEnum::OpConfig(OpConfig {
+            position: synthetic_position(),
+            children: op_specs_to_ast(configs, SymbolKind::OpConfigAttr)?,
+            offset: None,
+        }));
+    }
+    if let Some(graph) = &op.graph {
+        children.push(AstNodeEnum::GraphDef(graph_dict_to_ast(graph)?));
+    }
+
+    Ok(OpDef {
+        position: synthetic_position(),
+        annotations: Vec::new(),
+        children,
+        alias,
+        version,
+        offset: None,
+    })
+}
+
+fn op_specs_to_ast(
+    specs: &HashMap<String, HashMap<String, Value>>,
+    kind: SymbolKind,
+) -> ParseResult<Vec<AstNodeEnum>> {
+    let mut children = Vec::new();
+    for (name, spec) in sorted_entries(specs) {
+        let items = sorted_entries(spec)
+            .into_iter()
+            .map(|(item_name, value)| {
+                Ok(OpSpecItem {
+                    position: synthetic_position(),
+                    name: item_name.clone(),
+                    value: Box::new(value_to_ast(value)?),
+                })
+            })
+            .collect::<ParseResult<Vec<_>>>()?;
+        children.push(AstNodeEnum::OpSpec(OpSpec {
+            position: synthetic_position(),
+            name: synthetic_symbol(name, kind),
+            items: if items.is_empty() { None } else { Some(items) },
+        }));
+    }
+    Ok(children)
+}
+
+fn string_to_ast(value: &str) -> AstNodeEnum {
+    AstNodeEnum::StringLiteral(StringLiteral {
+        position: synthetic_position(),
+        value: value.to_string(),
+        quote: QuoteStyle::Double,
+        escaped_interpolations: Vec::new(),
+    })
+}
+
+/// Convert a JSON value back to a literal or collection AST node
+fn value_to_ast(value: &Value) -> ParseResult<AstNodeEnum> {
+    Ok(match value {
+        Value::Null => AstNodeEnum::NullLiteral(NullLiteral {
+            position: synthetic_position(),
+            raw: "null".to_string(),
+        }),
+        Value::Bool(b) => AstNodeEnum::BoolLiteral(BoolLiteral {
+            position: synthetic_position(),
+            raw: b.to_string(),
+            value: *b,
+        }),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                AstNodeEnum::NumberLiteral(NumberLiteral {
+                    position: synthetic_position(),
+                    raw: i.to_string(),
+                    value: i,
+                })
+            } else {
+                let f = n
+                    .as_f64()
+                    .ok_or_else(|| ParseError::general(format!("Unsupported number {}", n)))?;
+                AstNodeEnum::FloatLiteral(FloatLiteral {
+                    position: synthetic_position(),
+                    raw: n.to_string(),
+                    value: f,
+                })
+            }
+        }
+        Value::String(s) => string_to_ast(s),
+        Value::Array(items) => AstNodeEnum::ListStatement(ListStatement {
+            position: synthetic_position(),
+            items: items.iter().map(value_to_ast).collect::<ParseResult<Vec<_>>>()?,
+        }),
+        Value::Object(map) => AstNodeEnum::DictStatement(DictStatement {
+            position: synthetic_position(),
+            items: map
+                .iter()
+                .map(|(key, value)| {
+                    Ok(DictItem {
+                        position: synthetic_position(),
+                        key: Box::new(string_to_ast(key)),
+                        value: Box::new(value_to_ast(value)?),
+                    })
+                })
+                .collect::<ParseResult<Vec<_>>>()?,
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{Position, Module};
+
+    #[test]
+    fn test_compile_empty_module() {
+        let module = Module {
+            position: Position::new(1, 1, 1),
+            children: vec![],
+        };
+        let ast = AstNodeEnum::Module(module);
+        
+        let result = compile_ast(&ast).unwrap();
+        assert_eq!(result.gos_version, "0.5.2");
+        assert!(result.graphs.is_none());
+        assert!(result.ops.is_none());
+        assert!(result.vars.is_none());
+    }
+
+    #[test]
+    fn test_compile_boolean_conditions() {
+        let content = r#"
+graph {
+    r1 = a > 0 && b < 10 ? foo(a) : bar(b);
+    r2 = !(x == y) ? foo() : bar();
+};
+"#;
+        let ast = crate::parse(content).unwrap();
+        let result = compile_ast(&ast).unwrap();
+        let nodes = result.graphs.unwrap()[0].nodes.clone().unwrap();
+
+        let r1 = &nodes["r1"];
+        assert_eq!(r1.op_name.as_deref(), Some("builtin.conditions.str"));
+        assert_eq!(r1.condition.as_deref(), Some("a > 0 && b < 10"));
+        assert_eq!(r1.true_branch.as_ref().unwrap().op_name.as_deref(), Some("foo"));
+        assert_eq!(r1.false_branch.as_ref().unwrap().op_name.as_deref(), Some("bar"));
+
+        let r2 = &nodes["r2"];
+        assert_eq!(r2.condition.as_deref(), Some("!(x == y)"));
+    }
+
+    #[test]
+    fn test_condition_raw_round_trip() {
+        let content = "graph { r = x>=0 && name == 'a' ? foo(x) : bar(x); } as main;";
+        let compile = |source: &str| {
+            let ast = crate::parse(source).unwrap();
+            serde_json::to_value(compile_ast(&ast).unwrap()).unwrap()
+        };
+        let compiled = compile(content);
+        let condition = &compiled["graphs"][0]["nodes"]["r"]["condition"];
+        assert_eq!(condition, "x>=0 && name == 'a'");
+
+        let crate::DecompileResult::Text(text) = crate::decompile_from_data(compiled.clone(), None).unwrap() else {
+            panic!("Expected text result");
+        };
+        assert_eq!(&compile(&text)["graphs"][0]["nodes"]["r"]["condition"], condition);
+    }
+
+    #[test]
+    fn test_condition_string_operand_keeps_gos_quoting() {
+        let content = "graph { r = check(x, 'it\\'s', \"a\\tb\") ? foo(x) : bar(x); } as main;";
+        let compiled = serde_json::to_value(compile_ast(&crate::parse(content).unwrap()).unwrap()).unwrap();
+        assert_eq!(compiled["graphs"][0]["nodes"]["r"]["condition"], r#"check(x, 'it\'s', "a\tb")"#);
+    }
+
+    #[test]
+    fn test_compile_result_to_ast_round_trip() {
+        let content = r#"
+var {
+    name = "test";
+    limit = 42;
+    ratio = 0.5;
+    tags = ["a", "b"];
+} as config;
+var { debug = true; };
+graph {
+    description = "round trip";
+    a = foo(x).with(size=3, label="l").version("1.0.0").depend(b);
+    b = bar().property(weight=1.5);
+    r = a > 0 && b < 10 ? foo(a) : bar(b);
+} as main.version("2.0.0");
+"#;
+        let ast = crate::parse(content).unwrap();
+        let first = compile_ast(&ast).unwrap();
+        let rebuilt = compile_result_to_ast(&first).unwrap();
+        let second = compile_ast(&rebuilt).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&first).unwrap(),
+            serde_json::to_value(&second).unwrap()
+        );
+        match rebuilt {
+            AstNodeEnum::Module(module) => {
+                assert!(module.children.iter().any(|c| matches!(c, AstNodeEnum::GraphDef(_))));
+                assert_eq!(
+                    module.children.iter().filter(|c| matches!(c, AstNodeEnum::VarDef(_))).count(),
+                    2
+                );
+            }
+            _ => panic!("Expected Module"),
+        }
+    }
+
+    #[test]
+    fn test_compile_graph_only_op() {
+        let content = r#"
+op {
+    graph {
+        a = foo(x);
+        b = bar(a);
+    };
+} as my_op.version("1.0.0");
+"#;
+        let ast = crate::parse(content).unwrap();
+        let result = compile_ast(&ast).unwrap();
+
+        let ops = result.ops.expect("Expected ops");
+        assert_eq!(ops.len(), 1);
+        let op = &ops[0];
+        assert!(op.inputs.is_none());
+        assert!(op.outputs.is_none());
+        assert!(op.configs.is_none());
+
+        let metas = op.metas.as_ref().expect("Expected metas");
+        assert_eq!(metas.get("as"), Some(&Value::String("my_op".to_string())));
+        assert_eq!(metas.get("version"), Some(&Value::String("1.0.0".to_string())));
+
+        let graph = op.graph.as_ref().expect("Expected embedded graph");
+        let nodes = graph.nodes.as_ref().expect("Expected nodes");
+        assert!(nodes.contains_key("a"));
+        assert!(nodes.contains_key("b"));
+    }
+
+    #[test]
+    fn test_compile_key_value_inputs() {
+        let content = r#"
+graph {
+    a = foo(x=in1);
+    b = bar(x=a, weights=(w1, w2));
+};
+"#;
+        let ast = crate::parse(content).unwrap();
+        let result = compile_ast(&ast).unwrap();
+
+        let graphs = result.graphs.expect("Expected graphs");
+        let nodes = graphs[0].nodes.as_ref().expect("Expected nodes");
+        let names = |names: &[&str]| names.iter().map(|name| NodeInput::Name(name.to_string())).collect::<Vec<_>>();
+        assert_eq!(nodes["a"].inputs, Some(names(&["in1"])));
+        assert_eq!(nodes["b"].inputs, Some(names(&["a", "w1", "w2"])));
+    }
+
+    #[test]
+    fn test_compile_condition_context() {
+        let content = r#"
+var {
+    value = 42 if "debug" else 52;
+    level = 1 if "verbose" else 2;
+};
+"#;
+        let ast = crate::parse(content).unwrap();
+
+        let mut condition_context = HashMap::new();
+        condition_context.insert("debug".to_string(), false);
+        let options = CompileOptions {
+            condition_context,
+            ..Default::default()
+        };
+        let result = compile_ast_with_options(&ast, options).unwrap();
+        let vars = result.vars.expect("Expected vars");
+        assert_eq!(vars["value"], Value::from(52));
+        // Conditions missing from the context keep the conditional structure
+        assert_eq!(vars["level"], serde_json::json!({"value": 1, "condition": "verbose", "else_value": 2}));
+
+        let result = compile_ast(&ast).unwrap();
+        let vars = result.vars.expect("Expected vars");
+        assert_eq!(vars["value"], serde_json::json!({"value": 42, "condition": "debug", "else_value": 52}));
+    }
+
+    #[test]
+    fn test_compile_duplicate_dict_keys() {
+        let content = r#"
+var {
+    d = {"a": 1, "b": 2, "a": 3};
+};
+"#;
+        let ast = crate::parse(content).unwrap();
+
+        let result = compile_ast(&ast).unwrap();
+        let vars = result.vars.expect("Expected vars");
+        assert_eq!(vars["d"], serde_json::json!({"a": 3, "b": 2}));
+
+        let options = CompileOptions {
+            allow_duplicate_dict_keys: false,
+            ..Default::default()
+        };
+        match compile_ast_with_options(&ast, options) {
+            Err(ParseError::InvalidValue { line, column, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(column, 26);
+            }
+            other => panic!("Expected InvalidValue, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_compile_typed_node_attributes() {
+        let content = r#"
+graph {
+    a = foo(x).with(attr1=42, attr2=23.8, attr3=true, attr4=bar).override(true).property(weight=1.5).depend(b);
+};
+"#;
+        let ast = crate::parse(content).unwrap();
+        let result = compile_ast(&ast).unwrap();
+
+        let graphs = result.graphs.expect("Expected graphs");
+        let node = &graphs[0].nodes.as_ref().expect("Expected nodes")["a"];
+        let with = node.with.as_ref().expect("Expected with attributes");
+        assert_eq!(with["attr1"], Value::from(42));
+        assert_eq!(with["attr2"], Value::from(23.8));
+        assert_eq!(with["attr3"], Value::Bool(true));
+        assert_eq!(with["attr4"], Value::String("bar".to_string()));
+        assert_eq!(node.override_flag, Some(true));
+        assert_eq!(node.properties.as_ref().expect("Expected properties")["weight"], Value::from(1.5));
+        assert_eq!(node.depends.as_deref(), Some(&["b".to_string()][..]));
+
+        let json = serde_json::to_value(node).unwrap();
+        assert_eq!(json["with"]["attr2"], serde_json::json!(23.8));
+    }
+
+    #[test]
+    fn test_validate_wiring() {
+        let content = r#"
+var { version = "1.0"; } as config;
+graph {
+    source = "s3";
+    raw = load(source, config.version);
+    clean = normalize(raw);
+    out = save(x=claen, meta=raw.meta);
+};
+"#;
+        let ast = crate::parse(content).unwrap();
+        let AstNodeEnum::Module(module) = ast else {
+            panic!("Expected module");
+        };
+        let AstNodeEnum::GraphDef(graph) = &module.children[1] else {
+            panic!("Expected graph");
+        };
+
+        let errors = validate_wiring(graph);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        match &errors[0] {
+            ParseError::SemanticError { line, message, .. } => {
+                assert_eq!(*line, 7);
+                assert!(message.contains("claen"));
+            }
+            other => panic!("Expected SemanticError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_validate_module_wiring_knows_vars() {
+        let content = r#"
+var { x = 1; };
+var { version = "1.0"; } as config;
+graph {
+    a = foo(x, config.version);
+    b = bar(a, y).as(loader);
+    c = baz(z);
+} as main;
+"#;
+        let ast = crate::parse(content).unwrap();
+        let AstNodeEnum::Module(module) = ast else {
+            panic!("Expected module");
+        };
+
+        let messages: Vec<String> = validate_module_wiring(&module).iter().map(|error| error.to_string()).collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].contains("Input y of node loader"), "{}", messages[0]);
+        assert!(messages[1].contains("Input z of node c"), "{}", messages[1]);
+
+        // The graph alone does not know the var
+        let AstNodeEnum::GraphDef(graph) = &module.children[2] else {
+            panic!("Expected graph");
+        };
+        assert!(validate_wiring(graph).iter().any(|error| error.to_string().contains("Input x of node a")));
+    }
+
+    #[test]
+    fn test_graph_version_var_ref() {
+        let content = "var { version = \"2.1.0\"; } as config;\ngraph {\n    a = foo(x);\n} as main.version(config.version);";
+        let ast = crate::parse(content).unwrap();
+        let AstNodeEnum::Module(module) = &ast else {
+            panic!("Expected module");
+        };
+        let AstNodeEnum::GraphDef(graph) = &module.children[1] else {
+            panic!("Expected graph");
+        };
+        assert_eq!(graph.alias.as_ref().unwrap().name, "main");
+        assert!(matches!(graph.version.as_deref(), Some(AstNodeEnum::Symbol(s)) if s.name == "config.version"));
+
+        let result = compile_ast(&ast).unwrap();
+        assert_eq!(result.graphs.unwrap()[0].version.as_deref(), Some("2.1.0"));
+    }
+
+    #[test]
+    fn test_start_end_markers() {
+        let content = r#"
+graph {
+    a = load(x).as(start);
+    b = save(a).as(end);
+};
+"#;
+        let ast = crate::parse(content).unwrap();
+        let result = compile_ast(&ast).unwrap();
+        let graphs = result.graphs.expect("Expected graphs");
+        let nodes = graphs[0].nodes.as_ref().expect("Expected nodes");
+        assert_eq!(nodes["a"].start, Some(true));
+        assert_eq!(nodes["a"].alias, None);
+        assert_eq!(nodes["b"].end, Some(true));
+
+        let both = crate::parse("graph { a = load(x).as(start).as(end); };").unwrap();
+        match compile_ast(&both) {
+            Err(ParseError::SemanticError { message, .. }) => assert!(message.contains("both start and end")),
+            other => panic!("Expected SemanticError, got {:?}", other),
+        }
+
+        let two_starts = crate::parse("graph { a = load(x).as(start); b = load(y).as(start); };").unwrap();
+        match compile_ast(&two_starts) {
+            Err(ParseError::SemanticError { message, .. }) => assert!(message.contains("more than one start")),
+            other => panic!("Expected SemanticError, got {:?}", other),
+        }
+        let options = CompileOptions {
+            single_start_end: false,
+            ..Default::default()
+        };
+        assert!(compile_ast_with_options(&two_starts, options).is_ok());
+    }
+
+    #[test]
+    fn test_resolve_nested_var_references() {
+        let content = r#"
+var { a = 1; b = "x"; db = {"host": "h", "ports": [5432, 5433]}; } as config;
+graph {
+    features = ["config.a", "config.b"];
+    settings = {"first": "config.a", "nested": {"host": "config.db.host", "list": ["config.db.ports.1"]}};
+};
+"#;
+        let ast = crate::parse(content).unwrap();
+        let result = compile_ast(&ast).unwrap();
+        let graphs = result.graphs.expect("Expected graphs");
+        let properties = graphs[0].properties.as_ref().expect("Expected properties");
+
+        assert_eq!(properties["features"], serde_json::json!([1, "x"]));
+        assert_eq!(
+            properties["settings"],
+            serde_json::json!({"first": 1, "nested": {"host": "h", "list": [5433]}})
+        );
+    }
+
+    #[test]
+    fn test_compile_nested_vars() {
+        let content = r#"
+var { name = "test"; limit = 10; } as config;
+var { debug = true; };
+"#;
+        let ast = crate::parse(content).unwrap();
+
+        let flat = compile_ast(&ast).unwrap().vars.expect("Expected vars");
+        assert_eq!(flat["config.name"], Value::from("test"));
+        assert_eq!(flat["config.limit"], Value::from(10));
+        assert_eq!(flat["debug"], Value::Bool(true));
+
+        let options = CompileOptions {
+            nested_vars: true,
+            ..Default::default()
+        };
+        let nested = compile_ast_with_options(&ast, options.clone()).unwrap().vars.expect("Expected vars");
+        assert_eq!(nested.len(), 2);
+        assert_eq!(
+            nested["config"],
+            serde_json::json!({"name": "test", "limit": 10, "as": "config"})
+        );
+        assert_eq!(nested["debug"], Value::Bool(true));
+
+        let conflict = crate::parse(r#"
+var { name = "x"; } as config;
+var { config = 1; };
+"#).unwrap();
+        match compile_ast_with_options(&conflict, options) {
+            Err(ParseError::SemanticError { line, message, .. }) => {
+                assert_eq!(line, 3);
+                assert!(message.contains("Var config is"));
+            }
+            other => panic!("Expected SemanticError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_interpolate_vars() {
+        let options = CompileOptions {
+            interpolate_vars: true,
+            ..Default::default()
+        };
+        let content = r#"var { name = "gos"; port = 8080; } as config;
+var { greeting = "Hello ${config.name}"; } as text;
+graph {
+    url = "http://${ config.name }:${config.port}/";
+    literal = "cost \${config.port}";
+    backslash = "\\${config.name}";
+    path = r"C:\${config.name}";
+    a = load(x).with(msg="${text.greeting}!");
+} as main;"#;
+        let ast = crate::parse(content).unwrap();
+        let json = serde_json::to_value(compile_ast_with_options(&ast, options.clone()).unwrap()).unwrap();
+        assert_eq!(json["vars"]["text.greeting"], "Hello gos");
+        assert_eq!(json["graphs"][0]["properties"]["url"], "http://gos:8080/");
+        assert_eq!(json["graphs"][0]["properties"]["literal"], "cost ${config.port}");
+        // An escaped backslash and a raw string keep their backslash
+        assert_eq!(json["graphs"][0]["properties"]["backslash"], "\\gos");
+        assert_eq!(json["graphs"][0]["properties"]["path"], "C:\\gos");
+        assert_eq!(json["graphs"][0]["nodes"]["a"]["with"]["msg"], "Hello gos!");
+
+        // Strings are kept as they are without the option
+        let json = serde_json::to_value(compile_ast(&ast).unwrap()).unwrap();
+        assert_eq!(json["vars"]["text.greeting"], "Hello ${config.name}");
+        assert_eq!(json["graphs"][0]["properties"]["literal"], "cost ${config.port}");
+        assert_eq!(json["graphs"][0]["properties"]["backslash"], "\\${config.name}");
+        assert_eq!(json["graphs"][0]["properties"]["path"], "C:\\${config.name}");
+
+        let undefined = "graph {\n    url = \"http://${config.host}/\";\n} as main;";
+        let error = compile_ast_with_options(&crate::parse(undefined).unwrap(), options).unwrap_err();
+        assert!(matches!(error, ParseError::SemanticError { .. }));
+        assert_eq!((error.line(), error.column()), (Some(2), Some(11)));
+        assert!(error.to_string().contains("Undefined var config.host"), "{}", error);
+    }
+
+    #[test]
+    fn test_var_encoding_ordered_pairs() {
+        let options = CompileOptions {
+            var_encoding: VarEncoding::OrderedPairs,
+            ..Default::default()
+        };
+        let content = "var {\n    zeta = 1;\n    alpha = \"a\";\n    mid = [1, 2];\n};\nvar { name = \"gos\"; } as config;";
+        let result = compile_ast_with_options(&crate::parse(content).unwrap(), options).unwrap();
+        let json = serde_json::to_value(&result).unwrap();
+        let keys: Vec<_> = json["vars"].as_array().unwrap().iter().map(|pair| pair["key"].as_str().unwrap()).collect();
+        assert_eq!(keys, ["zeta", "alpha", "mid", "config.name", "config.as"]);
+        assert_eq!(json["vars"][2], serde_json::json!({"key": "mid", "value": [1, 2]}));
+
+        // Both forms read back to the same vars, pairs keeping their order
+        let reread: CompileResult = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(reread.vars, result.vars);
+        assert_eq!(serde_json::to_value(&reread).unwrap(), json);
+        let object = serde_json::to_value(compile_ast(&crate::parse(content).unwrap()).unwrap()).unwrap();
+        assert!(object["vars"].is_object());
+        let reread: CompileResult = serde_json::from_value(object).unwrap();
+        assert_eq!(reread.vars, result.vars);
+    }
+
+    #[test]
+    fn test_find_op_input_spec() {
+        let content = "op {\n    input {\n        x: (dtype=int, default=1);\n        y: str;\n    }\n} as my_op;\nop {\n    input { z: float; }\n} as other;";
+        let result = compile_ast(&crate::parse(content).unwrap()).unwrap();
+        let op = result.find_op("my_op").unwrap();
+        assert_eq!(op.alias(), Some("my_op"));
+        let spec = op.input_spec("x").unwrap();
+        assert_eq!(spec.get("dtype"), Some(&serde_json::json!("int")));
+        assert_eq!(spec.get("default"), Some(&serde_json::json!(1)));
+        assert!(op.input_spec("y").is_some());
+        assert!(op.input_spec("z").is_none());
+        assert!(result.find_op("other").unwrap().input_spec("z").is_some());
+        assert!(result.find_op("missing").is_none());
+    }
+
+    #[test]
+    fn test_compile_result_fingerprint() {
+        let fingerprint = |content: &str| compile_result_fingerprint(&compile_ast(&crate::parse(content).unwrap()).unwrap());
+        let original = fingerprint("var { a = 1; b = {\"x\": 1, \"y\": 2}; } as config;\ngraph {\n    a = foo(x);\n    b = bar(a);\n} as main;");
+        let reordered = fingerprint("var { b = {\"y\": 2, \"x\": 1}; a = 1; } as config;\ngraph {\n    b = bar(a);\n    a = foo(x);\n} as main;");
+        let changed = fingerprint("var { a = 1; b = {\"x\": 1, \"y\": 3}; } as config;\ngraph {\n    a = foo(x);\n    b = bar(a);\n} as main;");
+        assert_eq!(original.len(), 16);
+        assert_eq!(original, reordered);
+        assert_ne!(original, changed);
+    }
+
+    #[test]
+    fn test_compile_to_compact_json() {
+        let content = r#"
+var {
+    name = "a b";
+    n = 1;
+};
+"#;
+        let ast = crate::parse(content).unwrap();
+        let json = compile_to_compact_json(&ast).unwrap();
+
+        assert!(!json.contains('\n'));
+        assert!(!json.contains("null"));
+        assert!(!json.contains("graphs"));
+        assert!(!json.contains("ops"));
+        // Only the space inside the string value survives
+        assert_eq!(json.matches(' ').count(), 1);
+        assert_eq!(json.len(), r#"{"vars":{"name":"a b","n":1},"gos_version":"0.5.2"}"#.len());
+
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["vars"]["name"], "a b");
+        assert_eq!(value["vars"]["n"], 1);
+    }
+
+    #[test]
+    fn test_node_key_prefers_alias() {
+        let content = r#"
+graph {
+    e, f = multi(a).as(m);
+    p, q = split(a);
+    b = foo(a).as(renamed);
+    c = foo(a);
+} as main;
+"#;
+        let ast = crate::parse(content).unwrap();
+        let result = compile_ast(&ast).unwrap();
+        let nodes = result.graphs.as_ref().unwrap()[0].nodes.clone().unwrap();
+
+        let mut keys: Vec<&str> = nodes.keys().map(|key| key.as_str()).collect();
+        keys.sort();
+        assert_eq!(keys, ["c", "m", "p,q", "renamed"]);
+        assert_eq!(nodes["m"].outputs, Some(vec!["e".to_string(), "f".to_string()]));
+        assert_eq!(nodes["m"].alias.as_deref(), Some("m"));
+        assert_eq!(nodes["renamed"].outputs, Some(vec!["b".to_string()]));
+
+        let crate::DecompileResult::Text(text) = crate::decompile_from_data(serde_json::to_value(&result).unwrap(), None).unwrap() else {
+            panic!("Expected text result");
+        };
+        assert!(text.contains("e,f = multi(a).as(m);"), "{}", text);
+        assert!(text.contains("b = foo(a).as(renamed);"), "{}", text);
+    }
+
+    #[test]
+    fn test_reserved_graph_property_types() {
+        let ast = crate::parse("graph { description = 42; a = foo(); } as main;").unwrap();
+        let err = compile_ast(&ast).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidValue { line: 1, .. }), "{:?}", err);
+        assert!(err.to_string().contains("description must be a string"), "{}", err);
+
+        let ast = crate::parse("graph { description = \"ok\"; a = foo(); } as main;").unwrap();
+        let result = compile_ast(&ast).unwrap();
+        let properties = result.graphs.unwrap()[0].properties.clone().unwrap();
+        assert_eq!(properties["description"], "ok");
+    }
+
+    #[test]
+    fn test_compile_many_with_shared_vars() {
+        let shared_ast = crate::parse(r#"var { endpoint = "http://shared"; retries = 3; } as common;"#).unwrap();
+        let shared_vars = compile_ast(&shared_ast).unwrap().vars.unwrap();
+
+        let first = crate::parse("graph { a = foo(x).with(url=common.endpoint); } as first;").unwrap();
+        let second = crate::parse("var { retries = 5; } as common;\ngraph { b = bar(y).with(n=common.retries); } as second;").unwrap();
+        let compiler = Compiler::new();
+        let results = compiler.compile_many(&[("first", &first), ("second", &second)], &shared_vars).unwrap();
+
+        assert_eq!(results.len(), 2);
+        let (name, result) = &results[0];
+        assert_eq!(name, "first");
+        let node = &result.graphs.as_ref().unwrap()[0].nodes.as_ref().unwrap()["a"];
+        assert_eq!(node.with.as_ref().unwrap()["url"], "http://shared");
+        // Shared vars are in scope but not part of the module's own output
+        assert!(result.vars.is_none());
+
+        let (name, result) = &results[1];
+        assert_eq!(name, "second");
+        let node = &result.graphs.as_ref().unwrap()[0].nodes.as_ref().unwrap()["b"];
+        assert_eq!(node.with.as_ref().unwrap()["n"], 5);
+        assert_eq!(result.vars.as_ref().unwrap()["common.retries"], 5);
+
+        // Without the shared vars the reference is left as is
+        let alone = compile_ast(&first).unwrap();
+        let node = &alone.graphs.unwrap()[0].nodes.clone().unwrap()["a"];
+        assert_ne!(node.with.as_ref().unwrap()["url"], "http://shared");
+    }
+
+    #[test]
+    fn test_annotations_compile_to_metas() {
+        let content = r#"var { limit = 30; } as conf;
+@cached @timeout(conf.limit) @tags("a", "b")
+graph { x = foo(); } as main;
+@cached @owner("ml")
+op { meta { owner = "infra"; }; } as Foo;"#;
+        let result = compile_ast(&crate::parse(content).unwrap()).unwrap();
+
+        let metas = result.graphs.as_ref().unwrap()[0].metas.clone().unwrap();
+        assert_eq!(metas["cached"], true);
+        assert_eq!(metas["timeout"], 30);
+        assert_eq!(metas["tags"], serde_json::json!(["a", "b"]));
+
+        let metas = result.ops.as_ref().unwrap()[0].metas.clone().unwrap();
+        assert_eq!(metas["cached"], true);
+        // An explicit meta entry wins over the annotation
+        assert_eq!(metas["owner"], "infra");
+
+        // Graphs without annotations have no metas key
+        let json = compile_to_compact_json(&crate::parse("graph { x = foo(); };").unwrap()).unwrap();
+        assert!(!json.contains("metas"), "{}", json);
+    }
+
+    #[test]
+    fn test_validate_node_outputs() {
+        let content = r#"op {
+    output {
+        mean: float;
+        std: float;
+    };
+} as stats;
+graph {
+    mean, std = stats(x);
+    a, b, c = stats(y);
+    only = stats(z);
+    p, q = other(w);
+} as main;"#;
+        let result = compile_ast(&crate::parse(content).unwrap()).unwrap();
+        let ops = result.ops.as_ref().unwrap();
+        let graph = &result.graphs.as_ref().unwrap()[0];
+        let messages: Vec<String> = validate_node_outputs(graph, ops).iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages.len(), 1, "{:?}", messages);
+        assert!(messages[0].contains("Node a,b,c has 3 outputs, op stats declares 2 (mean, std)"), "{}", messages[0]);
+    }
+
+    #[test]
+    fn test_check_op_inputs() {
+        let content = r#"op {
+    input {
+        name: (dtype=str, length=[1, 5]);
+        size: (dtype=int, range=(0, 10]);
+        mode: (dtype=str, choice=("fast", "slow"));
+        tags: (dtype=list, length=2);
+        any: (help="free");
+    };
+} as Foo;"#;
+        let result = compile_ast(&crate::parse(content).unwrap()).unwrap();
+        let op = &result.ops.unwrap()[0];
+
+        let inputs = serde_json::json!({
+            "name": "abc", "size": 10, "mode": "slow", "tags": [1, 2], "any": {"x": 1}, "extra": 1
+        });
+        let errors = check_op_inputs(op, inputs.as_object().unwrap());
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let inputs = serde_json::json!({
+            "name": "abcdef", "size": 0, "mode": "medium", "tags": "ab"
+        });
+        let messages: Vec<String> = check_op_inputs(op, inputs.as_object().unwrap())
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(messages.len(), 4, "{:?}", messages);
+        let expected = [
+            "Input name has length 6, expected [1, 5]",
+            "Input size is 0, expected (0, 10]",
+            r#"Input mode is "medium", expected one of ["fast","slow"]"#,
+            "Input tags expects list, got \"ab\"",
+        ];
+        for text in expected {
+            assert!(messages.iter().any(|m| m.contains(text)), "missing {} in {:?}", text, messages);
+        }
+
+        let inputs = serde_json::json!({ "size": 2.5 });
+        assert_eq!(check_op_inputs(op, inputs.as_object().unwrap()).len(), 1);
+    }
+
+    #[test]
+    fn test_emit_source_map() {
+        let content = "var { limit = 3; } as cfg;\ngraph {\n    mode = 'fast';\n    node1 = load(src);\n    b = clean(node1);\n} as main;";
+        let ast = crate::parse(content).unwrap();
+        let options = CompileOptions {
+            emit_source_map: true,
+            ..Default::default()
+        };
+        let result = compile_ast_with_options(&ast, options).unwrap();
+        let source_map = result.source_map.as_ref().unwrap();
+        let node = &source_map["graphs[0].nodes.node1"];
+        assert_eq!((node.line, node.start), (4, 5));
+        assert_eq!(source_map["graphs[0].properties.mode"].line, 3);
+        assert_eq!(source_map["graphs[0]"].line, 2);
+        assert_eq!(source_map["vars.cfg.limit"].line, 1);
+
+        // A sidecar, the JSON is unchanged
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json, serde_json::to_value(compile_ast(&ast).unwrap()).unwrap());
+        assert!(compile_ast(&ast).unwrap().source_map.is_none());
+    }
+
+    #[test]
+    fn test_emit_edges() {
+        let content = "graph { a = load(src); b = clean(a).depend(init); init = setup(); } as main;";
+        let ast = crate::parse(content).unwrap();
+        let options = CompileOptions {
+            emit_edges: true,
+            ..Default::default()
+        };
+        let result = compile_ast_with_options(&ast, options).unwrap();
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["graphs"][0]["edges"], serde_json::json!([["a", "b"], ["init", "b"]]));
+
+        let edges = result.graphs.unwrap()[0].edges.clone().unwrap();
+        let expected = [("a", "b"), ("init", "b")].map(|(from, to)| (from.to_string(), to.to_string()));
+        assert_eq!(edges, expected);
+
+        // Off by default
+        assert!(compile_ast(&ast).unwrap().graphs.unwrap()[0].edges.is_none());
+    }
+
+    #[test]
+    fn test_literal_node_inputs() {
+        let content = "graph {\n    a = foo(x, [1,2], 'y', 1.5);\n} as main;";
+        let json = serde_json::to_value(compile_ast(&crate::parse(content).unwrap()).unwrap()).unwrap();
+        assert_eq!(json["graphs"][0]["nodes"]["a"]["inputs"], serde_json::json!(["x", "[1,2]", "y", "1.5"]));
+    }
+
+    #[test]
+    fn test_duplicate_node_key() {
+        let content = "graph {\n    a = foo(x);\n    b = bar(a).as(a);\n} as main;";
+        match compile_ast(&crate::parse(content).unwrap()) {
+            Err(ParseError::DuplicateDefinition { name, line, column }) => {
+                assert_eq!(name, "node 'a'");
+                assert_eq!((line, column), (3, 5));
+            }
+            other => panic!("Expected DuplicateDefinition, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_op_spec_names() {
+        let content = "op {\n    input {\n        x: (dtype=int);\n        x: (dtype=str);\n    };\n} as my_op;";
+        let ast = crate::parse(content).unwrap();
+        match compile_ast(&ast) {
+            Err(ParseError::DuplicateDefinition { name, line, column }) => {
+                assert_eq!(name, "op input 'x'");
+                assert_eq!((line, column), (4, 9));
+            }
+            other => panic!("Expected DuplicateDefinition, got {:?}", other),
+        }
+
+        // The same name in different sections is fine
+        let content = "op {\n    input { x: (dtype=int); };\n    output { x: (dtype=int); };\n} as my_op;";
+        assert!(compile_ast(&crate::parse(content).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_validate_versions() {
+        let options = CompileOptions {
+            validate_versions: true,
+            ..Default::default()
+        };
+        let valid = "graph {\n    a = foo(x).version(\"1.0.0\");\n} as main.version(\"2.10.3\");";
+        compile_ast_with_options(&crate::parse(valid).unwrap(), options.clone()).unwrap();
+
+        let bad_graph = "graph {\n    a = foo(x);\n} as main.version(\"1.0\");";
+        let ast = crate::parse(bad_graph).unwrap();
+        let error = compile_ast_with_options(&ast, options.clone()).unwrap_err();
+        assert!(matches!(error, ParseError::InvalidValue { .. }));
+        assert_eq!(error.line(), Some(3));
+        assert!(error.to_string().contains("1.0"), "{}", error);
+        // Stored as is without the option
+        assert!(compile_ast(&ast).is_ok());
+
+        let bad_node = "graph {\n    a = foo(x).version(\"v1.0.0\");\n} as main;";
+        let error = compile_ast_with_options(&crate::parse(bad_node).unwrap(), options).unwrap_err();
+        assert!(matches!(error, ParseError::InvalidValue { .. }));
+        assert_eq!((error.line(), error.column()), (Some(2), Some(24)));
+    }
+
+    #[test]
+    fn test_validate_log_levels() {
+        let options = CompileOptions {
+            validate_log_levels: true,
+            ..Default::default()
+        };
+        let valid = "graph {\n    a = foo(x).log(level=4, tag=\"a\");\n    b = bar(a).log(level=\"warning\").metrics(name=\"b\");\n} as main;";
+        let result = compile_ast_with_options(&crate::parse(valid).unwrap(), options.clone()).unwrap();
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["graphs"][0]["nodes"]["a"]["log"], serde_json::json!({"level": 4, "tag": "a"}));
+        assert_eq!(json["graphs"][0]["nodes"]["b"]["metrics"], serde_json::json!({"name": "b"}));
+
+        let invalid = "graph {\n    a = foo(x).log(level=5);\n} as main;";
+        let ast = crate::parse(invalid).unwrap();
+        let error = compile_ast_with_options(&ast, options).unwrap_err();
+        assert!(matches!(error, ParseError::SemanticError { .. }));
+        assert_eq!((error.line(), error.column()), (Some(2), Some(26)));
+        assert!(error.to_string().contains("Invalid log level 5"), "{}", error);
+        // Any level is kept without the option
+        assert!(compile_ast(&ast).is_ok());
+    }
+
+    #[test]
+    fn test_no_empty_collections() {
+        fn assert_no_empty(value: &Value, path: &str) {
+            match value {
+                Value::Object(map) => {
+                    assert!(!map.is_empty(), "empty object at {}", path);
+                    for (key, value) in map {
+                        assert_no_empty(value, &format!("{}.{}", path, key));
+                    }
+                }
+                Value::Array(items) => {
+                    assert!(!items.is_empty(), "empty array at {}", path);
+                    for (index, value) in items.iter().enumerate() {
+                        assert_no_empty(value, &format!("{}[{}]", path, index));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let options = CompileOptions {
+            emit_edges: true,
+            ..Default::default()
+        };
+        let content = "var {} as empty;\ngraph {} as nothing;\ngraph {\n    a = foo();\n    b = bar().with().property().log().metrics();\n    c = x > 0 ? foo() : bar();\n} as main;\nop {\n    meta {}\n    input {}\n    output {}\n    config {}\n} as foo;";
+        let result = compile_ast_with_options(&crate::parse(content).unwrap(), options).unwrap();
+        assert_no_empty(&serde_json::to_value(&result).unwrap(), "");
+    }
+
+    #[test]
+    fn test_strict_node_calls() {
+        let options = CompileOptions {
+            strict_node_calls: true,
+            ..Default::default()
+        };
+        let valid = "graph {\n    a = foo(x).with(k=1).property(p=2).log(level=1).metrics(m=2).funnel(step=\"a\").as(start);\n} as main;";
+        let result = compile_ast_with_options(&crate::parse(valid).unwrap(), options.clone()).unwrap();
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["graphs"][0]["nodes"]["a"]["metrics"], serde_json::json!({"m": 2}));
+        assert_eq!(json["graphs"][0]["nodes"]["a"]["funnel"], serde_json::json!({"step": "a"}));
+
+        let unknown = "graph {\n    a = foo(x).with(k=1).frobnicate();\n} as main;";
+        let ast = crate::parse(unknown).unwrap();
+        let error = compile_ast_with_options(&ast, options).unwrap_err();
+        assert!(matches!(error, ParseError::SemanticError { .. }));
+        assert_eq!((error.line(), error.column()), (Some(2), Some(26)));
+        assert!(error.to_string().contains("Unknown node call .frobnicate()"), "{}", error);
+        // Unknown calls are ignored without the option, with a warning
+        let json = serde_json::to_value(compile_ast(&ast).unwrap()).unwrap();
+        assert_eq!(json["graphs"][0]["nodes"]["a"]["with"], serde_json::json!({"k": 1}));
+        let compiler = Compiler::with_options(CompileOptions {
+            warnings: true,
+            ..Default::default()
+        });
+        compiler.compile(&crate::parse("graph {\n    a = foo(x).wiht(k=1);\n} as main;").unwrap()).unwrap();
+        let warnings = compiler.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!((warnings[0].line(), warnings[0].column()), (Some(2), Some(16)));
+        assert!(warnings[0].to_string().contains("Unknown node call .wiht()"), "{}", warnings[0]);
+    }
+
+    #[test]
+    fn test_fail_fast() {
+        let content = "graph {\n    a = foo(x).frobnicate();\n} as first;\ngraph {\n    b = bar(y);\n} as ok;\ngraph {\n    c = baz(z).wobble();\n} as second;";
+        let ast = crate::parse(content).unwrap();
+        let options = CompileOptions {
+            strict_node_calls: true,
+            ..Default::default()
+        };
+        assert!(options.fail_fast);
+        let (result, errors) = Compiler::with_options(options.clone()).compile_with_errors(&ast);
+        assert!(result.is_none());
+        assert_eq!(errors.errors.len(), 1);
+        assert_eq!(errors.errors[0].line(), Some(2));
+
+        let options = CompileOptions {
+            fail_fast: false,
+            ..options
+        };
+        let (result, errors) = Compiler::with_options(options.clone()).compile_with_errors(&ast);
+        assert!(result.is_none());
+        let lines: Vec<_> = errors.errors.iter().map(ParseError::line).collect();
+        assert_eq!(lines, [Some(2), Some(8)]);
+        assert!(errors.errors[1].to_string().contains(".wobble()"), "{}", errors.errors[1]);
+
+        // `compile` reports both, and a clean module still compiles
+        let error = compile_ast_with_options(&ast, options.clone()).unwrap_err();
+        assert!(error.to_string().contains("Multiple errors"), "{}", error);
+        let clean = crate::parse("graph {\n    b = bar(y);\n} as ok;").unwrap();
+        let (result, errors) = Compiler::with_options(options).compile_with_errors(&clean);
+        assert!(result.is_some() && !errors.has_errors());
+    }
+
+    #[test]
+    fn test_resolve_op_graph_refs() {
+        let options = CompileOptions {
+            resolve_op_graph_refs: true,
+            ..Default::default()
+        };
+        let content = "op {\n    meta {\n        graph_ref = \"scoring\";\n    }\n} as scorer;\ngraph {\n    s = score(x);\n} as scoring;";
+        let ast = crate::parse(content).unwrap();
+        let result = compile_ast_with_options(&ast, options.clone()).unwrap();
+        let graph = result.find_op("scorer").unwrap().graph.as_ref().expect("Expected embedded graph");
+        assert_eq!(graph.alias.as_deref(), Some("scoring"));
+        assert!(graph.nodes.as_ref().unwrap().contains_key("s"));
+        // The meta is kept as written, and nothing is embedded without the option
+        assert_eq!(result.find_op("scorer").unwrap().metas.as_ref().unwrap()["graph_ref"], "scoring");
+        assert!(compile_ast(&ast).unwrap().find_op("scorer").unwrap().graph.is_none());
+
+        let undefined = "op {\n    meta {\n        graph_ref = \"missing\";\n    }\n} as scorer;";
+        let error = compile_ast_with_options(&crate::parse(undefined).unwrap(), options).unwrap_err();
+        assert!(matches!(error, ParseError::SemanticError { .. }));
+        assert_eq!((error.line(), error.column()), (Some(3), Some(9)));
+        assert!(error.to_string().contains("Graph missing referenced by op meta graph_ref"), "{}", error);
+    }
+
+    #[test]
+    fn test_empty_graph_warning() {
+        let options = CompileOptions {
+            warnings: true,
+            ..Default::default()
+        };
+        let compiler = Compiler::with_options(options.clone());
+        compiler.compile(&crate::parse("graph {} as x;").unwrap()).unwrap();
+        let warnings = compiler.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0], ParseError::SemanticError { .. }));
+        assert!(warnings[0].to_string().contains("graph 'x' has no nodes"), "{}", warnings[0]);
+
+        // A reused compiler only reports the warnings of its last compile
+        compiler.compile(&crate::parse("graph { a = foo(); } as y;").unwrap()).unwrap();
+        assert!(compiler.warnings().is_empty());
+        let empty = crate::parse("graph {} as x;").unwrap();
+        compiler.compile(&empty).unwrap();
+        assert_eq!(compiler.compile_with_errors(&empty).1.warnings.len(), 1);
+        assert_eq!(compiler.compile_with_errors(&empty).1.warnings.len(), 1);
+
+        // Warnings are off by default
+        let compiler = Compiler::new();
+        compiler.compile(&crate::parse("graph {} as x;").unwrap()).unwrap();
+        assert!(compiler.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_whole_floats_stay_floats() {
+        let content = r#"var { timeout = 30.0; retries = 3; };
+graph { a = foo(x).with(t=30.0); } as main;"#;
+        let ast = crate::parse(content).unwrap();
+        let result = compile_ast(&ast).unwrap();
+
+        let timeout = &result.vars.as_ref().unwrap()["timeout"];
+        assert!(timeout.is_f64());
+        assert_eq!(timeout.to_string(), "30.0");
+        assert!(result.vars.as_ref().unwrap()["retries"].is_i64());
+
+        let json = compile_to_compact_json(&ast).unwrap();
+        assert!(json.contains(r#""timeout":30.0"#), "{}", json);
+        assert!(json.contains(r#""t":30.0"#), "{}", json);
+    }
+
+    #[test]
+    fn test_check_cross_graph_refs() {
+        let options = CompileOptions {
+            check_graph_refs: true,
+            ..Default::default()
+        };
+
+        let valid = r#"
+var { version = "1.0"; } as config;
+graph {
+    processed_data, metadata = ops.preprocessor(x);
+} as data_preprocessing;
+graph {
+    training_data = data_preprocessing.processed_data;
+    extra = data_preprocessing.metadata;
+    version = config.version;
+} as training;
+"#;
+        let ast = crate::parse(valid).unwrap();
+        let result = compile_ast_with_options(&ast, options.clone()).unwrap();
+        let training = &result.graphs.unwrap()[1];
+        assert_eq!(
+            training.properties.as_ref().unwrap().get("training_data"),
+            Some(&Value::String("data_preprocessing.processed_data".to_string()))
+        );
+
+        let dangling_output = r#"
+graph { processed_data = ops.preprocessor(x); } as data_preprocessing;
+graph { training_data = data_preprocessing.missing; } as training;
+"#;
+        let ast = crate::parse(dangling_output).unwrap();
+        match compile_ast_with_options(&ast, options.clone()) {
+            Err(ParseError::SemanticError { line, message, .. }) => {
+                assert_eq!(line, 3);
+                assert!(message.contains("no output missing"));
+            }
+            other => panic!("Expected SemanticError, got {:?}", other),
+        }
+
+        let dangling_graph = r#"graph { training_data = unknown.processed_data; } as training;"#;
+        let ast = crate::parse(dangling_graph).unwrap();
+        assert!(matches!(
+            compile_ast_with_options(&ast, options),
+            Err(ParseError::SemanticError { .. })
+        ));
+        // Without the flag the reference is kept as a plain property
+        assert!(compile_ast(&ast).is_ok());
     }
 
     #[test]
@@ -678,6 +3666,21 @@ mod tests {
             return_subgraphs: true,
             keep_order: true,
             plugin: Some("test_plugin".to_string()),
+            check_graph_refs: false,
+            condition_context: HashMap::new(),
+            allow_duplicate_dict_keys: true,
+            single_start_end: true,
+            nested_vars: false,
+            warnings: false,
+            emit_edges: false,
+            validate_versions: false,
+            validate_log_levels: false,
+            emit_source_map: false,
+            interpolate_vars: false,
+            strict_node_calls: false,
+            var_encoding: VarEncoding::Object,
+            fail_fast: true,
+            resolve_op_graph_refs: false,
         };
         let compiler = Compiler::with_options(options);
         assert!(compiler.options.return_op_names);
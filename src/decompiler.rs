@@ -3,19 +3,41 @@
 //! This module provides functionality to decompile GOS JSON format back to GOS source code.
 //! It supports various formatting options including indentation, line wrapping, and string escaping.
 
+use std::collections::HashMap;
 use std::fs;
+use std::io::{self, Write};
 use std::path::Path;
 use serde_json::Value;
 use regex::Regex;
 use std::cell::RefCell;
 
+/// How escape sequences in JSON strings are treated on output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EscapePolicy {
+    /// Write strings as they are in the JSON
+    #[default]
+    Keep,
+    /// Decode escape sequences such as `\n` once before writing
+    Unescape,
+    /// Decode escape sequences, then write them back in canonical escaped form
+    Reescape,
+}
+
 /// Options for decompilation process
 #[derive(Debug, Clone)]
 pub struct DecompileOptions {
     pub indent: usize,
     pub max_col: usize,
+    /// Same as `escape_policy: EscapePolicy::Unescape`
     pub unescape: bool,
     pub keep_order: bool,
+    pub escape_policy: EscapePolicy,
+    /// Replace references to the `vars` section by their values, as the
+    /// compiler does, for JSON that keeps unresolved references
+    pub resolve_vars: bool,
+    /// Fail on JSON with arrays and objects nested deeper than this, counting
+    /// the document itself as depth 1, rather than recursing without bound
+    pub max_depth: Option<usize>,
 }
 
 impl Default for DecompileOptions {
@@ -25,6 +47,9 @@ impl Default for DecompileOptions {
             max_col: 100,
             unescape: false,
             keep_order: false,
+            escape_policy: EscapePolicy::Keep,
+            resolve_vars: false,
+            max_depth: None,
         }
     }
 }
@@ -49,12 +74,68 @@ thread_local! {
 static VALID_IDENTIFIER: &str = r"^[a-zA-Z_\-$%@][a-zA-Z_\-$%@\.0-9]*$";
 static VALID_VERSION: &str = r"^[0-9]+\.[0-9]+\.[0-9]+$";
 
+/// Output target of the decompiler, either an in-memory `String` or a writer
+trait Sink {
+    fn push(&mut self, ch: char);
+    fn push_str(&mut self, s: &str);
+}
+
+impl Sink for String {
+    fn push(&mut self, ch: char) {
+        String::push(self, ch);
+    }
+
+    fn push_str(&mut self, s: &str) {
+        String::push_str(self, s);
+    }
+}
+
+/// Sink forwarding output to an `io::Write`, keeping the first write error
+struct WriterSink<'a, W: Write> {
+    writer: &'a mut W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Sink for WriterSink<'_, W> {
+    fn push(&mut self, ch: char) {
+        self.push_str(ch.encode_utf8(&mut [0; 4]));
+    }
+
+    fn push_str(&mut self, s: &str) {
+        if self.error.is_none() {
+            if let Err(e) = self.writer.write_all(s.as_bytes()) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
 /// Decompile from JSON data
 pub fn decompile_from_data(
     content: Value,
     options: Option<DecompileOptions>,
 ) -> Result<DecompileResult, String> {
-    let mut content = content;
+    let mut buffer = String::new();
+    decompile_into(&content, &mut buffer, options)?;
+    Ok(DecompileResult::Text(buffer))
+}
+
+/// Decompile JSON data straight into a writer, without building the whole
+/// output in memory
+pub fn decompile_to_writer<W: Write>(
+    content: &Value,
+    writer: &mut W,
+    options: Option<DecompileOptions>,
+) -> Result<(), String> {
+    let mut sink = WriterSink { writer, error: None };
+    decompile_into(content, &mut sink, options)?;
+    match sink.error {
+        Some(e) => Err(format!("Failed to write decompiled output: {}", e)),
+        None => Ok(()),
+    }
+}
+
+fn decompile_into(content: &Value, buffer: &mut dyn Sink, options: Option<DecompileOptions>) -> Result<(), String> {
     let options = options.unwrap_or_default();
     
     // Set thread-local options
@@ -62,16 +143,28 @@ pub fn decompile_from_data(
         *opts.borrow_mut() = options.clone();
     });
     
-    // Handle unescaping if requested
-    if options.unescape {
-        content = unescape_dfs(&content);
+    if let Some(max_depth) = options.max_depth {
+        if exceeds_depth(content, max_depth) {
+            return Err(format!("JSON is nested deeper than the maximum depth {}", max_depth));
+        }
     }
-    
+
+    let content = &wire_keys(content);
+    let resolved;
+    let content = if options.resolve_vars {
+        resolved = resolve_vars(content);
+        &resolved
+    } else {
+        content
+    };
+
     // For now, assume standard JSON format
     // TODO: Add plugin detection and conversion logic
-    let grl_text = decompile_std(&content)?;
-    
-    Ok(DecompileResult::Text(grl_text))
+    if options.unescape || options.escape_policy != EscapePolicy::Keep {
+        decompile_std(&unescape_dfs(content), buffer)
+    } else {
+        decompile_std(content, buffer)
+    }
 }
 
 /// Decompile from file
@@ -93,6 +186,225 @@ pub fn decompile(
     decompile_from_data(json_value, options)
 }
 
+/// GOS features used by a JSON document, counted by `analyze_json`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureSet {
+    pub graphs: usize,
+    pub nodes: usize,
+    pub conditions: usize,
+    pub for_loops: usize,
+    pub templates: usize,
+    pub refs: usize,
+    pub ops: usize,
+}
+
+impl FeatureSet {
+    pub fn has_conditions(&self) -> bool {
+        self.conditions > 0
+    }
+
+    pub fn has_for_loops(&self) -> bool {
+        self.for_loops > 0
+    }
+
+    pub fn has_templates(&self) -> bool {
+        self.templates > 0
+    }
+
+    pub fn has_refs(&self) -> bool {
+        self.refs > 0
+    }
+
+    pub fn has_ops(&self) -> bool {
+        self.ops > 0
+    }
+}
+
+/// Report the features a JSON document would need without decompiling it
+pub fn analyze_json(value: &Value) -> FeatureSet {
+    let mut features = FeatureSet::default();
+
+    if let Some(graphs) = value.get("graphs").and_then(|v| v.as_array()) {
+        for graph in graphs {
+            analyze_graph(graph, &mut features);
+        }
+    }
+
+    if let Some(ops) = value.get("ops").and_then(|v| v.as_array()) {
+        for op in ops {
+            features.ops += 1;
+            if let Some(graph) = op.get("graph") {
+                analyze_graph(graph, &mut features);
+            }
+        }
+    }
+
+    if let Some(nodes) = value.get("nodes").and_then(|v| v.as_object()) {
+        for node in nodes.values() {
+            analyze_node(node, &mut features);
+        }
+    }
+
+    features
+}
+
+fn analyze_graph(graph: &Value, features: &mut FeatureSet) {
+    features.graphs += 1;
+    if graph.get("template_graph").and_then(|v| v.as_str()).is_some() {
+        features.templates += 1;
+    }
+    if let Some(nodes) = graph.get("nodes").and_then(|v| v.as_object()) {
+        for node in nodes.values() {
+            analyze_node(node, features);
+        }
+    }
+}
+
+/// Uses the same checks as `NodeDecompiler::decompile` to classify a node
+fn analyze_node(node: &Value, features: &mut FeatureSet) {
+    features.nodes += 1;
+
+    if let Some(for_loop) = node.get("for_loop").and_then(|v| v.as_object()) {
+        if for_loop.get("inputs").is_some() && for_loop.get("outputs").is_some() {
+            features.for_loops += 1;
+        }
+    }
+
+    if node.get("op_name").and_then(|v| v.as_str()) == Some("builtin.conditions.str") {
+        features.conditions += 1;
+        for branch in ["true_branch", "false_branch"] {
+            if node.get(branch).and_then(|b| b.get("ref_graph")).is_some() {
+                features.refs += 1;
+            }
+        }
+    }
+
+    if node.get("ref_graph").is_some() {
+        features.refs += 1;
+    }
+}
+
+/// Whether any array or object is nested deeper than `max_depth`
+///
+/// Walks with an explicit stack, so checking does not overflow on the input
+/// it is meant to reject.
+fn exceeds_depth(content: &Value, max_depth: usize) -> bool {
+    let mut pending = vec![(content, 1)];
+    while let Some((value, depth)) = pending.pop() {
+        let children: Vec<&Value> = match value {
+            Value::Array(items) => items.iter().collect(),
+            Value::Object(map) => map.values().collect(),
+            _ => continue,
+        };
+        if depth > max_depth {
+            return true;
+        }
+        pending.extend(children.into_iter().map(|child| (child, depth + 1)));
+    }
+    false
+}
+
+/// Graph keys as `CompileResult` serializes them, with the wire name read here
+const COMPILER_GRAPH_KEYS: &[(&str, &str)] = &[("properties", "property")];
+
+/// Node keys as `CompileResult` serializes them, with the wire name read here
+const COMPILER_NODE_KEYS: &[(&str, &str)] = &[
+    ("outputs", "output"),
+    ("inputs", "input"),
+    ("depends", "depend"),
+    ("properties", "property"),
+    ("override_flag", "override"),
+];
+
+/// Rename the compiler's own graph and node keys, e.g. `inputs`, to the wire
+/// names, e.g. `input`, so compiled JSON decompiles like wire JSON
+///
+/// A wire key already present wins over the compiler's.
+fn wire_keys(content: &Value) -> Value {
+    let mut content = content.clone();
+    let graphs = content.get_mut("graphs").and_then(Value::as_array_mut);
+    for graph in graphs.into_iter().flatten() {
+        rename_keys(graph, COMPILER_GRAPH_KEYS);
+        let nodes = graph.get_mut("nodes").and_then(Value::as_object_mut);
+        for node in nodes.into_iter().flat_map(|nodes| nodes.values_mut()) {
+            wire_node_keys(node);
+        }
+    }
+    content
+}
+
+fn wire_node_keys(node: &mut Value) {
+    rename_keys(node, COMPILER_NODE_KEYS);
+    for branch in ["true_branch", "false_branch"] {
+        if let Some(branch) = node.get_mut(branch) {
+            wire_node_keys(branch);
+        }
+    }
+}
+
+fn rename_keys(value: &mut Value, keys: &[(&str, &str)]) {
+    let Value::Object(map) = value else {
+        return;
+    };
+    for (from, to) in keys {
+        if map.contains_key(*to) {
+            continue;
+        }
+        if let Some(moved) = map.remove(*from) {
+            map.insert(to.to_string(), moved);
+        }
+    }
+}
+
+/// Resolve var references in node inputs and call parameters
+///
+/// Like the compiler, a string naming a var, e.g. `config.src`, or a path
+/// into one is replaced by its value, see `lookup_var_path`. Only the
+/// `input`, `with`, `property`, `log` and `metrics` values of nodes and their
+/// branches are resolved; names, versions and ops are kept as they are.
+fn resolve_vars(content: &Value) -> Value {
+    // `vars` is an object or `VarEncoding::OrderedPairs`, as the compiler writes it
+    let vars = content.get("vars").map(|vars| serde_json::from_value::<crate::compiler::Vars>(vars.clone()));
+    let Some(Ok(vars)) = vars else {
+        return content.clone();
+    };
+    let mut resolved = content.clone();
+    let graphs = resolved.get_mut("graphs").and_then(Value::as_array_mut);
+    for graph in graphs.into_iter().flatten() {
+        let nodes = graph.get_mut("nodes").and_then(Value::as_object_mut);
+        for node in nodes.into_iter().flat_map(|nodes| nodes.values_mut()) {
+            resolve_node_vars(node, &vars);
+        }
+    }
+    resolved
+}
+
+fn resolve_node_vars(node: &mut Value, vars: &HashMap<String, Value>) {
+    let Value::Object(node) = node else {
+        return;
+    };
+    for (key, value) in node.iter_mut() {
+        match key.as_str() {
+            "input" | "with" | "property" | "log" | "metrics" => *value = resolve_var_references(value, vars),
+            "true_branch" | "false_branch" => resolve_node_vars(value, vars),
+            _ => {}
+        }
+    }
+}
+
+fn resolve_var_references(value: &Value, vars: &HashMap<String, Value>) -> Value {
+    match value {
+        Value::String(s) => crate::compiler::lookup_var_path(s, vars).unwrap_or(value).clone(),
+        Value::Array(arr) => Value::Array(arr.iter().map(|v| resolve_var_references(v, vars)).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, v)| (key.clone(), resolve_var_references(v, vars)))
+                .collect(),
+        ),
+        _ => value.clone(),
+    }
+}
+
 /// Recursively unescape strings in JSON data
 fn unescape_dfs(value: &Value) -> Value {
     match value {
@@ -106,33 +418,65 @@ fn unescape_dfs(value: &Value) -> Value {
         Value::Array(arr) => {
             Value::Array(arr.iter().map(unescape_dfs).collect())
         }
-        Value::String(s) => {
-            // Simple unescape - replace common escape sequences
-            let unescaped = s.replace("\\n", "\n")
-                .replace("\\t", "\t")
-                .replace("\\r", "\r")
-                .replace("\\\\", "\\")
-                .replace("\\\"", "\"")
-                .replace("\\'", "'");
-            Value::String(unescaped)
-        }
+        Value::String(s) => Value::String(unescape_str(s)),
         _ => value.clone(),
     }
 }
 
+/// Decode common escape sequences in a single pass, so `\\n` stays a
+/// backslash followed by `n`
+fn unescape_str(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(other @ ('\\' | '"' | '\'')) => result.push(other),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Quote a string for GOS output according to the escape policy
+///
+/// Every quoted string of the output goes through here, so the policy applies
+/// to values, op defaults and conditions alike.
+fn quote_str(s: &str) -> String {
+    let policy = OPTIONS.with(|opts| opts.borrow().escape_policy);
+    let escaped = match policy {
+        EscapePolicy::Reescape => s
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\t', "\\t")
+            .replace('\r', "\\r"),
+        EscapePolicy::Keep | EscapePolicy::Unescape => s.to_string(),
+    };
+    format!("'{}'", escaped.replace('\'', "\\'"))
+}
+
 /// Main decompilation function for standard JSON format
-fn decompile_std(std_data: &Value) -> Result<String, String> {
+fn decompile_std(std_data: &Value, buffer: &mut dyn Sink) -> Result<(), String> {
     if !std_data.is_object() {
         return Err("Decompile input must be a JSON object".to_string());
     }
     
-    let mut buffer = String::new();
-    
+
     // Handle graphs
     if let Some(graphs) = std_data.get("graphs") {
         if let Some(graphs_array) = graphs.as_array() {
             for (index, graph) in graphs_array.iter().enumerate() {
-                decompile_graph(&mut buffer, graph)?;
+                decompile_graph(buffer, graph)?;
                 if index < graphs_array.len() - 1 {
                     buffer.push_str("\n\n");
                 }
@@ -146,7 +490,7 @@ fn decompile_std(std_data: &Value) -> Result<String, String> {
     if let Some(ops) = std_data.get("ops") {
         if let Some(ops_array) = ops.as_array() {
             for (index, op) in ops_array.iter().enumerate() {
-                decompile_op(&mut buffer, op)?;
+                decompile_op(buffer, op)?;
                 if index < ops_array.len() - 1 {
                     buffer.push_str("\n\n");
                 }
@@ -159,20 +503,24 @@ fn decompile_std(std_data: &Value) -> Result<String, String> {
         if let Some(nodes_obj) = nodes.as_object() {
             for (node_as, node) in nodes_obj {
                 let decompiler = NodeDecompiler::new(node_as, node);
-                decompiler.decompile(&mut buffer)?;
+                decompiler.decompile(buffer)?;
             }
         }
     }
     
-    Ok(buffer)
+    Ok(())
 }
 
 /// Decompile a single graph
-fn decompile_graph(buffer: &mut String, graph: &Value) -> Result<(), String> {
+fn decompile_graph(buffer: &mut dyn Sink, graph: &Value) -> Result<(), String> {
     if !graph.is_object() {
         return Err("Graph must be a JSON object".to_string());
     }
     
+    if let Some(metas) = graph.get("metas").and_then(|v| v.as_object()) {
+        buffer.push_str(&annotations_str(metas));
+    }
+
     let template_graph = graph.get("template_graph").and_then(|v| v.as_str());
     
     if let Some(tpl) = template_graph {
@@ -194,6 +542,9 @@ fn decompile_graph(buffer: &mut String, graph: &Value) -> Result<(), String> {
     if let Some(props) = graph.get("property") {
         indent(buffer, options.indent);
         let mut param_formatter = ParamFormatter::new(props, ',');
+        if let Some(sets) = graph.get("set_property").and_then(|v| v.as_array()) {
+            param_formatter.sets = sets.iter().filter_map(|name| name.as_str()).collect();
+        }
         param_formatter.format(buffer, options.indent)?;
         buffer.push(';');
     }
@@ -239,7 +590,7 @@ impl<'a> NodeDecompiler<'a> {
         Self { node_as, node }
     }
     
-    fn decompile(&self, buffer: &mut String) -> Result<(), String> {
+    fn decompile(&self, buffer: &mut dyn Sink) -> Result<(), String> {
         let options = OPTIONS.with(|opts| opts.borrow().clone());
         
         // Check for outputs
@@ -249,12 +600,14 @@ impl<'a> NodeDecompiler<'a> {
         
         indent(buffer, options.indent);
         
-        let output_key = outputs.iter()
+        let output_names = outputs.iter()
             .filter_map(|v| v.as_str())
-            .collect::<Vec<_>>()
-            .join(",");
+            .collect::<Vec<_>>();
+        let output_key = output_names.join(",");
         
-        let has_as = output_key != self.node_as;
+        // A single-output node keyed by its output needs no alias, while a
+        // multi-output node always names itself with `.as(...)`
+        let has_as = output_names.len() > 1 || output_key != self.node_as;
         
         // Handle outputs
         if has_as {
@@ -275,7 +628,7 @@ impl<'a> NodeDecompiler<'a> {
             if !for_loop.is_empty() 
                 && for_loop.get("inputs").is_some() 
                 && for_loop.get("outputs").is_some() {
-                return self.for_loop(for_loop, buffer);
+                return self.for_loop(for_loop, buffer, has_as);
             }
         }
         
@@ -292,41 +645,45 @@ impl<'a> NodeDecompiler<'a> {
         Ok(())
     }
     
-    fn for_loop(&self, for_loop: &serde_json::Map<String, Value>, buffer: &mut String) -> Result<(), String> {
+    fn for_loop(&self, for_loop: &serde_json::Map<String, Value>, buffer: &mut dyn Sink, has_as: bool) -> Result<(), String> {
         buffer.push('[');
-        self.node_block(buffer, true)?; // has_as is true for for loops
+        self.node_block(buffer, has_as)?;
         
         let for_inputs = for_loop.get("inputs").and_then(|v| v.as_str()).unwrap_or("");
         let for_outputs = for_loop.get("outputs");
         
-        let for_outputs_str = if let Some(outputs) = for_outputs {
-            if let Some(arr) = outputs.as_array() {
-                arr.iter().filter_map(|v| v.as_str()).collect::<Vec<_>>().join(", ")
-            } else if let Some(s) = outputs.as_str() {
-                s.to_string()
-            } else {
-                String::new()
-            }
-        } else {
-            String::new()
+        // Outputs may be given either as a single string or as an array of strings
+        let for_outputs_str = match for_outputs {
+            Some(Value::Array(arr)) => arr.iter()
+                .filter_map(|v| v.as_str())
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>()
+                .join(", "),
+            Some(Value::String(s)) => s.trim().to_string(),
+            _ => String::new(),
         };
+        if for_outputs_str.is_empty() {
+            return Err(format!("For loop of node {} has no outputs", self.node_as));
+        }
         
         let options = OPTIONS.with(|opts| opts.borrow().clone());
-        let indent_ = options.indent * 2;
-        indent(buffer, indent_);
-        buffer.push_str(&format!("for {} in {}", for_outputs_str, for_inputs));
+        for_loop_break(buffer, options.indent * 2);
+        buffer.push_str(&format!("for {} in {}", for_outputs_str, for_inputs.trim()));
         
         if let Some(for_condition) = for_loop.get("condition").and_then(|v| v.as_str()) {
-            let indent_ = options.indent * 2;
-            indent(buffer, indent_);
-            buffer.push_str(&format!("if {}", for_condition));
+            let for_condition = for_condition.trim();
+            if !for_condition.is_empty() {
+                for_loop_break(buffer, options.indent * 2);
+                buffer.push_str(&format!("if {}", condition_str(for_condition)));
+            }
         }
         
         buffer.push_str("];");
         Ok(())
     }
     
-    fn condition_node(&self, buffer: &mut String) -> Result<(), String> {
+    fn condition_node(&self, buffer: &mut dyn Sink) -> Result<(), String> {
         let condition = self.node.get("condition")
             .and_then(|v| v.as_str())
             .ok_or_else(|| format!("Condition node {} must have string condition", self.node_as))?;
@@ -349,11 +706,17 @@ impl<'a> NodeDecompiler<'a> {
         Ok(())
     }
     
-    fn node_block(&self, buffer: &mut String, has_as: bool) -> Result<(), String> {
-        self.node_block_from_value(self.node, buffer, has_as, self.node_as)
+    fn node_block(&self, buffer: &mut dyn Sink, has_as: bool) -> Result<(), String> {
+        self.node_block_from_value(self.node, buffer, has_as, self.alias_name())
+    }
+
+    /// Name used in `.as(...)`: a key joined from several outputs is
+    /// aliased by its first output, the same key the compiler would assign
+    fn alias_name(&self) -> &'a str {
+        self.node_as.split(',').next().unwrap_or(self.node_as)
     }
     
-    fn node_block_from_value(&self, node: &Value, buffer: &mut String, has_as: bool, node_as: &str) -> Result<(), String> {
+    fn node_block_from_value(&self, node: &Value, buffer: &mut dyn Sink, has_as: bool, node_as: &str) -> Result<(), String> {
         let options = OPTIONS.with(|opts| opts.borrow().clone());
         
         let name = if let Some(ref_graph) = node.get("ref_graph").and_then(|v| v.as_str()) {
@@ -371,9 +734,15 @@ impl<'a> NodeDecompiler<'a> {
         // Handle inputs
         if let Some(inputs) = node.get("input") {
             if let Some(inputs_array) = inputs.as_array() {
-                // Handle array inputs
+                // Handle array inputs, a nested array is a `(a, b)` group
                 let input_strings: Vec<String> = inputs_array.iter()
-                    .filter_map(|v| v.as_str().map(String::from))
+                    .filter_map(|v| match v {
+                        Value::Array(group) => {
+                            let names: Vec<&str> = group.iter().filter_map(|name| name.as_str()).collect();
+                            Some(format!("({})", names.join(",")))
+                        }
+                        _ => v.as_str().map(String::from),
+                    })
                     .collect();
                 let input_refs: Vec<&str> = input_strings.iter().map(|s| s.as_str()).collect();
                 let _col = self.indent_inputs(&input_refs, options.indent * 2, ",", buffer);
@@ -398,10 +767,13 @@ impl<'a> NodeDecompiler<'a> {
         if let Some(attrs) = node.get("attrs").and_then(|v| v.as_array()) {
             for attr in attrs {
                 if let Some(attr_obj) = attr.as_object() {
-                    if let (Some(key), Some(value)) = (attr_obj.get("key"), attr_obj.get("value")) {
-                        if let (Some(key_str), Some(value_str)) = (key.as_str(), value.as_str()) {
-                            self.indent_str(buffer, &format!(".{}({})", key_str, value_str), 0);
-                        }
+                    if let (Some(key_str), Some(value)) = (attr_obj.get("key").and_then(|k| k.as_str()), attr_obj.get("value")) {
+                        // String values are written as is, anything else as a literal
+                        let value_str = match value.as_str() {
+                            Some(value_str) => value_str.to_string(),
+                            None => ParamFormatter::new(value, ',').format_value(value),
+                        };
+                        self.indent_str(buffer, &format!(".{}({})", key_str, value_str), 0);
                     }
                 }
             }
@@ -469,7 +841,7 @@ impl<'a> NodeDecompiler<'a> {
         Ok(())
     }
     
-    fn indent_list(&self, inputs: &[&str], col: usize, delimiter: &str, buffer: &mut String) -> usize {
+    fn indent_list(&self, inputs: &[&str], col: usize, delimiter: &str, buffer: &mut dyn Sink) -> usize {
         let options = OPTIONS.with(|opts| opts.borrow().clone());
         let candidate = inputs.join(delimiter);
         
@@ -493,7 +865,7 @@ impl<'a> NodeDecompiler<'a> {
         }
     }
     
-    fn indent_inputs(&self, inputs: &[&str], col: usize, delimiter: &str, buffer: &mut String) -> usize {
+    fn indent_inputs(&self, inputs: &[&str], col: usize, delimiter: &str, buffer: &mut dyn Sink) -> usize {
         let options = OPTIONS.with(|opts| opts.borrow().clone());
         let candidate: String = inputs.iter()
             .map(|&item| self.str_input(item))
@@ -525,7 +897,7 @@ impl<'a> NodeDecompiler<'a> {
         data.to_string()
     }
     
-    fn indent_str(&self, buffer: &mut String, input: &str, col: usize) -> usize {
+    fn indent_str(&self, buffer: &mut dyn Sink, input: &str, col: usize) -> usize {
         let options = OPTIONS.with(|opts| opts.borrow().clone());
         
         if col + input.len() > options.max_col && options.indent > 0 {
@@ -544,18 +916,24 @@ impl<'a> NodeDecompiler<'a> {
 struct ParamFormatter<'a> {
     inputs: &'a Value,
     delimiter: char,
+    /// Keys whose array values are written as set literals
+    sets: Vec<&'a str>,
 }
 
 impl<'a> ParamFormatter<'a> {
     fn new(inputs: &'a Value, delimiter: char) -> Self {
-        Self { inputs, delimiter }
+        Self { inputs, delimiter, sets: Vec::new() }
     }
     
-    fn format(&mut self, buffer: &mut String, col: usize) -> Result<usize, String> {
+    fn format(&mut self, buffer: &mut dyn Sink, col: usize) -> Result<usize, String> {
         if let Some(obj) = self.inputs.as_object() {
             let mut strings = Vec::new();
             for (k, v) in obj {
-                strings.push(format!("{}={}", k, self.format_value(v)));
+                let value = match v {
+                    Value::Array(items) if !items.is_empty() && self.sets.contains(&k.as_str()) => self.format_set(items),
+                    _ => self.format_value(v),
+                };
+                strings.push(format!("{}={}", k, value));
             }
             
             let candidate = strings.iter()
@@ -569,7 +947,7 @@ impl<'a> ParamFormatter<'a> {
                 let mut current_col = col;
                 for (i, (k, v)) in obj.iter().enumerate() {
                     current_col += strings[i].len() + 1;
-                    if current_col > options.max_col {
+                    if current_col > options.max_col && !self.sets.contains(&k.as_str()) {
                         let key = format!("{}=", k);
                         buffer.push_str(&key);
                         current_col = self.dfs(buffer, v, col + key.len(), 0)?;
@@ -591,7 +969,7 @@ impl<'a> ParamFormatter<'a> {
         }
     }
     
-    fn dfs(&mut self, buffer: &mut String, input: &Value, col: usize, deep: usize) -> Result<usize, String> {
+    fn dfs(&mut self, buffer: &mut dyn Sink, input: &Value, col: usize, deep: usize) -> Result<usize, String> {
         match input {
             Value::Object(obj) => self.dict(buffer, obj, col, deep + 1),
             Value::Array(arr) => self.list(buffer, arr, col, deep + 1),
@@ -603,9 +981,9 @@ impl<'a> ParamFormatter<'a> {
         }
     }
     
-    fn dict(&mut self, buffer: &mut String, inputs: &serde_json::Map<String, Value>, col: usize, deep: usize) -> Result<usize, String> {
+    fn dict(&mut self, buffer: &mut dyn Sink, inputs: &serde_json::Map<String, Value>, col: usize, deep: usize) -> Result<usize, String> {
         let strings: Vec<String> = inputs.iter()
-            .map(|(k, v)| format!("{}: {}", k, self.format_value(v)))
+            .map(|(k, v)| format!("{}: {}", quote_str(k), self.format_value(v)))
             .collect();
         
         let candidate = strings.join(",");
@@ -621,7 +999,7 @@ impl<'a> ParamFormatter<'a> {
                 current_col += strings[i].len() + 1;
                 
                 if current_col > options.max_col {
-                    let key = format!("{}: ", k);
+                    let key = format!("{}: ", quote_str(k));
                     buffer.push_str(&key);
                     current_col = self.dfs(buffer, v, col + options.indent + key.len(), deep + 1)?;
                 } else {
@@ -642,7 +1020,7 @@ impl<'a> ParamFormatter<'a> {
         Ok(current_col + 1)
     }
     
-    fn list(&mut self, buffer: &mut String, inputs: &[Value], col: usize, deep: usize) -> Result<usize, String> {
+    fn list(&mut self, buffer: &mut dyn Sink, inputs: &[Value], col: usize, deep: usize) -> Result<usize, String> {
         let strings: Vec<String> = inputs.iter()
             .map(|v| self.format_value(v))
             .collect();
@@ -675,19 +1053,38 @@ impl<'a> ParamFormatter<'a> {
         Ok(current_col + 1)
     }
     
+    /// `{a,b}` in array order, a single item keeps its trailing comma: `{a,}`
+    fn format_set(&self, items: &[Value]) -> String {
+        let items: Vec<String> = items.iter().map(|item| self.format_value(item)).collect();
+        if items.len() == 1 {
+            format!("{{{},}}", items[0])
+        } else {
+            format!("{{{}}}", items.join(","))
+        }
+    }
+    
     fn format_value(&self, value: &Value) -> String {
         match value {
-            Value::String(s) => format!("'{}'", s.replace('\'', "\\'")),
+            Value::String(s) => quote_str(s),
             Value::Number(n) => n.to_string(),
             Value::Bool(b) => b.to_string(),
             Value::Null => "null".to_string(),
-            _ => value.to_string(),
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| self.format_value(item)).collect();
+                format!("[{}]", items.join(","))
+            }
+            Value::Object(map) => {
+                let items: Vec<String> = map.iter()
+                    .map(|(k, v)| format!("{}:{}", quote_str(k), self.format_value(v)))
+                    .collect();
+                format!("{{{}}}", items.join(","))
+            }
         }
     }
 }
 
 /// Decompile an operation definition
-fn decompile_op(buffer: &mut String, op: &Value) -> Result<(), String> {
+fn decompile_op(buffer: &mut dyn Sink, op: &Value) -> Result<(), String> {
     if !op.is_object() {
         return Err("Operation must be a JSON object".to_string());
     }
@@ -709,9 +1106,12 @@ fn decompile_op(buffer: &mut String, op: &Value) -> Result<(), String> {
         indent(buffer, options.indent);
         buffer.push_str("meta {");
         
+        // Meta entries are statements, each ends with `;`
         let meta_value = Value::Object(copy_meta);
-        let mut param_formatter = ParamFormatter::new(&meta_value, ',');
+        indent(buffer, options.indent * 2);
+        let mut param_formatter = ParamFormatter::new(&meta_value, ';');
         param_formatter.format(buffer, options.indent * 2)?;
+        buffer.push(';');
         
         if options.indent > 0 {
             buffer.push('\n');
@@ -793,7 +1193,7 @@ fn decompile_op(buffer: &mut String, op: &Value) -> Result<(), String> {
 }
 
 /// Format operation specification
-fn op_spec_format(inputs: &serde_json::Map<String, Value>, buffer: &mut String, col: usize) -> Result<(), String> {
+fn op_spec_format(inputs: &serde_json::Map<String, Value>, buffer: &mut dyn Sink, col: usize) -> Result<(), String> {
     let options = OPTIONS.with(|opts| opts.borrow().clone());
     
     for (i, (name, spec)) in inputs.iter().enumerate() {
@@ -809,15 +1209,12 @@ fn op_spec_format(inputs: &serde_json::Map<String, Value>, buffer: &mut String,
                     "length" | "range" => {
                         op_length_range_format(v)
                     },
-                    "choice" => {
-                        if let Some(choices) = v.as_array() {
-                            let choices_str: Vec<String> = choices.iter()
-                                .map(|c| format!("'{}'", c.as_str().unwrap_or(&c.to_string())))
-                                .collect();
-                            format!("({})", choices_str.join(","))
-                        } else {
-                            format!("'{}'", v.as_str().unwrap_or(&v.to_string()))
-                        }
+                    "default" => {
+                        op_default_format(v)
+                    },
+                    // A tuple as written, numbers unquoted and `('a',)` for one choice
+                    "choice" if v.is_array() => {
+                        op_default_format(v)
                     },
                     _ => {
                         format!("'{}'", v.as_str().unwrap_or(&v.to_string()))
@@ -842,16 +1239,18 @@ fn op_spec_format(inputs: &serde_json::Map<String, Value>, buffer: &mut String,
 
 /// Format length/range specification
 fn op_length_range_format(inputs: &Value) -> String {
-    if let Some(eq) = inputs.get("eq").and_then(|v| v.as_i64()) {
-        return eq.to_string();
+    // Bounds are printed from the JSON number, so `-5` and `0.0` keep their form
+    let bound = |key: &str| inputs.get(key).filter(|v| v.is_number()).map(Value::to_string);
+    if let Some(eq) = bound("eq") {
+        return eq;
     }
     
     let mut result = String::new();
     
     // Handle lower bound
-    if let Some(ge) = inputs.get("ge").and_then(|v| v.as_i64()) {
+    if let Some(ge) = bound("ge") {
         result.push_str(&format!("[{}", ge));
-    } else if let Some(gt) = inputs.get("gt").and_then(|v| v.as_i64()) {
+    } else if let Some(gt) = bound("gt") {
         result.push_str(&format!("({}", gt));
     } else {
         result.push('[');
@@ -860,9 +1259,9 @@ fn op_length_range_format(inputs: &Value) -> String {
     result.push(',');
     
     // Handle upper bound
-    if let Some(le) = inputs.get("le").and_then(|v| v.as_i64()) {
+    if let Some(le) = bound("le") {
         result.push_str(&format!("{}]", le));
-    } else if let Some(lt) = inputs.get("lt").and_then(|v| v.as_i64()) {
+    } else if let Some(lt) = bound("lt") {
         result.push_str(&format!("{})", lt));
     } else {
         result.push(']');
@@ -871,6 +1270,47 @@ fn op_length_range_format(inputs: &Value) -> String {
     result
 }
 
+/// Format an op spec default value, rendering collections as tuples
+fn op_default_format(value: &Value) -> String {
+    match value {
+        Value::String(s) => quote_str(s),
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(op_default_format).collect();
+            if items.len() == 1 {
+                format!("({},)", items[0])
+            } else {
+                format!("({})", items.join(","))
+            }
+        }
+        Value::Object(map) => {
+            let items: Vec<String> = map.iter()
+                .map(|(k, v)| format!("{}:{}", quote_str(k), op_default_format(v)))
+                .collect();
+            format!("{{{}}}", items.join(","))
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// Format graph metas as the `@name(args) ` annotations they were compiled from
+fn annotations_str(metas: &serde_json::Map<String, Value>) -> String {
+    let mut result = String::new();
+    for (name, value) in metas {
+        result.push('@');
+        result.push_str(name);
+        match value {
+            Value::Bool(true) => {}
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(op_default_format).collect();
+                result.push_str(&format!("({})", items.join(",")));
+            }
+            _ => result.push_str(&format!("({})", op_default_format(value))),
+        }
+        result.push(' ');
+    }
+    result
+}
+
 /// Helper function to format input strings
 fn input_str(inputs: &Value) -> String {
     match inputs {
@@ -885,13 +1325,22 @@ fn input_str(inputs: &Value) -> String {
     }
 }
 
-/// Check if identifier is valid
+/// Check if identifier is valid as written
+fn is_plain_id(value: &str) -> bool {
+    Regex::new(VALID_IDENTIFIER).unwrap().is_match(value)
+}
+
+/// Write an identifier, in backticks when it is not a plain identifier
+///
+/// Only names a quoted identifier cannot hold, empty or with a backtick or a
+/// newline, are errors.
 fn check_id(value: &str) -> Result<String, String> {
-    let re = Regex::new(VALID_IDENTIFIER).unwrap();
-    if re.is_match(value) {
+    if is_plain_id(value) {
         Ok(value.to_string())
-    } else {
+    } else if value.is_empty() || value.contains(['`', '\n']) {
         Err(format!("Invalid identifier: {}", value))
+    } else {
+        Ok(format!("`{}`", value))
     }
 }
 
@@ -905,8 +1354,27 @@ fn check_version(value: &str) -> Result<String, String> {
     }
 }
 
+/// Break a for loop clause onto a new indented line, or separate it by a
+/// single space when indentation is disabled
+fn for_loop_break(buffer: &mut dyn Sink, spaces: usize) {
+    if spaces > 0 {
+        indent(buffer, spaces);
+    } else {
+        buffer.push(' ');
+    }
+}
+
+/// Format a condition, quoting it unless it is a plain (dotted) identifier
+fn condition_str(condition: &str) -> String {
+    if is_plain_id(condition) {
+        condition.to_string()
+    } else {
+        quote_str(condition)
+    }
+}
+
 /// Add indentation to buffer
-fn indent(buffer: &mut String, spaces: usize) {
+fn indent(buffer: &mut dyn Sink, spaces: usize) {
     if spaces > 0 {
         buffer.push('\n');
         for _ in 0..spaces {
@@ -951,6 +1419,9 @@ mod tests {
         assert!(check_id("valid_id").is_ok());
         assert!(check_id("valid-id").is_ok());
         assert!(check_id("valid$id").is_ok());
-        assert!(check_id("123invalid").is_err());
+        assert_eq!(check_id("123invalid").unwrap(), "`123invalid`");
+        assert_eq!(check_id("my node").unwrap(), "`my node`");
+        assert!(check_id("bad`name").is_err());
+        assert!(check_id("").is_err());
     }
 }
\ No newline at end of file
@@ -0,0 +1,227 @@
+//! Module dependency graph
+//!
+//! `module_dependency_graph` links the graphs of a module to the graphs,
+//! imports and vars they reference, and vars to the vars they reference.
+//! Unlike the node wiring inside a graph, this is a module-wide view, e.g. to
+//! find every graph affected by a change to a var.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ast::*;
+
+/// Kind of a dependency graph node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DepNodeKind {
+    Graph,
+    Import,
+    Var,
+}
+
+/// Kind of a dependency edge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DepEdgeKind {
+    /// `graph_alias.output`, `ref(graph_alias(...))` or a template graph
+    GraphRef,
+    /// An op from an import, e.g. `ops.load(x)` with `import custom.ops as ops;`
+    Import,
+    /// A var attribute, `alias.attr` or a bare attribute of a var without alias
+    VarUse,
+}
+
+/// A graph, import or var of the module
+///
+/// Graphs and vars are named by their alias, an anonymous graph is named
+/// `#<index>` by its position among the module's graphs and the attributes of
+/// a var without alias are nodes of their own. Imports are named by their
+/// alias, or their path without one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepNode {
+    pub kind: DepNodeKind,
+    pub name: String,
+}
+
+/// `from` depends on `to`, both indexes into `DepGraph::nodes`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepEdge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: DepEdgeKind,
+}
+
+/// Dependencies between the graphs, imports and vars of a module
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepGraph {
+    pub nodes: Vec<DepNode>,
+    /// Deduplicated, in the order the references appear
+    pub edges: Vec<DepEdge>,
+}
+
+impl DepGraph {
+    /// Index of the node of `kind` named `name`
+    pub fn find(&self, kind: DepNodeKind, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.kind == kind && node.name == name)
+    }
+
+    /// Nodes that the nodes named `name` depend on, with the edge kind
+    pub fn dependencies_of(&self, name: &str) -> Vec<(&DepNode, DepEdgeKind)> {
+        self.edges
+            .iter()
+            .filter(|edge| self.nodes[edge.from].name == name)
+            .map(|edge| (&self.nodes[edge.to], edge.kind))
+            .collect()
+    }
+
+    /// Nodes that depend on the nodes named `name`, with the edge kind
+    pub fn dependents_of(&self, name: &str) -> Vec<(&DepNode, DepEdgeKind)> {
+        self.edges
+            .iter()
+            .filter(|edge| self.nodes[edge.to].name == name)
+            .map(|edge| (&self.nodes[edge.from], edge.kind))
+            .collect()
+    }
+
+    fn add_node(&mut self, kind: DepNodeKind, name: &str) -> usize {
+        match self.find(kind, name) {
+            Some(index) => index,
+            None => {
+                self.nodes.push(DepNode { kind, name: name.to_string() });
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize, kind: DepEdgeKind) {
+        let edge = DepEdge { from, to, kind };
+        if from != to && !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+    }
+}
+
+/// Collects the referencing symbols of a subtree
+#[derive(Default)]
+struct References {
+    symbols: Vec<(SymbolKind, String)>,
+}
+
+impl Visitor<'_> for References {
+    fn visit_symbol(&mut self, symbol: &Symbol) {
+        match symbol.kind {
+            SymbolKind::NodeName
+            | SymbolKind::NodeInput
+            | SymbolKind::NodeParamValue
+            | SymbolKind::NodeDepend
+            | SymbolKind::VarRef
+            | SymbolKind::RefGraphName
+            | SymbolKind::GraphTemplate => self.symbols.push((symbol.kind, symbol.name.clone())),
+            _ => {}
+        }
+    }
+}
+
+fn references(node: &AstNodeEnum) -> Vec<(SymbolKind, String)> {
+    let mut references = References::default();
+    node.walk(&mut references);
+    references.symbols
+}
+
+/// Build the dependency graph of a module
+///
+/// A dotted reference is matched on its first segment, e.g. `main.a` against
+/// a var or graph `main`, and an op name against import aliases and paths,
+/// e.g. `ops.load` against `ops`. A bare reference only matches an attribute
+/// of a var without alias, and not when the graph defines the same name.
+pub fn module_dependency_graph(module: &Module) -> DepGraph {
+    let mut graph = DepGraph::default();
+    let mut sources: Vec<(usize, &AstNodeEnum, Vec<String>)> = Vec::new();
+
+    let mut graph_index = 0;
+    for child in &module.children {
+        match child {
+            AstNodeEnum::Import(import) => {
+                for item in &import.items {
+                    let name = item.alias.as_ref().unwrap_or(&item.path);
+                    graph.add_node(DepNodeKind::Import, &name.name);
+                }
+            }
+            AstNodeEnum::VarDef(var_def) => match &var_def.alias {
+                Some(alias) => {
+                    let index = graph.add_node(DepNodeKind::Var, &alias.name);
+                    let local = var_def
+                        .children
+                        .iter()
+                        .filter_map(|child| match child {
+                            AstNodeEnum::AttrDef(attr) => Some(attr.name.name.clone()),
+                            _ => None,
+                        })
+                        .collect();
+                    sources.push((index, child, local));
+                }
+                None => {
+                    for attr_child in &var_def.children {
+                        if let AstNodeEnum::AttrDef(attr) = attr_child {
+                            let index = graph.add_node(DepNodeKind::Var, &attr.name.name);
+                            sources.push((index, attr_child, Vec::new()));
+                        }
+                    }
+                }
+            },
+            AstNodeEnum::GraphDef(graph_def) => {
+                let name = match &graph_def.alias {
+                    Some(alias) => alias.name.clone(),
+                    None => format!("#{}", graph_index),
+                };
+                graph_index += 1;
+                let index = graph.add_node(DepNodeKind::Graph, &name);
+                sources.push((index, child, graph_local_names(graph_def)));
+            }
+            _ => {}
+        }
+    }
+
+    for (from, node, local) in sources {
+        for (kind, name) in references(node) {
+            if let Some((to, edge_kind)) = resolve_reference(&graph, kind, &name, &local) {
+                graph.add_edge(from, to, edge_kind);
+            }
+        }
+    }
+    graph
+}
+
+/// Names a graph defines, which shadow bare var references
+fn graph_local_names(graph_def: &GraphDef) -> Vec<String> {
+    let mut names = Vec::new();
+    for child in &graph_def.children {
+        match child {
+            AstNodeEnum::AttrDef(attr) => names.push(attr.name.name.clone()),
+            AstNodeEnum::RefDef(ref_def) => names.push(ref_def.name.name.clone()),
+            AstNodeEnum::NodeDef(node_def) => names.extend(node_def.outputs.iter().map(|s| s.name.clone())),
+            AstNodeEnum::ConditionDef(cond_def) => names.extend(cond_def.outputs.iter().map(|s| s.name.clone())),
+            _ => {}
+        }
+    }
+    names
+}
+
+fn resolve_reference(graph: &DepGraph, kind: SymbolKind, name: &str, local: &[String]) -> Option<(usize, DepEdgeKind)> {
+    if kind == SymbolKind::NodeName {
+        // `custom.ops.load` may come from `custom` or `custom.ops`
+        return name
+            .match_indices('.')
+            .find_map(|(end, _)| graph.find(DepNodeKind::Import, &name[..end]))
+            .map(|index| (index, DepEdgeKind::Import));
+    }
+    if matches!(kind, SymbolKind::RefGraphName | SymbolKind::GraphTemplate) {
+        return graph.find(DepNodeKind::Graph, name).map(|index| (index, DepEdgeKind::GraphRef));
+    }
+    match name.split_once('.') {
+        // Var references take precedence over graph references, as in the compiler
+        Some((prefix, _)) => graph
+            .find(DepNodeKind::Var, prefix)
+            .map(|index| (index, DepEdgeKind::VarUse))
+            .or_else(|| graph.find(DepNodeKind::Graph, prefix).map(|index| (index, DepEdgeKind::GraphRef))),
+        None if local.iter().any(|local| local == name) => None,
+        None => graph.find(DepNodeKind::Var, name).map(|index| (index, DepEdgeKind::VarUse)),
+    }
+}
@@ -0,0 +1,136 @@
+//! Structural diff of compiled results
+//!
+//! `diff_compile_results` compares two `CompileResult`s entry by entry rather
+//! than as JSON text, so the order of graphs, ops, nodes and map keys does not
+//! matter. Graphs are matched by alias and ops by their `as` meta, anonymous
+//! ones by their position among the anonymous ones.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::compiler::{CompileResult, GraphDict, OpDict};
+
+/// What happened to the entry at a `CompileDiff` path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DiffKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One difference between two compiled results
+///
+/// `path` locates the entry like a JSON path, with graphs and ops named by
+/// alias, e.g. `graphs.main.nodes.node1`, `graphs.main.properties.mode`,
+/// `ops.my_op` or `vars.config.limit`. An anonymous graph or op is `graphs[0]`
+/// or `ops[0]`, counting only the anonymous ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompileDiff {
+    pub kind: DiffKind,
+    pub path: String,
+    /// Value in the first result, `None` when added
+    pub old: Option<Value>,
+    /// Value in the second result, `None` when removed
+    pub new: Option<Value>,
+}
+
+/// Differences from `a` to `b`, sorted by path
+///
+/// Nodes and graph properties are compared one by one, any other graph field
+/// as a whole, e.g. `graphs.main.version`. Ops and vars are compared as a whole.
+pub fn diff_compile_results(a: &CompileResult, b: &CompileResult) -> Vec<CompileDiff> {
+    let mut diffs = Vec::new();
+
+    let graphs_a = keyed(a.graphs.iter().flatten(), "graphs", |graph| graph.alias.clone());
+    let graphs_b = keyed(b.graphs.iter().flatten(), "graphs", |graph| graph.alias.clone());
+    for path in union(&graphs_a, &graphs_b) {
+        match (graphs_a.get(&path), graphs_b.get(&path)) {
+            (Some(old), Some(new)) => diff_graphs(&path, old, new, &mut diffs),
+            (old, new) => push_diff(&mut diffs, path, old.map(to_value), new.map(to_value)),
+        }
+    }
+
+    let ops_a = keyed(a.ops.iter().flatten(), "ops", op_alias);
+    let ops_b = keyed(b.ops.iter().flatten(), "ops", op_alias);
+    for path in union(&ops_a, &ops_b) {
+        push_diff(&mut diffs, path.clone(), ops_a.get(&path).map(to_value), ops_b.get(&path).map(to_value));
+    }
+
+    let no_vars = HashMap::new();
+    diff_maps("vars", a.vars.as_deref().unwrap_or(&no_vars), b.vars.as_deref().unwrap_or(&no_vars), &mut diffs);
+
+    diffs.sort_by(|x, y| x.path.cmp(&y.path));
+    diffs
+}
+
+/// Entries by path, `<section>.<alias>` or `<section>[<index>]` without alias
+fn keyed<'a, T>(
+    items: impl Iterator<Item = &'a T>,
+    section: &str,
+    alias: impl Fn(&T) -> Option<String>,
+) -> HashMap<String, &'a T> {
+    let mut anonymous = 0;
+    items
+        .map(|item| match alias(item) {
+            Some(alias) => (format!("{}.{}", section, alias), item),
+            None => {
+                anonymous += 1;
+                (format!("{}[{}]", section, anonymous - 1), item)
+            }
+        })
+        .collect()
+}
+
+fn op_alias(op: &OpDict) -> Option<String> {
+    op.alias().map(str::to_string)
+}
+
+fn union<V>(a: &HashMap<String, V>, b: &HashMap<String, V>) -> BTreeSet<String> {
+    a.keys().chain(b.keys()).cloned().collect()
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+fn push_diff(diffs: &mut Vec<CompileDiff>, path: String, old: Option<Value>, new: Option<Value>) {
+    let kind = match (&old, &new) {
+        (None, None) => return,
+        (None, Some(_)) => DiffKind::Added,
+        (Some(_), None) => DiffKind::Removed,
+        (Some(old), Some(new)) if old == new => return,
+        (Some(_), Some(_)) => DiffKind::Changed,
+    };
+    diffs.push(CompileDiff { kind, path, old, new });
+}
+
+fn diff_maps(prefix: &str, a: &HashMap<String, Value>, b: &HashMap<String, Value>, diffs: &mut Vec<CompileDiff>) {
+    for key in union(a, b) {
+        push_diff(diffs, format!("{}.{}", prefix, key), a.get(&key).cloned(), b.get(&key).cloned());
+    }
+}
+
+fn diff_graphs(path: &str, a: &GraphDict, b: &GraphDict, diffs: &mut Vec<CompileDiff>) {
+    let (Value::Object(a), Value::Object(b)) = (to_value(a), to_value(b)) else {
+        return;
+    };
+    let empty = Map::new();
+    for key in a.keys().chain(b.keys()).collect::<BTreeSet<_>>() {
+        let (old, new) = (a.get(key), b.get(key));
+        if key == "properties" || key == "nodes" {
+            let entries = |value: Option<&Value>| -> HashMap<String, Value> {
+                value
+                    .and_then(Value::as_object)
+                    .unwrap_or(&empty)
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.clone()))
+                    .collect()
+            };
+            diff_maps(&format!("{}.{}", path, key), &entries(old), &entries(new), diffs);
+        } else {
+            push_diff(diffs, format!("{}.{}", path, key), old.cloned(), new.cloned());
+        }
+    }
+}
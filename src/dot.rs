@@ -0,0 +1,85 @@
+//! Graphviz DOT rendering of compiled graphs
+//!
+//! Nodes are labeled by their op name, `ref(graph)` for a graph reference, and
+//! wired as in `CompileOptions::emit_edges`, from the nodes producing their
+//! inputs and the nodes they depend on.
+
+use std::collections::HashMap;
+
+use crate::compiler::{node_edges, CompileResult, GraphDict, NodeDict};
+
+/// Render a graph as a DOT `digraph`, named by the graph alias
+pub fn to_dot(graph: &GraphDict) -> String {
+    let mut out = match &graph.alias {
+        Some(alias) => format!("digraph {} {{\n", quote(alias)),
+        None => "digraph {\n".to_string(),
+    };
+    write_graph_body(&mut out, graph, "", "    ");
+    out.push_str("}\n");
+    out
+}
+
+/// Render every graph of a compiled module as a `subgraph cluster_<name>` of
+/// one `digraph`
+///
+/// Node ids are prefixed with the graph name, `main.a`, so that nodes of
+/// different graphs stay apart. An anonymous graph is named `#<index>` by its
+/// position among the module's graphs.
+pub fn module_to_dot(result: &CompileResult) -> String {
+    let mut out = "digraph {\n".to_string();
+    for (index, graph) in result.graphs.iter().flatten().enumerate() {
+        let name = graph.alias.clone().unwrap_or_else(|| format!("#{}", index));
+        out.push_str(&format!("    subgraph {} {{\n", quote(&format!("cluster_{}", name))));
+        out.push_str(&format!("        label={};\n", quote(&name)));
+        write_graph_body(&mut out, graph, &format!("{}.", name), "        ");
+        out.push_str("    }\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Node and edge statements of a graph, nodes sorted by key
+fn write_graph_body(out: &mut String, graph: &GraphDict, prefix: &str, indent: &str) {
+    let empty = HashMap::new();
+    let nodes = graph.nodes.as_ref().unwrap_or(&empty);
+    let mut keys: Vec<&String> = nodes.keys().collect();
+    keys.sort();
+    for key in keys {
+        let id = quote(&format!("{}{}", prefix, key));
+        out.push_str(&format!("{}{} [label={}];\n", indent, id, quote(&node_label(key, &nodes[key]))));
+    }
+    let edges = match &graph.edges {
+        Some(edges) => edges.clone(),
+        None => node_edges(nodes),
+    };
+    for (from, to) in edges {
+        let from = quote(&format!("{}{}", prefix, from));
+        let to = quote(&format!("{}{}", prefix, to));
+        out.push_str(&format!("{}{} -> {};\n", indent, from, to));
+    }
+}
+
+/// Op name, `ref(graph)` for a graph reference, else the node key
+fn node_label(key: &str, node: &NodeDict) -> String {
+    match (&node.op_name, &node.ref_graph) {
+        (Some(op_name), _) => op_name.clone(),
+        (None, Some(ref_graph)) => format!("ref({})", ref_graph),
+        (None, None) => key.to_string(),
+    }
+}
+
+/// A DOT double-quoted string
+fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
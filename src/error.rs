@@ -4,6 +4,8 @@
 //! providing detailed error information including position and context.
 
 use std::fmt;
+use std::fs;
+use std::io;
 use thiserror::Error;
 
 /// Parse error types
@@ -45,6 +47,14 @@ pub enum ParseError {
         suggestion: String,
     },
 
+    /// A likely mistake in input that is otherwise valid, e.g. mixed quote styles
+    #[error("Warning at line {line}, column {column}: {message}")]
+    Warning {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
     #[error("Unsupported feature: {feature} at line {line}, column {column}")]
     UnsupportedFeature {
         feature: String,
@@ -59,6 +69,13 @@ pub enum ParseError {
         column: usize,
     },
 
+    #[error("Circular import {} at line {line}, column {column}", .cycle.join(" -> "))]
+    CircularImport {
+        cycle: Vec<String>,
+        line: usize,
+        column: usize,
+    },
+
     #[error("Parse error: {message}")]
     General { message: String },
 
@@ -69,6 +86,14 @@ pub enum ParseError {
     Pest(String),
 }
 
+/// How serious a `ParseError` is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    /// The input is still understood, e.g. deprecated syntax
+    Warning,
+}
+
 impl ParseError {
     pub fn syntax_error(line: usize, column: usize, message: impl Into<String>) -> Self {
         Self::SyntaxError {
@@ -116,6 +141,14 @@ impl ParseError {
         }
     }
 
+    pub fn warning(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self::Warning {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
     pub fn unsupported_feature(
         feature: impl Into<String>,
         line: usize,
@@ -136,6 +169,10 @@ impl ParseError {
         }
     }
 
+    pub fn circular_import(cycle: Vec<String>, line: usize, column: usize) -> Self {
+        Self::CircularImport { cycle, line, column }
+    }
+
     pub fn general(message: impl Into<String>) -> Self {
         Self::General {
             message: message.into(),
@@ -150,12 +187,62 @@ impl ParseError {
             | ParseError::SemanticError { line, .. }
             | ParseError::DuplicateDefinition { line, .. }
             | ParseError::DeprecatedFeature { line, .. }
+            | ParseError::Warning { line, .. }
             | ParseError::UnsupportedFeature { line, .. }
-            | ParseError::InvalidValue { line, .. } => Some(*line),
+            | ParseError::InvalidValue { line, .. }
+            | ParseError::CircularImport { line, .. } => Some(*line),
             _ => None,
         }
     }
 
+    /// Whether parsing can go on after this error, skipping the statement it is in
+    ///
+    /// Errors scoped to one statement are recoverable. Lexical errors, I/O
+    /// errors, raw pest errors and circular imports are fatal: what follows
+    /// cannot be read reliably, or at all.
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            ParseError::SyntaxError { .. }
+            | ParseError::SemanticError { .. }
+            | ParseError::DuplicateDefinition { .. }
+            | ParseError::DeprecatedFeature { .. }
+            | ParseError::Warning { .. }
+            | ParseError::UnsupportedFeature { .. }
+            | ParseError::InvalidValue { .. }
+            | ParseError::General { .. } => true,
+            ParseError::LexicalError { .. }
+            | ParseError::CircularImport { .. }
+            | ParseError::Io(_)
+            | ParseError::Pest(_) => false,
+        }
+    }
+
+    /// `Warning` for deprecated features and warnings, `Error` for everything else
+    pub fn severity(&self) -> Severity {
+        match self {
+            ParseError::DeprecatedFeature { .. } | ParseError::Warning { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// Move the error by `offset` lines, for errors found in a slice of the source
+    pub fn shift_line(&mut self, offset: isize) {
+        match self {
+            ParseError::SyntaxError { line, .. }
+            | ParseError::LexicalError { line, .. }
+            | ParseError::SemanticError { line, .. }
+            | ParseError::DuplicateDefinition { line, .. }
+            | ParseError::DeprecatedFeature { line, .. }
+            | ParseError::Warning { line, .. }
+            | ParseError::UnsupportedFeature { line, .. }
+            | ParseError::InvalidValue { line, .. }
+            | ParseError::CircularImport { line, .. } => {
+                *line = line.saturating_add_signed(offset);
+            }
+            _ => {}
+        }
+    }
+
     /// Get the column number if available
     pub fn column(&self) -> Option<usize> {
         match self {
@@ -164,11 +251,47 @@ impl ParseError {
             | ParseError::SemanticError { column, .. }
             | ParseError::DuplicateDefinition { column, .. }
             | ParseError::DeprecatedFeature { column, .. }
+            | ParseError::Warning { column, .. }
             | ParseError::UnsupportedFeature { column, .. }
-            | ParseError::InvalidValue { column, .. } => Some(*column),
+            | ParseError::InvalidValue { column, .. }
+            | ParseError::CircularImport { column, .. } => Some(*column),
             _ => None,
         }
     }
+
+    /// Render the error with the offending source line and a caret under
+    /// its column, or just the message when the error has no position
+    pub fn render(&self, source: &str) -> String {
+        let Some(line) = self.line() else {
+            return self.to_string();
+        };
+        let Some(text) = source.lines().nth(line.saturating_sub(1)) else {
+            return self.to_string();
+        };
+        let column = self.column().unwrap_or(1).max(1);
+        let gutter = " ".repeat(line.to_string().len());
+        format!(
+            "{}\n{} --> {}:{}\n{} |\n{} | {}\n{} | {}^",
+            self,
+            gutter,
+            line,
+            column,
+            gutter,
+            line,
+            text,
+            gutter,
+            " ".repeat(column - 1)
+        )
+    }
+
+    /// Same as `render`, reading the source from `path`
+    pub fn render_from_file(&self, path: &str) -> io::Result<String> {
+        if self.line().is_none() {
+            return Ok(self.to_string());
+        }
+        let source = fs::read_to_string(path)?;
+        Ok(self.render(&source))
+    }
 }
 
 // Note: This implementation will be added when the parser module is complete
@@ -342,6 +465,22 @@ pub mod helpers {
         )
     }
 
+    pub fn duplicate_node_key(name: &str, line: usize, column: usize) -> ParseError {
+        ParseError::duplicate_definition(
+            format!("node '{}'", name),
+            line,
+            column,
+        )
+    }
+
+    pub fn duplicate_op_spec(section: &str, name: &str, line: usize, column: usize) -> ParseError {
+        ParseError::duplicate_definition(
+            format!("op {} '{}'", section, name),
+            line,
+            column,
+        )
+    }
+
     pub fn deprecated_node_syntax(line: usize, column: usize) -> ParseError {
         ParseError::deprecated_feature(
             "node definition syntax",
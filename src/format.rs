@@ -6,11 +6,23 @@
 #![allow(dead_code)]
 
 use crate::ast::*;
+use crate::error::ParseResult;
 use crate::parser::parse_gos;
+use crate::pragma::Pragma;
 use crate::ParseOptions;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
+/// Prefix of a line comment, see `Formatter::with_line_comment_prefix`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentPrefix {
+    /// `# comment`
+    Hash,
+    /// `// comment`
+    DoubleSlash,
+}
+
 /// GOS code formatting tool
 /// 
 /// # Arguments
@@ -18,18 +30,16 @@ use std::path::Path;
 /// * `indent` - Indentation size (default: 4)
 /// * `max_col` - Maximum column width (default: 100)
 /// 
+/// A `# gos:` pragma in `content` overrides `indent` and `max_col`.
+/// 
 /// # Returns
 /// Formatted GOS text string
 pub fn format_from_data(content: &str, indent: usize, max_col: usize) -> Result<String, Box<dyn std::error::Error>> {
-    let options = ParseOptions {
-        ast: true,
-        tracking: true,
-        ..Default::default()
-    };
-    
-    let parsed = parse_gos(content, options)?;
-    let formatter = Formatter::new(indent, max_col);
-    Ok(formatter.format(&parsed, 0))
+    let mut formatter = Formatter::new(indent, max_col);
+    if let Some(pragma) = Pragma::from_source(content)? {
+        formatter = formatter.with_pragma(&pragma);
+    }
+    Ok(formatter.format_str(content)?)
 }
 
 /// GOS code formatting tool for files
@@ -55,6 +65,30 @@ pub fn format(filename: &str, indent: usize, max_col: usize) -> Result<String, B
     format_from_data(&content, indent, max_col)
 }
 
+/// Most common indentation step of a GOS source, 4 when nothing is indented
+///
+/// Each line indented deeper than the previous non-blank line counts one step
+/// of the difference, e.g. 2 for a file nesting by two spaces. Ties go to the
+/// smaller step. Lines indented with tabs are not counted.
+pub fn detect_indent(content: &str) -> usize {
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    let mut previous = 0;
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        if line.starts_with('\t') {
+            continue;
+        }
+        let width = line.len() - line.trim_start_matches(' ').len();
+        if width > previous {
+            *counts.entry(width - previous).or_default() += 1;
+        }
+        previous = width;
+    }
+    counts
+        .into_iter()
+        .max_by(|(step_a, count_a), (step_b, count_b)| count_a.cmp(count_b).then(step_b.cmp(step_a)))
+        .map_or(4, |(step, _)| step)
+}
+
 /// Indent buffer for managing indented output
 /// 
 /// This corresponds to the Python IndentBuffer class, providing
@@ -110,18 +144,28 @@ impl IndentBuffer {
     pub fn writeln(&mut self, args: &[&str]) -> usize {
         let len = self.writes(args);
         if self.indent_size > 0 {
-            self.buffer.push('\n');
+            self.newline();
             len + 1
         } else {
             len
         }
     }
 
+    /// End the current line, dropping its trailing whitespace
+    ///
+    /// Only line ends written here are trimmed, a newline inside a written
+    /// `"""` string is part of its value.
+    fn newline(&mut self) {
+        let len = self.buffer.trim_end_matches([' ', '\t']).len();
+        self.buffer.truncate(len);
+        self.buffer.push('\n');
+    }
+
     /// Write with indentation and newline
     pub fn writeln_indent(&mut self, args: &[&str]) -> usize {
         let len = self.write_indent(args);
         if self.indent_size > 0 {
-            self.buffer.push('\n');
+            self.newline();
             len + 1
         } else {
             len
@@ -162,6 +206,24 @@ pub struct Formatter {
     indent: usize,
     max_col: usize,
     cur_col: usize,
+    /// Write `var {` rather than `var{`
+    space_before_brace: bool,
+    /// Put every call of a node chain on its own line once the chain does not fit
+    consistent_wrapping: bool,
+    /// Always put every call of a node chain on its own line
+    chain_per_line: bool,
+    /// End a list, tuple, set or dict written over several lines with a comma
+    trailing_comma_multiline: bool,
+    /// Write the items of an import sorted by path
+    sort_imports: bool,
+    /// Write `0xFF` or `1_000` in plain decimal
+    normalize_numbers: bool,
+    /// Write `True`, `FALSE` or `Null` as `true`, `false` or `null`
+    normalize_keywords: bool,
+    /// Write every line comment with this prefix
+    line_comment_prefix: Option<CommentPrefix>,
+    /// Widths measured while formatting the current tree
+    widths: Widths,
 }
 
 impl Formatter {
@@ -171,29 +233,131 @@ impl Formatter {
             indent,
             max_col,
             cur_col: 0,
+            space_before_brace: true,
+            consistent_wrapping: false,
+            chain_per_line: false,
+            trailing_comma_multiline: false,
+            sort_imports: false,
+            normalize_numbers: false,
+            normalize_keywords: false,
+            line_comment_prefix: None,
+            widths: Widths::default(),
         }
     }
 
-    /// Format an AST node
+    /// Indent and max_col set by a `# gos:` pragma, others left as they are
+    pub fn with_pragma(mut self, pragma: &Pragma) -> Self {
+        if let Some(indent) = pragma.indent {
+            self.indent = indent;
+        }
+        if let Some(max_col) = pragma.max_col {
+            self.max_col = max_col;
+        }
+        self
+    }
+
+    /// Whether to put a space between a block keyword and its brace, true by default
+    pub fn with_space_before_brace(mut self, space_before_brace: bool) -> Self {
+        self.space_before_brace = space_before_brace;
+        self
+    }
+
+    /// Whether a node chain past max_col puts each `.attr(...)` on its own line,
+    /// rather than leaving the chain on one line, false by default
+    pub fn with_consistent_wrapping(mut self, consistent_wrapping: bool) -> Self {
+        self.consistent_wrapping = consistent_wrapping;
+        self
+    }
+
+    /// Whether each `.attr(...)` of a node chain goes on its own line under the
+    /// node whatever the width, as the decompiler writes them, false by default
+    pub fn with_chain_per_line(mut self, chain_per_line: bool) -> Self {
+        self.chain_per_line = chain_per_line;
+        self
+    }
+
+    /// Whether a collection wrapped one item per line gets a comma after its
+    /// last item, false by default. Collections on one line never get one.
+    pub fn with_trailing_comma_multiline(mut self, trailing_comma_multiline: bool) -> Self {
+        self.trailing_comma_multiline = trailing_comma_multiline;
+        self
+    }
+
+    /// Whether to sort the items of each import by path, e.g. `import a, c as d, b;`
+    /// to `import a, b, c as d;`, false by default
+    pub fn with_sort_imports(mut self, sort_imports: bool) -> Self {
+        self.sort_imports = sort_imports;
+        self
+    }
+
+    /// Whether to write hex, binary and underscored integers such as `0xFF` or
+    /// `1_000` in plain decimal, false by default so numbers keep their form
+    pub fn with_normalize_numbers(mut self, normalize_numbers: bool) -> Self {
+        self.normalize_numbers = normalize_numbers;
+        self.widths.normalize_numbers = normalize_numbers;
+        self
+    }
+
+    /// Whether to write booleans and null in lower case, e.g. `True` as `true`,
+    /// false by default so they keep their case. `format_str` then also accepts
+    /// them in any case, as with `ParseOptions::case_insensitive_keywords`.
+    pub fn with_normalize_keywords(mut self, normalize_keywords: bool) -> Self {
+        self.normalize_keywords = normalize_keywords;
+        self
+    }
+
+    /// Prefix to rewrite every `#` or `//` line comment to, `None` by default
+    /// to keep each as written
+    ///
+    /// Only the prefix changes, `// a # b` becomes `# a # b`. Block comments,
+    /// doc comments and a `#/...` or `#!...` comment, which would read as a doc
+    /// comment with `//`, are kept as written.
+    pub fn with_line_comment_prefix(mut self, line_comment_prefix: Option<CommentPrefix>) -> Self {
+        self.line_comment_prefix = line_comment_prefix;
+        self
+    }
+
+    /// Format an AST node, no line of the output ends with whitespace outside
+    /// a `"""` string
     pub fn format(&self, ast: &AstNodeEnum, begin_indent: usize) -> String {
-        let mut formatter = Self::new(self.indent, self.max_col);
+        let mut formatter = Self::new(self.indent, self.max_col)
+            .with_space_before_brace(self.space_before_brace)
+            .with_consistent_wrapping(self.consistent_wrapping)
+            .with_chain_per_line(self.chain_per_line)
+            .with_trailing_comma_multiline(self.trailing_comma_multiline)
+            .with_sort_imports(self.sort_imports)
+            .with_normalize_numbers(self.normalize_numbers)
+            .with_normalize_keywords(self.normalize_keywords)
+            .with_line_comment_prefix(self.line_comment_prefix);
         formatter.format_node(ast, begin_indent)
     }
 
+    /// Parse and format GOS source with this formatter's indent and max_col
+    pub fn format_str(&self, content: &str) -> ParseResult<String> {
+        let options = ParseOptions {
+            ast: true,
+            tracking: true,
+            case_insensitive_keywords: self.normalize_keywords,
+            ..Default::default()
+        };
+        let parsed = parse_gos(content, options)?;
+        Ok(self.format(&parsed, 0))
+    }
+
     /// Format a specific AST node type
     fn format_node(&mut self, ast: &AstNodeEnum, begin_indent: usize) -> String {
         match ast {
             AstNodeEnum::Module(node) => self.format_module(node, begin_indent),
             AstNodeEnum::Comment(node) => self.format_comment(node, begin_indent),
             AstNodeEnum::Symbol(node) => node.name.clone(),
-            AstNodeEnum::StringLiteral(node) => node.value.clone(),
-            AstNodeEnum::MultiLineStringLiteral(node) => node.value.clone(),
-            AstNodeEnum::NumberLiteral(node) => node.raw.clone(),
+            AstNodeEnum::StringLiteral(node) => format_string(node),
+            AstNodeEnum::MultiLineStringLiteral(node) => quote_multiline_string(&node.value),
+            AstNodeEnum::NumberLiteral(node) => format_number(node, self.normalize_numbers),
             AstNodeEnum::FloatLiteral(node) => node.raw.clone(),
-            AstNodeEnum::BoolLiteral(node) => node.raw.clone(),
+            AstNodeEnum::BoolLiteral(node) => format_bool(node, self.normalize_keywords),
             AstNodeEnum::DateTimeLiteral(node) => node.raw.clone(),
             AstNodeEnum::DateLiteral(node) => node.value.clone(),
-            AstNodeEnum::NullLiteral(_) => "null".to_string(),
+            AstNodeEnum::NullLiteral(node) => format_null(node, self.normalize_keywords),
             AstNodeEnum::Import(node) => self.format_import(node, begin_indent),
             AstNodeEnum::AttrDef(node) => self.format_attr_def(node, begin_indent),
             AstNodeEnum::RefDef(node) => self.format_ref_def(node, begin_indent),
@@ -213,6 +377,7 @@ impl Formatter {
             AstNodeEnum::ClosedInterval(node) => self.format_closed_interval(node, begin_indent),
             AstNodeEnum::MixInterval(node) => self.format_mix_interval(node, begin_indent),
             AstNodeEnum::NodeBlock(node) => self.format_node_block(node, begin_indent),
+            AstNodeEnum::ConditionDef(node) => self.format_condition_def(node, begin_indent),
             AstNodeEnum::ConditionBlock(node) => self.format_condition_block(node, begin_indent),
             AstNodeEnum::ConditionStatement(node) => self.format_condition_statement(node, begin_indent),
             _ => String::new(), // Handle other node types as needed
@@ -227,26 +392,79 @@ impl Formatter {
     /// Format comment node
     fn format_comment(&mut self, comment: &Comment, begin_indent: usize) -> String {
         let mut buffer = IndentBuffer::new(self.indent, begin_indent);
-        buffer.write_indent(&[&comment.value, "\n"]);
+        buffer.write_indent(&[&self.comment_text(comment)]);
+        buffer.newline();
         self.cur_col = 0;
         buffer.get_value().to_string()
     }
 
+    /// Comment text with the line comment prefix rewritten to `line_comment_prefix`
+    fn comment_text(&self, comment: &Comment) -> String {
+        let value = comment.value.as_str();
+        if comment.kind != CommentKind::Regular {
+            return value.to_string();
+        }
+        match self.line_comment_prefix {
+            Some(CommentPrefix::Hash) => match value.strip_prefix("//") {
+                Some(text) => format!("#{}", text),
+                None => value.to_string(),
+            },
+            Some(CommentPrefix::DoubleSlash) => match value.strip_prefix('#') {
+                Some(text) if !text.starts_with(['/', '!']) => format!("//{}", text),
+                _ => value.to_string(),
+            },
+            None => value.to_string(),
+        }
+    }
+
     /// Format import statement
     fn format_import(&mut self, import: &Import, begin_indent: usize) -> String {
         let mut buffer = IndentBuffer::new(self.indent, begin_indent);
         buffer.write_indent(&["import "]);
-        
-        for (index, item) in import.items.iter().enumerate() {
-            buffer.write(&item.path.name);
-            if let Some(alias) = &item.alias {
-                buffer.write(&format!(" as {}", alias.name));
+
+        let mut import_items: Vec<&ImportItem> = import.items.iter().collect();
+        if self.sort_imports {
+            import_items.sort_by(|a, b| a.path.name.cmp(&b.path.name));
+        }
+        let items: Vec<String> = import_items.iter()
+            .map(|item| match &item.alias {
+                Some(alias) => format!("{} as {}", item.path.name, alias.name),
+                None => item.path.name.clone(),
+            })
+            .collect();
+        let single_line = format!("{};", items.join(", "));
+        let prefix_len = begin_indent * self.indent + "import ".len();
+
+        if items.len() < 2 || prefix_len + single_line.len() <= self.max_col {
+            buffer.write(&single_line);
+            self.cur_col = prefix_len + single_line.len();
+            return buffer.get_value().to_string();
+        }
+
+        // One item per line, continuation lines aligned with the first item
+        // and `as` clauses aligned with each other
+        let path_width = import_items.iter()
+            .filter(|item| item.alias.is_some())
+            .map(|item| item.path.name.len())
+            .max()
+            .unwrap_or(0);
+        let continuation = " ".repeat(prefix_len);
+        for (index, item) in import_items.iter().enumerate() {
+            if index > 0 {
+                buffer.newline();
+                buffer.write(&continuation);
             }
-            if index + 1 < import.items.len() {
-                buffer.write(", ");
+            match &item.alias {
+                Some(alias) => {
+                    buffer.write(&format!("{:width$} as {}", item.path.name, alias.name, width = path_width));
+                }
+                None => {
+                    buffer.write(&item.path.name);
+                }
             }
+            buffer.write(if index + 1 < import_items.len() { "," } else { ";" });
         }
-        buffer.write(";");
+        self.cur_col = prefix_len + items.last().map_or(0, |item| item.len() + 1);
         buffer.get_value().to_string()
     }
 
@@ -255,7 +473,7 @@ impl Formatter {
         let mut buffer = IndentBuffer::new(self.indent, begin_indent);
         self.cur_col += buffer.write_indent(&[&attr.name.name, " = "]);
         let value_str = self.format_value(&attr.value, begin_indent);
-        buffer.write(&format!("{};", value_str));
+        buffer.write(&end_statement(&value_str));
         self.cur_col += 1;
         buffer.get_value().to_string()
     }
@@ -269,11 +487,12 @@ impl Formatter {
 
     /// Format variable definition
     fn format_var_def(&mut self, var: &VarDef, begin_indent: usize) -> String {
-        let body = self.format_brace("var", &var.children, begin_indent, var.position.line == 1);
+        let name = self.format_annotations(&var.annotations, "var", begin_indent);
+        let body = self.format_brace(&name, &var.children, begin_indent, var.position.line == 1);
         let result = if let Some(alias) = &var.alias {
-            format!("{} as {};", body, alias.name)
+            end_statement(&format!("{} as {}", body, alias.name))
         } else {
-            format!("{};", body)
+            end_statement(&body)
         };
         self.cur_col = result.len();
         result
@@ -281,7 +500,8 @@ impl Formatter {
 
     /// Format graph definition  
     fn format_graph_def(&mut self, graph: &GraphDef, begin_indent: usize) -> String {
-        let body = self.format_brace("graph", &graph.children, begin_indent, graph.position.line == 1);
+        let name = self.format_annotations(&graph.annotations, "graph", begin_indent);
+        let body = self.format_brace(&name, &graph.children, begin_indent, graph.position.line == 1);
         let mut buffer = IndentBuffer::new(self.indent, begin_indent);
         self.cur_col += buffer.write(&body);
         
@@ -292,7 +512,9 @@ impl Formatter {
                 self.cur_col += buffer.writes(&[".version(", &version_str, ")"]);
             }
         }
-        self.cur_col += buffer.write(";");
+        if !buffer.get_value().ends_with(';') {
+            self.cur_col += buffer.write(";");
+        }
         buffer.get_value().to_string()
     }
 
@@ -302,23 +524,24 @@ impl Formatter {
         
         for (index, output) in node.outputs.iter().enumerate() {
             if index == 0 {
-                buffer.write_indent(&[&output.name]);
+                self.cur_col += buffer.write_indent(&[&output.name]);
             } else {
-                buffer.write(&output.name);
+                self.cur_col += buffer.write(&output.name);
             }
             if index + 1 < node.outputs.len() {
-                buffer.write(", ");
+                self.cur_col += buffer.write(", ");
             }
         }
-        buffer.write(" = ");
+        self.cur_col += buffer.write(" = ");
         
         let value_str = self.format_node_block(&node.value, begin_indent);
-        buffer.write(&format!("{};", value_str));
+        buffer.write(&end_statement(&value_str));
         buffer.get_value().to_string()
     }
 
     /// Format node block
     fn format_node_block(&mut self, node: &NodeBlock, begin_indent: usize) -> String {
+        let start_col = self.cur_col;
         let mut buffer = IndentBuffer::new(self.indent, begin_indent);
         
         // Check if this is a reference or direct node call
@@ -337,12 +560,27 @@ impl Formatter {
         }
         
         // Format attributes
-        if let Some(attrs) = &node.attrs {
-            for attr in attrs {
-                buffer.writes(&[".", &attr.name.name, "("]);
-                let attr_value = self.format_node_attr_value(&attr.value, begin_indent);
-                buffer.writes(&[&attr_value, ")"]);
+        let attrs: Vec<String> = node
+            .attrs
+            .iter()
+            .flatten()
+            .map(|attr| format!(".{}({})", attr.name.name, self.format_node_attr_value(&attr.value, begin_indent)))
+            .collect();
+        // All or nothing: the chain and its closing `;` fit on the line, or
+        // every call goes on a line of its own
+        let single_line = start_col + buffer.get_value().len() + attrs.iter().map(String::len).sum::<usize>() + 1;
+        let wrap = self.chain_per_line || (self.consistent_wrapping && single_line > self.max_col);
+        if wrap && self.indent > 0 && !attrs.is_empty() {
+            buffer.indent();
+            for attr in &attrs {
+                buffer.writeln(&[""]);
+                self.cur_col = buffer.write_indent(&[attr]);
             }
+        } else {
+            for attr in &attrs {
+                buffer.write(attr);
+            }
+            self.cur_col = start_col + buffer.get_value().len();
         }
         
         buffer.get_value().to_string()
@@ -353,28 +591,18 @@ impl Formatter {
         let mut buffer = IndentBuffer::new(0, 0);
         
         match inputs {
-            NodeInputDef::Tuple(_tuple) => {
-                // for (index, item) in tuple.items.iter().enumerate() {
-                //     buffer.write(&item.name);
-                //     if index + 1 < tuple.items.len() {
-                //         buffer.write(", ");
-                //     }
-                // }
+            NodeInputDef::Tuple(tuple) => {
+                for (index, item) in tuple.items.iter().enumerate() {
+                    buffer.write(&self.format_node_input_value(item));
+                    if index + 1 < tuple.items.len() {
+                        buffer.write(", ");
+                    }
+                }
             }
             NodeInputDef::KeyValue(key_def) => {
-                for (index, _item) in key_def.items.iter().enumerate() {
-                    // if item.value.items.len() == 1 {
-                    //     buffer.writes(&[&item.key.name, "=", &item.value.items[0].name]);
-                    // } else {
-                    //     buffer.writes(&[&item.key.name, "=("]);
-                    //     for (idx, val) in item.value.items.iter().enumerate() {
-                    //         buffer.write(&val.name);
-                    //         if idx + 1 < item.value.items.len() {
-                    //             buffer.write(", ");
-                    //         }
-                    //     }
-                    //     buffer.write(")");
-                    // }
+                for (index, item) in key_def.items.iter().enumerate() {
+                    let value = self.format_node_input_value(&item.value);
+                    buffer.writes(&[&item.key.name, "=", &value]);
                     if index + 1 < key_def.items.len() {
                         buffer.write(", ");
                     }
@@ -385,11 +613,23 @@ impl Formatter {
         buffer.get_value().to_string()
     }
 
+    /// Format a single node input, a tuple of names is wrapped in parentheses
+    fn format_node_input_value(&mut self, value: &AstNodeEnum) -> String {
+        match value {
+            AstNodeEnum::NodeInputValues(values) => {
+                let names: Vec<&str> = values.items.iter().map(|item| item.name.as_str()).collect();
+                format!("({})", names.join(", "))
+            }
+            _ => self.format_value(value, 0),
+        }
+    }
+
     /// Format node attribute value
     fn format_node_attr_value(&mut self, value: &NodeAttrValue, _begin_indent: usize) -> String {
         match value {
             NodeAttrValue::Symbol(sym) => sym.name.clone(),
-            NodeAttrValue::String(str_lit) => str_lit.value.clone(),
+            NodeAttrValue::String(str_lit) => format_string(str_lit),
+            NodeAttrValue::Bool(boolean) => format_bool(boolean, self.normalize_keywords),
             // NodeAttrValue::ListParams(items) => {
             //     let mut buffer = IndentBuffer::new(0, 0);
             //     buffer.write("[");
@@ -402,25 +642,34 @@ impl Formatter {
             //     buffer.write("]");
             //     buffer.get_value().to_string()
             // }
-            NodeAttrValue::ListParamDef(_list) => {"".to_string()}
-            NodeAttrValue::ListSymbol(_list) => {"".to_string()}
+            NodeAttrValue::ListParamDef(params) => params
+                .iter()
+                .map(|param| format!("{}={}", param.name.name, self.format_value(&param.value, 0)))
+                .collect::<Vec<_>>()
+                .join(", "),
+            NodeAttrValue::ListSymbol(symbols) => symbols.iter().map(|symbol| symbol.name.as_str()).collect::<Vec<_>>().join(", "),
         }
     }
 
+    /// Format condition definition
+    fn format_condition_def(&mut self, cond: &ConditionDef, begin_indent: usize) -> String {
+        let mut buffer = IndentBuffer::new(self.indent, begin_indent);
+        let outputs = cond.outputs.iter()
+            .map(|output| output.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        buffer.write_indent(&[&outputs, " = "]);
+        let value_str = self.format_condition_block(&cond.value, begin_indent);
+        buffer.write(&end_statement(&value_str));
+        buffer.get_value().to_string()
+    }
+
     /// Format condition block
     fn format_condition_block(&mut self, cond: &ConditionBlock, begin_indent: usize) -> String {
         let mut buffer = IndentBuffer::new(self.indent, begin_indent);
         
-        match &*cond.condition {
-            ConditionExpr::Statement(stmt) => {
-                let left = self.format_value(&stmt.left_operand, begin_indent);
-                let right = self.format_value(&stmt.right_operand, begin_indent);
-                buffer.writes(&[&left, " ", &stmt.operator, " ", &right]);
-            }
-            ConditionExpr::Block(block) => {
-                buffer.write(&self.format_node_block(block, begin_indent));
-            }
-        }
+        let condition = self.format_condition_expr(&cond.condition, begin_indent);
+        buffer.write(&condition);
         
         buffer.write(" ? ");
         
@@ -453,6 +702,33 @@ impl Formatter {
         buffer.get_value().to_string()
     }
 
+    /// Format condition expression
+    fn format_condition_expr(&mut self, expr: &ConditionExpr, begin_indent: usize) -> String {
+        match expr {
+            ConditionExpr::Statement(stmt) => self.format_condition_statement(stmt, begin_indent),
+            ConditionExpr::Block(block) => self.format_node_block(block, begin_indent),
+            ConditionExpr::Symbol(sym) => sym.name.clone(),
+            ConditionExpr::String(str_lit) => format_string(str_lit),
+            ConditionExpr::MultiLineString(str_lit) => quote_multiline_string(&str_lit.value),
+            ConditionExpr::And(left, right) => {
+                let left = self.format_condition_expr(left, begin_indent);
+                let right = self.format_condition_expr(right, begin_indent);
+                format!("{} && {}", left, right)
+            }
+            ConditionExpr::Or(left, right) => {
+                let left = self.format_condition_expr(left, begin_indent);
+                let right = self.format_condition_expr(right, begin_indent);
+                format!("{} || {}", left, right)
+            }
+            ConditionExpr::Not(inner) => {
+                format!("!{}", self.format_condition_expr(inner, begin_indent))
+            }
+            ConditionExpr::Group(inner) => {
+                format!("({})", self.format_condition_expr(inner, begin_indent))
+            }
+        }
+    }
+
     /// Format condition statement
     fn format_condition_statement(&mut self, stmt: &ConditionStatement, begin_indent: usize) -> String {
         let left = self.format_value(&stmt.left_operand, begin_indent);
@@ -467,11 +743,21 @@ impl Formatter {
 
     /// Format operation meta section
     fn format_op_meta(&mut self, meta: &OpMeta, begin_indent: usize) -> String {
-        // Convert AttrDef vector to AstNodeEnum vector
-        let children: Vec<AstNodeEnum> = meta.children.iter()
-            .map(|attr| AstNodeEnum::AttrDef(attr.clone()))
-            .collect();
-        self.format_brace_end("meta", &children, begin_indent, true)
+        let body = self.format_brace_with("meta", !meta.children.is_empty(), begin_indent, true, |formatter, indent| {
+            let mut buffer = IndentBuffer::new(formatter.indent, indent);
+            for (index, attr) in meta.children.iter().enumerate() {
+                formatter.widths.cache.clear();
+                buffer.write(&formatter.format_attr_def(attr, indent));
+                if index + 1 < meta.children.len() {
+                    buffer.writeln(&[""]);
+                    formatter.cur_col = 0;
+                }
+            }
+            buffer.get_value().to_string()
+        });
+        let mut buffer = IndentBuffer::new(self.indent, begin_indent);
+        self.cur_col += buffer.writes(&[&body, ";"]);
+        buffer.get_value().to_string()
     }
 
     /// Format operation input section
@@ -501,7 +787,7 @@ impl Formatter {
             } else {
                 buffer.write("(");
                 for (index, item) in items.iter().enumerate() {
-                    let value_str = self.format_value(&item.value, begin_indent);
+                    let value_str = self.format_op_spec_value(&item.name, &item.value, begin_indent);
                     buffer.write(&format!("{}={}", item.name, value_str));
                     if index + 1 < items.len() {
                         buffer.write(", ");
@@ -513,6 +799,42 @@ impl Formatter {
         buffer.get_value().to_string()
     }
 
+    /// Format one `key=value` of an op spec the way the decompiler writes it:
+    /// `dtype` and intervals as is, choices as a tuple, other strings quoted
+    fn format_op_spec_value(&mut self, name: &str, value: &AstNodeEnum, begin_indent: usize) -> String {
+        match (name, value) {
+            ("dtype" | "length" | "range", _) => self.format_value(value, begin_indent),
+            ("choice", AstNodeEnum::TupleStatement(tuple)) => {
+                let choices: Vec<String> = tuple.items.iter()
+                    .map(|item| self.format_op_spec_literal(item, begin_indent))
+                    .collect();
+                format!("({})", choices.join(", "))
+            }
+            _ => self.format_op_spec_literal(value, begin_indent),
+        }
+    }
+
+    /// Format an op spec literal, quoting strings at any depth
+    fn format_op_spec_literal(&mut self, value: &AstNodeEnum, begin_indent: usize) -> String {
+        let (start, end, items) = match value {
+            AstNodeEnum::StringLiteral(s) => {
+                return format!("'{}'", s.value.replace('\\', "\\\\").replace('\'', "\\'"));
+            }
+            AstNodeEnum::TupleStatement(tuple) => ("(", ")", &tuple.items),
+            AstNodeEnum::ListStatement(list) => ("[", "]", &list.items),
+            AstNodeEnum::SetStatement(set) => ("{", "}", &set.items),
+            _ => return self.format_value(value, begin_indent),
+        };
+        let items: Vec<String> = items.iter()
+            .map(|item| self.format_op_spec_literal(item, begin_indent))
+            .collect();
+        if start == "(" && items.len() == 1 {
+            format!("({},)", items[0])
+        } else {
+            format!("{}{}{}", start, items.join(", "), end)
+        }
+    }
+
     /// Format dictionary statement
     fn format_dict_statement(&mut self, dict: &DictStatement, begin_indent: usize) -> String {
         self.format_sequence("{", "}", &dict.items, begin_indent, true)
@@ -538,10 +860,10 @@ impl Formatter {
         let mut parts = Vec::new();
         
         if let Some(ge) = &interval.ge {
-            parts.push(format!("[{}", ge.raw));
+            parts.push(format!("[{}", ge.raw()));
         }
         if let Some(le) = &interval.le {
-            parts.push(format!("{}]", le.raw));
+            parts.push(format!("{}]", le.raw()));
         }
         
         parts.join(", ")
@@ -550,17 +872,17 @@ impl Formatter {
     /// Format mixed interval
     fn format_mix_interval(&mut self, interval: &MixInterval, _begin_indent: usize) -> String {
         let left = if let Some(ge) = &interval.ge {
-            format!("[{}", ge.raw)
+            format!("[{}", ge.raw())
         } else if let Some(gt) = &interval.gt {
-            format!("({}", gt.raw)
+            format!("({}", gt.raw())
         } else {
             "(".to_string()
         };
         
         let right = if let Some(le) = &interval.le {
-            format!("{}]", le.raw)
+            format!("{}]", le.raw())
         } else if let Some(lt) = &interval.lt {
-            format!("{})", lt.raw)
+            format!("{})", lt.raw())
         } else {
             ")".to_string()
         };
@@ -571,14 +893,14 @@ impl Formatter {
     /// Helper method to format values
     fn format_value(&mut self, ast: &AstNodeEnum, begin_indent: usize) -> String {
         match ast {
-            AstNodeEnum::NumberLiteral(n) => n.raw.clone(),
+            AstNodeEnum::NumberLiteral(n) => format_number(n, self.normalize_numbers),
             AstNodeEnum::FloatLiteral(n) => n.raw.clone(),
-            AstNodeEnum::BoolLiteral(n) => n.raw.clone(),
+            AstNodeEnum::BoolLiteral(n) => format_bool(n, self.normalize_keywords),
             AstNodeEnum::DateLiteral(n) => n.value.clone(),
-            AstNodeEnum::StringLiteral(n) => n.value.clone(),
-            AstNodeEnum::MultiLineStringLiteral(n) => n.value.clone(),
+            AstNodeEnum::StringLiteral(n) => format_string(n),
+            AstNodeEnum::MultiLineStringLiteral(n) => quote_multiline_string(&n.value),
             AstNodeEnum::Symbol(n) => n.name.clone(),
-            AstNodeEnum::NullLiteral(_) => "null".to_string(),
+            AstNodeEnum::NullLiteral(node) => format_null(node, self.normalize_keywords),
             AstNodeEnum::DictStatement(n) => self.format_dict_statement(n, begin_indent),
             AstNodeEnum::ListStatement(n) => self.format_list_statement(n, begin_indent),
             AstNodeEnum::TupleStatement(n) => self.format_tuple_statement(n, begin_indent),
@@ -588,6 +910,10 @@ impl Formatter {
     }
 
     /// Format sequences with delimiters
+    ///
+    /// A lone tuple or set item keeps its trailing comma: `(x)` reads as a
+    /// plain value elsewhere and `{x}` does not parse. The grammar has no empty
+    /// set, `{}` is always a dict.
     fn format_sequence(&mut self, start: &str, end: &str, items: &[impl FormatItem], begin_indent: usize, is_dict: bool) -> String {
         if items.is_empty() {
             self.cur_col += 2;
@@ -595,7 +921,8 @@ impl Formatter {
         }
         
         let mut buffer = IndentBuffer::new(self.indent, begin_indent);
-        let new_line = self.need_line_for_items(items);
+        let lone_comma = !is_dict && (start == "(" || start == "{");
+        let new_line = self.need_line_for_items(items, lone_comma);
         
         if new_line {
             buffer.writeln(&[start]);
@@ -620,6 +947,9 @@ impl Formatter {
                 self.cur_col += buffer.write(&item_str);
             }
             
+            if (items.len() == 1 && lone_comma) || (index + 1 == items.len() && new_line && self.trailing_comma_multiline) {
+                self.cur_col += buffer.write(",");
+            }
             if index + 1 < items.len() {
                 next_new_line = new_line;
                 if next_new_line {
@@ -644,17 +974,32 @@ impl Formatter {
 
     /// Format brace-enclosed sections
     fn format_brace(&mut self, name: &str, children: &[AstNodeEnum], begin_indent: usize, is_first_line: bool) -> String {
+        self.format_brace_with(name, !children.is_empty(), begin_indent, is_first_line, |formatter, indent| {
+            formatter.format_list_with_comment(children, indent)
+        })
+    }
+
+    /// Format a brace-enclosed section whose body `format_body` writes at the given indent
+    fn format_brace_with(
+        &mut self,
+        name: &str,
+        has_body: bool,
+        begin_indent: usize,
+        is_first_line: bool,
+        format_body: impl FnOnce(&mut Self, usize) -> String,
+    ) -> String {
         let mut buffer = IndentBuffer::new(self.indent, begin_indent);
         
         if !is_first_line {
             buffer.writeln(&[""]);
         }
-        buffer.writeln_indent(&[name, " {"]);
+        let brace = if self.space_before_brace { " {" } else { "{" };
+        buffer.writeln_indent(&[name, brace]);
         self.cur_col = 0;
         
-        if !children.is_empty() {
+        if has_body {
             buffer.indent();
-            let body = self.format_list_with_comment(children, buffer.current_indent);
+            let body = format_body(self, buffer.current_indent);
             if body.ends_with('\n') {
                 buffer.write(&body);
             } else {
@@ -668,9 +1013,27 @@ impl Formatter {
         buffer.get_value().to_string()
     }
 
+    /// Prefix a definition keyword with its annotations, e.g. `@timeout(30) graph`
+    fn format_annotations(&mut self, annotations: &[Annotation], keyword: &str, begin_indent: usize) -> String {
+        let mut parts = Vec::with_capacity(annotations.len() + 1);
+        for annotation in annotations {
+            if annotation.args.is_empty() {
+                parts.push(format!("@{}", annotation.name.name));
+            } else {
+                let args: Vec<String> = annotation.args.iter()
+                    .map(|arg| self.format_op_spec_literal(arg, begin_indent))
+                    .collect();
+                parts.push(format!("@{}({})", annotation.name.name, args.join(", ")));
+            }
+        }
+        parts.push(keyword.to_string());
+        parts.join(" ")
+    }
+
     /// Format brace sections with version support
     fn format_brace_as_version(&mut self, node: &OpDef, name: &str, begin_indent: usize) -> String {
-        let body = self.format_brace(name, &node.children, begin_indent, node.position.line == 1);
+        let name = self.format_annotations(&node.annotations, name, begin_indent);
+        let body = self.format_brace(&name, &node.children, begin_indent, node.position.line == 1);
         let mut buffer = IndentBuffer::new(self.indent, begin_indent);
         self.cur_col += buffer.write(&body);
         
@@ -695,23 +1058,25 @@ impl Formatter {
     /// Format list with comments
     fn format_list_with_comment(&mut self, children: &[AstNodeEnum], begin_indent: usize) -> String {
         let mut buffer = IndentBuffer::new(self.indent, begin_indent);
-        let mut next_comment = false;
+        let mut skip_comments = 0;
         
         for (index, child) in children.iter().enumerate() {
-            if next_comment {
-                next_comment = false;
+            if skip_comments > 0 {
+                skip_comments -= 1;
                 continue;
             }
             
             let cur_end = child.position().end_line;
+            self.widths.cache.clear();
             let child_str = self.format_node(child, begin_indent);
             buffer.write(&child_str);
             
             // Check for inline comment
-            if let Some(comment) = self.get_inline_comment(index, cur_end, children) {
-                buffer.writes(&[" ", &comment, "\n"]);
+            if let Some((comment, count)) = self.get_inline_comment(index, cur_end, children) {
+                buffer.writes(&[" ", &comment]);
+                buffer.newline();
                 self.cur_col = 0;
-                next_comment = true;
+                skip_comments = count;
                 continue;
             }
             
@@ -724,49 +1089,185 @@ impl Formatter {
         buffer.get_value().to_string()
     }
 
-    /// Check if inline comment exists
-    fn get_inline_comment(&self, index: usize, cur_end: usize, children: &[AstNodeEnum]) -> Option<String> {
-        if index + 1 < children.len() {
-            if let AstNodeEnum::Comment(comment) = &children[index + 1] {
-                if comment.position.line == cur_end {
-                    return Some(comment.value.clone());
-                }
-            }
+    /// Comments following `children[index]` on its last line `cur_end`, joined,
+    /// with how many children they span
+    fn get_inline_comment(&self, index: usize, cur_end: usize, children: &[AstNodeEnum]) -> Option<(String, usize)> {
+        let comments: Vec<String> = children[index + 1..]
+            .iter()
+            .map_while(|child| match child {
+                AstNodeEnum::Comment(comment) if comment.position.line == cur_end => Some(self.comment_text(comment)),
+                _ => None,
+            })
+            .collect();
+        if comments.is_empty() {
+            None
+        } else {
+            Some((comments.join(" "), comments.len()))
         }
-        None
     }
 
-    /// Calculate value length for line breaking decisions
-    fn value_length(&self, ast: &AstNodeEnum) -> usize {
-        match ast {
-            AstNodeEnum::NumberLiteral(n) => n.raw.len(),
+    /// Check if line break is needed
+    fn need_line(&mut self, ast: &AstNodeEnum) -> bool {
+        (self.cur_col + self.widths.measure(ast) > self.max_col) && self.indent > 0
+    }
+
+    /// Check if a sequence no longer fits on the current line
+    fn need_line_for_items<T: FormatItem>(&mut self, items: &[T], lone_comma: bool) -> bool {
+        (self.cur_col + self.widths.measure_sequence(items, lone_comma) > self.max_col) && self.indent > 0
+    }
+}
+
+/// Single-line width of a value as `format_value` renders it, without building the string
+pub(crate) fn measure(node: &AstNodeEnum) -> usize {
+    Widths::default().measure(node)
+}
+
+/// Memoized `measure`
+///
+/// Each nested sequence checks its own width before formatting, so without
+/// the memo a value nested n deep would be measured n times. Nodes are keyed
+/// by address, so the memo is cleared before each statement: a freed node's
+/// address may be taken by a later one.
+#[derive(Debug, Default)]
+struct Widths {
+    cache: HashMap<usize, usize>,
+    /// Numbers are measured as `Formatter::with_normalize_numbers` writes them
+    normalize_numbers: bool,
+}
+
+impl Widths {
+    fn measure(&mut self, node: &AstNodeEnum) -> usize {
+        let key = node as *const AstNodeEnum as usize;
+        if let Some(width) = self.cache.get(&key) {
+            return *width;
+        }
+        let width = self.measure_uncached(node);
+        self.cache.insert(key, width);
+        width
+    }
+
+    fn measure_uncached(&mut self, node: &AstNodeEnum) -> usize {
+        match node {
+            AstNodeEnum::NumberLiteral(n) => format_number(n, self.normalize_numbers).len(),
             AstNodeEnum::FloatLiteral(n) => n.raw.len(),
             AstNodeEnum::BoolLiteral(n) => n.raw.len(),
-            AstNodeEnum::StringLiteral(n) => n.value.len(),
-            AstNodeEnum::MultiLineStringLiteral(n) => n.value.len(),
+            AstNodeEnum::DateTimeLiteral(n) => n.raw.len(),
+            AstNodeEnum::DateLiteral(n) => n.value.len(),
+            AstNodeEnum::StringLiteral(n) => format_string(n).len(),
+            AstNodeEnum::MultiLineStringLiteral(n) => {
+                // See `quote_multiline_string`: six quotes, doubled backslashes, escaped `"""`
+                6 + n.value.len() + n.value.matches('\\').count() + 3 * n.value.matches("\"\"\"").count()
+            }
             AstNodeEnum::Symbol(n) => n.name.len(),
             AstNodeEnum::NullLiteral(_) => 4,
-            AstNodeEnum::DateLiteral(n) => n.value.len(),
-            _ => 0, // Simplified - would need full implementation
+            AstNodeEnum::DictStatement(n) => self.measure_sequence(&n.items, false),
+            AstNodeEnum::ListStatement(n) => self.measure_sequence(&n.items, false),
+            AstNodeEnum::TupleStatement(n) => self.measure_sequence(&n.items, true),
+            AstNodeEnum::SetStatement(n) => self.measure_sequence(&n.items, true),
+            AstNodeEnum::ClosedInterval(n) => {
+                let ge = n.ge.as_ref().map_or(0, |ge| ge.raw().len() + 1);
+                let le = n.le.as_ref().map_or(0, |le| le.raw().len() + 1);
+                ge + le + if ge > 0 && le > 0 { 2 } else { 0 }
+            }
+            AstNodeEnum::MixInterval(n) => {
+                let left = n.ge.as_ref().or(n.gt.as_ref()).map_or(0, |v| v.raw().len());
+                let right = n.le.as_ref().or(n.lt.as_ref()).map_or(0, |v| v.raw().len());
+                left + right + 4
+            }
+            _ => 0,
         }
     }
 
-    /// Check if line break is needed
-    fn need_line(&self, ast: &AstNodeEnum) -> bool {
-        (self.cur_col + self.value_length(ast) > self.max_col) && self.indent > 0
+    /// Width of `start item, item end`, plus the comma kept after a lone tuple or set item
+    fn measure_sequence<T: FormatItem>(&mut self, items: &[T], lone_comma: bool) -> usize {
+        let items_width: usize = items.iter().map(|item| item.measure_item(self)).sum();
+        let separators = 2 * items.len().saturating_sub(1);
+        let comma = usize::from(lone_comma && items.len() == 1);
+        2 + items_width + separators + comma
     }
+}
 
-    /// Check if line break is needed for items
-    fn need_line_for_items<T>(&self, items: &[T]) -> bool {
-        // Simplified logic - would need proper implementation
-        items.len() > 3
+/// Terminate a statement with exactly one semicolon
+fn end_statement(statement: &str) -> String {
+    format!("{};", statement.trim_end().trim_end_matches(';'))
+}
+
+/// A number as written, or its decimal value when `normalize` is set
+fn format_number(number: &NumberLiteral, normalize: bool) -> String {
+    if normalize {
+        number.value.to_string()
+    } else {
+        number.raw.clone()
+    }
+}
+
+/// A boolean as written, or in lower case when `normalize` is set
+fn format_bool(boolean: &BoolLiteral, normalize: bool) -> String {
+    if normalize {
+        boolean.value.to_string()
+    } else {
+        boolean.raw.clone()
     }
 }
 
+/// `null` as written, or in lower case when `normalize` is set
+fn format_null(null: &NullLiteral, normalize: bool) -> String {
+    if normalize {
+        "null".to_string()
+    } else {
+        null.raw.clone()
+    }
+}
+
+/// A string literal in its recorded quotes, raw when it has backslashes,
+/// e.g. `r"C:\dir"`
+///
+/// Raw strings take no escapes, so a value that also has its quote, a newline
+/// or an escaped `\${` is escaped instead.
+pub(crate) fn format_string(string: &StringLiteral) -> String {
+    let quote = match string.quote {
+        QuoteStyle::Double => '"',
+        QuoteStyle::Single => '\'',
+    };
+    let value = &string.value;
+    let escaped = &string.escaped_interpolations;
+    if value.contains('\\') && !value.contains(quote) && !value.contains('\n') && escaped.is_empty() {
+        return format!("r{quote}{value}{quote}");
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push(quote);
+    for (index, ch) in value.char_indices() {
+        match ch {
+            '$' if escaped.contains(&index) => quoted.push_str("\\$"),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if ch == quote => {
+                quoted.push('\\');
+                quoted.push(ch);
+            }
+            ch => quoted.push(ch),
+        }
+    }
+    quoted.push(quote);
+    quoted
+}
+
+/// Wrap a multiline string value in triple quotes, keeping its whitespace intact
+///
+/// Only backslashes and quotes that would terminate the literal are escaped,
+/// so the parser unescapes the result back to the original value.
+fn quote_multiline_string(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace("\"\"\"", "\\\"\\\"\\\"");
+    format!("\"\"\"{}\"\"\"", escaped)
+}
+
 /// Trait for formatting different item types
 trait FormatItem {
     fn format_as_item(&self, formatter: &mut Formatter, begin_indent: usize) -> String;
     fn format_as_dict_item(&self, formatter: &mut Formatter, begin_indent: usize) -> String;
+    fn measure_item(&self, widths: &mut Widths) -> usize;
 }
 
 impl FormatItem for AstNodeEnum {
@@ -777,6 +1278,10 @@ impl FormatItem for AstNodeEnum {
     fn format_as_dict_item(&self, formatter: &mut Formatter, begin_indent: usize) -> String {
         formatter.format_value(self, begin_indent)
     }
+
+    fn measure_item(&self, widths: &mut Widths) -> usize {
+        widths.measure(self)
+    }
 }
 
 impl FormatItem for DictItem {
@@ -789,6 +1294,10 @@ impl FormatItem for DictItem {
     fn format_as_dict_item(&self, formatter: &mut Formatter, begin_indent: usize) -> String {
         self.format_as_item(formatter, begin_indent)
     }
+
+    fn measure_item(&self, widths: &mut Widths) -> usize {
+        widths.measure(&self.key) + 2 + widths.measure(&self.value)
+    }
 }
 
 #[cfg(test)]
@@ -813,4 +1322,433 @@ mod tests {
         let result = format_from_data(content, 4, 100);
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_format_short_import_inline() {
+        let content = "import a.b as c, d;";
+        let formatted = format_from_data(content, 4, 100).unwrap();
+        assert_eq!(formatted.trim_end(), "import a.b as c, d;");
+    }
+
+    #[test]
+    fn test_format_long_import_wraps() {
+        let content = "import company.data.loaders as loaders, company.models.transformer, company.metrics as m;";
+        let formatted = format_from_data(content, 4, 40).unwrap();
+        let expected = [
+            "import company.data.loaders as loaders,",
+            "       company.models.transformer,",
+            "       company.metrics      as m;",
+        ]
+        .join("\n");
+        assert_eq!(formatted.trim_end(), expected);
+        assert!(crate::parse(&formatted).is_ok());
+    }
+
+    #[test]
+    fn test_format_multiline_string() {
+        let content = "var {\n    text = \"\"\"first line\n    indented  line\n\tend\"\"\";\n};";
+        let formatted = format_from_data(content, 4, 100).unwrap();
+        assert!(formatted.contains("\"\"\"first line\n    indented  line\n\tend\"\"\""));
+
+        let reformatted = format_from_data(&formatted, 4, 100).unwrap();
+        assert_eq!(formatted, reformatted);
+        match crate::parse(&formatted).unwrap() {
+            AstNodeEnum::Module(module) => match &module.children[0] {
+                AstNodeEnum::VarDef(var) => match &var.children[0] {
+                    AstNodeEnum::AttrDef(attr) => match attr.value.as_ref() {
+                        AstNodeEnum::MultiLineStringLiteral(s) => {
+                            assert_eq!(s.value, "first line\n    indented  line\n\tend");
+                        }
+                        other => panic!("Expected multiline string, got {:?}", other),
+                    },
+                    _ => panic!("Expected AttrDef"),
+                },
+                _ => panic!("Expected VarDef"),
+            },
+            _ => panic!("Expected Module"),
+        }
+    }
+
+    #[test]
+    fn test_format_key_value_inputs() {
+        let content = "graph { a = foo(x=in1); b = bar(x=a, weights=(w1,w2,)); };";
+        let formatted = format_from_data(content, 4, 100).unwrap();
+        assert!(formatted.contains("foo(x=in1)"), "got:\n{}", formatted);
+        assert!(formatted.contains("bar(x=a, weights=(w1, w2))"), "got:\n{}", formatted);
+        assert_eq!(format_from_data(&formatted, 4, 100).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_collection_delimiters() {
+        let content = "var { t = (1,); s = {1,}; pair = {1, 2}; l = []; d = {}; u = (); x = [1]; };";
+        let formatted = format_from_data(content, 4, 100).unwrap();
+        for expected in ["t = (1,);", "s = {1,};", "pair = {1, 2};", "l = [];", "d = {};", "u = ();", "x = [1];"] {
+            assert!(formatted.contains(expected), "missing {} in:\n{}", expected, formatted);
+        }
+        assert_eq!(format_from_data(&formatted, 4, 100).unwrap(), formatted);
+
+        let compile = |source: &str| {
+            serde_json::to_value(crate::compile_ast(&crate::parse(source).unwrap()).unwrap()).unwrap()
+        };
+        assert_eq!(compile(&formatted), compile(content));
+    }
+
+    #[test]
+    fn test_measure_matches_single_line_format() {
+        let content = r#"var {
+    n = -12; f = 1.5e3; b = true; z = null; s = "plain";
+    m = """a\b
+"""; l = [1, [2, 3], []]; t = (1,); u = ("x", 2.0); set = {1, 2,};
+    d = {'k': [1, 2], 'e': {}}; nested = {'a': ({1,}, [null])};
+};"#;
+        let AstNodeEnum::Module(module) = crate::parse(content).unwrap() else {
+            panic!("Expected Module");
+        };
+        let AstNodeEnum::VarDef(var) = &module.children[0] else {
+            panic!("Expected VarDef");
+        };
+        let mut formatter = Formatter::new(4, 1000);
+        for child in &var.children {
+            let AstNodeEnum::AttrDef(attr) = child else { continue };
+            let rendered = formatter.format_value(&attr.value, 0);
+            assert!(!rendered.contains(",\n"), "{} wrapped: {}", attr.name.name, rendered);
+            assert_eq!(measure(&attr.value), rendered.len(), "{}: {}", attr.name.name, rendered);
+        }
+    }
+
+    #[test]
+    fn test_format_wraps_by_width() {
+        // Short collections stay on one line whatever their item count
+        let content = "var { l = [1, 2, 3, 4, 5, 6]; };";
+        let formatted = format_from_data(content, 4, 100).unwrap();
+        assert!(formatted.contains("l = [1, 2, 3, 4, 5, 6];"), "got:\n{}", formatted);
+
+        // A collection past max_col breaks one item per line
+        let content = "var { sizes = [1234567890, 9876543210]; };";
+        let formatted = format_from_data(content, 4, 30).unwrap();
+        assert!(formatted.contains("sizes = [\n"), "got:\n{}", formatted);
+        assert!(crate::parse(&formatted).is_ok(), "got:\n{}", formatted);
+    }
+
+    #[test]
+    fn test_format_trailing_block_comments() {
+        let content = r#"var {
+    limit = 10;
+} as config; /* end var comment */
+graph { a = foo(); } as main; /* first */ /* second */
+# own line
+"#;
+        let formatted = format_from_data(content, 4, 100).unwrap();
+        assert!(formatted.contains("} as config; /* end var comment */\n"), "got:\n{}", formatted);
+        assert!(formatted.contains("} as main; /* first */ /* second */\n# own line"), "got:\n{}", formatted);
+        assert_eq!(format_from_data(&formatted, 4, 100).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_str() {
+        let formatter = Formatter::new(4, 100);
+        let content = "graph { a = foo(x); } as main;";
+        assert_eq!(formatter.format_str(content).unwrap(), format_from_data(content, 4, 100).unwrap());
+
+        let err = formatter.format_str("graph { a = ; } as main;").unwrap_err();
+        assert!(matches!(err, crate::ParseError::SyntaxError { line: 1, .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn test_format_op_spec_matches_decompiler() {
+        // Keys in sorted order, as the decompiler writes them
+        let content = r#"op {
+    input {
+        w: (default="q", dtype=str, length=3, pattern="^a");
+        y: (choice=("a", "b"), dtype=float, help="the y");
+        z: (dtype=list, length=[1, 5], range=(0, 10]);
+    };
+} as my_op;"#;
+        let formatted = format_from_data(content, 4, 100).unwrap();
+        let ast = crate::parse(content).unwrap();
+        let compiled = serde_json::to_value(crate::compile_ast(&ast).unwrap()).unwrap();
+        let crate::DecompileResult::Text(decompiled) = crate::decompile_from_data(compiled, None).unwrap() else {
+            panic!("Expected text result");
+        };
+
+        let strip = |text: &str| text.split_whitespace().collect::<String>();
+        let (formatted, decompiled) = (strip(&formatted), strip(&decompiled));
+        for name in ["w", "y", "z"] {
+            let spec = |text: &str| {
+                let start = text.find(&format!("{}:(", name)).unwrap();
+                text[start..start + text[start..].find(");").unwrap()].to_string()
+            };
+            assert_eq!(spec(&formatted), spec(&decompiled));
+        }
+    }
+
+    #[test]
+    fn test_format_space_before_brace() {
+        let content = "var { limit = 10; } as config;\ngraph { a = foo(x); } as main;\nop { meta { k = 1; }; } as my_op;";
+        let spaced = Formatter::new(4, 100).format_str(content).unwrap();
+        assert!(spaced.contains("var {\n") && spaced.contains("graph {\n"), "got:\n{}", spaced);
+        assert!(spaced.contains("meta {\n"), "got:\n{}", spaced);
+
+        let tight = Formatter::new(4, 100).with_space_before_brace(false).format_str(content).unwrap();
+        assert!(tight.contains("var{\n") && tight.contains("graph{\n"), "got:\n{}", tight);
+        assert!(tight.contains("op{\n") && tight.contains("meta{\n"), "got:\n{}", tight);
+        assert!(!tight.contains(" {\n"), "got:\n{}", tight);
+
+        // Both styles reparse to the same tree
+        let original = crate::parse(content).unwrap();
+        for formatted in [&spaced, &tight] {
+            let reparsed = crate::parse(formatted).unwrap_or_else(|e| panic!("{}\n{}", e, formatted));
+            assert!(crate::ast::eq_ignore_position(&original, &reparsed), "got:\n{}", formatted);
+        }
+    }
+
+    #[test]
+    fn test_format_consistent_wrapping() {
+        let content = "graph {\n    result = transform(data).with(scale=2, offset=10).property(cache=true).depend(setup);\n} as main;";
+        let formatter = Formatter::new(4, 60).with_consistent_wrapping(true);
+
+        // Past max_col every call of the chain is on its own line
+        let wrapped = formatter.format_str(content).unwrap();
+        let expected = "    result = transform(data)\n        .with(scale=2, offset=10)\n        .property(cache=true)\n        .depend(setup);";
+        assert!(wrapped.contains(expected), "got:\n{}", wrapped);
+
+        // Within max_col the whole chain stays on one line
+        let inlined = Formatter::new(4, 100).with_consistent_wrapping(true).format_str(content).unwrap();
+        let expected = "    result = transform(data).with(scale=2, offset=10).property(cache=true).depend(setup);";
+        assert!(inlined.contains(expected), "got:\n{}", inlined);
+
+        // Off by default, the chain is never split
+        assert_eq!(Formatter::new(4, 60).format_str(content).unwrap(), inlined);
+
+        let original = crate::parse(content).unwrap();
+        for formatted in [&wrapped, &inlined] {
+            let reparsed = crate::parse(formatted).unwrap_or_else(|e| panic!("{}\n{}", e, formatted));
+            assert!(crate::ast::eq_ignore_position(&original, &reparsed), "got:\n{}", formatted);
+        }
+        assert_eq!(formatter.format_str(&wrapped).unwrap(), wrapped);
+    }
+
+    #[test]
+    fn test_format_chain_per_line() {
+        let content = "graph {\n    a = load(src).with(batch=32).depend(setup).as(start);\n    b = clean(a);\n} as main;";
+        let formatter = Formatter::new(4, 100).with_chain_per_line(true);
+
+        // Short chains are wrapped too, a node without calls is left alone
+        let formatted = formatter.format_str(content).unwrap();
+        let expected = "    a = load(src)\n        .with(batch=32)\n        .depend(setup)\n        .as(start);\n    b = clean(a);";
+        assert!(formatted.contains(expected), "got:\n{}", formatted);
+        assert_eq!(formatter.format_str(&formatted).unwrap(), formatted);
+        let reparsed = crate::parse(&formatted).unwrap();
+        assert!(crate::ast::eq_ignore_position(&crate::parse(content).unwrap(), &reparsed), "got:\n{}", formatted);
+
+        let two_spaces = Formatter::new(2, 100).with_chain_per_line(true).format_str(content).unwrap();
+        assert!(two_spaces.contains("  a = load(src)\n    .with(batch=32)\n    .depend(setup)"), "got:\n{}", two_spaces);
+
+        let inlined = Formatter::new(4, 100).format_str(content).unwrap();
+        assert!(inlined.contains("    a = load(src).with(batch=32).depend(setup).as(start);"), "got:\n{}", inlined);
+    }
+
+    #[test]
+    fn test_format_raw_string() {
+        let content = "var {\n    path = r\"C:\\path\\to\\file\";\n    pattern = r'say \"\\d\"';\n};";
+        let formatter = Formatter::new(4, 100);
+        let formatted = formatter.format_str(content).unwrap();
+        assert_eq!(formatted.trim_end(), content);
+        assert_eq!(formatter.format_str(&formatted).unwrap(), formatted);
+
+        // An escaped backslash comes back as a raw string of the same value
+        let escaped = formatter.format_str("var {\n    path = \"C:\\\\dir\";\n};").unwrap();
+        assert_eq!(escaped.trim_end(), "var {\n    path = r\"C:\\dir\";\n};");
+    }
+
+    #[test]
+    fn test_format_string_quotes() {
+        let formatter = Formatter::new(4, 100);
+        // Strings keep their quotes
+        let content = "var {\n    a = \"x y\";\n    b = 'x y';\n};";
+        assert_eq!(formatter.format_str(content).unwrap().trim_end(), content);
+
+        // Values a raw string cannot hold are escaped
+        let content = "var {\n    a = \"say \\\"\\\\d\\\"\";\n    b = 'it\\'s';\n    c = \"a\\nb\";\n    d = \"\\\\ \\${x}\";\n};";
+        let formatted = formatter.format_str(content).unwrap();
+        assert_eq!(formatted.trim_end(), content);
+        assert_eq!(formatter.format_str(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_keeps_number_forms() {
+        let content = "var {\n    mask = 0xFF;\n    flags = 0b1010;\n    total = 1_000_000;\n    items = [0x10, -2_0];\n};\n\ngraph {\n    a = foo(x).with(limit=0x7f);\n} as main;";
+        let formatter = Formatter::new(4, 100);
+        let formatted = formatter.format_str(content).unwrap();
+        assert_eq!(formatted.trim_end(), content);
+
+        let normalized = formatter.with_normalize_numbers(true).format_str(content).unwrap();
+        assert!(normalized.contains("mask = 255;"), "got:\n{}", normalized);
+        assert!(normalized.contains("flags = 10;"), "got:\n{}", normalized);
+        assert!(normalized.contains("total = 1000000;"), "got:\n{}", normalized);
+        assert!(normalized.contains("items = [16, -20];"), "got:\n{}", normalized);
+        assert!(normalized.contains(".with(limit=127)"), "got:\n{}", normalized);
+    }
+
+    #[test]
+    fn test_format_normalize_keywords() {
+        let content = "var {\n    a = True;\n    b = False;\n    c = [true, True];\n};\n\ngraph {\n    n = foo(x).with(flag=True);\n} as main;";
+        let formatter = Formatter::new(4, 100);
+        assert_eq!(formatter.format_str(content).unwrap().trim_end(), content);
+
+        let normalized = formatter.with_normalize_keywords(true);
+        let expected = content.replace("True", "true").replace("False", "false");
+        assert_eq!(normalized.format_str(content).unwrap().trim_end(), expected);
+
+        // Any case is accepted under the option, and kept without it
+        let shouting = "var {\n    a = TRUE;\n    b = Null;\n};";
+        assert_eq!(normalized.format_str(shouting).unwrap().trim_end(), "var {\n    a = true;\n    b = null;\n};");
+        let options = ParseOptions {
+            ast: true,
+            case_insensitive_keywords: true,
+            ..Default::default()
+        };
+        let ast = parse_gos(shouting, options).unwrap();
+        assert_eq!(Formatter::new(4, 100).format(&ast, 0).trim_end(), shouting);
+    }
+
+    #[test]
+    fn test_format_no_trailing_whitespace() {
+        let content = "var {   \n    a = 1;  \n\n    text = \"\"\"keep   \nthese  \"\"\";   \n    items = [\"alpha\", \"beta\", \"gamma\", \"delta\"];\n};\t\n\ngraph {\n    # note   \n    n = foo(x).with(k=1).property(p=2).log(level=1);\n\n    m = bar(n);\n} as main;   ";
+        for formatter in [Formatter::new(4, 20), Formatter::new(4, 20).with_chain_per_line(true)] {
+            let formatted = formatter.format_str(content).unwrap();
+            let text_line = formatted.lines().position(|line| line.contains("keep")).unwrap();
+            for (index, line) in formatted.lines().enumerate().filter(|(index, _)| *index != text_line) {
+                assert_eq!(line, line.trim_end(), "line {} has trailing whitespace:\n{}", index + 1, formatted);
+            }
+            // Spaces inside a multi-line string are part of its value
+            assert!(formatted.contains("\"\"\"keep   \nthese  \"\"\""), "got:\n{}", formatted);
+        }
+    }
+
+    #[test]
+    fn test_format_keeps_multiline_string_after_quotes() {
+        // A `"""` in a single-quoted string or a comment does not start a multi-line string
+        let sources = [
+            "var { q = '\"\"\"'; s = \"\"\"keep   \nend\"\"\"; };",
+            "var {\n    # a \"\"\" in a comment\n    s = \"\"\"keep   \nend\"\"\";\n};",
+        ];
+        for content in sources {
+            let formatted = Formatter::new(4, 100).format_str(content).unwrap();
+            let compiled = |source: &str| {
+                let result = crate::compile_ast(&crate::parse(source).unwrap()).unwrap();
+                result.vars.unwrap()["s"].clone()
+            };
+            assert_eq!(compiled(&formatted), "keep   \nend", "got:\n{}", formatted);
+            assert_eq!(compiled(&formatted), compiled(content));
+        }
+    }
+
+    #[test]
+    fn test_format_string_condition_keeps_gos_quoting() {
+        let content = "graph {\n    r = 'it\\'s' ? foo(x) : bar(x);\n} as main;";
+        let formatted = Formatter::new(4, 100).format_str(content).unwrap();
+        assert_eq!(formatted, content);
+    }
+
+    #[test]
+    fn test_format_quotes_node_attr_strings() {
+        let content = "graph {\n    a = foo(x).version(\"1.0.0\").condition('it\\'s');\n} as main;";
+        let formatted = Formatter::new(4, 100).format_str(content).unwrap();
+        assert_eq!(formatted, content);
+    }
+
+    #[test]
+    fn test_format_widths_independent_of_statement_order() {
+        let short = "op {\n    meta {\n        a = [1, 2];\n    }\n} as x;";
+        let long = "op {\n    meta {\n        b = [1111111111, 2222222222];\n    }\n} as y;";
+        let formatter = Formatter::new(4, 30);
+        let expected_long = "        b = [\n            1111111111,\n            2222222222\n        ];";
+        for content in [format!("{}\n{}", short, long), format!("{}\n{}", long, short)] {
+            let formatted = formatter.format_str(&content).unwrap();
+            assert!(formatted.contains("        a = [1, 2];"), "got:\n{}", formatted);
+            assert!(formatted.contains(expected_long), "got:\n{}", formatted);
+        }
+    }
+
+    #[test]
+    fn test_format_line_comment_prefix() {
+        let content = "//! module doc\n# hash with // inside\n// slashes with # inside\n/* block # // */\n#!not a doc\n\nvar {\n    a = 1; // inline\n};";
+        let hash = Formatter::new(4, 100).with_line_comment_prefix(Some(CommentPrefix::Hash));
+        let formatted = hash.format_str(content).unwrap();
+        let expected = "//! module doc\n# hash with // inside\n# slashes with # inside\n/* block # // */\n#!not a doc\n\nvar {\n    a = 1; # inline\n};";
+        assert_eq!(formatted.trim_end(), expected);
+
+        let slashes = Formatter::new(4, 100).with_line_comment_prefix(Some(CommentPrefix::DoubleSlash));
+        let formatted = slashes.format_str(content).unwrap();
+        let expected = "//! module doc\n// hash with // inside\n// slashes with # inside\n/* block # // */\n#!not a doc\n\nvar {\n    a = 1; // inline\n};";
+        assert_eq!(formatted.trim_end(), expected);
+
+        // Comments keep their prefix by default
+        assert_eq!(Formatter::new(4, 100).format_str(content).unwrap().trim_end(), content);
+    }
+
+    #[test]
+    fn test_format_trailing_comma_multiline() {
+        let content = "var {\n    items = [100000, 200000, 300000];\n    point = (1, 2);\n    one = (1,);\n};";
+        let formatter = Formatter::new(4, 30).with_trailing_comma_multiline(true);
+        let formatted = formatter.format_str(content).unwrap();
+        let expected = "    items = [\n        100000,\n        200000,\n        300000,\n    ];";
+        assert!(formatted.contains(expected), "got:\n{}", formatted);
+        // Collections that stay on one line are left alone
+        assert!(formatted.contains("    point = (1, 2);"), "got:\n{}", formatted);
+        assert!(formatted.contains("    one = (1,);"), "got:\n{}", formatted);
+
+        let plain = Formatter::new(4, 30).format_str(content).unwrap();
+        assert!(plain.contains("        300000\n    ];"), "got:\n{}", plain);
+    }
+
+    #[test]
+    fn test_detect_indent() {
+        let two = "graph {\n  a = foo(x)\n    .with(k=1);\n  b = bar(a);\n} as main;\nvar {\n  n = 1;\n};";
+        let four = "graph {\n    a = foo(x);\n\n    b = bar(a);\n} as main;\nop {\n    input {\n        x: int;\n    }\n} as my_op;";
+        assert_eq!(detect_indent(two), 2);
+        assert_eq!(detect_indent(four), 4);
+        assert_eq!(detect_indent("var { n = 1; };"), 4);
+        assert_eq!(detect_indent(&four.replace("    ", "        ")), 8);
+    }
+
+    #[test]
+    fn test_format_sort_imports() {
+        let content = "import zeta.ops, alpha.io as io, mid;";
+        let sorted = Formatter::new(4, 100).with_sort_imports(true).format_str(content).unwrap();
+        assert_eq!(sorted.trim_end(), "import alpha.io as io, mid, zeta.ops;");
+
+        // Wrapped imports are sorted too, aliases stay with their paths
+        let wrapped = Formatter::new(4, 20).with_sort_imports(true).format_str(content).unwrap();
+        assert!(wrapped.starts_with("import alpha.io as io,\n"), "got:\n{}", wrapped);
+        assert!(wrapped.trim_end().ends_with("zeta.ops;"), "got:\n{}", wrapped);
+
+        // Source order by default
+        let unsorted = Formatter::new(4, 100).format_str(content).unwrap();
+        assert_eq!(unsorted.trim_end(), content);
+        assert!(crate::parse(&sorted).is_ok(), "got:\n{}", sorted);
+    }
+
+    #[test]
+    fn test_format_single_semicolon() {
+        let fixtures = [
+            r#"import a.b as c, d;"#,
+            r#"var { name = "test"; limit = 10; } as config;"#,
+            r#"var { items = [1, 2, 3]; dict = {"a": 1}; };"#,
+            r#"graph { a = foo(x).version("1.0.0"); b, c = bar(a).as(alias); } as main.version("1.0");"#,
+            r#"graph { r = a > 0 && b < 10 ? foo(a) : bar(b); };"#,
+            "var {\n    text = \"\"\"a;\nb;\"\"\";\n};\ngraph { x = foo(); };",
+        ];
+        for fixture in fixtures {
+            let formatted = format_from_data(fixture, 4, 100).unwrap();
+            assert!(!formatted.contains(";;"), "double semicolon in:\n{}", formatted);
+            assert_eq!(
+                formatted.matches(';').count(),
+                fixture.matches(';').count(),
+                "semicolon count changed for:\n{}",
+                formatted
+            );
+        }
+    }
 }
\ No newline at end of file
@@ -36,28 +36,45 @@
 pub mod ast;
 pub mod compiler;
 pub mod decompiler;
+pub mod dependency;
+pub mod diff;
+pub mod dot;
 pub mod error;
 pub mod format;
 pub mod parser;
+pub mod plan;
+pub mod pragma;
+pub mod source;
 
 #[cfg(test)]
 pub mod tests;
 
 // Re-export main types for convenience
 pub use ast::*;
-pub use compiler::{compile_ast, compile_ast_with_options, Compiler, CompileOptions, CompileResult};
-pub use decompiler::{decompile, decompile_from_data, DecompileOptions, DecompileResult};
-pub use error::{ParseError, ParseResult, ErrorCollection};
-pub use format::{format_from_data, format, Formatter, IndentBuffer};
-pub use parser::{parse_gos, ParseOptions};
+pub use compiler::{check_op_inputs, compile_ast, compile_result_fingerprint, compile_ast_with_options, compile_result_to_ast, compile_to_compact_json, validate_module_wiring, validate_node_outputs, validate_wiring, Compiler, CompileOptions, CompileResult, VarEncoding, VarPair, Vars};
+pub use dependency::{module_dependency_graph, DepEdge, DepEdgeKind, DepGraph, DepNode, DepNodeKind};
+pub use diff::{diff_compile_results, CompileDiff, DiffKind};
+pub use dot::{module_to_dot, to_dot};
+pub use decompiler::{analyze_json, decompile, decompile_from_data, decompile_to_writer, DecompileOptions, DecompileResult, EscapePolicy, FeatureSet};
+pub use error::{ParseError, ParseResult, ErrorCollection, Severity};
+pub use format::{detect_indent, CommentPrefix, format_from_data, format, Formatter, IndentBuffer};
+pub use parser::{check_name_shadowing, check_quote_consistency, parse_embedded, parse_for_completion, parse_gos, parse_gos_with_errors, parse_with_imports, reparse, Case, CompletionContext, CompletionKind, ParseOptions, Parser, TextEdit};
+pub use plan::{to_execution_plan, to_pipeline, ExecutionPlan, Pipeline, PipelineGraph, PlanInput, PlannedNode};
+pub use pragma::Pragma;
+pub use source::Source;
 
 /// Parse GOS content with default options (AST mode enabled)
 pub fn parse(content: &str) -> ParseResult<AstNodeEnum> {
-    parse_gos(content, ParseOptions {
+    let options = ParseOptions {
         ast: true,
         tracking: true,
         ..Default::default()
-    })
+    };
+    let options = match Pragma::from_source(content)? {
+        Some(pragma) => pragma.parse_options(options),
+        None => options,
+    };
+    parse_gos(content, options)
 }
 
 /// Parse GOS content with error collection enabled
@@ -68,21 +85,7 @@ pub fn parse_with_errors(content: &str) -> (Option<AstNodeEnum>, ErrorCollection
         tracking: true,
         ..Default::default()
     };
-    
-    match parse_gos(content, options) {
-        Ok(ast) => (Some(ast), ErrorCollection::new()),
-        Err(ParseError::General { message }) => {
-            // Try to extract error collection from general error
-            let mut errors = ErrorCollection::new();
-            errors.add_error(ParseError::General { message });
-            (None, errors)
-        }
-        Err(error) => {
-            let mut errors = ErrorCollection::new();
-            errors.add_error(error);
-            (None, errors)
-        }
-    }
+    parse_gos_with_errors(content, options)
 }
 
 /// Validate GOS syntax without building AST
@@ -95,11 +98,168 @@ pub fn validate(content: &str) -> ParseResult<()> {
     Ok(())
 }
 
+/// Validate several files, returning the errors of each path in the given order
+///
+/// A file that cannot be read gets a single `ParseError::Io` and the batch
+/// carries on with the next file.
+pub fn validate_files(paths: &[&str]) -> Vec<(String, ErrorCollection)> {
+    paths
+        .iter()
+        .map(|path| {
+            let errors = match std::fs::read_to_string(path) {
+                Ok(content) => {
+                    let options = ParseOptions {
+                        ast: false,
+                        error: true,
+                        ..Default::default()
+                    };
+                    parse_gos_with_errors(&content, options).1
+                }
+                Err(e) => {
+                    let mut errors = ErrorCollection::new();
+                    errors.add_error(ParseError::Io(format!("{}: {}", path, e)));
+                    errors
+                }
+            };
+            (path.to_string(), errors)
+        })
+        .collect()
+}
+
+/// Parse, compile and validate GOS content into a `Pipeline`
+///
+/// On top of compiling with `check_graph_refs`, every graph is checked with
+/// `validate_module_wiring` and `validate_node_outputs`, and its nodes are ordered
+/// with `to_execution_plan`. All the validation errors are reported together.
+pub fn load_pipeline(content: &str) -> ParseResult<Pipeline> {
+    let ast = parse(content)?;
+    let mut errors = ErrorCollection::new();
+    if let AstNodeEnum::Module(module) = &ast {
+        validate_module_wiring(module).into_iter().for_each(|error| errors.add_error(error));
+    }
+    errors.into_result(())?;
+
+    let options = CompileOptions {
+        check_graph_refs: true,
+        ..Default::default()
+    };
+    let result = compile_ast_with_options(&ast, options)?;
+    let ops = result.ops.as_deref().unwrap_or_default();
+    let mut errors = ErrorCollection::new();
+    for graph in result.graphs.iter().flatten() {
+        validate_node_outputs(graph, ops).into_iter().for_each(|error| errors.add_error(error));
+    }
+    errors.into_result(())?;
+    to_pipeline(&result)
+}
+
+/// Parse GOS content and serialize the syntax tree, positions included, to JSON
+///
+/// Unlike the compiler output this keeps every AST node, so tools can work on
+/// the tree itself; `keep_comments = false` drops the `Comment` nodes.
+/// `parse_from_ast_json` reads it back.
+pub fn parse_to_ast_json(content: &str, pretty: bool, keep_comments: bool) -> ParseResult<String> {
+    let mut ast = parse(content)?;
+    if !keep_comments {
+        strip_comments(&mut ast);
+    }
+    let json = if pretty {
+        serde_json::to_string_pretty(&ast)
+    } else {
+        serde_json::to_string(&ast)
+    };
+    json.map_err(|e| ParseError::general(format!("Failed to serialize AST: {}", e)))
+}
+
+/// Read a syntax tree written by `parse_to_ast_json`
+pub fn parse_from_ast_json(json: &str) -> ParseResult<AstNodeEnum> {
+    serde_json::from_str(json)
+        .map_err(|e| ParseError::general(format!("Failed to deserialize AST: {}", e)))
+}
+
 /// Get version information
 pub fn version() -> &'static str {
     env!("CARGO_PKG_VERSION")
 }
 
+/// GOS language spec version this implementation targets
+///
+/// Written as `gos_version` in compiled output; unrelated to the crate version.
+pub fn language_version() -> &'static str {
+    "0.5.2"
+}
+
+/// Get a human-readable summary of the GOS syntax
+///
+/// The summary is hand-maintained alongside `gos.pest`; each production is
+/// named after the pest rule it describes.
+pub fn grammar_summary() -> &'static str {
+    GRAMMAR_SUMMARY
+}
+
+const GRAMMAR_SUMMARY: &str = r##"GOS grammar summary
+===================
+
+gos                ::= { all_statements [";"] | COMMENT }
+all_statements     ::= import_def | var_def | graph_def | op_def | node_def
+COMMENT            ::= "#" { any character except newline } | DOC_COMMENT | MODULE_DOC_COMMENT
+                   | "//" { any character except newline } | "/*" { any character } "*/"
+DOC_COMMENT        ::= "///" { any character except newline }
+MODULE_DOC_COMMENT ::= "//!" { any character except newline }
+
+import_def         ::= "import" dotted_name ["as" name] { "," dotted_name ["as" name] }
+
+var_def            ::= "var" "{" { attr_def [";"] } "}" ["as" name]
+attr_def           ::= dotted_name "=" value ["if" if_condition] ["else" value]
+
+annotations        ::= annotation { annotation }
+annotation         ::= "@" name ["(" [annotation_arg { "," annotation_arg } [","]] ")"]
+annotation_arg     ::= value | dotted_name
+
+graph_def          ::= [annotations] ("graph" | "graph" ":" as_stmt) "{" { graph_stmt } "}" ["as" as_stmt]
+as_stmt            ::= dotted_name ["(" string ")"]
+graph_stmt         ::= comma_dotted_names "=" (condition_section | node_block | value | comma_dotted_names | for_loop_block) [";"]
+
+node_def           ::= comma_dotted_names "=" (node_block | for_loop_block)
+node_block         ::= dotted_name "(" [inputs_def] ")" { node_attrs }
+                   | "ref" "(" dotted_name "(" [inputs_def] ")" ")" { node_attrs }
+inputs_def         ::= name "=" input { "," name "=" input } | input { "," input }
+input              ::= input_name | value | "(" input_name { "," input_name } [","] ")"
+input_name         ::= dotted_name { "." digits | "[" digits "]" }
+node_attrs         ::= ".version" "(" string ")" | ".name" "(" string ")"
+                   | ".as" "(" name ")" | ".depend" "(" comma_dotted_names ")"
+                   | ".with" "(" param_defs ")" | ".property" "(" param_defs ")"
+                   | ".condition" "(" string ")" | ".override" "(" bool ")"
+                   | ".log" "(" param_defs ")" | ".metrics" "(" param_defs ")" | ".funnel" "(" param_defs ")"
+                   | "." name "(" [param_defs] ")"
+for_loop_block     ::= "[" node_block "for" comma_dotted_names "in" dotted_name ["if" if_condition] "]"
+
+condition_section  ::= condition_stmt "?" (condition_section | node_block) ":" (condition_section | node_block)
+condition_stmt     ::= condition_stmt "||" condition_stmt | condition_stmt "&&" condition_stmt | "!" condition_stmt
+                   | "(" condition_stmt ")" | comparison_stmt | node_block | if_condition
+comparison_stmt    ::= operand (">" | ">=" | "<" | "<=" | "==" | "!=") operand | name "~=" string
+
+op_def             ::= [annotations] "op" "{" { op_section [";"] } "}" ["as" as_stmt]
+op_section         ::= "meta" "{" { attr_def [";"] } "}"
+                   | ("input" | "output" | "config") "{" { name ":" op_spec_def [";"] } "}"
+                   | graph_def
+op_spec_def        ::= dtype | "(" op_spec_key_def { "," op_spec_key_def } ")"
+op_spec_key_def    ::= "dtype" "=" dtype | "default" "=" value | "pattern" "=" string
+                   | "help" "=" string | "choice" "=" tuple_stmt | "length" "=" (number | closed_interval)
+                   | "range" "=" mix_interval | "url" "=" string | "fname" "=" string
+
+value              ::= string | multi_line_string | number | float | bool | datetime | "null"
+                   | "date" "(" string ")" | dict_stmt | list_stmt | tuple_stmt | set_stmt
+STRING             ::= '"' { char | escape } '"' | "'" { char | escape } "'"
+                   | 'r"' { char } '"' | "r'" { char } "'"   (raw, no escapes)
+dict_stmt          ::= "{" [string ":" value { "," string ":" value } [","]] "}"
+list_stmt          ::= "[" [value { "," value } [","]] "]"
+tuple_stmt         ::= "(" [value { "," value } [","]] ")"
+set_stmt           ::= "{" value "," [value { "," value } [","]] "}"
+comma_dotted_names ::= dotted_name { "," dotted_name } [","]   (trailing "," with allow_trailing_comma)
+dotted_name        ::= name { "." name }
+"##;
+
 #[cfg(test)]
 mod mytests {
     use super::*;
@@ -171,6 +331,79 @@ mod mytests {
         assert!(ver.contains('.'), "Version should contain dots");
     }
 
+    #[test]
+    fn test_ast_json_round_trip() {
+        let content = r#"
+import a.b as c;
+var { name = "test"; ratio = 0.5; tags = ["a", "b"]; } as config;
+graph {
+    a = foo(x).with(k=1).version("1.0");
+    r = a > 0 ? foo(a) : bar(a);
+} as main;
+op { input { x: (dtype=int, length=[1, 5]); }; } as my_op;
+"#;
+        let ast = parse(content).unwrap();
+        for pretty in [false, true] {
+            let json = parse_to_ast_json(content, pretty, true).unwrap();
+            assert_eq!(json.contains('\n'), pretty);
+            assert_eq!(parse_from_ast_json(&json).unwrap(), ast);
+        }
+        assert!(parse_from_ast_json("{\"NotANode\": {}}").is_err());
+    }
+
+    #[test]
+    fn test_ast_json_comments() {
+        let content = r#"# header
+var { name = "test"; } as config;
+graph {
+    # inside graph
+    a = foo(x);
+} as main;
+"#;
+        let count_comments = |json: &str| json.matches("\"Comment\"").count();
+
+        let kept = parse_to_ast_json(content, false, true).unwrap();
+        assert_eq!(count_comments(&kept), 2, "{}", kept);
+        assert_eq!(parse_from_ast_json(&kept).unwrap(), parse(content).unwrap());
+
+        let stripped = parse_to_ast_json(content, false, false).unwrap();
+        assert_eq!(count_comments(&stripped), 0, "{}", stripped);
+        let ast = parse_from_ast_json(&stripped).unwrap();
+        let compile = |ast: &AstNodeEnum| serde_json::to_value(compile_ast(ast).unwrap()).unwrap();
+        assert_eq!(compile(&ast), compile(&parse(content).unwrap()));
+    }
+
+    #[test]
+    fn test_language_version() {
+        let ver = language_version();
+        let parts: Vec<&str> = ver.split('.').collect();
+        assert_eq!(parts.len(), 3, "expected major.minor.patch, got {}", ver);
+        assert!(parts.iter().all(|part| part.parse::<u32>().is_ok()), "non-numeric part in {}", ver);
+
+        let ast = parse("graph { a = foo(); };").unwrap();
+        assert_eq!(compile_ast(&ast).unwrap().gos_version, ver);
+    }
+
+    #[test]
+    fn test_grammar_summary() {
+        let summary = grammar_summary();
+        assert!(!summary.is_empty());
+        for keyword in ["var", "graph", "op", "import", "node_block", "value"] {
+            assert!(summary.contains(keyword), "Summary should mention {}", keyword);
+        }
+
+        // Every production in the summary should name a rule in the pest grammar
+        let grammar = include_str!("gos.pest");
+        for line in summary.lines() {
+            if let Some((rule, _)) = line.split_once("::=") {
+                let rule = rule.trim();
+                if !rule.is_empty() {
+                    assert!(grammar.contains(&format!("{} = ", rule)), "Unknown rule {}", rule);
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_parse_with_errors_succ() {
         let content = r#"
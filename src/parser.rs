@@ -4,12 +4,17 @@
 //! into AST nodes.
 #![allow(dead_code)]
 
-use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use pest_derive::Parser;
 use regex::Regex;
 
 use crate::ast::*;
-use crate::error::{ErrorCollection, ParseError, ParseResult};
+use crate::error::{ErrorCollection, ParseError, ParseResult, Severity};
 
 #[derive(Parser)]
 #[grammar = "gos.pest"]
@@ -30,6 +35,183 @@ pub struct ParseOptions {
     pub tracking: bool,
     /// Enable debug mode
     pub debug: bool,
+    /// Stop collecting errors after this many, unlimited if `None`
+    pub max_errors: Option<usize>,
+    /// Warn about defined identifiers not written in this case
+    pub identifier_case: Option<Case>,
+    /// Accept a trailing comma after a name list, e.g. `a, b, = foo();`
+    pub allow_trailing_comma: bool,
+    /// Accept `true`, `false` and `null` in any case, e.g. `TRUE` or `Null`
+    pub case_insensitive_keywords: bool,
+}
+
+/// Identifier naming convention checked by `ParseOptions::identifier_case`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    /// `snake_case`
+    Snake,
+    /// `camelCase`
+    Camel,
+    /// `PascalCase`
+    Pascal,
+}
+
+impl Case {
+    /// Whether `name` is written in this case; digits are allowed after the first character
+    pub fn matches(self, name: &str) -> bool {
+        let mut chars = name.chars();
+        let Some(first) = chars.next() else {
+            return false;
+        };
+        match self {
+            Case::Snake => {
+                (first.is_ascii_lowercase() || first == '_')
+                    && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            }
+            Case::Camel => first.is_ascii_lowercase() && chars.all(|c| c.is_ascii_alphanumeric()),
+            Case::Pascal => first.is_ascii_uppercase() && chars.all(|c| c.is_ascii_alphanumeric()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Case::Snake => "snake_case",
+            Case::Camel => "camelCase",
+            Case::Pascal => "PascalCase",
+        }
+    }
+}
+
+/// Collects a warning for every defined identifier not written in `case`
+struct CaseChecker {
+    case: Case,
+    warnings: Vec<ParseError>,
+}
+
+impl Visitor<'_> for CaseChecker {
+    fn visit_symbol(&mut self, symbol: &Symbol) {
+        // Only names the user defines; references may point at external ops or files
+        let defines = matches!(
+            symbol.kind,
+            SymbolKind::ImportAsName
+                | SymbolKind::VarAttr
+                | SymbolKind::VarAsName
+                | SymbolKind::GraphProperty
+                | SymbolKind::GraphAsName
+                | SymbolKind::NodeOutput
+                | SymbolKind::NodeAsName
+                | SymbolKind::OpAsName
+                | SymbolKind::ForLoopOutputs
+        );
+        if defines && !self.case.matches(&symbol.name) {
+            self.warnings.push(ParseError::semantic_error(
+                symbol.position.line,
+                symbol.position.start,
+                format!("Identifier {} is not {}", symbol.name, self.case.name()),
+            ));
+        }
+    }
+}
+
+/// Warn at each string literal quoted differently from the first one in the
+/// module, e.g. `'x'` in a file that starts with `"y"`
+///
+/// Multi-line strings are not checked, they are always written with `"""`.
+pub fn check_quote_consistency(module: &Module) -> Vec<ParseError> {
+    let quoted: Vec<(&Position, QuoteStyle)> = string_literals(module)
+        .into_iter()
+        .filter_map(|(position, _, quote)| Some((position, quote?)))
+        .collect();
+    let Some(&(_, style)) = quoted.first() else {
+        return Vec::new();
+    };
+    let expected = match style {
+        QuoteStyle::Double => "double",
+        QuoteStyle::Single => "single",
+    };
+    quoted
+        .into_iter()
+        .filter(|(_, quote)| *quote != style)
+        .map(|(position, _)| {
+            ParseError::warning(
+                position.line,
+                position.start,
+                format!("String literal is not in {} quotes like the rest of the file", expected),
+            )
+        })
+        .collect()
+}
+
+/// Collects import, var and graph names and the node outputs that may shadow them
+#[derive(Default)]
+struct ShadowChecker<'a> {
+    names: HashMap<&'a str, &'static str>,
+    outputs: Vec<&'a Symbol>,
+}
+
+impl<'a> Visitor<'a> for ShadowChecker<'a> {
+    fn visit_symbol(&mut self, symbol: &'a Symbol) {
+        let kind = match symbol.kind {
+            SymbolKind::ImportName | SymbolKind::ImportAsName => "import",
+            SymbolKind::VarAsName => "var",
+            SymbolKind::GraphAsName => "graph",
+            SymbolKind::NodeOutput => {
+                self.outputs.push(symbol);
+                return;
+            }
+            _ => return,
+        };
+        self.names.entry(&symbol.name).or_insert(kind);
+    }
+}
+
+/// Warn at each node output named like an import, var alias or graph alias,
+/// e.g. `config = load(x);` in a module with `var { ... } as config;`
+///
+/// Names are collected from the whole module first, so a definition after
+/// the node still counts.
+pub fn check_name_shadowing(module: &Module) -> Vec<ParseError> {
+    let mut checker = ShadowChecker::default();
+    module.walk(&mut checker);
+    checker
+        .outputs
+        .iter()
+        .filter_map(|output| {
+            let kind = checker.names.get(output.name.as_str())?;
+            Some(ParseError::warning(
+                output.position.line,
+                output.position.start,
+                format!("Node output {} shadows the {} name {}", output.name, kind, output.name),
+            ))
+        })
+        .collect()
+}
+
+impl ParseOptions {
+    /// Recommended options for validation: build the AST with position
+    /// tracking and fail on any collected error
+    pub fn strict() -> Self {
+        Self {
+            ast: true,
+            error: true,
+            tracking: true,
+            ..Default::default()
+        }
+    }
+
+    /// Recommended options for editors and tooling: build the AST with
+    /// symbols and position tracking, returning it even if errors were
+    /// collected, and accept trailing commas and keywords in any case
+    pub fn lenient() -> Self {
+        Self {
+            ast: true,
+            symbol: true,
+            tracking: true,
+            allow_trailing_comma: true,
+            case_insensitive_keywords: true,
+            ..Default::default()
+        }
+    }
 }
 
 /// Main parsing function - entry point for GOS parsing
@@ -39,6 +221,458 @@ pub fn parse_gos(content: &str, options: ParseOptions) -> ParseResult<AstNodeEnu
     parser.parse(content)
 }
 
+/// Value of a `NUMBER`, e.g. `-0x1F`, `0b101` or `1_000`, `None` past `i64`
+fn parse_integer(raw: &str) -> Option<i64> {
+    let digits = raw.replace('_', "");
+    let (negative, digits) = match digits.as_bytes().first() {
+        Some(b'-') => (true, &digits[1..]),
+        Some(b'+') => (false, &digits[1..]),
+        _ => (false, digits.as_str()),
+    };
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x" | "0X") => (16, &digits[2..]),
+        Some("0b" | "0B") => (2, &digits[2..]),
+        _ => (10, digits),
+    };
+    // Parse with the sign so that `i64::MIN` fits
+    let signed = if negative { format!("-{}", digits) } else { digits.to_string() };
+    i64::from_str_radix(&signed, radix).ok()
+}
+
+/// Drop the backticks of quoted identifiers, `` `my node`.out `` reads as `my node.out`
+fn unquote_identifier(text: &str) -> String {
+    text.replace('`', "")
+}
+
+/// Reusable parser for parsing many sources with the same options
+///
+/// The pest grammar is compiled into `GosParser` and the escape regex is
+/// built once per process, so each `parse` call only pays for the parse itself.
+#[derive(Debug, Clone, Default)]
+pub struct Parser {
+    options: ParseOptions,
+}
+
+impl Parser {
+    pub fn new(options: ParseOptions) -> Self {
+        Self { options }
+    }
+
+    pub fn options(&self) -> &ParseOptions {
+        &self.options
+    }
+
+    pub fn parse(&self, content: &str) -> ParseResult<AstNodeEnum> {
+        parse_gos(content, self.options.clone())
+    }
+}
+
+/// Parse GOS content, returning the AST together with all collected errors
+///
+/// With `options.error` set, a syntax error does not stop parsing: the source
+/// is re-parsed statement by statement and one error is collected for each
+/// broken statement, up to `options.max_errors` or the first error that is not
+/// `ParseError::is_recoverable`. Deprecated syntax is collected
+/// in `warnings` and does not drop the AST; with `options.identifier_case` set,
+/// defined names in another case are collected there too.
+pub fn parse_gos_with_errors(content: &str, options: ParseOptions) -> (Option<AstNodeEnum>, ErrorCollection) {
+    let mut parser = GosParserImpl::new(options);
+    let recover = parser.options.error;
+    match parser.parse_collect(content, recover) {
+        Ok(ast) if !parser.errors.has_errors() => {
+            if let Some(case) = parser.options.identifier_case {
+                let mut checker = CaseChecker { case, warnings: Vec::new() };
+                ast.walk(&mut checker);
+                checker.warnings.into_iter().for_each(|warning| parser.add_warning(warning));
+            }
+            (Some(ast), parser.errors)
+        }
+        Ok(_) => (None, parser.errors),
+        Err(error) => {
+            parser.add_error(error);
+            (None, parser.errors)
+        }
+    }
+}
+
+/// A single text replacement, given as byte offsets into the old source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    /// Start of the replaced range
+    pub start: usize,
+    /// End of the replaced range (exclusive)
+    pub end: usize,
+    /// Text inserted in place of the range
+    pub text: String,
+}
+
+/// Re-parse `new_src` after `edit` was applied to `old_src`
+///
+/// Top-level statements entirely before the edited lines are reused as-is and
+/// statements after them are reused with their lines shifted; only the lines
+/// in between are parsed again. Falls back to a full parse when the edited
+/// region does not parse on its own.
+pub fn reparse(old: &Module, old_src: &str, new_src: &str, edit: TextEdit) -> ParseResult<Module> {
+    if edit.start > edit.end || edit.end > old_src.len() {
+        return Err(ParseError::general(format!(
+            "Invalid edit range {}..{}",
+            edit.start, edit.end
+        )));
+    }
+
+    let edit_first_line = old_src[..edit.start].matches('\n').count() + 1;
+    let edit_last_line = old_src[..edit.end].matches('\n').count() + 1;
+    let delta = edit.text.matches('\n').count() as isize
+        - old_src[edit.start..edit.end].matches('\n').count() as isize;
+
+    let children = &old.children;
+    let mut prefix = children
+        .iter()
+        .take_while(|child| child.position().end_line < edit_first_line)
+        .count();
+    let mut suffix = children.len()
+        - children
+            .iter()
+            .rposition(|child| child.position().line <= edit_last_line)
+            .map_or(0, |index| index + 1);
+    suffix = suffix.min(children.len() - prefix);
+
+    // Reused statements must not share a line with the re-parsed region
+    while prefix > 0
+        && prefix + suffix < children.len()
+        && children[prefix].position().line <= children[prefix - 1].position().end_line
+    {
+        prefix -= 1;
+    }
+    while suffix > 0 {
+        let first_kept = children.len() - suffix;
+        let shares_line = first_kept > prefix
+            && children[first_kept - 1].position().end_line >= children[first_kept].position().line;
+        if !shares_line {
+            break;
+        }
+        suffix -= 1;
+    }
+
+    let region_first_line = if prefix > 0 {
+        children[prefix - 1].position().end_line + 1
+    } else {
+        1
+    };
+    let region_last_line = if suffix > 0 {
+        children[children.len() - suffix].position().line.saturating_add_signed(delta) - 1
+    } else {
+        usize::MAX
+    };
+    let region = new_src
+        .split_inclusive('\n')
+        .skip(region_first_line - 1)
+        .take(region_last_line.saturating_sub(region_first_line - 1))
+        .collect::<String>();
+
+    let options = ParseOptions {
+        ast: true,
+        tracking: true,
+        ..Default::default()
+    };
+    let mut middle = match parse_gos(&region, options.clone()) {
+        Ok(AstNodeEnum::Module(module)) => module.children,
+        _ => {
+            return match parse_gos(new_src, options)? {
+                AstNodeEnum::Module(module) => Ok(module),
+                _ => Err(ParseError::general("Expected Module as root AST node")),
+            };
+        }
+    };
+    middle.shift_lines(region_first_line as isize - 1);
+
+    let mut new_children = children[..prefix].to_vec();
+    new_children.extend(middle);
+    for child in &children[children.len() - suffix..] {
+        let mut child = child.clone();
+        child.shift_lines(delta);
+        new_children.push(child);
+    }
+
+    let mut position = old.position.clone();
+    position.end_line = position.end_line.saturating_add_signed(delta);
+    Ok(Module {
+        position,
+        children: new_children,
+    })
+}
+
+/// Parse every GOS fragment between `start_marker` and `end_marker` in a
+/// larger document, e.g. a markdown file with "```gos" fences
+///
+/// Each fragment is returned with its position in the document, and the AST
+/// and errors of a fragment carry document lines and columns. A start marker
+/// without a matching end marker is ignored.
+pub fn parse_embedded(content: &str, start_marker: &str, end_marker: &str) -> Vec<(Position, ParseResult<AstNodeEnum>)> {
+    let mut fragments = Vec::new();
+    let mut offset = 0;
+    while let Some(found) = content[offset..].find(start_marker) {
+        let start = offset + found + start_marker.len();
+        let Some(length) = content[start..].find(end_marker) else {
+            break;
+        };
+        let end = start + length;
+        offset = end + end_marker.len();
+
+        let (line, column) = line_column(content, start);
+        let (end_line, end_column) = line_column(content, end);
+        // Pad the first line so columns match the document, then shift the lines
+        let padded = format!("{}{}", " ".repeat(column - 1), &content[start..end]);
+        let delta = line as isize - 1;
+        let result = parse_gos(&padded, ParseOptions {
+            ast: true,
+            tracking: true,
+            ..Default::default()
+        });
+        let result = match result {
+            Ok(mut ast) => {
+                ast.shift_lines(delta);
+                Ok(ast)
+            }
+            Err(mut error) => {
+                error.shift_line(delta);
+                Err(error)
+            }
+        };
+        fragments.push((Position::new_all(line, end_line, column, end_column), result));
+    }
+    fragments
+}
+
+/// 1-based line and column of byte offset `offset`, columns counted in chars
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+/// What an editor can offer at a completion cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    /// A top-level keyword: `import`, `var`, `graph` or `op`
+    Keyword,
+    /// The op called by a node, after `output =` in a graph
+    OpName,
+    /// A node attribute after `).`, e.g. `with` or `version`
+    NodeAttribute,
+    /// A node input inside the call parentheses
+    NodeInput,
+    /// A var or property value after `=`
+    Value,
+    /// A new name at the start of a statement inside a block
+    Name,
+    /// Nothing sensible to offer
+    Unknown,
+}
+
+/// Result of `parse_for_completion`
+#[derive(Debug, Clone)]
+pub struct CompletionContext {
+    pub kind: CompletionKind,
+    /// The partially typed (possibly dotted) word before the cursor
+    pub prefix: String,
+    /// Grammar rules the parser expected at the start of `prefix`
+    pub expected: Vec<String>,
+    /// The statements before the cursor that parse, recovered past the broken one
+    pub partial: Option<AstNodeEnum>,
+}
+
+/// Describe what can be typed at byte offset `cursor` of possibly incomplete input
+///
+/// Never fails: the statements that parse are returned as `partial`, and the
+/// kind is derived from the enclosing block and the text before the word at
+/// the cursor.
+pub fn parse_for_completion(content: &str, cursor: usize) -> CompletionContext {
+    use pest::Parser;
+
+    let mut cursor = cursor.min(content.len());
+    while !content.is_char_boundary(cursor) {
+        cursor -= 1;
+    }
+    let text = &content[..cursor];
+    let prefix_start = text
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_alphanumeric() || *c == '_' || *c == '.')
+        .last()
+        .map_or(text.len(), |(index, _)| index);
+    let (head, mut prefix) = (&text[..prefix_start], &text[prefix_start..]);
+
+    // A character no rule accepts makes pest report the expected set at `head`'s end
+    let expected = match GosParser::parse(Rule::gos, &format!("{}\u{1}", head)) {
+        Err(error) if error.location == pest::error::InputLocation::Pos(head.len()) => match error.variant {
+            pest::error::ErrorVariant::ParsingError { positives, .. } => {
+                positives.iter().map(|rule| format!("{:?}", rule)).collect()
+            }
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+
+    let before = head.trim_end();
+    let kind = if before.ends_with(')') && prefix.starts_with('.') {
+        prefix = &prefix[1..];
+        CompletionKind::NodeAttribute
+    } else {
+        match (enclosing_block(before).as_deref(), before.chars().last()) {
+            (None, None | Some(';' | '}')) => CompletionKind::Keyword,
+            (Some("graph"), Some('=')) => CompletionKind::OpName,
+            (Some("graph"), Some('(' | ',')) => CompletionKind::NodeInput,
+            (Some(_), Some('=' | ':')) => CompletionKind::Value,
+            (Some(_), Some(';' | '{' | '}')) => CompletionKind::Name,
+            _ => CompletionKind::Unknown,
+        }
+    };
+
+    let mut parser = GosParserImpl::new(ParseOptions {
+        ast: true,
+        error: true,
+        tracking: true,
+        ..Default::default()
+    });
+    let partial = parser.parse_collect(head, true).ok();
+
+    CompletionContext {
+        kind,
+        prefix: prefix.to_string(),
+        expected,
+        partial,
+    }
+}
+
+/// Keyword of the innermost brace block still open at the end of `text`,
+/// `dict` for a brace opening a value
+///
+/// Braces in strings and comments are skipped; an unterminated one runs to
+/// the end of `text`, or of its line for a single-line string or comment.
+fn enclosing_block(text: &str) -> Option<String> {
+    let mut blocks = Vec::new();
+    let mut index = 0;
+    while let Some(c) = text[index..].chars().next() {
+        let rest = &text[index..];
+        let skip = if rest.starts_with("\"\"\"") || rest.starts_with("'''") {
+            rest[3..].find(&rest[..3]).map_or(rest.len(), |end| end + 6)
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            body.find("*/").map_or(rest.len(), |end| end + 4)
+        } else if rest.starts_with('#') || rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if c == '"' || c == '\'' {
+            string_len(rest, text[..index].ends_with('r'))
+        } else {
+            match c {
+                '{' => {
+                    let before = text[..index].trim_end();
+                    let word: String = before
+                        .chars()
+                        .rev()
+                        .take_while(|c| c.is_alphanumeric() || *c == '_')
+                        .collect::<Vec<_>>()
+                        .into_iter()
+                        .rev()
+                        .collect();
+                    blocks.push(if word.is_empty() { "dict".to_string() } else { word });
+                }
+                '}' => {
+                    blocks.pop();
+                }
+                _ => {}
+            }
+            c.len_utf8()
+        };
+        index += skip;
+    }
+    blocks.pop()
+}
+
+/// Length of the single-line string literal `text` starts with, quotes
+/// included; a raw string takes no escapes
+fn string_len(text: &str, raw: bool) -> usize {
+    let mut chars = text.char_indices();
+    let Some((_, quote)) = chars.next() else {
+        return 0;
+    };
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' if !raw => {
+                chars.next();
+            }
+            '\n' => return index,
+            c if c == quote => return index + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// Parse a GOS file and every file it imports, the imports first
+///
+/// `import a.b` reads `a/b.gos` relative to the importing file. Each file is
+/// parsed once; an import back into a file that is still being resolved fails
+/// with `ParseError::CircularImport` listing the cycle, e.g. `a -> b -> a`.
+pub fn parse_with_imports(path: impl AsRef<Path>) -> ParseResult<Vec<(PathBuf, AstNodeEnum)>> {
+    let path = path.as_ref();
+    let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut resolver = ImportResolver::default();
+    resolver.resolve(path, name)?;
+    Ok(resolver.modules)
+}
+
+/// Depth-first import walk state
+#[derive(Default)]
+struct ImportResolver {
+    /// Files being resolved, outermost first, with the name they were imported as
+    stack: Vec<(PathBuf, String)>,
+    done: HashSet<PathBuf>,
+    modules: Vec<(PathBuf, AstNodeEnum)>,
+}
+
+impl ImportResolver {
+    fn resolve(&mut self, path: &Path, name: String) -> ParseResult<()> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| ParseError::Io(format!("{}: {}", path.display(), e)))?;
+        if self.done.contains(&path) {
+            return Ok(());
+        }
+        let content = fs::read_to_string(&path)
+            .map_err(|e| ParseError::Io(format!("{}: {}", path.display(), e)))?;
+        let ast = parse_gos(&content, ParseOptions {
+            ast: true,
+            tracking: true,
+            ..Default::default()
+        })?;
+
+        self.stack.push((path.clone(), name));
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        if let AstNodeEnum::Module(module) = &ast {
+            for child in &module.children {
+                let AstNodeEnum::Import(import) = child else {
+                    continue;
+                };
+                for item in &import.items {
+                    let target = dir.join(item.path.name.replace('.', "/")).with_extension("gos");
+                    let target = target.canonicalize().unwrap_or(target);
+                    if let Some(start) = self.stack.iter().position(|(open, _)| *open == target) {
+                        let mut cycle: Vec<String> = self.stack[start..].iter().map(|(_, name)| name.clone()).collect();
+                        cycle.push(item.path.name.clone());
+                        return Err(ParseError::circular_import(cycle, item.position.line, item.position.start));
+                    }
+                    self.resolve(&target, item.path.name.clone())?;
+                }
+            }
+        }
+        self.stack.pop();
+        self.done.insert(path.clone());
+        self.modules.push((path, ast));
+        Ok(())
+    }
+}
+
 /// Internal parser implementation
 struct GosParserImpl {
     options: ParseOptions,
@@ -73,8 +707,21 @@ impl GosParserImpl {
     }
 
     fn parse(&mut self, content: &str) -> ParseResult<AstNodeEnum> {
+        let ast = self.parse_collect(content, false)?;
+        if self.options.error && self.errors.has_errors() {
+            self.errors.clone().into_result(ast)
+        } else {
+            Ok(ast)
+        }
+    }
+
+    fn parse_collect(&mut self, content: &str, recover: bool) -> ParseResult<AstNodeEnum> {
         use pest::Parser;
-        let pairs = GosParser::parse(Rule::gos, content).map_err(|e| ParseError::from(e))?;
+        let pairs = match GosParser::parse(Rule::gos, content) {
+            Ok(pairs) => pairs,
+            Err(_) if recover => return Ok(self.parse_recovering(content)),
+            Err(e) => return Err(ParseError::from(e)),
+        };
 
         let mut result = None;
         for pair in pairs {
@@ -88,16 +735,73 @@ impl GosParserImpl {
             }
         }
 
-        match result {
-            Some(ast) => {
-                if self.options.error && self.errors.has_errors() {
-                    self.errors.clone().into_result(ast)
-                } else {
-                    Ok(ast)
+        result.ok_or_else(|| ParseError::general("No valid GOS content found"))
+    }
+
+    /// Parse each top-level statement on its own, collecting an error for
+    /// every statement that fails instead of stopping at the first one
+    fn parse_recovering(&mut self, content: &str) -> AstNodeEnum {
+        use pest::Parser;
+        let mut children = Vec::new();
+        let mut error_count = 0;
+        let mut statements = split_statements(content).into_iter().peekable();
+
+        while let Some((line, column, statement)) = statements.next() {
+            // Pad the first line so columns match the original source
+            let padded = format!("{}{}", " ".repeat(column - 1), statement);
+            let line_offset = line as isize - 1;
+            let warning_count = self.errors.warnings.len();
+            let parsed = GosParser::parse(Rule::gos, &padded)
+                .map_err(ParseError::from)
+                .and_then(|mut pairs| match pairs.next() {
+                    Some(pair) => self.parse_gos_root(pair),
+                    None => Err(ParseError::general("No valid GOS content found")),
+                });
+            // Deprecations and other warnings stay warnings, at their line in the whole source
+            for warning in &mut self.errors.warnings[warning_count..] {
+                warning.shift_line(line_offset);
+            }
+
+            match parsed {
+                Ok(AstNodeEnum::Module(mut module)) => {
+                    module.children.shift_lines(line_offset);
+                    children.extend(module.children);
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(mut error) => {
+                    error.shift_line(line_offset);
+                    if error.severity() == Severity::Warning {
+                        self.add_warning(error);
+                        continue;
+                    }
+                    let recoverable = error.is_recoverable();
+                    self.add_error(error);
+                    if !recoverable {
+                        break;
+                    }
                 }
             }
-            None => Err(ParseError::general("No valid GOS content found")),
+
+            error_count += 1;
+            if self.options.max_errors.is_some_and(|max| error_count >= max) {
+                if statements.peek().is_some() {
+                    self.add_error(ParseError::general("too many errors, stopping"));
+                }
+                break;
+            }
         }
+
+        let position = match (children.first(), children.last()) {
+            (Some(first), Some(last)) => Position::new_all(
+                first.position().line,
+                last.position().end_line,
+                first.position().start,
+                last.position().end,
+            ),
+            _ => Position::new(1, 1, 1),
+        };
+        AstNodeEnum::Module(Module { position, children })
     }
 
     fn parse_gos_root(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
@@ -194,6 +898,7 @@ impl GosParserImpl {
 
     fn parse_var_def(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
         let position = self.get_position(&pair);
+        let mut annotations = Vec::new();
         let mut children = Vec::new();
         let mut alias = None;
         let offset = None;
@@ -202,6 +907,9 @@ impl GosParserImpl {
         for inner_pair in pair.into_inner() {
             self.debug(&inner_pair);
             match inner_pair.as_rule() {
+                Rule::annotations => {
+                    annotations = self.parse_annotations(inner_pair)?;
+                }
                 Rule::LBRACE | Rule::RBRACE | Rule::var => {
                     // skip var{}
                 }
@@ -216,7 +924,7 @@ impl GosParserImpl {
                 Rule::attr_defs => {
                     for attr_def_end_pair in inner_pair.into_inner() {
                         self.debug(&attr_def_end_pair);
-                        if let Ok(attr) = self.attr_def_comment(attr_def_end_pair) {
+                        if let Some(attr) = self.attr_def_comment(attr_def_end_pair)? {
                             children.push(attr);
                         }
                     }
@@ -230,23 +938,25 @@ impl GosParserImpl {
 
         Ok(AstNodeEnum::VarDef(VarDef {
             position,
+            annotations,
             children,
             alias,
             offset,
         }))
     }
 
-    fn attr_def_comment(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
+    /// Parse one attribute of a var block, `None` for a pair without an attribute
+    fn attr_def_comment(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<Option<AstNodeEnum>> {
         if pair.as_rule() == Rule::COMMENT {
-            return self.parse_comment(pair);
+            return self.parse_comment(pair).map(Some);
         }
         for inner_pair in pair.into_inner() {
             self.debug(&inner_pair);
             if inner_pair.as_rule() == Rule::attr_def {
-                return self.parse_attr_def(inner_pair);
+                return self.parse_attr_def(inner_pair).map(Some);
             }
         }
-        Err(ParseError::general("Invalid attribute definition"))
+        Ok(None)
     }
 
     fn parse_attr_def(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
@@ -309,6 +1019,15 @@ impl GosParserImpl {
                 Rule::dotted_as_names => {
                     items = self.parse_dotted_as_names(inner_pair)?;
                 }
+                Rule::empty_import => {
+                    // Point at the `;` right after the keyword
+                    let end = self.get_position(&inner_pair);
+                    return Err(ParseError::syntax_error(
+                        end.end_line,
+                        end.end,
+                        "import requires at least one module name",
+                    ));
+                }
                 _ => {}
             }
         }
@@ -366,6 +1085,7 @@ impl GosParserImpl {
 
     fn parse_graph_def(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
         let position = self.get_position(&pair);
+        let mut annotations = Vec::new();
         let mut children = Vec::new();
         let mut alias = None;
         let mut version = None;
@@ -376,6 +1096,9 @@ impl GosParserImpl {
         for graph_pair in pair.into_inner() {
             self.debug(&graph_pair);
             match graph_pair.as_rule() {
+                Rule::annotations => {
+                    annotations = self.parse_annotations(graph_pair)?;
+                }
                 Rule::COMMENT => {
                     children.push(self.parse_comment(graph_pair)?);
                 }
@@ -384,6 +1107,11 @@ impl GosParserImpl {
                     for stmt_pair in graph_pair.into_inner() {
                         self.debug(&stmt_pair);
                         if stmt_pair.as_rule() == Rule::graph_stmt {
+                            // A rejected trailing comma fails the graph, other statement errors are skipped
+                            let comma = stmt_pair.clone().into_inner().flatten().find(|p| p.as_rule() == Rule::trailing_comma);
+                            if let Some(comma) = comma {
+                                self.check_trailing_comma(&comma)?;
+                            }
                             if let Ok(stmt_node) = self.parse_graph_stmt(stmt_pair) {
                                 children.push(stmt_node);
                             }
@@ -401,6 +1129,7 @@ impl GosParserImpl {
 
         Ok(AstNodeEnum::GraphDef(GraphDef {
             position,
+            annotations,
             children,
             alias,
             version,
@@ -422,9 +1151,16 @@ impl GosParserImpl {
         for inner_pair in pair.into_inner() {
             self.debug(&inner_pair);
             match inner_pair.as_rule() {
+                Rule::dotted_name if has_version => {
+                    version = Some(AstNodeEnum::Symbol(Symbol {
+                        position: self.get_position(&inner_pair),
+                        name: inner_pair.as_str().to_string(),
+                        kind: SymbolKind::VarRef,
+                    }));
+                }
                 Rule::dotted_name => {
                     position = Some(self.get_position(&inner_pair));
-                    alias = Some(inner_pair.as_str().to_string());
+                    alias = Some(unquote_identifier(inner_pair.as_str()));
                 }
                 Rule::LPAREN | Rule::RPAREN => {
                     has_version = true;
@@ -480,22 +1216,320 @@ impl GosParserImpl {
         }
     }
 
-    fn parse_op_def(&mut self, _pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
-        // Simplified op parsing - implement based on needs
+    fn parse_op_def(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
+        let position = self.get_position(&pair);
+        let mut annotations = Vec::new();
+        let mut children = Vec::new();
+        let mut alias = None;
+        let mut version = None;
+
+        for op_pair in pair.into_inner() {
+            self.debug(&op_pair);
+            match op_pair.as_rule() {
+                Rule::annotations => {
+                    annotations = self.parse_annotations(op_pair)?;
+                }
+                Rule::op_block => {
+                    // op_block -> op_stmt -> op_sections -> op_section_comment
+                    let section_pairs = op_pair
+                        .into_inner()
+                        .flat_map(|stmt| stmt.into_inner())
+                        .flat_map(|sections| sections.into_inner())
+                        .flat_map(|section_comment| section_comment.into_inner());
+                    for section_pair in section_pairs {
+                        match section_pair.as_rule() {
+                            Rule::COMMENT => children.push(self.parse_comment(section_pair)?),
+                            Rule::op_section => {
+                                for inner_pair in section_pair.into_inner() {
+                                    children.push(self.parse_op_section(inner_pair)?);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Rule::as_stmt => {
+                    let (op_alias, op_version) = self.parse_as_stmt(op_pair)?;
+                    alias = op_alias.map(|symbol| symbol.with_kind(SymbolKind::OpAsName));
+                    version = match op_version.map(|v| *v) {
+                        Some(AstNodeEnum::StringLiteral(s)) => Some(s.value),
+                        _ => None,
+                    };
+                }
+                _ => {}
+            }
+        }
+
         Ok(AstNodeEnum::OpDef(OpDef {
-            position: Position::new(1, 1, 1),
-            children: vec![],
-            alias: None,
-            version: None,
+            position,
+            annotations,
+            children,
+            alias,
+            version,
             offset: None,
         }))
     }
 
+    /// Parse the `@name` / `@name(args)` annotations before a definition
+    fn parse_annotations(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<Vec<Annotation>> {
+        let mut annotations = Vec::new();
+        for annotation_pair in pair.into_inner() {
+            self.debug(&annotation_pair);
+            let position = self.get_position(&annotation_pair);
+            let mut name = None;
+            let mut args = Vec::new();
+            for inner_pair in annotation_pair.into_inner() {
+                match inner_pair.as_rule() {
+                    Rule::annotation_name => {
+                        let mut symbol =
+                            self.parse_symbol(inner_pair, SymbolKind::AnnotationName)?;
+                        symbol.name.remove(0);
+                        name = Some(symbol);
+                    }
+                    Rule::value => args.push(self.parse_value(inner_pair)?),
+                    Rule::dotted_name => args.push(AstNodeEnum::Symbol(
+                        self.parse_dotted_name_as_symbol(inner_pair, SymbolKind::VarRef)?,
+                    )),
+                    _ => {}
+                }
+            }
+            annotations.push(Annotation {
+                position,
+                name: name.ok_or_else(|| ParseError::general("Missing annotation name"))?,
+                args,
+            });
+        }
+        Ok(annotations)
+    }
+
+    fn parse_op_section(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
+        self.debug(&pair);
+        let position = self.get_position(&pair);
+        match pair.as_rule() {
+            Rule::op_meta_def => {
+                let mut children = Vec::new();
+                for block_pair in pair.into_inner().filter(|p| p.as_rule() == Rule::op_meta_block) {
+                    // op_meta_stmt_comment
+                    for stmt_pair in block_pair.into_inner().flat_map(|p| p.into_inner()) {
+                        if stmt_pair.as_rule() == Rule::param_def {
+                            let param = self.parse_param_def(stmt_pair)?;
+                            children.push(AttrDef {
+                                position: param.position,
+                                name: param.name.with_kind(SymbolKind::OpMetaAttr),
+                                value: param.value,
+                                condition: None,
+                                else_value: None,
+                            });
+                        }
+                    }
+                }
+                Ok(AstNodeEnum::OpMeta(OpMeta {
+                    position,
+                    children,
+                    offset: None,
+                }))
+            }
+            Rule::op_input_def => Ok(AstNodeEnum::OpInput(OpInput {
+                position,
+                children: self.parse_op_spec_stmts(pair, SymbolKind::OpInputAttr)?,
+                offset: None,
+            })),
+            Rule::op_output_def => Ok(AstNodeEnum::OpOutput(OpOutput {
+                position,
+                children: self.parse_op_spec_stmts(pair, SymbolKind::OpOutputAttr)?,
+                offset: None,
+            })),
+            Rule::op_config_def => Ok(AstNodeEnum::OpConfig(OpConfig {
+                position,
+                children: self.parse_op_spec_stmts(pair, SymbolKind::OpConfigAttr)?,
+                offset: None,
+            })),
+            Rule::sub_graph_def => {
+                let graph_pair = pair.into_inner().next().ok_or_else(|| {
+                    ParseError::syntax_error(position.line, position.start, "Expected graph definition")
+                })?;
+                self.parse_graph_def(graph_pair)
+            }
+            _ => Err(ParseError::syntax_error(
+                position.line,
+                position.start,
+                "Unexpected op section",
+            )),
+        }
+    }
+
+    fn parse_op_spec_stmts(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+        kind: SymbolKind,
+    ) -> ParseResult<Vec<AstNodeEnum>> {
+        let mut children = Vec::new();
+        for stmts_pair in pair.into_inner().filter(|p| p.as_rule() == Rule::op_spec_stmts) {
+            for stmt_pair in stmts_pair.into_inner() {
+                let position = self.get_position(&stmt_pair);
+                let mut inner_pairs = stmt_pair.into_inner().peekable();
+                if let Some(comment) = inner_pairs.next_if(|p| p.as_rule() == Rule::COMMENT) {
+                    children.push(self.parse_comment(comment)?);
+                    continue;
+                }
+                let name_pair = inner_pairs.next().ok_or_else(|| {
+                    ParseError::syntax_error(position.line, position.start, "Expected op spec name")
+                })?;
+                let name = self.parse_symbol(name_pair, kind)?;
+                let mut items = Vec::new();
+                // Skip COLON, then op_spec_def_end -> op_spec_def
+                for def_pair in inner_pairs
+                    .filter(|p| p.as_rule() == Rule::op_spec_def_end)
+                    .flat_map(|p| p.into_inner())
+                    .filter(|p| p.as_rule() == Rule::op_spec_def)
+                    .flat_map(|p| p.into_inner())
+                {
+                    match def_pair.as_rule() {
+                        Rule::op_spec_dtype_def => {
+                            let dtype_position = self.get_position(&def_pair);
+                            items.push(OpSpecItem {
+                                position: dtype_position,
+                                name: "dtype".to_string(),
+                                value: Box::new(AstNodeEnum::Symbol(
+                                    self.parse_symbol(def_pair, SymbolKind::OpSpecDtype)?,
+                                )),
+                            });
+                        }
+                        Rule::op_spec_key_defs => {
+                            for key_pair in def_pair.into_inner() {
+                                if key_pair.as_rule() == Rule::op_spec_key_def {
+                                    items.push(self.parse_op_spec_key_def(key_pair)?);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                children.push(AstNodeEnum::OpSpec(OpSpec {
+                    position,
+                    name,
+                    items: if items.is_empty() { None } else { Some(items) },
+                }));
+            }
+        }
+        Ok(children)
+    }
+
+    fn parse_op_spec_key_def(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<OpSpecItem> {
+        let position = self.get_position(&pair);
+        let mut def_pair = pair.into_inner().next().ok_or_else(|| {
+            ParseError::syntax_error(position.line, position.start, "Expected op spec item")
+        })?;
+        // op_spec_default_def wraps default_def / default_ref_def
+        if def_pair.as_rule() == Rule::op_spec_default_def {
+            def_pair = def_pair.into_inner().next().ok_or_else(|| {
+                ParseError::syntax_error(position.line, position.start, "Expected default value")
+            })?;
+        }
+        let is_default_ref = def_pair.as_rule() == Rule::default_ref_def;
+
+        let mut inner_pairs = def_pair.into_inner();
+        let name = inner_pairs
+            .next()
+            .map(|p| p.as_str().to_string())
+            .ok_or_else(|| ParseError::syntax_error(position.line, position.start, "Expected op spec key"))?;
+        // Skip DEFINED_BY (=)
+        inner_pairs.next();
+        let value_pair = inner_pairs.next().ok_or_else(|| {
+            ParseError::syntax_error(position.line, position.start, "Expected op spec value")
+        })?;
+        self.debug(&value_pair);
+
+        let value = match value_pair.as_rule() {
+            Rule::value => self.parse_value(value_pair)?,
+            Rule::STRING => self.parse_string_literal(value_pair)?,
+            Rule::tuple_stmt => self.parse_tuple_statement(value_pair)?,
+            Rule::fix_length => {
+                let number_pair = value_pair.into_inner().next().ok_or_else(|| {
+                    ParseError::syntax_error(position.line, position.start, "Expected length")
+                })?;
+                self.parse_number_literal(number_pair)?
+            }
+            Rule::closed_interval => self.parse_interval(value_pair, true)?,
+            Rule::mix_interval => self.parse_interval(value_pair, false)?,
+            Rule::dotted_name if is_default_ref => {
+                AstNodeEnum::Symbol(self.parse_symbol(value_pair, SymbolKind::VarRef)?)
+            }
+            _ => AstNodeEnum::Symbol(self.parse_symbol(value_pair, SymbolKind::OpSpecDtype)?),
+        };
+
+        Ok(OpSpecItem {
+            position,
+            name,
+            value: Box::new(value),
+        })
+    }
+
+    /// Parse `[1, 10]`-style closed intervals and `(1, 10]`-style mixed intervals
+    fn parse_interval(&mut self, pair: pest::iterators::Pair<Rule>, closed: bool) -> ParseResult<AstNodeEnum> {
+        let position = self.get_position(&pair);
+        let (mut ge, mut gt, mut le, mut lt) = (None, None, None, None);
+        let mut lower_open = false;
+        let mut upper_open = false;
+        let mut after_comma = false;
+
+        for inner_pair in pair.into_inner() {
+            match inner_pair.as_rule() {
+                Rule::left_interval => lower_open = inner_pair.as_str() == "(",
+                Rule::right_interval => upper_open = inner_pair.as_str() == ")",
+                Rule::COMMA => after_comma = true,
+                Rule::NUMBER | Rule::FLOAT => {
+                    let literal = if inner_pair.as_rule() == Rule::NUMBER {
+                        self.parse_number_literal(inner_pair)?
+                    } else {
+                        self.parse_float_literal(inner_pair)?
+                    };
+                    let bound = match literal {
+                        AstNodeEnum::NumberLiteral(number) => IntervalBound::Number(number),
+                        AstNodeEnum::FloatLiteral(float) => IntervalBound::Float(float),
+                        _ => unreachable!(),
+                    };
+                    if after_comma {
+                        le = Some(bound);
+                    } else {
+                        ge = Some(bound);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if closed {
+            return Ok(AstNodeEnum::ClosedInterval(ClosedInterval { position, ge, le }));
+        }
+        if lower_open {
+            gt = ge.take();
+        }
+        if upper_open {
+            lt = le.take();
+        }
+        Ok(AstNodeEnum::MixInterval(MixInterval {
+            position,
+            ge,
+            gt,
+            le,
+            lt,
+        }))
+    }
+
     fn parse_comment(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
         let position = self.get_position(&pair);
         let value = pair.as_str().to_string();
+        let kind = match pair.into_inner().next().map(|inner| inner.as_rule()) {
+            Some(Rule::DOC_COMMENT) => CommentKind::Doc,
+            Some(Rule::MODULE_DOC_COMMENT) => CommentKind::ModuleDoc,
+            _ => CommentKind::Regular,
+        };
 
-        Ok(AstNodeEnum::Comment(Comment { position, value }))
+        Ok(AstNodeEnum::Comment(Comment { position, value, kind }))
     }
 
     fn parse_value(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
@@ -526,13 +1560,25 @@ impl GosParserImpl {
         let position = self.get_position(&pair);
         let raw_value = pair.as_str();
 
-        // Remove quotes and unescape
-        let content = &raw_value[1..raw_value.len() - 1];
-        let value = self.unicode_escape_tool.unescape(content);
+        // Remove quotes and unescape, a raw string is kept verbatim
+        let (raw, quoted) = match raw_value.strip_prefix('r') {
+            Some(quoted) => (true, quoted),
+            None => (false, raw_value),
+        };
+        let content = &quoted[1..quoted.len() - 1];
+        let (value, escaped_interpolations) = if raw {
+            (content.to_string(), Vec::new())
+        } else {
+            let tool = &self.unicode_escape_tool;
+            (tool.unescape(content), tool.escaped_interpolations(content))
+        };
 
+        let quote = if quoted.starts_with('\'') { QuoteStyle::Single } else { QuoteStyle::Double };
         Ok(AstNodeEnum::StringLiteral(StringLiteral {
             position,
             value,
+            quote,
+            escaped_interpolations,
         }))
     }
 
@@ -558,7 +1604,7 @@ impl GosParserImpl {
     ) -> ParseResult<AstNodeEnum> {
         let position = self.get_position(&pair);
         let raw = pair.as_str().to_string();
-        let value = raw.parse::<i64>().map_err(|_| {
+        let value = parse_integer(&raw).ok_or_else(|| {
             ParseError::invalid_value("Invalid number", position.line, position.start)
         })?;
 
@@ -592,6 +1638,9 @@ impl GosParserImpl {
     ) -> ParseResult<AstNodeEnum> {
         let position = self.get_position(&pair);
         let raw = pair.as_str().to_string();
+        if !matches!(raw.as_str(), "true" | "false" | "True" | "False") {
+            self.check_keyword_case(&position, &raw)?;
+        }
         let value = raw.to_lowercase() == "true";
 
         Ok(AstNodeEnum::BoolLiteral(BoolLiteral {
@@ -634,7 +1683,22 @@ impl GosParserImpl {
         pair: pest::iterators::Pair<Rule>,
     ) -> ParseResult<AstNodeEnum> {
         let position = self.get_position(&pair);
-        Ok(AstNodeEnum::NullLiteral(NullLiteral { position }))
+        let raw = pair.as_str().to_string();
+        if raw != "null" {
+            self.check_keyword_case(&position, &raw)?;
+        }
+        Ok(AstNodeEnum::NullLiteral(NullLiteral { position, raw }))
+    }
+
+    fn check_keyword_case(&self, position: &Position, raw: &str) -> ParseResult<()> {
+        if self.options.case_insensitive_keywords {
+            return Ok(());
+        }
+        Err(ParseError::syntax_error(
+            position.line,
+            position.start,
+            format!("Unexpected case in {}, set case_insensitive_keywords to accept it", raw),
+        ))
     }
 
     fn parse_dict_statement(
@@ -767,9 +1831,17 @@ impl GosParserImpl {
             ParseError::syntax_error(position.line, position.start, "Expected date string")
         })?;
 
-        let raw_value = string_pair.as_str();
+        let raw_value = string_pair.as_str().trim_start_matches('r');
         let content = &raw_value[1..raw_value.len() - 1]; // Remove quotes
 
+        if !is_valid_date(content) {
+            return Err(ParseError::invalid_value(
+                format!("Invalid date {}", content),
+                position.line,
+                position.start,
+            ));
+        }
+
         Ok(AstNodeEnum::DateLiteral(DateLiteral {
             position,
             value: content.to_string(),
@@ -802,7 +1874,7 @@ impl GosParserImpl {
         kind: SymbolKind,
     ) -> ParseResult<Symbol> {
         let position = self.get_position(&pair);
-        let name = pair.as_str().to_string();
+        let name = unquote_identifier(pair.as_str());
 
         Ok(Symbol {
             position,
@@ -816,11 +1888,12 @@ impl GosParserImpl {
         pair: pest::iterators::Pair<Rule>,
         kind: SymbolKind,
     ) -> ParseResult<Symbol> {
-        if pair.as_rule() != Rule::dotted_name {
+        // An input name is a dotted name that may end in output indices
+        if !matches!(pair.as_rule(), Rule::dotted_name | Rule::input_name) {
             return Err(ParseError::general("NOT dotted name"));
         }
         let position = self.get_position(&pair);
-        let name = pair.as_str().to_string();
+        let name = unquote_identifier(pair.as_str());
         Ok(Symbol {
             position,
             name,
@@ -910,7 +1983,9 @@ impl GosParserImpl {
                     return self.parse_node_block(inner_pair, position, name_pair);
                 }
                 Rule::for_loop_block => {}
-                Rule::condition_section => {}
+                Rule::condition_section => {
+                    return self.parse_condition_def(inner_pair, position, name_pair);
+                }
                 _ => break,
             }
         }
@@ -929,11 +2004,13 @@ impl GosParserImpl {
         let mut attributes: Vec<NodeAttr> = Vec::new();
         let outputs = self.parse_comma_dotted_names(name_pair, SymbolKind::NodeOutput)?;
 
+        let mut name_kind = SymbolKind::NodeName;
         for inner_pair in pair.into_inner() {
             self.debug(&inner_pair);
             match inner_pair.as_rule() {
+                Rule::r#ref => name_kind = SymbolKind::RefGraphName,
                 Rule::dotted_name => {
-                    node_name = Some(self.parse_symbol(inner_pair, SymbolKind::NodeName)?);
+                    node_name = Some(self.parse_symbol(inner_pair, name_kind)?);
                 }
                 Rule::inputs_def => {
                     inputs = Some(self.parse_node_inputs_def(inner_pair)?);
@@ -964,12 +2041,279 @@ impl GosParserImpl {
     fn parse_condition_def(
         &mut self,
         pair: pest::iterators::Pair<Rule>,
+        position: &Position,
+        name_pair: pest::iterators::Pair<Rule>,
     ) -> ParseResult<AstNodeEnum> {
+        let outputs = self.parse_comma_dotted_names(name_pair, SymbolKind::NodeOutput)?;
+        let block = self.parse_condition_section(pair)?;
+
+        Ok(AstNodeEnum::ConditionDef(ConditionDef {
+            position: position.clone(),
+            outputs,
+            value: Box::new(block),
+        }))
+    }
+
+    fn parse_condition_section(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<ConditionBlock> {
         let position = self.get_position(&pair);
-        Ok(AstNodeEnum::Comment(Comment {
+        let mut condition = None;
+        let mut branches = Vec::new();
+
+        for inner_pair in pair.into_inner() {
+            self.debug(&inner_pair);
+            match inner_pair.as_rule() {
+                Rule::condition_stmt => {
+                    condition = Some(self.parse_condition_stmt(inner_pair)?);
+                }
+                Rule::condition_node_stmt => {
+                    branches.push(self.parse_condition_node_stmt(inner_pair)?);
+                }
+                _ => {}
+            }
+        }
+
+        let condition = condition.ok_or_else(|| {
+            ParseError::syntax_error(position.line, position.start, "Expected condition")
+        })?;
+        if branches.len() != 2 {
+            return Err(ParseError::syntax_error(
+                position.line,
+                position.start,
+                "Expected true and false branches",
+            ));
+        }
+        let false_branch = branches.pop().unwrap();
+        let true_branch = branches.pop().unwrap();
+
+        Ok(ConditionBlock {
             position,
-            value: "condition_def".to_string(),
-        }))
+            condition: Box::new(condition),
+            true_branch: Box::new(true_branch),
+            false_branch: Box::new(false_branch),
+        })
+    }
+
+    fn parse_condition_node_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<AstNodeEnum> {
+        for inner_pair in pair.into_inner() {
+            self.debug(&inner_pair);
+            match inner_pair.as_rule() {
+                Rule::condition_section => {
+                    return Ok(AstNodeEnum::ConditionBlock(
+                        self.parse_condition_section(inner_pair)?,
+                    ));
+                }
+                Rule::node_block => {
+                    return Ok(AstNodeEnum::NodeBlock(
+                        self.parse_node_func_block_as_node_block(inner_pair)?,
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Err(ParseError::general("Invalid condition branch"))
+    }
+
+    fn parse_condition_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<ConditionExpr> {
+        for inner_pair in pair.into_inner() {
+            self.debug(&inner_pair);
+            if inner_pair.as_rule() == Rule::condition_or_expr {
+                return self.parse_condition_or_expr(inner_pair);
+            }
+        }
+        Err(ParseError::general("Invalid condition statement"))
+    }
+
+    /// Parse `a || b || ...` into a left-associative `ConditionExpr::Or` tree
+    fn parse_condition_or_expr(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<ConditionExpr> {
+        let mut expr = None;
+        for inner_pair in pair.into_inner() {
+            self.debug(&inner_pair);
+            if inner_pair.as_rule() == Rule::condition_and_expr {
+                let right = self.parse_condition_and_expr(inner_pair)?;
+                expr = Some(match expr {
+                    Some(left) => ConditionExpr::Or(Box::new(left), Box::new(right)),
+                    None => right,
+                });
+            }
+        }
+        expr.ok_or_else(|| ParseError::general("Empty condition expression"))
+    }
+
+    /// Parse `a && b && ...` into a left-associative `ConditionExpr::And` tree
+    fn parse_condition_and_expr(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<ConditionExpr> {
+        let mut expr = None;
+        for inner_pair in pair.into_inner() {
+            self.debug(&inner_pair);
+            if inner_pair.as_rule() == Rule::condition_unary {
+                let right = self.parse_condition_unary(inner_pair)?;
+                expr = Some(match expr {
+                    Some(left) => ConditionExpr::And(Box::new(left), Box::new(right)),
+                    None => right,
+                });
+            }
+        }
+        expr.ok_or_else(|| ParseError::general("Empty condition expression"))
+    }
+
+    fn parse_condition_unary(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<ConditionExpr> {
+        let mut negated = false;
+        for inner_pair in pair.into_inner() {
+            self.debug(&inner_pair);
+            match inner_pair.as_rule() {
+                Rule::OP_NOT => negated = true,
+                Rule::condition_unary => {
+                    let expr = self.parse_condition_unary(inner_pair)?;
+                    return Ok(if negated {
+                        ConditionExpr::Not(Box::new(expr))
+                    } else {
+                        expr
+                    });
+                }
+                Rule::condition_atom => return self.parse_condition_atom(inner_pair),
+                _ => {}
+            }
+        }
+        Err(ParseError::general("Invalid condition expression"))
+    }
+
+    fn parse_condition_atom(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<ConditionExpr> {
+        for inner_pair in pair.into_inner() {
+            self.debug(&inner_pair);
+            match inner_pair.as_rule() {
+                Rule::condition_or_expr => {
+                    let expr = self.parse_condition_or_expr(inner_pair)?;
+                    return Ok(ConditionExpr::Group(Box::new(expr)));
+                }
+                Rule::comparison_stmt => {
+                    return Ok(ConditionExpr::Statement(Box::new(
+                        self.parse_comparison_stmt(inner_pair)?,
+                    )));
+                }
+                Rule::node_block => {
+                    return Ok(ConditionExpr::Block(
+                        self.parse_node_func_block_as_node_block(inner_pair)?,
+                    ));
+                }
+                Rule::if_condition => {
+                    for cond_pair in inner_pair.into_inner() {
+                        match cond_pair.as_rule() {
+                            Rule::dotted_name => {
+                                return Ok(ConditionExpr::Symbol(
+                                    self.parse_symbol(cond_pair, SymbolKind::NodeInput)?,
+                                ));
+                            }
+                            Rule::STRING => {
+                                if let AstNodeEnum::StringLiteral(string) =
+                                    self.parse_string_literal(cond_pair)?
+                                {
+                                    return Ok(ConditionExpr::String(string));
+                                }
+                            }
+                            Rule::MULTI_LINE_STRING => {
+                                if let AstNodeEnum::MultiLineStringLiteral(string) =
+                                    self.parse_multiline_string_literal(cond_pair)?
+                                {
+                                    return Ok(ConditionExpr::MultiLineString(string));
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(ParseError::general("Invalid condition operand"))
+    }
+
+    fn parse_comparison_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<ConditionStatement> {
+        // comparison_stmt wraps exactly one comparison_xx rule
+        let comparison = pair
+            .into_inner()
+            .next()
+            .ok_or_else(|| ParseError::general("Empty comparison"))?;
+        let position = self.get_position(&comparison);
+        let raw = comparison.as_str().trim().to_string();
+        let mut operands = Vec::new();
+        let mut operator = None;
+
+        for inner_pair in comparison.into_inner() {
+            self.debug(&inner_pair);
+            match inner_pair.as_rule() {
+                Rule::OP_GT
+                | Rule::OP_GE
+                | Rule::OP_LT
+                | Rule::OP_LE
+                | Rule::OP_EQ
+                | Rule::OP_NE
+                | Rule::OP_MATCH => operator = Some(inner_pair.as_str().to_string()),
+                Rule::operand2 | Rule::operand3 => {
+                    if let Some(operand_pair) = inner_pair.into_inner().next() {
+                        operands.push(self.parse_condition_operand(operand_pair)?);
+                    }
+                }
+                Rule::all_identifier | Rule::STRING => {
+                    operands.push(self.parse_condition_operand(inner_pair)?);
+                }
+                _ => {}
+            }
+        }
+
+        if operands.len() != 2 || operator.is_none() {
+            return Err(ParseError::syntax_error(
+                position.line,
+                position.start,
+                "Invalid comparison",
+            ));
+        }
+        let right_operand = operands.pop().unwrap();
+        let left_operand = operands.pop().unwrap();
+
+        Ok(ConditionStatement {
+            position,
+            left_operand: Box::new(left_operand),
+            right_operand: Box::new(right_operand),
+            operator: operator.unwrap(),
+            raw,
+        })
+    }
+
+    fn parse_condition_operand(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<AstNodeEnum> {
+        match pair.as_rule() {
+            Rule::NUMBER => self.parse_number_literal(pair),
+            Rule::FLOAT => self.parse_float_literal(pair),
+            Rule::STRING => self.parse_string_literal(pair),
+            _ => Ok(AstNodeEnum::Symbol(
+                self.parse_symbol(pair, SymbolKind::NodeInput)?,
+            )),
+        }
     }
 
     fn parse_comma_dotted_names_for_one_symbol(
@@ -992,8 +2336,10 @@ impl GosParserImpl {
         let mut names = Vec::new();
         for inner_pair in pair.into_inner() {
             self.debug(&inner_pair);
-            if inner_pair.as_rule() == Rule::dotted_name {
-                names.push(self.parse_symbol(inner_pair, kind)?);
+            match inner_pair.as_rule() {
+                Rule::dotted_name => names.push(self.parse_symbol(inner_pair, kind)?),
+                Rule::trailing_comma => self.check_trailing_comma(&inner_pair)?,
+                _ => {}
             }
         }
         if names.len() == 0 {
@@ -1002,6 +2348,18 @@ impl GosParserImpl {
         Ok(names)
     }
 
+    fn check_trailing_comma(&self, pair: &pest::iterators::Pair<Rule>) -> ParseResult<()> {
+        if self.options.allow_trailing_comma {
+            return Ok(());
+        }
+        let position = self.get_position(pair);
+        Err(ParseError::syntax_error(
+            position.line,
+            position.start,
+            "Trailing comma in name list, set allow_trailing_comma to accept it",
+        ))
+    }
+
     fn parse_node_def(&mut self, pair: pest::iterators::Pair<Rule>) -> ParseResult<AstNodeEnum> {
         let position = self.get_position(&pair);
         let mut outputs = Vec::new();
@@ -1012,10 +2370,14 @@ impl GosParserImpl {
                 Rule::comma_dotted_names => {
                     // Parse output identifiers
                     for id_pair in inner_pair.into_inner() {
-                        if id_pair.as_rule() == Rule::dotted_name {
-                            let symbol =
-                                self.parse_dotted_name_as_symbol(id_pair, SymbolKind::NodeOutput)?;
-                            outputs.push(symbol);
+                        match id_pair.as_rule() {
+                            Rule::dotted_name => {
+                                let symbol =
+                                    self.parse_dotted_name_as_symbol(id_pair, SymbolKind::NodeOutput)?;
+                                outputs.push(symbol);
+                            }
+                            Rule::trailing_comma => self.check_trailing_comma(&id_pair)?,
+                            _ => {}
                         }
                     }
                 }
@@ -1052,21 +2414,19 @@ impl GosParserImpl {
         let mut inputs = None;
         let mut attrs = Vec::new();
 
+        let mut name_kind = SymbolKind::NodeName;
         for inner_pair in pair.into_inner() {
             match inner_pair.as_rule() {
+                Rule::r#ref => name_kind = SymbolKind::RefGraphName,
                 Rule::dotted_name => {
-                    name = self.parse_dotted_name_as_symbol(inner_pair, SymbolKind::NodeName)?;
+                    name = self.parse_dotted_name_as_symbol(inner_pair, name_kind)?;
                 }
                 Rule::inputs_def => {
                     inputs = Some(self.parse_node_inputs_def(inner_pair)?);
                 }
                 Rule::node_attrs => {
                     // Parse node function attributes like .version("1.0.0")
-                    for attr_pair in inner_pair.into_inner() {
-                        if let Ok(attr) = self.parse_node_attr(attr_pair) {
-                            attrs.push(attr);
-                        }
-                    }
+                    attrs.push(self.parse_node_attr(inner_pair)?);
                 }
                 _ => {}
             }
@@ -1114,8 +2474,10 @@ impl GosParserImpl {
 
         for inner_pair in pair.into_inner() {
             self.debug(&inner_pair);
-            if inner_pair.as_rule() == Rule::one_inputs_def {
-                items.push(Box::new(self.parse_one_inputs_def(inner_pair)?));
+            match inner_pair.as_rule() {
+                Rule::one_inputs_def => items.push(Box::new(self.parse_one_inputs_def(inner_pair)?)),
+                Rule::inputs_key_values => items.push(Box::new(self.parse_inputs_key_values(inner_pair)?)),
+                _ => {}
             }
         }
 
@@ -1155,6 +2517,9 @@ impl GosParserImpl {
                 Rule::one_inputs_def => {
                     value = Some(self.parse_one_inputs_def(inner_pair)?);
                 }
+                Rule::inputs_key_values => {
+                    value = Some(self.parse_inputs_key_values(inner_pair)?);
+                }
                 _ => {}
             }
         }
@@ -1166,6 +2531,21 @@ impl GosParserImpl {
         })
     }
 
+    /// `(a, b)`, the names of a grouped input
+    fn parse_inputs_key_values(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> ParseResult<AstNodeEnum> {
+        let position = self.get_position(&pair);
+        let mut items = Vec::new();
+        for name_pair in pair.into_inner() {
+            if name_pair.as_rule() == Rule::input_name {
+                items.push(self.parse_dotted_name_as_symbol(name_pair, SymbolKind::NodeInput)?);
+            }
+        }
+        Ok(AstNodeEnum::NodeInputValues(NodeInputValues { position, items }))
+    }
+
     fn parse_one_inputs_def(
         &mut self,
         pair: pest::iterators::Pair<Rule>,
@@ -1173,7 +2553,7 @@ impl GosParserImpl {
         for inner_pair in pair.into_inner() {
             self.debug(&inner_pair);
             match inner_pair.as_rule() {
-                Rule::dotted_name => {
+                Rule::input_name => {
                     return Ok(AstNodeEnum::Symbol(
                         self.parse_dotted_name_as_symbol(inner_pair, SymbolKind::NodeInput)?,
                     ));
@@ -1200,9 +2580,19 @@ impl GosParserImpl {
                 | Rule::with
                 | Rule::as_keyword
                 | Rule::condition
-                | Rule::property => {
+                | Rule::r#override
+                | Rule::property
+                | Rule::log
+                | Rule::metrics
+                | Rule::funnel
+                | Rule::IDENTIFIER => {
                     name = Some(self.parse_symbol(inner_pair, SymbolKind::NodeAttrName)?);
                 }
+                Rule::BOOL => {
+                    if let AstNodeEnum::BoolLiteral(boolean) = self.parse_bool_literal(inner_pair)? {
+                        value = Some(NodeAttrValue::Bool(boolean));
+                    }
+                }
                 Rule::STRING => match self.parse_string_literal(inner_pair) {
                     Ok(AstNodeEnum::StringLiteral(str_value)) => {
                         value = Some(NodeAttrValue::String(str_value));
@@ -1232,7 +2622,8 @@ impl GosParserImpl {
         Ok(NodeAttr {
             position,
             name: name.unwrap(),
-            value: value.unwrap(),
+            // `.call()` of an unknown call without params
+            value: value.unwrap_or(NodeAttrValue::ListParamDef(Vec::new())),
             offset: None,
         })
     }
@@ -1289,15 +2680,100 @@ impl GosParserImpl {
     }
 }
 
+/// Split source into top-level statements terminated by `;`
+///
+/// Returns each statement with the line and column it starts at. Brackets,
+/// strings and comments are skipped so nested `;` do not split a statement.
+fn split_statements(content: &str) -> Vec<(usize, usize, &str)> {
+    let bytes = content.as_bytes();
+    let mut ranges = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        match bytes[i] {
+            b'#' => i = find_from(bytes, i, b"\n").unwrap_or(bytes.len()),
+            b'/' if rest.starts_with(b"//") => i = find_from(bytes, i, b"\n").unwrap_or(bytes.len()),
+            b'/' if rest.starts_with(b"/*") => {
+                i = find_from(bytes, i + 2, b"*/").map_or(bytes.len(), |end| end + 1);
+            }
+            quote @ (b'"' | b'\'') => {
+                let triple = [quote; 3];
+                if rest.starts_with(&triple) {
+                    i = find_from(bytes, i + 3, &triple).map_or(bytes.len(), |end| end + 2);
+                } else {
+                    i += 1;
+                    while i < bytes.len() && bytes[i] != quote && bytes[i] != b'\n' {
+                        if bytes[i] == b'\\' {
+                            i += 1;
+                        }
+                        i += 1;
+                    }
+                }
+            }
+            b'{' | b'(' | b'[' => depth += 1,
+            b'}' | b')' | b']' => depth = depth.saturating_sub(1),
+            b';' if depth == 0 => {
+                ranges.push(start..i + 1);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if !content[start.min(content.len())..].trim().is_empty() {
+        ranges.push(start..content.len());
+    }
+
+    let mut line = 1;
+    let mut last = 0;
+    ranges
+        .into_iter()
+        .map(|range| {
+            line += content[last..range.start].matches('\n').count();
+            last = range.start;
+            let line_start = content[..range.start].rfind('\n').map_or(0, |index| index + 1);
+            let column = content[line_start..range.start].chars().count() + 1;
+            (line, column, &content[range])
+        })
+        .collect()
+}
+
+fn find_from(bytes: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    bytes
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|index| from + index)
+}
+
+/// Whether a `date(...)` string is a date, a local datetime, or an ISO-8601
+/// datetime with a `Z` or `+08:00` style offset
+fn is_valid_date(content: &str) -> bool {
+    NaiveDate::parse_from_str(content, "%Y-%m-%d").is_ok()
+        || ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+            .iter()
+            .any(|format| NaiveDateTime::parse_from_str(content, format).is_ok())
+        || DateTime::parse_from_rfc3339(content).is_ok()
+}
+
+/// Escape regex shared by all parser instances
+fn escape_regex() -> &'static Regex {
+    static ESCAPE_REGEX: OnceLock<Regex> = OnceLock::new();
+    ESCAPE_REGEX.get_or_init(|| Regex::new(r"\\(.)").unwrap())
+}
+
 /// Unicode escape tool for handling string escapes
 struct UnicodeEscapeTool {
-    escape_regex: Regex,
+    escape_regex: &'static Regex,
 }
 
 impl UnicodeEscapeTool {
     fn new() -> Self {
         Self {
-            escape_regex: Regex::new(r"\\(.)").unwrap(),
+            escape_regex: escape_regex(),
         }
     }
 
@@ -1314,4 +2790,20 @@ impl UnicodeEscapeTool {
             })
             .to_string()
     }
+
+    /// Byte offsets in `unescape(input)` of each `${` written `\${`
+    fn escaped_interpolations(&self, input: &str) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        let (mut unescaped_len, mut last) = (0, 0);
+        for caps in self.escape_regex.captures_iter(input) {
+            let escape = caps.get(0).unwrap();
+            unescaped_len += escape.start() - last;
+            if &caps[1] == "$" && input[escape.end()..].starts_with('{') {
+                offsets.push(unescaped_len);
+            }
+            unescaped_len += self.unescape(escape.as_str()).len();
+            last = escape.end();
+        }
+        offsets
+    }
 }
@@ -0,0 +1,286 @@
+//! Lowering of compiled graphs into a flat execution plan
+//!
+//! `to_execution_plan` orders the nodes of every graph of a `CompileResult`
+//! so that each node comes after the nodes it reads from or depends on, as in
+//! `CompileOptions::emit_edges`. An input naming another graph's output, e.g.
+//! `other.c`, is wired to the node producing `c` in the graph `other`, and a
+//! `ref(graph)` node runs after every node of the referenced graph.
+//!
+//! `to_pipeline` groups a plan back by graph, next to the graph properties and
+//! the compiled vars, for executors that do not want to read the JSON.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::ast::split_output_index;
+use crate::compiler::{lookup_var_path, node_edges, CompileResult, GraphDict, NodeDict, NodeInput};
+use crate::error::{ParseError, ParseResult};
+
+/// Where a planned node reads one of its inputs from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum PlanInput {
+    /// An output of an earlier node of the plan, `output` as written in the
+    /// input, e.g. `a` or `a.0`
+    Node { id: String, output: String },
+    /// A name no node produces, e.g. a graph input
+    External(String),
+}
+
+/// One node invocation of an `ExecutionPlan`
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedNode {
+    /// `<graph>.<key>`, graphs named as in `module_to_dot`
+    pub id: String,
+    pub graph: String,
+    pub key: String,
+    pub op_name: Option<String>,
+    pub ref_graph: Option<String>,
+    /// Positional inputs in order, the names of a group flattened
+    pub inputs: Vec<PlanInput>,
+    /// The compiled node, for its `with`, `property` and other clauses
+    pub node: NodeDict,
+}
+
+/// The nodes of a compiled module in an order they can run in
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExecutionPlan {
+    pub nodes: Vec<PlannedNode>,
+    /// `(node id, input)` in plan order for each `External` input that no graph
+    /// property or var provides either: a graph input, or a dangling reference
+    pub unresolved: Vec<(String, String)>,
+}
+
+impl ExecutionPlan {
+    /// The planned node `<graph>.<key>`
+    pub fn node(&self, id: &str) -> Option<&PlannedNode> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+}
+
+/// One graph of a `Pipeline`
+#[derive(Debug, Clone, Serialize)]
+pub struct PipelineGraph {
+    /// The graph alias, or `#<index>` for an anonymous graph
+    pub name: String,
+    pub version: Option<String>,
+    /// Graph properties, var references resolved
+    pub properties: HashMap<String, Value>,
+    /// The nodes of the graph in plan order
+    pub nodes: Vec<PlannedNode>,
+}
+
+/// A compiled module as graphs of ordered nodes, see `crate::load_pipeline`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Pipeline {
+    /// Graphs in source order
+    pub graphs: Vec<PipelineGraph>,
+    /// Compiled vars, references to other vars resolved
+    pub vars: HashMap<String, Value>,
+    /// Ids of every node of every graph, `<graph>.<key>`, in an order they can run in
+    pub order: Vec<String>,
+}
+
+impl Pipeline {
+    /// The graph named `name`
+    pub fn graph(&self, name: &str) -> Option<&PipelineGraph> {
+        self.graphs.iter().find(|graph| graph.name == name)
+    }
+
+    /// The node `<graph>.<key>`
+    pub fn node(&self, id: &str) -> Option<&PlannedNode> {
+        self.graphs.iter().flat_map(|graph| &graph.nodes).find(|node| node.id == id)
+    }
+}
+
+/// Order the nodes of every graph of `result` into one plan
+///
+/// Ties are broken by graph position, then node key, so the plan is stable.
+/// Fails on a graph name used twice, on a cycle, on a reference to an
+/// undefined graph or graph output, and on a `.depend(...)` that no node of
+/// the graph provides. Graph inputs are not declared, so an input nothing
+/// provides is kept in `ExecutionPlan::unresolved` for the caller to check.
+pub fn to_execution_plan(result: &CompileResult) -> ParseResult<ExecutionPlan> {
+    let empty = HashMap::new();
+    let no_values = HashMap::new();
+    let graphs: Vec<(String, &HashMap<String, NodeDict>)> = result
+        .graphs
+        .iter()
+        .flatten()
+        .enumerate()
+        .map(|(index, graph)| (graph_name(index, graph), graph.nodes.as_ref().unwrap_or(&empty)))
+        .collect();
+    let vars = result.vars.as_deref().unwrap_or(&no_values);
+    let mut names = HashSet::new();
+    if let Some((name, _)) = graphs.iter().find(|(name, _)| !names.insert(name.as_str())) {
+        return Err(ParseError::general(format!("Graph {} is defined more than once", name)));
+    }
+    let producers: HashMap<&str, HashMap<&str, &str>> =
+        graphs.iter().map(|(name, nodes)| (name.as_str(), producers(nodes))).collect();
+
+    // Nodes are numbered in tie-break order: graph position, then key
+    let mut ids: Vec<(usize, &str)> = Vec::new();
+    for (graph_index, (_, nodes)) in graphs.iter().enumerate() {
+        let mut keys: Vec<&str> = nodes.keys().map(String::as_str).collect();
+        keys.sort();
+        ids.extend(keys.into_iter().map(|key| (graph_index, key)));
+    }
+    let index_of: HashMap<(usize, &str), usize> = ids.iter().enumerate().map(|(index, id)| (*id, index)).collect();
+
+    let mut inputs: Vec<Vec<PlanInput>> = vec![Vec::new(); ids.len()];
+    let mut unresolved: Vec<Vec<String>> = vec![Vec::new(); ids.len()];
+    let mut before: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); ids.len()];
+    for (graph_index, (name, nodes)) in graphs.iter().enumerate() {
+        let local = &producers[name.as_str()];
+        let properties = result.graphs.iter().flatten().nth(graph_index)
+            .and_then(|graph| graph.properties.as_ref())
+            .unwrap_or(&no_values);
+        for (from, to) in node_edges(nodes) {
+            before[index_of[&(graph_index, to.as_str())]].insert(index_of[&(graph_index, from.as_str())]);
+        }
+        for (key, node) in nodes.iter() {
+            let id = format!("{}.{}", name, key);
+            let index = index_of[&(graph_index, key.as_str())];
+            for depend in node.depends.iter().flatten() {
+                if !local.contains_key(depend.as_str()) {
+                    return Err(ParseError::general(format!("Node {} depends on undefined {}", id, depend)));
+                }
+            }
+            for input in node.inputs.iter().flatten().flat_map(NodeInput::names) {
+                let base = split_output_index(input).map_or(input.as_str(), |(base, _)| base);
+                let planned = match local.get(base) {
+                    Some(&producer) => PlanInput::Node { id: format!("{}.{}", name, producer), output: input.clone() },
+                    None => match resolve_graph_output(input, &producers)? {
+                        Some((graph, producer, output)) => {
+                            let Some(graph_index) = graphs.iter().position(|(name, _)| name == graph) else {
+                                return Err(ParseError::general(format!("Node {} refers to undefined graph {}", id, graph)));
+                            };
+                            before[index].insert(index_of[&(graph_index, producer)]);
+                            PlanInput::Node { id: format!("{}.{}", graph, producer), output: output.to_string() }
+                        }
+                        None => {
+                            if !properties.contains_key(base) && lookup_var_path(input, vars).is_none() {
+                                unresolved[index].push(input.clone());
+                            }
+                            PlanInput::External(input.clone())
+                        }
+                    },
+                };
+                inputs[index].push(planned);
+            }
+            if let Some(ref_graph) = &node.ref_graph {
+                let Some(ref_index) = graphs.iter().position(|(name, _)| name == ref_graph) else {
+                    return Err(ParseError::general(format!("Node {} refers to undefined graph {}", id, ref_graph)));
+                };
+                let referenced = ids.iter().filter(|(graph_index, _)| *graph_index == ref_index);
+                before[index].extend(referenced.map(|id| index_of[id]));
+            }
+        }
+    }
+
+    // Kahn's algorithm, always taking the first ready node
+    let mut remaining: Vec<usize> = before.iter().map(BTreeSet::len).collect();
+    let mut after: Vec<Vec<usize>> = vec![Vec::new(); ids.len()];
+    for (index, sources) in before.iter().enumerate() {
+        for &source in sources {
+            after[source].push(index);
+        }
+    }
+    let mut ready: BTreeSet<usize> = (0..ids.len()).filter(|&index| remaining[index] == 0).collect();
+    let mut plan = ExecutionPlan::default();
+    while let Some(index) = ready.pop_first() {
+        let (graph_index, key) = ids[index];
+        let (name, nodes) = &graphs[graph_index];
+        let node = &nodes[key];
+        plan.nodes.push(PlannedNode {
+            id: format!("{}.{}", name, key),
+            graph: name.clone(),
+            key: key.to_string(),
+            op_name: node.op_name.clone(),
+            ref_graph: node.ref_graph.clone(),
+            inputs: std::mem::take(&mut inputs[index]),
+            node: node.clone(),
+        });
+        let id = &plan.nodes[plan.nodes.len() - 1].id;
+        plan.unresolved.extend(unresolved[index].drain(..).map(|input| (id.clone(), input)));
+        for &next in &after[index] {
+            remaining[next] -= 1;
+            if remaining[next] == 0 {
+                ready.insert(next);
+            }
+        }
+    }
+
+    if plan.nodes.len() < ids.len() {
+        let cycle: Vec<String> = (0..ids.len())
+            .filter(|&index| remaining[index] > 0)
+            .map(|index| format!("{}.{}", graphs[ids[index].0].0, ids[index].1))
+            .collect();
+        return Err(ParseError::general(format!("Nodes {} form a cycle", cycle.join(", "))));
+    }
+    Ok(plan)
+}
+
+/// Plan `result` with `to_execution_plan` and group the planned nodes by graph
+pub fn to_pipeline(result: &CompileResult) -> ParseResult<Pipeline> {
+    let plan = to_execution_plan(result)?;
+    let mut pipeline = Pipeline {
+        order: plan.nodes.iter().map(|node| node.id.clone()).collect(),
+        vars: result.vars.as_deref().cloned().unwrap_or_default(),
+        ..Default::default()
+    };
+    for (index, graph) in result.graphs.iter().flatten().enumerate() {
+        pipeline.graphs.push(PipelineGraph {
+            name: graph_name(index, graph),
+            version: graph.version.clone(),
+            properties: graph.properties.clone().unwrap_or_default(),
+            nodes: Vec::new(),
+        });
+    }
+    for node in plan.nodes {
+        if let Some(graph) = pipeline.graphs.iter_mut().find(|graph| graph.name == node.graph) {
+            graph.nodes.push(node);
+        }
+    }
+    Ok(pipeline)
+}
+
+/// The graph alias, or `#<index>` for an anonymous graph
+fn graph_name(index: usize, graph: &GraphDict) -> String {
+    graph.alias.clone().unwrap_or_else(|| format!("#{}", index))
+}
+
+/// Node key producing each name of a graph: the key itself, the alias and the outputs
+fn producers(nodes: &HashMap<String, NodeDict>) -> HashMap<&str, &str> {
+    let mut producers = HashMap::new();
+    for (key, node) in nodes {
+        producers.insert(key.as_str(), key.as_str());
+        if let Some(alias) = &node.alias {
+            producers.insert(alias.as_str(), key.as_str());
+        }
+        for output in node.outputs.iter().flatten() {
+            producers.insert(output.as_str(), key.as_str());
+        }
+    }
+    producers
+}
+
+/// Graph, node key and output name of an input `graph.output`, `None` when the
+/// input does not start with a graph name
+fn resolve_graph_output<'a>(
+    input: &'a str,
+    producers: &HashMap<&'a str, HashMap<&'a str, &'a str>>,
+) -> ParseResult<Option<(&'a str, &'a str, &'a str)>> {
+    let Some((graph, output)) = input.split_once('.') else {
+        return Ok(None);
+    };
+    let Some((&graph, graph_producers)) = producers.get_key_value(graph) else {
+        return Ok(None);
+    };
+    let base = split_output_index(output).map_or(output, |(base, _)| base);
+    match graph_producers.get(base) {
+        Some(&producer) => Ok(Some((graph, producer, output))),
+        None => Err(ParseError::general(format!("Graph {} has no output {}", graph, base))),
+    }
+}
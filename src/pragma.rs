@@ -0,0 +1,126 @@
+//! GOS pragma comment
+//!
+//! A file can describe how tools should treat it with a comment on its first
+//! line, before any statement:
+//!
+//! ```text
+//! # gos: indent=2 max_col=80 keep_order
+//! ```
+//!
+//! Settings are separated by whitespace, see the `pragma` rule of the grammar.
+//! A number setting is `key=value`, a flag is `key`, `key=true` or
+//! `key=false`. The pragma seeds option defaults: `crate::parse`,
+//! `format_from_data` and `Source` apply it, options passed explicitly
+//! elsewhere are left alone.
+
+use pest::Parser;
+
+use crate::compiler::CompileOptions;
+use crate::error::{ParseError, ParseResult};
+use crate::format::Formatter;
+use crate::parser::{GosParser, ParseOptions, Rule};
+
+/// Settings read from a `# gos:` pragma, `None` when not given
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pragma {
+    /// Formatter indent
+    pub indent: Option<usize>,
+    /// Formatter maximum column
+    pub max_col: Option<usize>,
+    /// Compiler `keep_order`
+    pub keep_order: Option<bool>,
+    /// Parser `allow_trailing_comma`
+    pub allow_trailing_comma: Option<bool>,
+    /// Parser `case_insensitive_keywords`
+    pub case_insensitive_keywords: Option<bool>,
+    /// Compiler `nested_vars`
+    pub nested_vars: Option<bool>,
+}
+
+impl Pragma {
+    /// Read the pragma of a file, `None` when the first non-blank line is not one
+    ///
+    /// A malformed pragma, an unknown setting or a bad value fails with a
+    /// `SyntaxError` at the setting.
+    pub fn from_source(content: &str) -> ParseResult<Option<Self>> {
+        let Some((index, line)) = content.lines().enumerate().find(|(_, line)| !line.trim().is_empty()) else {
+            return Ok(None);
+        };
+        let is_pragma = line
+            .trim_start()
+            .strip_prefix('#')
+            .is_some_and(|comment| comment.trim_start().starts_with("gos:"));
+        if !is_pragma {
+            return Ok(None);
+        }
+
+        let line_number = index + 1;
+        let column = |offset: usize| line[..offset].chars().count() + 1;
+        let pairs = GosParser::parse(Rule::pragma, line).map_err(|error| {
+            let offset = match error.location {
+                pest::error::InputLocation::Pos(offset) | pest::error::InputLocation::Span((offset, _)) => offset,
+            };
+            ParseError::syntax_error(line_number, column(offset), "Malformed pragma setting, expected key or key=value")
+        })?;
+        let mut pragma = Self::default();
+        for setting in pairs.flatten().filter(|pair| pair.as_rule() == Rule::pragma_setting) {
+            let error = |message: String| ParseError::syntax_error(line_number, column(setting.as_span().start()), message);
+            let mut parts = setting.clone().into_inner();
+            let key = parts.next().map_or("", |key| key.as_str());
+            let value = parts.next().map(|value| value.as_str());
+            match key {
+                "indent" => pragma.indent = Some(parse_number(key, value).map_err(error)?),
+                "max_col" => pragma.max_col = Some(parse_number(key, value).map_err(error)?),
+                "keep_order" => pragma.keep_order = Some(parse_flag(key, value).map_err(error)?),
+                "allow_trailing_comma" => pragma.allow_trailing_comma = Some(parse_flag(key, value).map_err(error)?),
+                "case_insensitive_keywords" => {
+                    pragma.case_insensitive_keywords = Some(parse_flag(key, value).map_err(error)?)
+                }
+                "nested_vars" => pragma.nested_vars = Some(parse_flag(key, value).map_err(error)?),
+                _ => return Err(error(format!("Unknown pragma setting {}", key))),
+            }
+        }
+        Ok(Some(pragma))
+    }
+
+    /// `base` with the parser settings of the pragma applied
+    pub fn parse_options(&self, mut base: ParseOptions) -> ParseOptions {
+        if let Some(allow) = self.allow_trailing_comma {
+            base.allow_trailing_comma = allow;
+        }
+        if let Some(insensitive) = self.case_insensitive_keywords {
+            base.case_insensitive_keywords = insensitive;
+        }
+        base
+    }
+
+    /// `base` with the compiler settings of the pragma applied
+    pub fn compile_options(&self, mut base: CompileOptions) -> CompileOptions {
+        if let Some(keep_order) = self.keep_order {
+            base.keep_order = keep_order;
+        }
+        if let Some(nested_vars) = self.nested_vars {
+            base.nested_vars = nested_vars;
+        }
+        base
+    }
+
+    /// Formatter with the pragma's indent and max_col, 4 and 100 by default
+    pub fn formatter(&self) -> Formatter {
+        Formatter::new(4, 100).with_pragma(self)
+    }
+}
+
+fn parse_number(key: &str, value: Option<&str>) -> Result<usize, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("Pragma setting {} expects a number, e.g. {}=4", key, key))
+}
+
+fn parse_flag(key: &str, value: Option<&str>) -> Result<bool, String> {
+    match value {
+        None | Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(_) => Err(format!("Pragma setting {} expects true or false", key)),
+    }
+}
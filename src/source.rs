@@ -0,0 +1,94 @@
+//! GOS source holder
+//!
+//! A `Source` reads its content once and parses it on first use, so a tool
+//! that validates, formats and compiles the same file does the I/O and the
+//! parse a single time.
+
+use std::cell::OnceCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ast::AstNodeEnum;
+use crate::compiler::{Compiler, CompileOptions, CompileResult};
+use crate::error::ParseResult;
+use crate::format::Formatter;
+use crate::pragma::Pragma;
+
+/// GOS content with its parsed AST cached
+#[derive(Debug)]
+pub struct Source {
+    path: Option<PathBuf>,
+    content: String,
+    ast: OnceCell<ParseResult<AstNodeEnum>>,
+}
+
+impl Source {
+    /// Read a GOS file
+    pub fn from_file(path: impl AsRef<Path>) -> ParseResult<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            content,
+            ast: OnceCell::new(),
+        })
+    }
+
+    /// Wrap GOS content held in memory
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(content: &str) -> Self {
+        Self {
+            path: None,
+            content: content.to_string(),
+            ast: OnceCell::new(),
+        }
+    }
+
+    /// File the content was read from, `None` for `from_str`
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Source text
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Parsed AST, parsed on the first call and cached, errors included
+    pub fn parse(&self) -> ParseResult<&AstNodeEnum> {
+        self.ast
+            .get_or_init(|| crate::parse(&self.content))
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// Check the syntax
+    pub fn validate(&self) -> ParseResult<()> {
+        self.parse().map(|_| ())
+    }
+
+    /// Format the source, see `Formatter`, a `# gos:` pragma overrides
+    /// `indent` and `max_col`
+    pub fn format(&self, indent: usize, max_col: usize) -> ParseResult<String> {
+        let mut formatter = Formatter::new(indent, max_col);
+        if let Some(pragma) = Pragma::from_source(&self.content)? {
+            formatter = formatter.with_pragma(&pragma);
+        }
+        Ok(formatter.format(self.parse()?, 0))
+    }
+
+    /// Compile the source with default options, seeded by a `# gos:` pragma
+    pub fn compile(&self) -> ParseResult<CompileResult> {
+        let options = CompileOptions::default();
+        let options = match Pragma::from_source(&self.content)? {
+            Some(pragma) => pragma.compile_options(options),
+            None => options,
+        };
+        self.compile_with_options(options)
+    }
+
+    /// Compile the source with the given options
+    pub fn compile_with_options(&self, options: CompileOptions) -> ParseResult<CompileResult> {
+        Compiler::with_options(options).compile(self.parse()?)
+    }
+}
@@ -1,7 +1,7 @@
 //! Tests for the GOS decompiler module
 
-use crate::decompiler::{decompile_from_data, decompile, DecompileOptions, DecompileResult};
-use serde_json::json;
+use crate::decompiler::{analyze_json, decompile_from_data, decompile, decompile_to_writer, DecompileOptions, DecompileResult, EscapePolicy, FeatureSet};
+use serde_json::{json, Value};
 use std::fs;
 use tempfile::NamedTempFile;
 
@@ -81,6 +81,34 @@ fn test_node_with_version_and_alias() {
     }
 }
 
+#[test]
+fn test_node_attrs_with_non_string_values() {
+    let data = json!({
+        "graphs": [{
+            "nodes": {
+                "my_node": {
+                    "output": ["my_node"],
+                    "op_name": "test.op",
+                    "attrs": [
+                        {"key": "retry", "value": 42},
+                        {"key": "cache", "value": true},
+                        {"key": "opts", "value": {"a": [1, 2]}},
+                        {"key": "mode", "value": "fast"}
+                    ]
+                }
+            }
+        }]
+    });
+
+    let DecompileResult::Text(text) = decompile_from_data(data, None).unwrap() else {
+        panic!("Expected text result");
+    };
+    assert!(text.contains(".retry(42)"), "{}", text);
+    assert!(text.contains(".cache(true)"), "{}", text);
+    assert!(text.contains(".opts({'a':[1,2]})"), "{}", text);
+    assert!(text.contains(".mode(fast)"), "{}", text);
+}
+
 #[test]
 fn test_node_with_dependencies() {
     let data = json!({
@@ -169,6 +197,185 @@ fn test_for_loop_node() {
     }
 }
 
+#[test]
+fn test_analyze_json_features() {
+    let data = json!({
+        "graphs": [{
+            "nodes": {
+                "result": {
+                    "output": ["result"],
+                    "op_name": "builtin.conditions.str",
+                    "condition": "x > 0",
+                    "true_branch": {"op_name": "math.add", "input": ["x", "1"]},
+                    "false_branch": {"ref_graph": "other", "input": ["x"]}
+                },
+                "items": {
+                    "output": ["items"],
+                    "op_name": "test.op",
+                    "for_loop": {"inputs": "xs", "outputs": ["x"]}
+                }
+            }
+        }]
+    });
+
+    let features = analyze_json(&data);
+    assert_eq!(features, FeatureSet {
+        graphs: 1,
+        nodes: 2,
+        conditions: 1,
+        for_loops: 1,
+        templates: 0,
+        refs: 1,
+        ops: 0,
+    });
+    assert!(features.has_conditions());
+    assert!(features.has_for_loops());
+    assert!(!features.has_templates());
+    assert!(!features.has_ops());
+}
+
+#[test]
+fn test_for_loop_without_condition() {
+    let data = json!({
+        "graphs": [{
+            "nodes": {
+                "result": {
+                    "output": ["result"],
+                    "op_name": "test.op",
+                    "for_loop": {
+                        "inputs": "items",
+                        "outputs": ["x", "y"]
+                    }
+                }
+            }
+        }]
+    });
+    
+    let result = decompile_from_data(data, None).unwrap();
+    match result {
+        DecompileResult::Text(text) => {
+            assert!(text.contains("result = [test.op()\n        for x, y in items];"));
+            assert!(!text.contains(" if "));
+            assert!(crate::parse(&text).is_ok(), "Decompiled text should re-parse: {}", text);
+        },
+        _ => panic!("Expected text result"),
+    }
+}
+
+#[test]
+fn test_for_loop_single_string_output() {
+    let data = json!({
+        "graphs": [{
+            "nodes": {
+                "result": {
+                    "output": ["result"],
+                    "op_name": "test.op",
+                    "for_loop": {
+                        "inputs": "items",
+                        "outputs": "x",
+                        "condition": "x > 0"
+                    }
+                }
+            }
+        }]
+    });
+    
+    let options = DecompileOptions {
+        indent: 0,
+        ..Default::default()
+    };
+    let result = decompile_from_data(data, Some(options)).unwrap();
+    match result {
+        DecompileResult::Text(text) => {
+            assert!(text.contains("result = [test.op() for x in items if 'x > 0'];"));
+            assert!(crate::parse(&text).is_ok(), "Decompiled text should re-parse: {}", text);
+        },
+        _ => panic!("Expected text result"),
+    }
+}
+
+#[test]
+fn test_single_output_node_without_alias() {
+    let data = json!({
+        "graphs": [{
+            "nodes": {
+                "result": {
+                    "op_name": "test.op",
+                    "output": ["result"],
+                    "input": ["x"]
+                }
+            }
+        }]
+    });
+
+    let result = decompile_from_data(data, None).unwrap();
+    match result {
+        DecompileResult::Text(text) => {
+            assert!(text.contains("result = test.op(x)"));
+            assert!(!text.contains(".as("));
+            assert!(crate::parse(&text).is_ok());
+        }
+        _ => panic!("Expected text result"),
+    }
+}
+
+#[test]
+fn test_multi_output_node_alias() {
+    for key in ["first", "first,second"] {
+        let data = json!({
+            "graphs": [{
+                "nodes": {
+                    key: {
+                        "op_name": "test.op",
+                        "output": ["first", "second"],
+                        "input": ["x"]
+                    }
+                }
+            }]
+        });
+
+        let result = decompile_from_data(data, None).unwrap();
+        match result {
+            DecompileResult::Text(text) => {
+                assert!(text.contains("first,second = test.op(x)"), "{}", text);
+                assert!(text.contains(".as(first)"), "{}", text);
+                assert!(crate::parse(&text).is_ok());
+            }
+            _ => panic!("Expected text result"),
+        }
+    }
+}
+
+#[test]
+fn test_op_config_tuple_default_round_trip() {
+    let content = r#"
+op {
+    config {
+        sizes: (dtype=list, default=(1, 2, 3), help="sizes");
+        tags: (dtype=list, default={"a", "b"});
+    };
+} as my_op;
+"#;
+    let compile = |source: &str| {
+        let ast = crate::parse(source).unwrap();
+        serde_json::to_value(crate::compile_ast(&ast).unwrap()).unwrap()
+    };
+    let compiled = compile(content);
+    let configs = &compiled["ops"][0]["configs"];
+    assert_eq!(configs["sizes"]["default"], json!([1, 2, 3]));
+    assert_eq!(configs["tags"]["default"], json!(["a", "b"]));
+
+    let result = decompile_from_data(compiled.clone(), None).unwrap();
+    match result {
+        DecompileResult::Text(text) => {
+            assert!(text.contains("default=(1,2,3)"), "{}", text);
+            let recompiled = compile(&text);
+            assert_eq!(recompiled["ops"][0]["configs"], *configs);
+        }
+        _ => panic!("Expected text result"),
+    }
+}
+
 #[test]
 fn test_operation_decompile() {
     let data = json!({
@@ -271,11 +478,83 @@ fn test_unescape_option() {
     }
 }
 
+fn decompile_property_text(text: &str, escape_policy: EscapePolicy) -> String {
+    let data = json!({
+        "graphs": [{
+            "property": {"text": text}
+        }]
+    });
+    let options = DecompileOptions {
+        escape_policy,
+        ..Default::default()
+    };
+    match decompile_from_data(data, Some(options)).unwrap() {
+        DecompileResult::Text(text) => text,
+        _ => panic!("Expected text result"),
+    }
+}
+
+#[test]
+fn test_escape_policy_modes() {
+    let escaped = "line1\\nit's";
+    assert!(decompile_property_text(escaped, EscapePolicy::Keep).contains(r"'line1\nit\'s'"));
+    assert!(decompile_property_text(escaped, EscapePolicy::Unescape).contains("'line1\nit\\'s'"));
+    assert!(decompile_property_text(escaped, EscapePolicy::Reescape).contains(r"'line1\nit\'s'"));
+}
+
+#[test]
+fn test_escape_policy_reescape_idempotent() {
+    // Already-escaped and raw strings decompile to the same canonical form
+    let first = decompile_property_text("a\\tb\\\\c", EscapePolicy::Reescape);
+    assert_eq!(first, decompile_property_text("a\tb\\c", EscapePolicy::Reescape));
+    assert!(first.contains(r"'a\tb\\c'"), "got {}", first);
+
+    // Feeding the canonical form back in yields the same output again
+    let start = first.find('\'').unwrap() + 1;
+    let end = first.rfind('\'').unwrap();
+    let canonical = &first[start..end];
+    assert_eq!(decompile_property_text(canonical, EscapePolicy::Reescape), first);
+}
+
+#[test]
+fn test_escape_policy_applies_to_defaults_and_conditions() {
+    let data = json!({
+        "ops": [{
+            "as": "my_op",
+            "configs": {"sep": {"dtype": "str", "default": "a\tb"}}
+        }],
+        "graphs": [{
+            "nodes": {
+                "result": {
+                    "output": ["result"],
+                    "op_name": "test.op",
+                    "for_loop": {"inputs": "items", "outputs": ["item"], "condition": "item == 'a\tb'"}
+                }
+            }
+        }]
+    });
+    let decompile_with = |escape_policy| {
+        let options = DecompileOptions { escape_policy, ..Default::default() };
+        match decompile_from_data(data.clone(), Some(options)).unwrap() {
+            DecompileResult::Text(text) => text,
+            _ => panic!("Expected text result"),
+        }
+    };
+
+    let kept = decompile_with(EscapePolicy::Keep);
+    assert!(kept.contains("default='a\tb'"), "{}", kept);
+    assert!(kept.contains("if 'item == \\'a\tb\\''"), "{}", kept);
+
+    let reescaped = decompile_with(EscapePolicy::Reescape);
+    assert!(reescaped.contains(r"default='a\tb'"), "{}", reescaped);
+    assert!(reescaped.contains(r"if 'item == \'a\tb\''"), "{}", reescaped);
+}
+
 #[test]
 fn test_invalid_identifier() {
     let data = json!({
         "graphs": [{
-            "as": "123invalid",
+            "as": "bad`name",
             "nodes": {
                 "valid_node": {
                     "output": ["valid_node"],
@@ -290,6 +569,34 @@ fn test_invalid_identifier() {
     assert!(result.unwrap_err().contains("Invalid identifier"));
 }
 
+#[test]
+fn test_quoted_identifier() {
+    let data = json!({
+        "graphs": [{
+            "as": "123 graph",
+            "nodes": {
+                "my node": {
+                    "output": ["out", "extra"],
+                    "op_name": "test.op",
+                    "input": ["x"]
+                }
+            }
+        }]
+    });
+
+    let DecompileResult::Text(text) = decompile_from_data(data, None).unwrap() else {
+        panic!("Expected text result");
+    };
+    assert!(text.contains(".as(`my node`)"), "{}", text);
+    assert!(text.contains("} as `123 graph`;"), "{}", text);
+
+    // The quoted names read back without their backticks
+    let compiled = crate::compile_ast(&crate::parse(&text).unwrap()).unwrap();
+    let graph = &compiled.graphs.unwrap()[0];
+    assert_eq!(graph.alias.as_deref(), Some("123 graph"));
+    assert!(graph.nodes.as_ref().unwrap().contains_key("my node"));
+}
+
 
 #[test]
 fn test_decompile_from_file() {
@@ -373,6 +680,36 @@ fn test_multiple_graphs() {
     }
 }
 
+#[test]
+fn test_decompile_to_writer_matches_text() {
+    let data = json!({
+        "graphs": [{
+            "property": {"name": "first"},
+            "nodes": {
+                "a": {"output": ["a"], "op_name": "foo", "input": ["x"]}
+            },
+            "as": "first"
+        }, {
+            "nodes": {
+                "result": {
+                    "output": ["result"],
+                    "op_name": "builtin.conditions.str",
+                    "condition": "x > 0",
+                    "true_branch": {"op_name": "math.add", "input": ["x", "1"]},
+                    "false_branch": {"op_name": "math.sub", "input": ["x", "1"]}
+                }
+            }
+        }]
+    });
+
+    let DecompileResult::Text(text) = decompile_from_data(data.clone(), None).unwrap() else {
+        panic!("Expected text result");
+    };
+    let mut output: Vec<u8> = Vec::new();
+    decompile_to_writer(&data, &mut output, None).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), text);
+}
+
 #[test]
 fn test_graph_with_properties() {
     let data = json!({
@@ -526,6 +863,53 @@ fn test_operation_with_range_spec() {
     }
 }
 
+#[test]
+fn test_interval_float_and_negative_bounds() {
+    let content = "op {\n    input {\n        ratio: (dtype=float, range=[0.0, 1.0]);\n    }\n} as scale;";
+    let compiled = serde_json::to_value(crate::compile_ast(&crate::parse(content).unwrap()).unwrap()).unwrap();
+    assert_eq!(compiled["ops"][0]["inputs"]["ratio"]["range"], json!({"ge": 0.0, "le": 1.0}));
+    let DecompileResult::Text(text) = decompile_from_data(compiled, None).unwrap() else {
+        panic!("Expected text result");
+    };
+    assert!(text.contains("ratio:(dtype=float,range=[0.0,1.0]);"), "{}", text);
+    assert_roundtrip(content);
+
+    let content = "op {\n    input {\n        offset: (dtype=int, range=(-5, 5]);\n    }\n} as shift;";
+    let compiled = serde_json::to_value(crate::compile_ast(&crate::parse(content).unwrap()).unwrap()).unwrap();
+    assert_eq!(compiled["ops"][0]["inputs"]["offset"]["range"], json!({"gt": -5, "le": 5}));
+    assert_roundtrip(content);
+
+    // `-0.0` stays a float with its sign
+    let content = "op {\n    input {\n        delta: (dtype=float, range=[-0.0, 1.0));\n    }\n} as step;";
+    let compiled = serde_json::to_value(crate::compile_ast(&crate::parse(content).unwrap()).unwrap()).unwrap();
+    let ge = compiled["ops"][0]["inputs"]["delta"]["range"]["ge"].as_f64().unwrap();
+    assert!(ge == 0.0 && ge.is_sign_negative(), "{}", ge);
+    let DecompileResult::Text(text) = decompile_from_data(compiled, None).unwrap() else {
+        panic!("Expected text result");
+    };
+    assert!(text.contains("range=[-0.0,1.0)"), "{}", text);
+    assert_roundtrip(content);
+}
+
+#[test]
+fn test_op_choice_tuple_round_trip() {
+    let content = "op {\n    input {\n        mode: (dtype=str, choice=('a','b','c'));\n        level: (dtype=int, choice=(1,2), default=1);\n        only: (dtype=str, choice=('x',));\n    }\n} as pick;";
+    let compiled = serde_json::to_value(crate::compile_ast(&crate::parse(content).unwrap()).unwrap()).unwrap();
+    let inputs = &compiled["ops"][0]["inputs"];
+    assert_eq!(inputs["mode"]["choice"], json!(["a", "b", "c"]));
+    assert_eq!(inputs["level"]["choice"], json!([1, 2]));
+    assert_eq!(inputs["only"]["choice"], json!(["x"]));
+
+    let DecompileResult::Text(text) = decompile_from_data(compiled.clone(), None).unwrap() else {
+        panic!("Expected text result");
+    };
+    assert!(text.contains("mode:(choice=('a','b','c'),dtype=str);"), "{}", text);
+    assert!(text.contains("level:(choice=(1,2),default=1,dtype=int);"), "{}", text);
+    assert!(text.contains("only:(choice=('x',),dtype=str);"), "{}", text);
+    let recompiled = serde_json::to_value(crate::compile_ast(&crate::parse(&text).unwrap()).unwrap()).unwrap();
+    assert_eq!(recompiled, compiled);
+}
+
 #[test]
 fn test_operation_with_exact_length() {
     let data = json!({
@@ -594,6 +978,9 @@ fn test_decompile_options() {
         max_col: 50,
         unescape: true,
         keep_order: true,
+        escape_policy: EscapePolicy::Keep,
+        resolve_vars: false,
+        max_depth: None,
     };
     
     let result = decompile_from_data(data, Some(options)).unwrap();
@@ -606,6 +993,121 @@ fn test_decompile_options() {
     }
 }
 
+#[test]
+fn test_decompile_resolve_vars() {
+    let data = json!({
+        "graphs": [{
+            "as": "main",
+            "nodes": {
+                "a": {
+                    "input": ["config.src"],
+                    "op_name": "load",
+                    "output": ["a"],
+                    "with": {"limit": "config.db.limit"}
+                }
+            }
+        }],
+        "vars": {
+            "config.as": "config",
+            "config.src": "table_a",
+            "config.db": {"limit": 10}
+        }
+    });
+
+    let options = DecompileOptions {
+        resolve_vars: true,
+        ..Default::default()
+    };
+    match decompile_from_data(data.clone(), Some(options)).unwrap() {
+        DecompileResult::Text(text) => {
+            assert!(text.contains("a = load(table_a)"), "{}", text);
+            assert!(text.contains(".with(limit=10)"), "{}", text);
+        }
+        _ => panic!("Expected text result"),
+    }
+    // References are kept as they are by default
+    match decompile_from_data(data, None).unwrap() {
+        DecompileResult::Text(text) => assert!(text.contains("a = load(config.src)"), "{}", text),
+        _ => panic!("Expected text result"),
+    }
+
+    // Only node inputs and call parameters are resolved, here with vars as
+    // ordered pairs and a var named like the op, the output and the version
+    let data = json!({
+        "graphs": [{
+            "as": "main",
+            "nodes": {
+                "a": {
+                    "input": ["src"],
+                    "op_name": "load",
+                    "output": ["a"],
+                    "version": "v",
+                    "property": {"p": "src"}
+                }
+            }
+        }],
+        "vars": [
+            {"key": "src", "value": "table_a"},
+            {"key": "load", "value": "other"},
+            {"key": "a", "value": "b"},
+            {"key": "v", "value": "2.0"}
+        ]
+    });
+    let options = DecompileOptions {
+        resolve_vars: true,
+        ..Default::default()
+    };
+    match decompile_from_data(data, Some(options)).unwrap() {
+        DecompileResult::Text(text) => {
+            assert!(text.contains("a = load(table_a)"), "{}", text);
+            assert!(text.contains(".version('v')"), "{}", text);
+            assert!(text.contains(".property(p='table_a')"), "{}", text);
+        }
+        _ => panic!("Expected text result"),
+    }
+}
+
+#[test]
+fn test_decompile_max_depth() {
+    let options = DecompileOptions {
+        max_depth: Some(64),
+        ..Default::default()
+    };
+    let with_value = |x: Value| {
+        json!({
+            "graphs": [{
+                "as": "main",
+                "nodes": {
+                    "a": {"op_name": "foo", "output": ["a"], "with": {"x": x}}
+                }
+            }]
+        })
+    };
+
+    // Building and dropping a value this deep recurses in serde_json itself,
+    // give it a larger stack than the test thread has
+    let deep_options = options.clone();
+    let error = std::thread::Builder::new()
+        .stack_size(256 << 20)
+        .spawn(move || {
+            let mut deep = json!(1);
+            for _ in 0..10000 {
+                deep = Value::Array(vec![deep]);
+            }
+            decompile_to_writer(&with_value(deep), &mut Vec::new(), Some(deep_options)).unwrap_err()
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(error, "JSON is nested deeper than the maximum depth 64");
+
+    // Within the limit the document decompiles as usual
+    match decompile_from_data(with_value(json!([[1]])), Some(options)).unwrap() {
+        DecompileResult::Text(text) => assert!(text.contains(".with(x=[[1]])"), "{}", text),
+        _ => panic!("Expected text result"),
+    }
+}
+
 #[test]
 fn test_complex_nested_structure() {
     let data = json!({
@@ -662,4 +1164,162 @@ fn test_invalid_input_not_object() {
     let result = decompile_from_data(data, None);
     assert!(result.is_err());
     assert!(result.unwrap_err().contains("Decompile input must be a JSON object"));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_op_meta_nested_values_round_trip() {
+    let content = r#"
+op {
+    meta {
+        tags = ["a", "b"];
+        config = {"k": 1, "inner": {"x": [1, 2], "name": "it's"}};
+    };
+} as my_op;
+"#;
+    let compile = |source: &str| {
+        let ast = crate::parse(source).unwrap();
+        serde_json::to_value(crate::compile_ast(&ast).unwrap()).unwrap()
+    };
+    let compiled = compile(content);
+    let metas = &compiled["ops"][0]["metas"];
+    assert_eq!(metas["tags"], json!(["a", "b"]));
+    assert_eq!(metas["config"], json!({"k": 1, "inner": {"x": [1, 2], "name": "it's"}}));
+
+    let DecompileResult::Text(text) = decompile_from_data(compiled.clone(), None).unwrap() else {
+        panic!("Expected text result");
+    };
+    assert_eq!(&compile(&text)["ops"][0]["metas"], metas, "{}", text);
+}
+
+#[test]
+fn test_graph_set_property_round_trip() {
+    let content = r#"
+graph {
+    tags = {"c", "a", "b"};
+    a = foo(x);
+} as main;
+"#;
+    let compile = |source: &str| {
+        let ast = crate::parse(source).unwrap();
+        serde_json::to_value(crate::compile_ast(&ast).unwrap()).unwrap()
+    };
+    let compiled = compile(content);
+    let graph = &compiled["graphs"][0];
+    assert_eq!(graph["properties"]["tags"], json!(["c", "a", "b"]));
+    assert_eq!(graph["set_property"], json!(["tags"]));
+
+    let DecompileResult::Text(text) = decompile_from_data(compiled.clone(), None).unwrap() else {
+        panic!("Expected text result");
+    };
+    assert!(text.contains("tags={'c','a','b'};"), "{}", text);
+    assert_eq!(compile(&text)["graphs"][0], *graph, "{}", text);
+}
+
+#[test]
+fn test_funnel_round_trip() {
+    let content = "graph {\n    a = foo(x).funnel(steps=[\"view\", \"buy\"], window=7);\n} as main;";
+    let compile = |source: &str| {
+        let ast = crate::parse(source).unwrap();
+        serde_json::to_value(crate::compile_ast(&ast).unwrap()).unwrap()
+    };
+    let compiled = compile(content);
+    let node = &compiled["graphs"][0]["nodes"]["a"];
+    assert_eq!(node["funnel"], json!({"steps": ["view", "buy"], "window": 7}));
+    assert!(node.get("with").is_none());
+
+    let DecompileResult::Text(text) = decompile_from_data(compiled.clone(), None).unwrap() else {
+        panic!("Expected text result");
+    };
+    assert!(text.contains(".funnel(steps=['view','buy'],window=7)"), "{}", text);
+    assert_eq!(compile(&text), compiled, "{}", text);
+}
+
+#[test]
+fn test_grouped_input_round_trip() {
+    let content = "graph {\n    a = concat(x, (y, z), w);\n} as main;";
+    let compiled = serde_json::to_value(crate::compile_ast(&crate::parse(content).unwrap()).unwrap()).unwrap();
+    assert_eq!(compiled["graphs"][0]["nodes"]["a"]["inputs"], json!(["x", ["y", "z"], "w"]));
+
+    let DecompileResult::Text(text) = decompile_from_data(compiled, None).unwrap() else {
+        panic!("Expected text result");
+    };
+    assert!(text.contains("a = concat(x,(y,z),w);"), "{}", text);
+    assert_roundtrip(content);
+}
+
+#[test]
+fn test_indexed_input_round_trip() {
+    use crate::ast::AstNodeEnum;
+
+    let content = "graph {\n    a,b = split(x).as(a);\n    c = join(a.0,b[1]);\n} as main;";
+    let ast = crate::parse(content).unwrap();
+    let AstNodeEnum::Module(module) = &ast else {
+        panic!("Expected Module");
+    };
+    let AstNodeEnum::GraphDef(graph) = &module.children[0] else {
+        panic!("Expected GraphDef");
+    };
+    let AstNodeEnum::NodeDef(node) = &graph.children[1] else {
+        panic!("Expected NodeDef");
+    };
+    let indices: Vec<_> = node.value.input_symbols().iter().map(|input| input.output_index()).collect();
+    assert_eq!(indices, [Some(("a", 0)), Some(("b", 1))]);
+
+    let options = crate::CompileOptions {
+        emit_edges: true,
+        ..Default::default()
+    };
+    let compiled = serde_json::to_value(crate::compile_ast_with_options(&ast, options).unwrap()).unwrap();
+    assert_eq!(compiled["graphs"][0]["nodes"]["c"]["inputs"], json!(["a.0", "b[1]"]));
+    assert_eq!(compiled["graphs"][0]["edges"], json!([["a", "c"]]));
+
+    let DecompileResult::Text(text) = decompile_from_data(compiled, None).unwrap() else {
+        panic!("Expected text result");
+    };
+    assert!(text.contains("c = join(a.0,b[1]);"), "{}", text);
+    assert_roundtrip(content);
+}
+
+/// Parse, compile and decompile `content`, then check the decompiled text
+/// parses back to the same tree
+fn assert_roundtrip(content: &str) {
+    let ast = crate::parse(content).unwrap_or_else(|e| panic!("{}\n{}", e, content));
+    let compiled = serde_json::to_value(crate::compile_ast(&ast).unwrap()).unwrap();
+    let DecompileResult::Text(text) = decompile_from_data(compiled, None).unwrap() else {
+        panic!("Expected text result");
+    };
+    let reparsed = crate::parse(&text).unwrap_or_else(|e| panic!("{}\n{}", e, text));
+    assert!(
+        crate::ast::eq_ignore_position(&ast, &reparsed),
+        "round trip changed the tree\n--- original\n{}\n--- decompiled\n{}",
+        content,
+        text
+    );
+}
+
+/// Snippets written the way the decompiler prints them, one feature each
+const ROUNDTRIP_CORPUS: &[&str] = &[
+    "graph {\n    a = foo(x);\n} as main;",
+    "graph {\n    a = foo(x,y).version('1.0.0');\n} as main;",
+    "graph {\n    a = foo(x,(y,z));\n} as main;",
+    "graph {\n    a = foo(x);\n    b = bar(a).depend(a);\n} as main;",
+    "graph {\n    a = foo(x).with(k=1,s='v');\n} as main;",
+    "graph {\n    a = foo(x).property(flag=true,ratio=0.5);\n} as main;",
+    "graph {\n    a,b = split(x).as(a);\n} as main;",
+    "graph {\n    a = x > 0 ? foo(x) : bar(x);\n} as main;",
+    "graph {\n    description='pipeline';\n    a = foo(x);\n} as main;",
+    "graph {\n    a = foo(x);\n} as main.version('2.1.0');",
+    "graph {\n    a = foo();\n} as main;",
+    "graph {\n    a = foo(x).with(items=[1,2,3]);\n} as main;",
+    "graph {\n    a = foo(x).with(nested={'k':1,'v':'s'});\n} as main;",
+    "graph {\n    a = foo(x);\n} as first;\n\ngraph {\n    b = bar(y);\n} as second;",
+    "graph {\n    a = foo(x)\n        .property(p=true)\n        .with(k=1);\n} as main;",
+    "graph {\n    a = foo(x).funnel(steps=['view','buy'],window=7);\n} as main;",
+];
+
+#[test]
+fn test_roundtrip_corpus() {
+    for content in ROUNDTRIP_CORPUS {
+        assert_roundtrip(content);
+    }
+}
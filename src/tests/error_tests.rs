@@ -191,6 +191,19 @@ graph {
             _ => panic!("Expected syntax error for incomplete import"),
         }
     }
+
+    #[test]
+    fn test_empty_import_message() {
+        for (content, line, column) in [("import;", 1, 7), ("var { a = 1; };\nimport ;", 2, 8)] {
+            match assert_parse_error(content) {
+                ParseError::SyntaxError { line: l, column: c, message } => {
+                    assert_eq!(message, "import requires at least one module name");
+                    assert_eq!((l, c), (line, column), "for {:?}", content);
+                }
+                other => panic!("Expected SyntaxError, got {:?}", other),
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -365,4 +378,152 @@ graph {
             _ => panic!("Expected syntax error for multiple errors"),
         }
     }
+
+    #[test]
+    fn test_collect_errors_per_statement() {
+        let content = r#"
+var { a = 1; };
+var { b = ; };
+graph { x = foo(); };
+var { = 2; };
+"#;
+        let (ast, errors) = crate::parse_gos_with_errors(content, default_test_options());
+        assert!(ast.is_none());
+        assert_eq!(errors.errors.len(), 2);
+        let lines: Vec<_> = errors.errors.iter().map(|e| e.line()).collect();
+        assert_eq!(lines, vec![Some(3), Some(5)]);
+    }
+
+    #[test]
+    fn test_deprecation_warnings_keep_ast() {
+        let content = "var { a = 1; };\nvar { since = 2024-01-01T00:00:00Z; };\n";
+        let (ast, errors) = crate::parse_with_errors(content);
+        assert!(ast.is_some());
+        assert!(!errors.has_errors());
+        assert_eq!(errors.warnings.len(), 1);
+        assert!(matches!(errors.warnings[0], ParseError::DeprecatedFeature { line: 2, column: 15, .. }));
+
+        // Warnings of statements parsed during recovery point at the original line
+        let content = format!("var {{ b = ; }};\n{}", content);
+        let (ast, errors) = crate::parse_with_errors(&content);
+        assert!(ast.is_none());
+        assert_eq!(errors.errors.len(), 1);
+        assert_eq!(errors.warnings.len(), 1);
+        assert_eq!(errors.warnings[0].line(), Some(3));
+    }
+
+    #[test]
+    fn test_max_errors_limit() {
+        let content = (0..20)
+            .map(|i| format!("var {{ name_{} = ; }};\n", i))
+            .collect::<String>();
+        let options = ParseOptions {
+            max_errors: Some(5),
+            ..default_test_options()
+        };
+        let (ast, errors) = crate::parse_gos_with_errors(&content, options);
+        assert!(ast.is_none());
+        assert_eq!(errors.errors.len(), 6);
+        assert!(errors.errors[..5]
+            .iter()
+            .all(|e| matches!(e, ParseError::SyntaxError { .. })));
+        assert!(errors.errors[5].to_string().contains("too many errors, stopping"));
+
+        // Unlimited by default
+        let (_, errors) = crate::parse_gos_with_errors(&content, default_test_options());
+        assert_eq!(errors.errors.len(), 20);
+    }
+
+    #[test]
+    fn test_error_classification() {
+        use crate::error::Severity;
+
+        let cases = [
+            (ParseError::syntax_error(1, 1, "x"), true, Severity::Error),
+            (ParseError::lexical_error(1, 1, '$'), false, Severity::Error),
+            (ParseError::semantic_error(1, 1, "x"), true, Severity::Error),
+            (ParseError::duplicate_definition("x", 1, 1), true, Severity::Error),
+            (ParseError::deprecated_feature("x", 1, 1, "y"), true, Severity::Warning),
+            (ParseError::unsupported_feature("x", 1, 1), true, Severity::Error),
+            (ParseError::invalid_value("x", 1, 1), true, Severity::Error),
+            (ParseError::circular_import(vec!["a".into(), "a".into()], 1, 1), false, Severity::Error),
+            (ParseError::general("x"), true, Severity::Error),
+            (ParseError::Io("x".into()), false, Severity::Error),
+            (ParseError::Pest("x".into()), false, Severity::Error),
+        ];
+        for (error, recoverable, severity) in cases {
+            assert_eq!(error.is_recoverable(), recoverable, "{:?}", error);
+            assert_eq!(error.severity(), severity, "{:?}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod render_tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use crate::error::ParseError;
+
+    #[test]
+    fn test_render_from_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "var {{ a = 1; }};\ngraph {{ x = foo(y; }};\n").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let error = ParseError::syntax_error(2, 18, "expected RPAREN");
+        let rendered = error.render_from_file(path).unwrap();
+        assert_eq!(
+            rendered,
+            "Syntax error at line 2, column 18: expected RPAREN\n  --> 2:18\n  |\n2 | graph { x = foo(y; };\n  |                  ^"
+        );
+
+        let general = ParseError::general("no position");
+        assert_eq!(general.render_from_file(path).unwrap(), general.to_string());
+        assert!(error.render_from_file("/nonexistent/file.gos").is_err());
+    }
+}
+
+#[cfg(test)]
+mod import_error_tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::error::ParseError;
+    use crate::parse_with_imports;
+
+    #[test]
+    fn test_circular_import() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.gos"), "import b;\ngraph { x = foo(); } as ga;\n").unwrap();
+        fs::write(dir.path().join("b.gos"), "graph { y = bar(); } as gb;\nimport a;\n").unwrap();
+
+        let error = parse_with_imports(dir.path().join("a.gos")).unwrap_err();
+        match &error {
+            ParseError::CircularImport { cycle, line, column } => {
+                assert_eq!(cycle, &["a", "b", "a"]);
+                assert_eq!((*line, *column), (2, 8));
+            }
+            other => panic!("Expected CircularImport, got {:?}", other),
+        }
+        assert!(error.to_string().contains("a -> b -> a"), "{}", error);
+    }
+
+    #[test]
+    fn test_imports_resolved_once() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("lib")).unwrap();
+        fs::write(dir.path().join("main.gos"), "import lib.common, lib.extra;\n").unwrap();
+        fs::write(dir.path().join("lib/extra.gos"), "import common;\n").unwrap();
+        fs::write(dir.path().join("lib/common.gos"), "var { n = 1; };\n").unwrap();
+
+        let modules = parse_with_imports(dir.path().join("main.gos")).unwrap();
+        let names: Vec<_> = modules
+            .iter()
+            .map(|(path, _)| path.file_stem().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["common", "extra", "main"]);
+    }
 }
@@ -95,6 +95,10 @@ var {
             error: false,
             tracking: false,
             debug: false,
+            max_errors: None,
+            identifier_case: None,
+            allow_trailing_comma: false,
+            case_insensitive_keywords: false,
         };
         let ast1 = parse_gos(content, minimal_options).expect("Parse should succeed");
         
@@ -105,6 +109,10 @@ var {
             error: true,
             tracking: true,
             debug: true,
+            max_errors: None,
+            identifier_case: None,
+            allow_trailing_comma: false,
+            case_insensitive_keywords: false,
         };
         let ast2 = parse_gos(content, full_options).expect("Parse should succeed");
         
@@ -137,6 +145,10 @@ graph {
             error: true, // Enable error collection
             tracking: true,
             debug: false,
+            max_errors: None,
+            identifier_case: None,
+            allow_trailing_comma: false,
+            case_insensitive_keywords: false,
         };
         
         let result = parse_gos(content, options);
@@ -149,15 +161,41 @@ graph {
             }
         }
     }
+
+    #[test]
+    fn test_strict_and_lenient_presets() {
+        let strict = ParseOptions::strict();
+        assert!(strict.ast);
+        assert!(strict.error);
+        assert!(strict.tracking);
+        assert!(!strict.symbol);
+        assert!(!strict.debug);
+
+        let lenient = ParseOptions::lenient();
+        assert!(lenient.ast);
+        assert!(!lenient.error);
+        assert!(lenient.symbol);
+        assert!(lenient.tracking);
+        assert!(!lenient.debug);
+        assert!(lenient.allow_trailing_comma);
+        assert!(lenient.case_insensitive_keywords);
+
+        let content = r#"var { name = "test"; } as config;"#;
+        assert!(parse_gos(content, ParseOptions::strict()).is_ok());
+        assert!(parse_gos(content, ParseOptions::lenient()).is_ok());
+
+        let loose = "var { debug = True; } as config;\ngraph {\n    a, b, = split(x);\n} as main;";
+        assert!(parse_gos(loose, ParseOptions::strict()).is_err());
+        assert!(parse_gos(loose, ParseOptions::lenient()).is_ok());
+    }
 }
 
 #[cfg(test)]
 mod complex_scenario_tests {
     use super::*;
 
-    #[test]
-    fn test_large_complex_gos_file() {
-        let content = r#"
+    /// A module with imports, nested vars and three chained graphs
+    const COMPLEX_GOS: &str = r#"
 # Complex GOS file with multiple features
 import builtin;
 import custom.operators as ops;
@@ -303,6 +341,10 @@ This is a comprehensive example demonstrating:
 7. Real-world pipeline patterns
 */
 "#;
+
+    #[test]
+    fn test_large_complex_gos_file() {
+        let content = COMPLEX_GOS;
         
         let ast = assert_parse_success(content);
         match ast {
@@ -334,6 +376,210 @@ This is a comprehensive example demonstrating:
         }
     }
 
+    #[test]
+    fn test_diff_compile_results() {
+        use crate::diff::*;
+
+        let before = r#"
+var { limit = 10; } as config;
+graph {
+    mode = "fast";
+    a = load(src);
+} as main;
+graph { x = foo(); } as other;
+"#;
+        // Graphs reordered, a node added and a property changed
+        let after = r#"
+graph { x = foo(); } as other;
+var { limit = 10; } as config;
+graph {
+    mode = "safe";
+    a = load(src);
+    b = clean(a);
+} as main;
+"#;
+        let compile = |content: &str| crate::compile_ast(&assert_parse_success(content)).unwrap();
+        let diffs = diff_compile_results(&compile(before), &compile(after));
+        assert_eq!(
+            diffs,
+            [
+                CompileDiff {
+                    kind: DiffKind::Added,
+                    path: "graphs.main.nodes.b".to_string(),
+                    old: None,
+                    new: Some(serde_json::json!({"op_name": "clean", "inputs": ["a"], "outputs": ["b"]})),
+                },
+                CompileDiff {
+                    kind: DiffKind::Changed,
+                    path: "graphs.main.properties.mode".to_string(),
+                    old: Some(serde_json::json!("fast")),
+                    new: Some(serde_json::json!("safe")),
+                },
+            ]
+        );
+
+        let removed = diff_compile_results(&compile(after), &compile("graph { x = foo(); } as other;"));
+        let paths: Vec<_> = removed.iter().map(|diff| (diff.kind, diff.path.as_str())).collect();
+        assert_eq!(
+            paths,
+            [(DiffKind::Removed, "graphs.main"), (DiffKind::Removed, "vars.config.as"), (DiffKind::Removed, "vars.config.limit")]
+        );
+        assert!(diff_compile_results(&compile(before), &compile(before)).is_empty());
+    }
+
+    #[test]
+    fn test_graph_to_dot() {
+        use crate::dot::*;
+
+        let content = r#"
+graph {
+    a = load("say \"hi\"");
+    b = clean(a);
+    c = merge(a, b).depend(a);
+} as main;
+graph { x = foo(); } as other;
+"#;
+        let result = crate::compile_ast(&assert_parse_success(content)).unwrap();
+        let graphs = result.graphs.as_ref().unwrap();
+        let main = graphs.iter().find(|graph| graph.alias.as_deref() == Some("main")).unwrap();
+        assert_eq!(
+            to_dot(main),
+            r#"digraph "main" {
+    "a" [label="load"];
+    "b" [label="clean"];
+    "c" [label="merge"];
+    "a" -> "b";
+    "a" -> "c";
+    "b" -> "c";
+}
+"#
+        );
+
+        let module = module_to_dot(&result);
+        assert!(module.starts_with("digraph {\n"));
+        assert!(module.contains("    subgraph \"cluster_main\" {\n        label=\"main\";\n"));
+        assert!(module.contains("        \"main.a\" -> \"main.b\";\n"));
+        assert!(module.contains("        \"other.x\" [label=\"foo\"];\n"));
+    }
+
+    #[test]
+    fn test_execution_plan() {
+        use crate::plan::*;
+
+        let content = r#"
+graph {
+    report = summarize(prep.clean, prep.stats.0);
+    publish = upload(report).depend(report);
+} as main;
+graph {
+    raw = load(path);
+    clean = dedupe(raw);
+    stats, hist = profile(clean).as(stats);
+} as prep;
+"#;
+        let result = crate::compile_ast(&assert_parse_success(content)).unwrap();
+        let plan = to_execution_plan(&result).unwrap();
+        let order: Vec<&str> = plan.nodes.iter().map(|node| node.id.as_str()).collect();
+        assert_eq!(order, ["prep.raw", "prep.clean", "prep.stats", "main.report", "main.publish"]);
+
+        let raw = plan.node("prep.raw").unwrap();
+        assert_eq!(raw.op_name.as_deref(), Some("load"));
+        assert_eq!(raw.inputs, [PlanInput::External("path".to_string())]);
+        assert_eq!(plan.unresolved, [("prep.raw".to_string(), "path".to_string())]);
+        assert_eq!(
+            plan.node("main.report").unwrap().inputs,
+            [
+                PlanInput::Node { id: "prep.clean".to_string(), output: "clean".to_string() },
+                PlanInput::Node { id: "prep.stats".to_string(), output: "stats.0".to_string() },
+            ]
+        );
+
+        let dangling = "graph {\n    a = foo(prep.missing);\n} as main;\ngraph {\n    b = bar();\n} as prep;";
+        let result = crate::compile_ast(&assert_parse_success(dangling)).unwrap();
+        let error = to_execution_plan(&result).unwrap_err();
+        assert!(error.to_string().contains("Graph prep has no output missing"), "{}", error);
+
+        // Names nothing provides are kept; properties and vars are provided
+        let external = "var { n = 1; } as limits;\ngraph {\n    source = \"s3\";\n    a = foo(source, limits.n, x, typo);\n} as main;";
+        let result = crate::compile_ast(&assert_parse_success(external)).unwrap();
+        let plan = to_execution_plan(&result).unwrap();
+        assert_eq!(plan.unresolved, [("main.a".to_string(), "x".to_string()), ("main.a".to_string(), "typo".to_string())]);
+
+        let cycle = "graph {\n    a = foo(b);\n    b = bar(a);\n} as main;";
+        let result = crate::compile_ast(&assert_parse_success(cycle)).unwrap();
+        let error = to_execution_plan(&result).unwrap_err();
+        assert!(error.to_string().contains("Nodes main.a, main.b form a cycle"), "{}", error);
+
+        let mut result = crate::compile_ast(&assert_parse_success("graph {\n    a = foo();\n} as main;")).unwrap();
+        let graphs = result.graphs.as_mut().unwrap();
+        graphs.push(graphs[0].clone());
+        let error = to_execution_plan(&result).unwrap_err();
+        assert!(error.to_string().contains("Graph main is defined more than once"), "{}", error);
+    }
+
+    #[test]
+    fn test_load_pipeline() {
+        let pipeline = crate::load_pipeline(COMPLEX_GOS).unwrap();
+        let graphs: Vec<(&str, usize)> = pipeline.graphs.iter().map(|graph| (graph.name.as_str(), graph.nodes.len())).collect();
+        assert_eq!(graphs, [("data_preprocessing", 4), ("model_training", 3), ("deployment", 2)]);
+        assert_eq!(pipeline.order.len(), 9);
+        assert_eq!(pipeline.graph("model_training").unwrap().version.as_deref(), Some("2.1.0"));
+        assert_eq!(pipeline.vars["pipeline_config.version"], serde_json::json!("2.1.0"));
+
+        // Every input produced by a node is produced earlier in the order
+        for (position, id) in pipeline.order.iter().enumerate() {
+            for input in &pipeline.node(id).unwrap().inputs {
+                if let crate::PlanInput::Node { id: producer, .. } = input {
+                    assert!(pipeline.order[..position].contains(producer), "{} runs before {}", id, producer);
+                }
+            }
+        }
+        let raw_data = pipeline.node("data_preprocessing.raw_data").unwrap();
+        assert_eq!(raw_data.node.with.as_ref().unwrap()["batch_size"], serde_json::json!(1000));
+        assert_eq!(
+            pipeline.node("deployment.deployed_model").unwrap().inputs,
+            [crate::PlanInput::Node { id: "model_training.final_model".to_string(), output: "final_model".to_string() }]
+        );
+
+        let with_var = crate::load_pipeline("var {\n    x = 1;\n};\ngraph {\n    a = foo(x);\n} as main;").unwrap();
+        assert_eq!(with_var.order, ["main.a"]);
+
+        let unwired = "graph {\n    a = foo(missing);\n} as main;";
+        let error = crate::load_pipeline(unwired).unwrap_err();
+        assert!(error.to_string().contains("Input missing of node a is not defined"), "{}", error);
+    }
+
+    #[test]
+    fn test_module_dependency_graph() {
+        use crate::dependency::*;
+
+        let AstNodeEnum::Module(module) = assert_parse_success(COMPLEX_GOS) else {
+            panic!("Expected Module node");
+        };
+        let deps = module_dependency_graph(&module);
+
+        let training = DepNode { kind: DepNodeKind::Graph, name: "model_training".to_string() };
+        assert_eq!(
+            deps.dependencies_of("deployment"),
+            [
+                (&DepNode { kind: DepNodeKind::Import, name: "ops".to_string() }, DepEdgeKind::Import),
+                (&training, DepEdgeKind::GraphRef),
+                (&DepNode { kind: DepNodeKind::Import, name: "builtin".to_string() }, DepEdgeKind::Import),
+                (&DepNode { kind: DepNodeKind::Var, name: "pipeline_config".to_string() }, DepEdgeKind::VarUse),
+            ]
+        );
+        assert_eq!(deps.dependents_of("data_preprocessing"), [(&training, DepEdgeKind::GraphRef)]);
+
+        let mut config_users: Vec<&str> = deps
+            .dependents_of("pipeline_config")
+            .iter()
+            .map(|(node, _)| node.name.as_str())
+            .collect();
+        config_users.sort();
+        assert_eq!(config_users, ["data_preprocessing", "deployment", "model_training"]);
+        assert_eq!(deps.dependents_of("builtin").len(), 3);
+    }
+
     #[test]
     fn test_unicode_and_special_characters() {
         let content = r#"
@@ -456,6 +702,28 @@ graph {{
             _ => panic!("Expected Module node"),
         }
     }
+
+    #[test]
+    fn test_format_deeply_nested_structures() {
+        let depth = 30;
+        let mut content = String::from("var { deeply_nested = ");
+        for i in 0..depth {
+            content.push_str(&format!(r#"{{"level_{}": [1, "#, i));
+        }
+        content.push('2');
+        for _ in 0..depth {
+            content.push_str("]}");
+        }
+        content.push_str("; }");
+
+        let start = std::time::Instant::now();
+        let formatted = crate::format_from_data(&content, 4, 40).unwrap();
+        let duration = start.elapsed();
+
+        // Every level is measured once, not once per enclosing level
+        assert!(duration.as_millis() < 1000, "Formatting took too long: {:?}", duration);
+        assert!(formatted.contains("\"level_29\": [1, 2]"), "got:\n{}", formatted);
+    }
 }
 
 #[cfg(test)]
@@ -485,6 +753,62 @@ var {
         }
     }
 
+    #[test]
+    fn test_validate_files() {
+        let mut valid = NamedTempFile::new().expect("Failed to create temp file");
+        valid.write_all(b"graph { a = foo(x); } as main;\n").expect("Failed to write to temp file");
+        let mut invalid = NamedTempFile::new().expect("Failed to create temp file");
+        invalid.write_all(b"graph {\n    a = ;\n} as main;\n").expect("Failed to write to temp file");
+        let missing = valid.path().with_extension("missing.gos");
+
+        let paths = [
+            valid.path().to_str().unwrap(),
+            missing.to_str().unwrap(),
+            invalid.path().to_str().unwrap(),
+        ];
+        let results = crate::validate_files(&paths);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].0, paths[0]);
+        assert!(!results[0].1.has_errors());
+
+        // A missing file does not stop the files after it
+        assert_eq!(results[1].1.errors.len(), 1);
+        assert!(matches!(&results[1].1.errors[0], crate::ParseError::Io(message) if message.starts_with(paths[1])));
+
+        assert!(results[2].1.has_errors());
+        assert_eq!(results[2].1.errors[0].line(), Some(2));
+    }
+
+    #[test]
+    fn test_source_reuses_parse() {
+        let content = "var { retries = 3; } as conf;\ngraph { a = foo(x).with(n=conf.retries); } as main;\n";
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        temp_file.write_all(content.as_bytes()).expect("Failed to write to temp file");
+
+        let source = crate::Source::from_file(temp_file.path()).unwrap();
+        assert_eq!(source.path(), Some(temp_file.path()));
+        assert_eq!(source.content(), content);
+
+        let first = source.parse().unwrap();
+        source.validate().unwrap();
+        let formatted = source.format(4, 100).unwrap();
+        let compiled = source.compile().unwrap();
+        // Every operation works on the one cached tree
+        assert!(std::ptr::eq(first, source.parse().unwrap()));
+
+        assert_eq!(formatted, crate::format_from_data(content, 4, 100).unwrap());
+        let node = &compiled.graphs.unwrap()[0].nodes.clone().unwrap()["a"];
+        assert_eq!(node.with.as_ref().unwrap()["n"], 3);
+
+        // Parse errors are cached too
+        let broken = crate::Source::from_str("graph { a = ");
+        assert!(broken.validate().is_err());
+        assert!(broken.compile().is_err());
+        assert!(broken.path().is_none());
+
+        assert!(crate::Source::from_file("/nonexistent/file.gos").is_err());
+    }
+
     #[test]
     fn test_parse_empty_file() {
         let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
@@ -588,4 +912,50 @@ as pipeline; # Comment after alias
             _ => panic!("All should parse as modules"),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod pragma_tests {
+    use crate::{format_from_data, parse, CompileOptions, ParseError, Pragma, Source};
+
+    #[test]
+    fn test_pragma_sets_formatter_indent() {
+        let content = "# gos: indent=2 max_col=80 keep_order\nvar { a = 1; } as conf;\n";
+        let pragma = Pragma::from_source(content).unwrap().unwrap();
+        assert_eq!(pragma.indent, Some(2));
+        assert_eq!(pragma.max_col, Some(80));
+        assert_eq!(pragma.keep_order, Some(true));
+
+        let formatted = pragma.formatter().format_str(content).unwrap();
+        assert!(formatted.contains("\n  a = 1;\n"), "got:\n{}", formatted);
+        assert!(pragma.compile_options(CompileOptions::default()).keep_order);
+    }
+
+    #[test]
+    fn test_pragma_applies_to_format_and_parse() {
+        let content = "# gos: indent=2\nvar { a = 1; } as conf;\n";
+        let formatted = format_from_data(content, 4, 100).unwrap();
+        assert!(formatted.contains("\n  a = 1;\n"), "got:\n{}", formatted);
+        let formatted = Source::from_str(content).format(4, 100).unwrap();
+        assert!(formatted.contains("\n  a = 1;\n"), "got:\n{}", formatted);
+
+        let graph = "graph {\n    a, b,\n    = split(x);\n};";
+        assert!(parse(graph).is_err());
+        assert!(parse(&format!("# gos: allow_trailing_comma\n{}", graph)).is_ok());
+    }
+
+    #[test]
+    fn test_pragma_absent_or_invalid() {
+        assert_eq!(Pragma::from_source("# plain comment\nvar { a = 1; }").unwrap(), None);
+        assert_eq!(Pragma::from_source("var { a = 1; }\n# gos: indent=2").unwrap(), None);
+        assert_eq!(Pragma::from_source("").unwrap(), None);
+
+        let error = Pragma::from_source("#gos: indent=2 colour").unwrap_err();
+        assert!(matches!(error, ParseError::SyntaxError { .. }));
+        assert_eq!((error.line(), error.column()), (Some(1), Some(16)));
+        assert!(Pragma::from_source("# gos: indent=two").is_err());
+        assert!(Pragma::from_source("# gos: keep_order=yes").is_err());
+        let error = Pragma::from_source("# gos: indent=2 =3").unwrap_err();
+        assert_eq!((error.line(), error.column()), (Some(1), Some(17)));
+    }
+}
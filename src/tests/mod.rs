@@ -20,6 +20,10 @@ pub fn default_test_options() -> ParseOptions {
         error: true,
         tracking: true,
         debug: true,
+        max_errors: None,
+        identifier_case: None,
+        allow_trailing_comma: false,
+        case_insensitive_keywords: false,
     }
 }
 
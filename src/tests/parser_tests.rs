@@ -492,6 +492,114 @@ var {
             _ => panic!("Expected Module"),
         }
     }
+
+    fn parse_date_value(content: &str) -> String {
+        let AstNodeEnum::Module(module) = assert_parse_success(content) else {
+            panic!("Expected Module");
+        };
+        let AstNodeEnum::VarDef(var_def) = &module.children[0] else {
+            panic!("Expected VarDef");
+        };
+        let AstNodeEnum::AttrDef(attr_def) = &var_def.children[0] else {
+            panic!("Expected AttrDef");
+        };
+        let AstNodeEnum::DateLiteral(date) = attr_def.value.as_ref() else {
+            panic!("Expected DateLiteral");
+        };
+        date.value.clone()
+    }
+
+    #[test]
+    fn test_parse_date_with_timezone() {
+        assert_eq!(
+            parse_date_value(r#"var { d = date("2024-12-31T23:59:59Z"); };"#),
+            "2024-12-31T23:59:59Z"
+        );
+        assert_eq!(
+            parse_date_value(r#"var { d = date("2024-12-31T23:59:59+08:00"); };"#),
+            "2024-12-31T23:59:59+08:00"
+        );
+
+        let error = assert_parse_error(r#"var { d = date("2024-12-31T23:59:59+25:00"); };"#);
+        assert!(matches!(error, ParseError::InvalidValue { line: 1, .. }), "got {:?}", error);
+    }
+
+    #[test]
+    fn test_parse_exponent_without_dot() {
+        let content = "var {\n    a = 1e10;\n    b = 1E10;\n    c = 1e+10;\n    d = 1e-10;\n};";
+        let AstNodeEnum::Module(module) = assert_parse_success(content) else {
+            panic!("Expected Module");
+        };
+        let AstNodeEnum::VarDef(var_def) = &module.children[0] else {
+            panic!("Expected VarDef");
+        };
+        let expected = [("1e10", 1e10), ("1E10", 1e10), ("1e+10", 1e10), ("1e-10", 1e-10)];
+        for (index, (raw, value)) in expected.into_iter().enumerate() {
+            let AstNodeEnum::AttrDef(attr_def) = &var_def.children[index] else {
+                panic!("Expected AttrDef");
+            };
+            let line = index + 2;
+            let pos = Position::new(line, 9, 9 + raw.len());
+            assert_float_value(attr_def.value.clone(), &pos, raw, value);
+        }
+
+        // Not read as `1` followed by `e10` in a condition either
+        let content = "graph {\n    a = x > 1e10 ? foo(x) : bar(x);\n} as main;";
+        assert_parse_success(content);
+    }
+
+    #[test]
+    fn test_parse_hex_binary_and_underscored_numbers() {
+        let content = "var {\n    a = 0xFF;\n    b = -0x10;\n    c = 0b1010;\n    d = 1_000_000;\n    e = 0xFF_FF;\n};";
+        let AstNodeEnum::Module(module) = assert_parse_success(content) else {
+            panic!("Expected Module");
+        };
+        let AstNodeEnum::VarDef(var_def) = &module.children[0] else {
+            panic!("Expected VarDef");
+        };
+        let expected = [("0xFF", 255), ("-0x10", -16), ("0b1010", 10), ("1_000_000", 1_000_000), ("0xFF_FF", 0xFFFF)];
+        for (index, (raw, value)) in expected.into_iter().enumerate() {
+            let AstNodeEnum::AttrDef(attr_def) = &var_def.children[index] else {
+                panic!("Expected AttrDef");
+            };
+            let pos = Position::new(index + 2, 9, 9 + raw.len());
+            assert_number_value(attr_def.value.clone(), &pos, raw, value);
+        }
+
+        // An underscore only goes between digits
+        assert_parse_error("var { a = 1__0; };");
+        assert_parse_error("var { a = 0x; };");
+    }
+
+    #[test]
+    fn test_parse_raw_string() {
+        let content = r#"var {
+    path = r"C:\path\to\file";
+    pattern = r'^\d+\.\w*$';
+    escaped = "C:\\path";
+};"#;
+        let AstNodeEnum::Module(module) = assert_parse_success(content) else {
+            panic!("Expected Module");
+        };
+        let AstNodeEnum::VarDef(var_def) = &module.children[0] else {
+            panic!("Expected VarDef");
+        };
+        let expected = [
+            (r"C:\path\to\file", QuoteStyle::Double),
+            (r"^\d+\.\w*$", QuoteStyle::Single),
+            (r"C:\path", QuoteStyle::Double),
+        ];
+        for (index, (value, quote)) in expected.into_iter().enumerate() {
+            let AstNodeEnum::AttrDef(attr_def) = &var_def.children[index] else {
+                panic!("Expected AttrDef");
+            };
+            let AstNodeEnum::StringLiteral(literal) = &*attr_def.value else {
+                panic!("Expected StringLiteral");
+            };
+            assert_eq!(literal.value, value);
+            assert_eq!(literal.quote, quote);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1146,6 +1254,25 @@ mod comment_tests {
         }
     }
 
+    #[test]
+    fn test_parse_comment_at_eof_without_newline() {
+        let content = "var { a = 1; };\n# last comment";
+        let AstNodeEnum::Module(module) = assert_parse_success(content) else {
+            panic!("Expected Module");
+        };
+        match module.children.last() {
+            Some(AstNodeEnum::Comment(comment)) => {
+                assert_eq!(comment.value, "# last comment");
+                assert_eq!(comment.kind, CommentKind::Regular);
+                assert_eq!(
+                    (comment.position.line, comment.position.start, comment.position.end),
+                    (2, 1, 15)
+                );
+            }
+            other => panic!("Expected Comment, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_parse_multiline_comment() {
         let content = r#"
@@ -1169,6 +1296,56 @@ multiline comment
             _ => panic!("Expected Module"),
         }
     }
+
+    #[test]
+    fn test_parse_doc_comment_kinds() {
+        let content = r#"//! Module doc
+/// Item doc
+// Regular
+//// Not a doc
+# Hash
+/* Block */
+var { a = 1; } as config;
+"#;
+        let AstNodeEnum::Module(module) = assert_parse_success(content) else {
+            panic!("Expected Module");
+        };
+        let comments: Vec<_> = module
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                AstNodeEnum::Comment(comment) => Some((comment.kind, comment.value.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            comments,
+            [
+                (CommentKind::ModuleDoc, "//! Module doc"),
+                (CommentKind::Doc, "/// Item doc"),
+                (CommentKind::Regular, "// Regular"),
+                (CommentKind::Regular, "//// Not a doc"),
+                (CommentKind::Regular, "# Hash"),
+                (CommentKind::Regular, "/* Block */"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_doc_comment_in_graph() {
+        let content = "graph {\n    /// Loads the data\n    a = load(src);\n} as main;";
+        let AstNodeEnum::Module(module) = assert_parse_success(content) else {
+            panic!("Expected Module");
+        };
+        let AstNodeEnum::GraphDef(graph_def) = &module.children[0] else {
+            panic!("Expected GraphDef");
+        };
+        let AstNodeEnum::Comment(comment) = &graph_def.children[0] else {
+            panic!("Expected Comment");
+        };
+        assert_eq!(comment.kind, CommentKind::Doc);
+        assert_eq!(comment.value, "/// Loads the data");
+    }
 }
 
 #[cfg(test)]
@@ -1377,3 +1554,847 @@ graph {
         }
     }
 }
+
+#[cfg(test)]
+mod condition_tests {
+    use crate::ast::*;
+    use crate::tests::*;
+
+    fn parse_condition(content: &str) -> ConditionBlock {
+        let ast = assert_parse_success(content);
+        match ast {
+            AstNodeEnum::Module(module) => match &module.children[0] {
+                AstNodeEnum::GraphDef(graph_def) => match &graph_def.children[0] {
+                    AstNodeEnum::ConditionDef(cond_def) => {
+                        assert_eq!(cond_def.outputs[0].name, "r");
+                        *cond_def.value.clone()
+                    }
+                    _ => panic!("Expected ConditionDef"),
+                },
+                _ => panic!("Expected GraphDef"),
+            },
+            _ => panic!("Expected Module"),
+        }
+    }
+
+    fn assert_comparison(expr: &ConditionExpr, left: &str, operator: &str, right: &str) {
+        match expr {
+            ConditionExpr::Statement(stmt) => {
+                assert_eq!(stmt.operator, operator);
+                match (&*stmt.left_operand, &*stmt.right_operand) {
+                    (AstNodeEnum::Symbol(l), AstNodeEnum::NumberLiteral(r)) => {
+                        assert_eq!(l.name, left);
+                        assert_eq!(r.raw, right);
+                    }
+                    (AstNodeEnum::Symbol(l), AstNodeEnum::Symbol(r)) => {
+                        assert_eq!(l.name, left);
+                        assert_eq!(r.name, right);
+                    }
+                    _ => panic!("Unexpected comparison operands"),
+                }
+            }
+            _ => panic!("Expected ConditionExpr::Statement"),
+        }
+    }
+
+    #[test]
+    fn test_parse_and_condition() {
+        let content = r#"
+graph {
+    r = a > 0 && b < 10 ? foo(a) : bar(b);
+};
+"#;
+        let block = parse_condition(content);
+        match &*block.condition {
+            ConditionExpr::And(left, right) => {
+                assert_comparison(left, "a", ">", "0");
+                assert_comparison(right, "b", "<", "10");
+            }
+            _ => panic!("Expected ConditionExpr::And"),
+        }
+        match &*block.true_branch {
+            AstNodeEnum::NodeBlock(node) => assert_eq!(node.name.name, "foo"),
+            _ => panic!("Expected NodeBlock"),
+        }
+        match &*block.false_branch {
+            AstNodeEnum::NodeBlock(node) => assert_eq!(node.name.name, "bar"),
+            _ => panic!("Expected NodeBlock"),
+        }
+    }
+
+    #[test]
+    fn test_parse_not_group_condition() {
+        let content = r#"
+graph {
+    r = !(x == y) ? foo() : bar();
+};
+"#;
+        let block = parse_condition(content);
+        match &*block.condition {
+            ConditionExpr::Not(inner) => match &**inner {
+                ConditionExpr::Group(grouped) => assert_comparison(grouped, "x", "==", "y"),
+                _ => panic!("Expected ConditionExpr::Group"),
+            },
+            _ => panic!("Expected ConditionExpr::Not"),
+        }
+    }
+
+    #[test]
+    fn test_parse_or_precedence() {
+        let content = r#"
+graph {
+    r = a > 0 || b > 0 && c.empty() ? foo() : bar();
+};
+"#;
+        let block = parse_condition(content);
+        match &*block.condition {
+            ConditionExpr::Or(left, right) => {
+                assert_comparison(left, "a", ">", "0");
+                match &**right {
+                    ConditionExpr::And(_, call) => match &**call {
+                        ConditionExpr::Block(node) => assert_eq!(node.name.name, "c.empty"),
+                        _ => panic!("Expected ConditionExpr::Block"),
+                    },
+                    _ => panic!("Expected ConditionExpr::And"),
+                }
+            }
+            _ => panic!("Expected ConditionExpr::Or"),
+        }
+    }
+
+    fn render(expr: &ConditionExpr) -> String {
+        match expr {
+            ConditionExpr::Block(node) => node.name.name.clone(),
+            ConditionExpr::Symbol(symbol) => symbol.name.clone(),
+            ConditionExpr::Not(inner) => format!("!{}", render(inner)),
+            ConditionExpr::Group(inner) => format!("({})", render(inner)),
+            ConditionExpr::And(left, right) => format!("{} && {}", render(left), render(right)),
+            ConditionExpr::Or(left, right) => format!("{} || {}", render(left), render(right)),
+            other => panic!("Unexpected condition {:?}", other),
+        }
+    }
+
+    fn normalized(condition: &str) -> ConditionExpr {
+        let content = format!("graph {{\n    r = {} ? foo() : bar();\n}};", condition);
+        normalize_condition(&parse_condition(&content).condition)
+    }
+
+    #[test]
+    fn test_normalize_condition_de_morgan() {
+        let expr = normalized("!(a.ok() && b.ok())");
+        match &expr {
+            ConditionExpr::Or(left, right) => {
+                assert!(matches!(**left, ConditionExpr::Not(_)));
+                assert!(matches!(**right, ConditionExpr::Not(_)));
+            }
+            _ => panic!("Expected ConditionExpr::Or"),
+        }
+        assert_eq!(render(&expr), "!a.ok || !b.ok");
+
+        assert_eq!(render(&normalized("!(a.ok() || !b.ok()) && c.ok()")), "!a.ok && b.ok && c.ok");
+        assert_eq!(render(&normalized("!!a.ok()")), "a.ok");
+        // The `&&` under the `||` keeps binding tighter
+        assert_eq!(render(&normalized("!(a.ok() && (b.ok() || c.ok()))")), "!a.ok || !b.ok && !c.ok");
+        assert_eq!(render(&normalized("(a.ok() || b.ok()) && c.ok()")), "(a.ok || b.ok) && c.ok");
+    }
+
+    #[test]
+    fn test_normalize_condition_flattens() {
+        let expr = normalized("a.ok() || (b.ok() || (c.ok() || d.ok()))");
+        assert_eq!(render(&expr), "a.ok || b.ok || c.ok || d.ok");
+        // Left-nested: ((a || b) || c) || d
+        let ConditionExpr::Or(left, last) = &expr else {
+            panic!("Expected ConditionExpr::Or");
+        };
+        assert_eq!(render(last), "d.ok");
+        assert_eq!(render(left), "a.ok || b.ok || c.ok");
+        assert_eq!(normalize_condition(&expr), expr);
+    }
+}
+
+#[cfg(test)]
+mod reparse_tests {
+    use crate::ast::*;
+    use crate::parser::{reparse, TextEdit};
+    use crate::tests::*;
+
+    const SOURCE: &str = r#"var { name = "test"; } as config;
+
+graph {
+    a = foo(x);
+} as first;
+
+# trailing graph
+graph {
+    b = bar(y);
+} as second;
+"#;
+
+    fn module(ast: AstNodeEnum) -> Module {
+        match ast {
+            AstNodeEnum::Module(module) => module,
+            _ => panic!("Expected Module node"),
+        }
+    }
+
+    fn apply(source: &str, edit: &TextEdit) -> String {
+        format!("{}{}{}", &source[..edit.start], edit.text, &source[edit.end..])
+    }
+
+    #[test]
+    fn test_reparse_reuses_untouched_statements() {
+        let old = module(assert_parse_success(SOURCE));
+        let start = SOURCE.find("foo(x)").unwrap();
+        let edit = TextEdit {
+            start,
+            end: start + "foo(x)".len(),
+            text: "foo(x);\n    extra = baz(a)".to_string(),
+        };
+        let new_src = apply(SOURCE, &edit);
+
+        let new = reparse(&old, SOURCE, &new_src, edit).expect("Reparse should succeed");
+        assert_eq!(new.children.len(), old.children.len());
+
+        // The var before the edit is reused unchanged
+        assert_eq!(new.children[0], old.children[0]);
+
+        // Statements after the edit are reused, shifted down one line
+        let mut shifted = old.children[3].clone();
+        shifted.shift_lines(1);
+        assert_eq!(new.children[3], shifted);
+        assert_eq!(new.children[3].position().line, old.children[3].position().line + 1);
+
+        // The edited graph was re-parsed
+        match &new.children[1] {
+            AstNodeEnum::GraphDef(graph) => assert_eq!(graph.children.len(), 2),
+            _ => panic!("Expected GraphDef"),
+        }
+
+        // The result matches a full parse of the new source
+        let full = module(assert_parse_success(&new_src));
+        assert_eq!(new.children, full.children);
+    }
+
+    #[test]
+    fn test_reparse_line_removal_and_fallback() {
+        let old = module(assert_parse_success(SOURCE));
+
+        // Removing the comment line shifts the last graph up
+        let start = SOURCE.find("# trailing graph\n").unwrap();
+        let edit = TextEdit {
+            start,
+            end: start + "# trailing graph\n".len(),
+            text: String::new(),
+        };
+        let new_src = apply(SOURCE, &edit);
+        let new = reparse(&old, SOURCE, &new_src, edit).expect("Reparse should succeed");
+        let full = module(assert_parse_success(&new_src));
+        assert_eq!(new.children, full.children);
+
+        // An edit that unbalances braces cannot be re-parsed on its own
+        let start = SOURCE.find("} as first;").unwrap();
+        let edit = TextEdit {
+            start,
+            end: start + 1,
+            text: String::new(),
+        };
+        let new_src = apply(SOURCE, &edit);
+        assert!(reparse(&old, SOURCE, &new_src, edit).is_err());
+    }
+}
+
+#[cfg(test)]
+mod reuse_tests {
+    use crate::ast::*;
+    use crate::parser::{ParseOptions, Parser};
+    use crate::tests::*;
+
+    #[test]
+    fn test_parser_reused_for_many_snippets() {
+        let parser = Parser::new(ParseOptions {
+            debug: false,
+            ..default_test_options()
+        });
+        for index in 0..1000 {
+            let content = format!("var {{ name{} = \"a\\tb\"; }};", index);
+            let ast = parser.parse(&content).unwrap();
+            let AstNodeEnum::Module(module) = ast else {
+                panic!("Expected module");
+            };
+            assert_eq!(module.children.len(), 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod string_literal_tests {
+    use crate::ast::*;
+    use crate::tests::*;
+
+    #[test]
+    fn test_string_literals_with_positions() {
+        let content = r#"var {
+    title = "Hello";
+    body = """multi
+line""";
+    d = {"k": "v"};
+};
+graph {
+    name = "g";
+    a = foo(x).with(k="attr");
+};"#;
+        let ast = assert_parse_success(content);
+        let AstNodeEnum::Module(module) = ast else {
+            panic!("Expected module");
+        };
+
+        let strings: Vec<(usize, usize, usize, &str)> = string_literals(&module)
+            .into_iter()
+            .map(|(position, value, _)| (position.line, position.end_line, position.start, value))
+            .collect();
+        assert_eq!(
+            strings,
+            vec![
+                (2, 2, 13, "Hello"),
+                (3, 4, 12, "multi\nline"),
+                (5, 5, 10, "k"),
+                (5, 5, 15, "v"),
+                (8, 8, 12, "g"),
+                (9, 9, 23, "attr"),
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod rename_tests {
+    use crate::ast::*;
+    use crate::tests::*;
+
+    #[test]
+    fn test_rename_var_alias_and_references() {
+        let content = r#"var { version = "1.0"; } as config;
+graph {
+    v = config.version;
+    config = 1;
+};"#;
+        let AstNodeEnum::Module(mut module) = assert_parse_success(content) else {
+            panic!("Expected module");
+        };
+
+        let count = rename_symbol(&mut module, SymbolKind::VarAsName, "config", "settings");
+        assert_eq!(count, 2);
+
+        let AstNodeEnum::VarDef(var_def) = &module.children[0] else {
+            panic!("Expected var definition");
+        };
+        assert_eq!(var_def.alias.as_ref().unwrap().name, "settings");
+
+        let AstNodeEnum::GraphDef(graph_def) = &module.children[1] else {
+            panic!("Expected graph definition");
+        };
+        let AstNodeEnum::RefDef(ref_def) = &graph_def.children[0] else {
+            panic!("Expected reference");
+        };
+        assert_eq!(ref_def.value.name, "settings.version");
+        // The graph property of the same name is left alone
+        let AstNodeEnum::AttrDef(attr_def) = &graph_def.children[1] else {
+            panic!("Expected graph property");
+        };
+        assert_eq!(attr_def.name.name, "config");
+
+        assert_eq!(rename_symbol(&mut module, SymbolKind::VarAsName, "config", "other"), 0);
+    }
+
+    #[test]
+    fn test_replace_value_keep_position() {
+        let content = "var {\n    limit = 10;\n};";
+        let AstNodeEnum::Module(mut module) = assert_parse_success(content) else {
+            panic!("Expected module");
+        };
+        let AstNodeEnum::VarDef(var_def) = &mut module.children[0] else {
+            panic!("Expected var definition");
+        };
+        let AstNodeEnum::AttrDef(attr_def) = &mut var_def.children[0] else {
+            panic!("Expected attribute");
+        };
+        let original = attr_def.value.position().clone();
+        assert_eq!((original.line, original.start), (2, 13));
+
+        let replacement = AstNodeEnum::StringLiteral(StringLiteral {
+            position: Position::new(0, 0, 0),
+            value: "unlimited".to_string(),
+            quote: QuoteStyle::Double,
+            escaped_interpolations: Vec::new(),
+        });
+        replace_value_keep_position(&mut attr_def.value, replacement);
+        let AstNodeEnum::StringLiteral(literal) = &*attr_def.value else {
+            panic!("Expected string literal");
+        };
+        assert_eq!(literal.value, "unlimited");
+        assert_eq!(literal.position, original);
+    }
+}
+
+#[cfg(test)]
+mod completion_tests {
+    use crate::ast::*;
+    use crate::{parse_for_completion, CompletionKind};
+
+    #[test]
+    fn test_completion_kinds() {
+        let cases = [
+            ("graph { node = builtin.", CompletionKind::OpName, "builtin."),
+            ("graph { a = foo(x).ver", CompletionKind::NodeAttribute, "ver"),
+            ("graph { a = foo(x, ", CompletionKind::NodeInput, ""),
+            ("var { limit = ", CompletionKind::Value, ""),
+            ("var { a = 1; } as v;\ngr", CompletionKind::Keyword, "gr"),
+            ("graph { a = foo(); b", CompletionKind::Name, "b"),
+        ];
+        for (content, kind, prefix) in cases {
+            let context = parse_for_completion(content, content.len());
+            assert_eq!(context.kind, kind, "for {:?}", content);
+            assert_eq!(context.prefix, prefix, "for {:?}", content);
+        }
+    }
+
+    #[test]
+    fn test_completion_partial_ast_and_cursor() {
+        let content = "var { a = 1; } as v;\ngraph { x = foo(y). } as g;";
+        let cursor = content.find("). ").unwrap() + 2;
+        let context = parse_for_completion(content, cursor);
+        assert_eq!(context.kind, CompletionKind::NodeAttribute);
+        assert_eq!(context.prefix, "");
+
+        let Some(AstNodeEnum::Module(module)) = context.partial else {
+            panic!("Expected a partial module");
+        };
+        assert!(matches!(module.children.first(), Some(AstNodeEnum::VarDef(_))));
+
+        // The expected set comes from the grammar at the cursor
+        let context = parse_for_completion("var { limit = ", 14);
+        assert_eq!(context.kind, CompletionKind::Value);
+        assert!(context.expected.iter().any(|rule| rule == "value"), "{:?}", context.expected);
+        let context = parse_for_completion("var { a = 1; };\n", 16);
+        assert_eq!(context.kind, CompletionKind::Keyword);
+        assert!(context.expected.iter().any(|rule| rule == "statement_def"), "{:?}", context.expected);
+    }
+
+    #[test]
+    fn test_completion_skips_braces_in_comments_and_strings() {
+        let cases = [
+            "graph {\n    # a } in a comment\n    a = foo(x, ",
+            "graph {\n    /* } */ a = foo(x, ",
+            "graph {\n    // {\n    a = foo(x, ",
+            "graph {\n    s = 'it\\'s }';\n    a = foo(x, ",
+            "graph {\n    d = \"\"\"\n}\n\"\"\";\n    a = foo(x, ",
+        ];
+        for content in cases {
+            let context = parse_for_completion(content, content.len());
+            assert_eq!(context.kind, CompletionKind::NodeInput, "for {:?}", content);
+        }
+    }
+}
+
+#[cfg(test)]
+mod identifier_case_tests {
+    use crate::error::ParseError;
+    use crate::tests::*;
+    use crate::{parse_gos_with_errors, Case, ParseOptions};
+
+    #[test]
+    fn test_case_matches() {
+        assert!(Case::Snake.matches("max_retries2"));
+        assert!(!Case::Snake.matches("maxRetries"));
+        assert!(Case::Camel.matches("maxRetries"));
+        assert!(!Case::Camel.matches("MaxRetries"));
+        assert!(Case::Pascal.matches("MaxRetries"));
+        assert!(!Case::Pascal.matches("max_retries"));
+    }
+
+    #[test]
+    fn test_snake_case_warnings() {
+        let content = "var { maxRetries = 3; timeout = 1; } as config;\ngraph { outNode = foo(maxRetries); } as main;";
+        let options = ParseOptions {
+            identifier_case: Some(Case::Snake),
+            ..default_test_options()
+        };
+        let (ast, errors) = parse_gos_with_errors(content, options);
+        assert!(ast.is_some());
+        assert!(!errors.has_errors());
+
+        let messages: Vec<String> = errors.warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(errors.warnings.len(), 2, "{:?}", messages);
+        assert!(matches!(errors.warnings[0], ParseError::SemanticError { line: 1, column: 7, .. }));
+        assert!(messages[0].contains("maxRetries is not snake_case"), "{:?}", messages);
+        assert!(messages[1].contains("outNode is not snake_case"), "{:?}", messages);
+
+        // No check without the option
+        let (_, errors) = parse_gos_with_errors(content, default_test_options());
+        assert!(!errors.has_warnings());
+    }
+}
+
+#[cfg(test)]
+mod annotation_tests {
+    use crate::ast::*;
+    use crate::tests::*;
+
+    #[test]
+    fn test_parse_annotations() {
+        let content = "@cached @timeout(30) @tags('a', 2,)\ngraph { x = foo(); } as main;\n@deprecated var { a = 1; };\nop { meta { b = 1; }; } as Foo;";
+        let ast = assert_parse_success(content);
+        let AstNodeEnum::Module(module) = ast else {
+            panic!("Expected Module");
+        };
+
+        let AstNodeEnum::GraphDef(graph_def) = &module.children[0] else {
+            panic!("Expected GraphDef");
+        };
+        let names: Vec<&str> = graph_def.annotations.iter().map(|a| a.name.name.as_str()).collect();
+        assert_eq!(names, ["cached", "timeout", "tags"]);
+        assert_eq!(graph_def.annotations[0].name.kind, SymbolKind::AnnotationName);
+        assert!(graph_def.annotations[0].args.is_empty());
+        assert!(matches!(&graph_def.annotations[1].args[..], [AstNodeEnum::NumberLiteral(n)] if n.value == 30));
+        assert_eq!(graph_def.annotations[2].args.len(), 2);
+        // The definition starts at its first annotation
+        assert_eq!(graph_def.position.line, 1);
+        assert_eq!(graph_def.annotations[1].position.start, 9);
+
+        let AstNodeEnum::VarDef(var_def) = &module.children[1] else {
+            panic!("Expected VarDef");
+        };
+        assert_eq!(var_def.annotations.len(), 1);
+        assert_eq!(var_def.annotations[0].name.name, "deprecated");
+
+        let AstNodeEnum::OpDef(op_def) = &module.children[2] else {
+            panic!("Expected OpDef");
+        };
+        assert!(op_def.annotations.is_empty());
+    }
+
+    #[test]
+    fn test_annotation_requires_definition() {
+        let result = crate::parse("@cached x = foo();");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_annotated_var_compiles_and_formats() {
+        let content = "@deprecated var {\n    a = 1;\n};\ngraph {\n    x = foo(a);\n} as main;\n";
+        let result = crate::compile_ast(&assert_parse_success(content)).unwrap();
+        assert_eq!(result.vars.unwrap().get("a"), Some(&serde_json::json!(1)));
+
+        let formatted = crate::format_from_data(content, 4, 80).unwrap();
+        assert!(formatted.starts_with("@deprecated var {"), "{}", formatted);
+    }
+}
+
+#[cfg(test)]
+mod output_list_tests {
+    use crate::ast::*;
+    use crate::tests::*;
+    use crate::{parse_gos, ParseOptions};
+
+    fn node_outputs(ast: &AstNodeEnum) -> Vec<Symbol> {
+        let AstNodeEnum::Module(module) = ast else {
+            panic!("Expected Module");
+        };
+        let AstNodeEnum::GraphDef(graph_def) = &module.children[0] else {
+            panic!("Expected GraphDef");
+        };
+        let AstNodeEnum::NodeDef(node_def) = &graph_def.children[0] else {
+            panic!("Expected NodeDef");
+        };
+        node_def.outputs.clone()
+    }
+
+    #[test]
+    fn test_multi_line_outputs() {
+        let content = "graph {\n    first,\n    second ,\n  third = split(x);\n};";
+        let outputs = node_outputs(&assert_parse_success(content));
+        let names: Vec<&str> = outputs.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["first", "second", "third"]);
+        let starts: Vec<(usize, usize, usize)> = outputs
+            .iter()
+            .map(|s| (s.position.line, s.position.start, s.position.end))
+            .collect();
+        assert_eq!(starts, [(2, 5, 10), (3, 5, 11), (4, 3, 8)]);
+    }
+
+    #[test]
+    fn test_trailing_comma_outputs() {
+        let content = "graph {\n    a, b,\n    = split(x);\n};";
+        let options = ParseOptions {
+            allow_trailing_comma: true,
+            ..default_test_options()
+        };
+        let outputs = node_outputs(&parse_gos(content, options).unwrap());
+        let names: Vec<&str> = outputs.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(outputs[1].position.line, 2);
+
+        // Rejected unless allowed, at the comma
+        let error = parse_gos(content, default_test_options()).unwrap_err();
+        assert_eq!((error.line(), error.column()), (Some(2), Some(9)));
+        assert!(error.to_string().contains("Trailing comma"), "{}", error);
+        assert!(crate::parse(content).is_err());
+
+        // Also inside a node of the graph
+        let depend = "graph {\n    b = foo(x).depend(a,);\n};";
+        let error = parse_gos(depend, default_test_options()).unwrap_err();
+        assert_eq!((error.line(), error.column()), (Some(2), Some(24)));
+    }
+}
+
+#[cfg(test)]
+mod node_accessor_tests {
+    use crate::ast::*;
+    use crate::tests::*;
+
+    fn graph_nodes(content: &str) -> Vec<NodeDef> {
+        let AstNodeEnum::Module(module) = assert_parse_success(content) else {
+            panic!("Expected Module");
+        };
+        let AstNodeEnum::GraphDef(graph_def) = &module.children[0] else {
+            panic!("Expected GraphDef");
+        };
+        graph_def
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                AstNodeEnum::NodeDef(node_def) => Some(node_def.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_node_accessors() {
+        let nodes = graph_nodes(
+            "graph {\n    a, b = lib.split(x, y, 3);\n    c = merge(left=a, right=(b, w), n=1);\n    d = ref(sub(c));\n};",
+        );
+
+        assert_eq!(nodes[0].op_name(), "lib.split");
+        assert_eq!(nodes[0].output_names(), ["a", "b"]);
+        assert_eq!(nodes[0].input_names(), ["x", "y"]);
+        assert!(!nodes[0].is_ref());
+
+        assert_eq!(nodes[1].op_name(), "merge");
+        assert_eq!(nodes[1].output_names(), ["c"]);
+        assert_eq!(nodes[1].input_names(), ["a", "b", "w"]);
+        assert_eq!(nodes[1].value.input_symbols()[1].position.line, 3);
+
+        assert_eq!(nodes[2].op_name(), "sub");
+        assert_eq!(nodes[2].input_names(), ["c"]);
+        assert!(nodes[2].is_ref());
+    }
+
+    #[test]
+    fn test_resolved_inputs() {
+        let nodes = graph_nodes(
+            "graph {\n    a = split(x, y, 3);\n    b = merge(left=a, right=(x, w), n=1.5);\n    c = noop();\n};",
+        );
+
+        // Literals rendered as written
+        let raw = |value: &AstNodeEnum| match value {
+            AstNodeEnum::NumberLiteral(number) => Ok(number.raw.clone()),
+            AstNodeEnum::FloatLiteral(float) => Ok(float.raw.clone()),
+            other => Err(format!("Unexpected literal {:?}", other)),
+        };
+        let positional = nodes[0].value.try_resolved_inputs(raw).unwrap();
+        assert_eq!(positional.len(), 3);
+        assert!(positional.iter().all(|input| input.key.is_none()));
+        assert_eq!(positional[0].values, ["x"]);
+        assert_eq!(positional[2].values, ["3"]);
+
+        let keyword = nodes[1].value.try_resolved_inputs(raw).unwrap();
+        assert_eq!(
+            keyword,
+            vec![
+                ResolvedInput { key: Some("left".to_string()), values: vec!["a".to_string()], grouped: false },
+                ResolvedInput {
+                    key: Some("right".to_string()),
+                    values: vec!["x".to_string(), "w".to_string()],
+                    grouped: true,
+                },
+                ResolvedInput { key: Some("n".to_string()), values: vec!["1.5".to_string()], grouped: false },
+            ]
+        );
+
+        assert!(nodes[2].value.try_resolved_inputs(raw).unwrap().is_empty());
+
+        // Without a renderer literals are written as in the source
+        assert_eq!(nodes[1].value.resolved_inputs(), keyword);
+        assert!(nodes[2].value.resolved_inputs().is_empty());
+        let literals = graph_nodes("graph {\n    a = foo(x, [1, 'y'], {'k': true});\n};");
+        let values: Vec<Vec<String>> = literals[0].value.resolved_inputs().into_iter().map(|input| input.values).collect();
+        assert_eq!(values, [vec!["x"], vec!["[1,y]"], vec!["{k:true}"]]);
+    }
+}
+
+#[cfg(test)]
+mod keyword_case_tests {
+    use crate::ast::*;
+    use crate::tests::*;
+    use crate::{parse_gos, ParseOptions};
+
+    fn var_values(ast: &AstNodeEnum) -> Vec<AstNodeEnum> {
+        let AstNodeEnum::Module(module) = ast else {
+            panic!("Expected Module");
+        };
+        let AstNodeEnum::VarDef(var_def) = &module.children[0] else {
+            panic!("Expected VarDef");
+        };
+        var_def
+            .children
+            .iter()
+            .filter_map(|child| match child {
+                AstNodeEnum::AttrDef(attr) => Some(*attr.value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_case_insensitive_keywords() {
+        let content = "var {\n    a = True;\n    b = FALSE;\n    c = Null;\n}";
+        let options = ParseOptions {
+            case_insensitive_keywords: true,
+            ..default_test_options()
+        };
+        let values = var_values(&parse_gos(content, options).unwrap());
+        match &values[..] {
+            [AstNodeEnum::BoolLiteral(a), AstNodeEnum::BoolLiteral(b), AstNodeEnum::NullLiteral(c)] => {
+                assert_eq!((a.value, a.raw.as_str()), (true, "True"));
+                assert_eq!((b.value, b.raw.as_str()), (false, "FALSE"));
+                assert_eq!(c.raw, "Null");
+            }
+            other => panic!("Unexpected values {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_keyword_case_rejected_by_default() {
+        // `True`/`False` have always been accepted
+        assert_parse_success("var {\n    a = True;\n    b = False;\n}");
+
+        let error = parse_gos("var {\n    a = FALSE;\n}", default_test_options()).unwrap_err();
+        assert_eq!((error.line(), error.column()), (Some(2), Some(9)));
+        assert!(error.to_string().contains("case_insensitive_keywords"), "{}", error);
+        assert!(crate::parse("var {\n    c = Null;\n}").is_err());
+    }
+
+    #[test]
+    fn test_keyword_prefixed_identifiers() {
+        let content = "graph { n = foo(x).with(k=NULLX, t=TrueValue, f=false_ish, z=nullx); } as g;";
+        let json = serde_json::to_value(crate::compile_ast(&assert_parse_success(content)).unwrap()).unwrap();
+        let with = &json["graphs"][0]["nodes"]["n"]["with"];
+        assert_eq!(with["k"], "NULLX");
+        assert_eq!(with["t"], "TrueValue");
+        assert_eq!(with["f"], "false_ish");
+        assert_eq!(with["z"], "nullx");
+    }
+}
+
+#[cfg(test)]
+mod embedded_tests {
+    use crate::ast::*;
+    use crate::parse_embedded;
+
+    #[test]
+    fn test_parse_embedded_fragments() {
+        let document = "# Pipeline\n\nThe main graph:\n\n```gos\ngraph {\n    a = load(src);\n} as main;\n```\n\nA broken one: ```gos graph { b = ; }```\n";
+        let fragments = parse_embedded(document, "```gos", "```");
+        assert_eq!(fragments.len(), 2);
+
+        let (position, result) = &fragments[0];
+        assert_eq!((position.line, position.end_line), (5, 9));
+        let AstNodeEnum::Module(module) = result.as_ref().unwrap() else {
+            panic!("Expected Module");
+        };
+        let AstNodeEnum::GraphDef(graph) = &module.children[0] else {
+            panic!("Expected GraphDef");
+        };
+        assert_eq!(graph.position.line, 6);
+        let AstNodeEnum::NodeDef(node) = &graph.children[0] else {
+            panic!("Expected NodeDef");
+        };
+        assert_eq!((node.position.line, node.position.start), (7, 5));
+
+        // Errors point into the document, on the fence line itself here
+        let (position, result) = &fragments[1];
+        assert_eq!((position.line, position.start), (11, 21));
+        let error = result.as_ref().unwrap_err();
+        assert_eq!(error.line(), Some(11));
+        assert_eq!(error.column(), Some(34));
+    }
+}
+
+#[cfg(test)]
+mod quote_style_tests {
+    use crate::ast::*;
+    use crate::{check_quote_consistency, parse, ParseError, Severity};
+
+    #[test]
+    fn test_check_quote_consistency() {
+        let content = "var {\n    a = 'x';\n    b = \"y\";\n    c = 'z';\n};\ngraph {\n    n = foo(a).version(\"1.0\");\n} as main;";
+        let AstNodeEnum::Module(module) = parse(content).unwrap() else {
+            panic!("Expected Module");
+        };
+        let warnings = check_quote_consistency(&module);
+        let positions: Vec<_> = warnings
+            .iter()
+            .map(|warning| match warning {
+                ParseError::Warning { line, column, message } => {
+                    assert!(message.contains("single quotes"), "{}", message);
+                    assert_eq!(warning.severity(), Severity::Warning);
+                    (*line, *column)
+                }
+                other => panic!("Expected Warning, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(positions, [(3, 9), (7, 24)]);
+
+        let AstNodeEnum::Module(module) = parse("var { a = \"x\"; b = \"\"\"y\"\"\"; };").unwrap() else {
+            panic!("Expected Module");
+        };
+        assert!(check_quote_consistency(&module).is_empty());
+
+        // Also as a condition, in a file of single quotes
+        let condition = "var { a = 'x'; };\ngraph {\n    r = \"\"\"x > 1\"\"\" ? foo(a) : bar(a);\n} as main;";
+        let AstNodeEnum::Module(module) = parse(condition).unwrap() else {
+            panic!("Expected Module");
+        };
+        assert!(check_quote_consistency(&module).is_empty());
+    }
+}
+
+#[cfg(test)]
+mod shadowing_tests {
+    use crate::ast::*;
+    use crate::{check_name_shadowing, parse, ParseError, Severity};
+
+    #[test]
+    fn test_check_name_shadowing() {
+        let content = "var {\n    size = 1;\n} as config;\ngraph {\n    config = load(x);\n    y = foo(config);\n} as main;";
+        let AstNodeEnum::Module(module) = parse(content).unwrap() else {
+            panic!("Expected Module");
+        };
+        let warnings = check_name_shadowing(&module);
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert_eq!(warnings[0].severity(), Severity::Warning);
+        match &warnings[0] {
+            ParseError::Warning { line, column, message } => {
+                assert_eq!((*line, *column), (5, 5));
+                assert!(message.contains("var name config"), "{}", message);
+            }
+            other => panic!("Expected Warning, got {:?}", other),
+        }
+
+        let AstNodeEnum::Module(module) = parse("graph {\n    y = foo(x);\n} as main;").unwrap() else {
+            panic!("Expected Module");
+        };
+        assert!(check_name_shadowing(&module).is_empty());
+    }
+}